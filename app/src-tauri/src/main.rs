@@ -19,10 +19,12 @@ use nusamai::{
     },
     source::{citygml::CityGmlSourceProvider, DataSourceProvider},
     transformer::{
-        self, MappingRules, MultiThreadTransformer, NusamaiTransformBuilder, TransformBuilder,
+        self, transform::ProjectionTransform, MappingRules, MultiThreadTransformer,
+        NusamaiTransformBuilder, TransformBuilder,
     },
 };
 use nusamai_plateau::models::TopLevelCityObject;
+use nusamai_projection::vshift::Jgd2011ToWgs84;
 use tauri_plugin_log::{LogTarget, RotationStrategy, TimezoneStrategy};
 use thiserror::Error;
 
@@ -120,12 +122,15 @@ fn select_sink_provider(filetype: &str) -> Option<Box<dyn DataSinkProvider>> {
 }
 
 #[tauri::command(async)]
+#[allow(clippy::too_many_arguments)]
 fn run_conversion(
     input_paths: Vec<String>,
     output_path: String,
     filetype: String,
     epsg: u16,
     rules_path: String,
+    geoid_path: String,
+    keep_orthometric_height: bool,
     tasks_state: tauri::State<ConversionTasksState>,
     window: tauri::Window,
 ) -> Result<(), Error> {
@@ -146,6 +151,12 @@ fn run_conversion(
         log::error!("{}", msg);
         return Err(Error::InvalidPath(msg));
     };
+    // Check if the geoid model file is set, and if it exists
+    if !geoid_path.is_empty() && !PathBuf::from_str(&geoid_path).unwrap().exists() {
+        let msg = format!("Geoid model file does not exist: {}", geoid_path);
+        log::error!("{}", msg);
+        return Err(Error::InvalidPath(msg));
+    };
 
     // If the directory for the output path does not exist, create it
     let output_path_buf = PathBuf::from_str(&output_path).unwrap();
@@ -182,6 +193,11 @@ fn run_conversion(
 
     let mut requirements = sink.make_requirements();
     requirements.set_output_epsg(epsg);
+    if let Err(err) = ProjectionTransform::check_output_epsg(epsg) {
+        let msg = format!("Invalid output CRS: {}", err);
+        log::error!("{}", msg);
+        return Err(Error::InvalidSetting(msg));
+    }
 
     let source = {
         let source_provider: Box<dyn DataSourceProvider> = Box::new(CityGmlSourceProvider {
@@ -221,12 +237,23 @@ fn run_conversion(
             Some(mapping_rules)
         };
 
+        let jgd2wgs = if geoid_path.is_empty() {
+            Jgd2011ToWgs84::default()
+        } else {
+            Jgd2011ToWgs84::from_file(&geoid_path).map_err(|e| {
+                let msg = format!("Error loading geoid model: {}", e);
+                log::error!("{}", msg);
+                Error::InvalidSetting(msg)
+            })?
+        };
+
         let request = {
             let mut request = transformer::Request::from(requirements);
             request.set_mapping_rules(mapping_rules);
+            request.keep_orthometric_height = keep_orthometric_height;
             request
         };
-        let transform_builder = NusamaiTransformBuilder::new(request);
+        let transform_builder = NusamaiTransformBuilder::with_geoid(request, jgd2wgs);
         let mut schema = nusamai_citygml::schema::Schema::default();
         TopLevelCityObject::collect_schema(&mut schema);
         transform_builder.transform_schema(&mut schema);
@@ -39,7 +39,18 @@ const filetypeOptions: Record<string, { label: string; extensions: string[]; eps
 				{ value: 10171, label: 'JGD2011 / 平面直角座標系 X + 標高 (EPSG:10171)' },
 				{ value: 10172, label: 'JGD2011 / 平面直角座標系 XI + 標高 (EPSG:10172)' },
 				{ value: 10173, label: 'JGD2011 / 平面直角座標系 XII + 標高 (EPSG:10173)' },
-				{ value: 10174, label: 'JGD2011 / 平面直角座標系 XIII + 標高 (EPSG:10174)' }
+				{ value: 10174, label: 'JGD2011 / 平面直角座標系 XIII + 標高 (EPSG:10174)' },
+				{ value: 6688, label: 'JGD2011 / UTM 51N (EPSG:6688)' },
+				{ value: 6689, label: 'JGD2011 / UTM 52N (EPSG:6689)' },
+				{ value: 6690, label: 'JGD2011 / UTM 53N (EPSG:6690)' },
+				{ value: 6691, label: 'JGD2011 / UTM 54N (EPSG:6691)' },
+				{ value: 6692, label: 'JGD2011 / UTM 55N (EPSG:6692)' },
+				{ value: 32651, label: 'WGS 84 / UTM 51N (EPSG:32651)' },
+				{ value: 32652, label: 'WGS 84 / UTM 52N (EPSG:32652)' },
+				{ value: 32653, label: 'WGS 84 / UTM 53N (EPSG:32653)' },
+				{ value: 32654, label: 'WGS 84 / UTM 54N (EPSG:32654)' },
+				{ value: 32655, label: 'WGS 84 / UTM 55N (EPSG:32655)' },
+				{ value: 32656, label: 'WGS 84 / UTM 56N (EPSG:32656)' }
 			]
 		},
 		geojson: {
@@ -121,7 +132,18 @@ const filetypeOptions: Record<string, { label: string; extensions: string[]; eps
 				{ value: 10171, label: 'JGD2011 / 平面直角座標系 X + 標高 (EPSG:10171)' },
 				{ value: 10172, label: 'JGD2011 / 平面直角座標系 XI + 標高 (EPSG:10172)' },
 				{ value: 10173, label: 'JGD2011 / 平面直角座標系 XII + 標高 (EPSG:10173)' },
-				{ value: 10174, label: 'JGD2011 / 平面直角座標系 XIII + 標高 (EPSG:10174)' }
+				{ value: 10174, label: 'JGD2011 / 平面直角座標系 XIII + 標高 (EPSG:10174)' },
+				{ value: 6688, label: 'JGD2011 / UTM 51N (EPSG:6688)' },
+				{ value: 6689, label: 'JGD2011 / UTM 52N (EPSG:6689)' },
+				{ value: 6690, label: 'JGD2011 / UTM 53N (EPSG:6690)' },
+				{ value: 6691, label: 'JGD2011 / UTM 54N (EPSG:6691)' },
+				{ value: 6692, label: 'JGD2011 / UTM 55N (EPSG:6692)' },
+				{ value: 32651, label: 'WGS 84 / UTM 51N (EPSG:32651)' },
+				{ value: 32652, label: 'WGS 84 / UTM 52N (EPSG:32652)' },
+				{ value: 32653, label: 'WGS 84 / UTM 53N (EPSG:32653)' },
+				{ value: 32654, label: 'WGS 84 / UTM 54N (EPSG:32654)' },
+				{ value: 32655, label: 'WGS 84 / UTM 55N (EPSG:32655)' },
+				{ value: 32656, label: 'WGS 84 / UTM 56N (EPSG:32656)' }
 			]
 		}
 	};
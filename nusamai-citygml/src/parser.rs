@@ -251,6 +251,18 @@ impl<'b, R: BufRead> SubTreeReader<'_, 'b, R> {
         None
     }
 
+    pub fn find_uom_attr(&mut self) -> Option<String> {
+        let Some(start) = &self.state.current_start else {
+            panic!("find_uom_attr() must be called immediately after encountering a start tag.");
+        };
+        for attr in start.attributes().flatten() {
+            if attr.key.as_ref() == b"uom" {
+                return Some(String::from_utf8_lossy(attr.value.as_ref()).into_owned());
+            }
+        }
+        None
+    }
+
     pub fn skip_current_element(&mut self) -> Result<(), ParseError> {
         let Some(start) = &self.state.current_start else {
             panic!("skip_current_element() must be called immediately after encountering a new starting tag.");
@@ -50,6 +50,9 @@ pub struct Attribute {
     #[serde(default, skip_serializing_if = "is_some_one")]
     pub max_occurs: Option<u16>,
     pub original_name: Option<String>,
+    /// Unit of measure of the values, if known (e.g. `m`, `m2`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uom: Option<String>,
 }
 
 impl Attribute {
@@ -68,6 +71,7 @@ impl Default for Attribute {
             min_occurs: 0,
             max_occurs: Some(1),
             original_name: None,
+            uom: None,
         }
     }
 }
@@ -114,22 +114,14 @@ impl CityGmlElement for Code {
 
         if let Some(code_space) = code_space {
             let base_url = st.context().source_url();
-            match st
+            // NOTE: Unavailable codelists are handled by the resolver according to its policy
+            if let Some(v) = st
                 .context()
                 .code_resolver()
-                .resolve(base_url, &code_space, &code)
+                .resolve(base_url, &code_space, &code)?
             {
-                Ok(Some(v)) => {
-                    self.value = v;
-                    return Ok(());
-                }
-                Ok(None) => {}
-                Err(_) => {
-                    // FIXME
-                    log::warn!("Failed to lookup code {} form {}", code, code_space);
-                    self.value = code;
-                    return Ok(());
-                }
+                self.value = v;
+                return Ok(());
             }
         }
         self.value = code;
@@ -257,21 +249,30 @@ impl CityGmlElement for bool {
 #[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Measure {
     value: f64,
-    // pub uom: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uom: Option<String>,
 }
 
 impl Measure {
     pub fn new(value: f64) -> Self {
-        Self { value }
+        Self { value, uom: None }
+    }
+    pub fn with_uom(value: f64, uom: Option<String>) -> Self {
+        Self { value, uom }
     }
     pub fn value(&self) -> f64 {
         self.value
     }
+    /// Unit of measure (e.g. `m`, `m2`)
+    pub fn uom(&self) -> Option<&str> {
+        self.uom.as_deref()
+    }
 }
 
 impl CityGmlElement for Measure {
     #[inline(never)]
     fn parse<R: BufRead>(&mut self, st: &mut SubTreeReader<R>) -> Result<(), ParseError> {
+        self.uom = st.find_uom_attr();
         let text = st.parse_text()?;
         match text.parse() {
             Ok(v) => {
@@ -479,6 +480,7 @@ impl CityGmlElement for Color {
             min_occurs: 3,
             max_occurs: Some(3),
             original_name: None,
+            uom: None,
         }
     }
 }
@@ -547,6 +549,7 @@ impl CityGmlElement for ColorPlusOpacity {
             min_occurs: 4,
             max_occurs: Some(4),
             original_name: None,
+            uom: None,
         }
     }
 }
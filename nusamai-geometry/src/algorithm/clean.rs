@@ -0,0 +1,140 @@
+//! Cleaning of rings: repeated points, spikes and degenerate rings
+
+use super::plane::newell_normal;
+
+/// Removes repeated points and spikes from an (implicitly closed) ring and returns the indices of the retained points.
+///
+/// Consecutive points closer than the tolerance are merged into the first of them.
+/// A point is a spike if the ring turns back along (almost) the same line at it,
+/// i.e. the shorter of its two edges lies within the tolerance from the longer one.
+/// Spikes are removed repeatedly, so the result may have less than 3 points.
+pub fn clean_ring(ring: &[[f64; 3]], tolerance: f64) -> Vec<usize> {
+    let tol2 = tolerance * tolerance;
+
+    // Repeated points, including the implicit closing edge
+    let mut kept: Vec<usize> = Vec::with_capacity(ring.len());
+    for (i, p) in ring.iter().enumerate() {
+        match kept.last() {
+            Some(&last) if dist2(&ring[last], p) <= tol2 => {}
+            _ => kept.push(i),
+        }
+    }
+    while kept.len() > 1 && dist2(&ring[kept[0]], &ring[kept[kept.len() - 1]]) <= tol2 {
+        kept.pop();
+    }
+
+    // Spikes
+    while kept.len() >= 3 {
+        let n = kept.len();
+        let Some(i) = (0..n).find(|&i| {
+            is_spike(
+                &ring[kept[(i + n - 1) % n]],
+                &ring[kept[i]],
+                &ring[kept[(i + 1) % n]],
+                tolerance,
+            )
+        }) else {
+            break;
+        };
+        kept.remove(i);
+
+        // The neighbours of the spike may coincide
+        let n = kept.len();
+        if n > 1 {
+            let (prev, next) = ((i + n - 1) % n, i % n);
+            if dist2(&ring[kept[prev]], &ring[kept[next]]) <= tol2 {
+                kept.remove(next);
+            }
+        }
+    }
+
+    kept
+}
+
+/// Returns `true` if the ring has no area, i.e. it has less than 3 points or
+/// it is thinner than the tolerance on average (twice the area divided by the perimeter).
+pub fn is_degenerate_ring(ring: &[[f64; 3]], tolerance: f64) -> bool {
+    if ring.len() < 3 {
+        return true;
+    }
+    let n = newell_normal(ring);
+    let area = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt() / 2.0;
+    let perimeter: f64 = ring
+        .iter()
+        .enumerate()
+        .map(|(i, a)| dist2(a, &ring[(i + 1) % ring.len()]).sqrt())
+        .sum();
+    // NaN (e.g. from non-finite coordinates) is also regarded as degenerate
+    !matches!(
+        (2.0 * area).partial_cmp(&(tolerance * perimeter)),
+        Some(std::cmp::Ordering::Greater)
+    )
+}
+
+fn is_spike(a: &[f64; 3], b: &[f64; 3], c: &[f64; 3], tolerance: f64) -> bool {
+    let u = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    let v = [c[0] - b[0], c[1] - b[1], c[2] - b[2]];
+    if u[0] * v[0] + u[1] * v[1] + u[2] * v[2] <= 0.0 {
+        return false;
+    }
+    // Distance from the end of the shorter edge to the line of the longer one
+    let cross = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let cross_len2 = cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2];
+    let longer2 = dist2(a, b).max(dist2(c, b));
+    cross_len2 <= tolerance * tolerance * longer2
+}
+
+fn dist2(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_points() {
+        let ring = [
+            [0., 0., 0.],
+            [0., 0., 0.],
+            [1., 0., 0.],
+            [1., 1e-6, 0.],
+            [1., 1., 0.],
+            [0., 1., 0.],
+            [0., 0., 0.],
+        ];
+        assert_eq!(clean_ring(&ring, 1e-3), [0, 2, 4, 5]);
+    }
+
+    #[test]
+    fn test_spikes() {
+        // A square with a spike going out and back on the right edge
+        let ring = [
+            [0., 0., 0.],
+            [1., 0., 0.],
+            [1., 0.5, 0.],
+            [3., 0.5, 0.],
+            [1., 0.5, 0.],
+            [1., 1., 0.],
+            [0., 1., 0.],
+        ];
+        assert_eq!(clean_ring(&ring, 1e-3), [0, 1, 2, 5, 6]);
+
+        // Collapses into nothing
+        let ring = [[0., 0., 0.], [1., 0., 0.], [2., 0., 0.]];
+        assert!(clean_ring(&ring, 1e-3).len() < 3);
+    }
+
+    #[test]
+    fn test_degenerate_ring() {
+        let square = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+        assert!(!is_degenerate_ring(&square, 1e-3));
+        let sliver = [[0., 0., 0.], [10., 0., 0.], [10., 1e-4, 0.], [0., 1e-4, 0.]];
+        assert!(is_degenerate_ring(&sliver, 1e-3));
+        assert!(is_degenerate_ring(&square[..2], 1e-3));
+    }
+}
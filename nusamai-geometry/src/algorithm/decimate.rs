@@ -0,0 +1,407 @@
+//! Triangle mesh decimation based on quadric error metrics (Garland & Heckbert)
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+/// Decimates a triangle mesh by repeatedly collapsing its cheapest edges.
+///
+/// Each collapse moves a vertex onto one of its neighbours (i.e. no new vertices are created),
+/// so the attributes of the vertices (e.g. texture coordinates) stay valid.
+/// The error of a collapse is the distance from the moved vertex to the original planes around it,
+/// and edges are collapsed only while the error is within `max_error`.
+/// The borders of open meshes are constrained to stay in place as well.
+///
+/// Vertices marked as `locked` are never moved. Collapses that would flip a triangle
+/// or make the mesh non-manifold are rejected.
+///
+/// Returns the remaining triangles as pairs of (index of the original triangle, vertex indices).
+pub fn decimate_mesh(
+    positions: &[[f64; 3]],
+    triangles: &[[u32; 3]],
+    locked: &[bool],
+    max_error: f64,
+) -> Vec<(usize, [u32; 3])> {
+    let mut mesh = Mesh::new(positions, triangles, locked);
+    if max_error > 0.0 {
+        mesh.decimate(max_error * max_error);
+    }
+    mesh.triangles
+        .into_iter()
+        .enumerate()
+        .filter(|&(ti, _)| mesh.alive[ti])
+        .collect()
+}
+
+/// Symmetric 4x4 matrix, stored as its upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Quadric of the squared distance to the plane `n·p + d = 0` (`n` is a unit vector)
+    fn from_plane([a, b, c]: [f64; 3], d: f64) -> Self {
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+
+    fn error(&self, &[x, y, z]: &[f64; 3]) -> f64 {
+        let q = &self.0;
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+#[derive(PartialEq)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl Eq for Collapse {}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Min-heap by cost
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct Mesh<'a> {
+    positions: &'a [[f64; 3]],
+    locked: &'a [bool],
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    removed: Vec<bool>,
+    versions: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl<'a> Mesh<'a> {
+    fn new(positions: &'a [[f64; 3]], triangles: &[[u32; 3]], locked: &'a [bool]) -> Self {
+        let n = positions.len();
+        let mut vertex_triangles = vec![Vec::new(); n];
+        let mut quadrics = vec![Quadric::default(); n];
+        let mut edges: HashMap<(u32, u32), (usize, usize)> = HashMap::new();
+
+        for (ti, tri) in triangles.iter().enumerate() {
+            for &v in tri {
+                vertex_triangles[v as usize].push(ti);
+            }
+            if let Some(n) = triangle_unit_normal(positions, tri) {
+                let q = Quadric::from_plane(n, -dot(&n, &positions[tri[0] as usize]));
+                for &v in tri {
+                    quadrics[v as usize] = quadrics[v as usize].add(&q);
+                }
+            }
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                edges.entry((a.min(b), a.max(b))).or_insert((0, ti)).0 += 1;
+            }
+        }
+
+        // Constrain the border edges with planes perpendicular to their triangles
+        for (&(a, b), &(count, ti)) in &edges {
+            if count != 1 {
+                continue;
+            }
+            let Some(n) = triangle_unit_normal(positions, &triangles[ti]) else {
+                continue;
+            };
+            let (pa, pb) = (&positions[a as usize], &positions[b as usize]);
+            let Some(m) = normalize(cross(&sub(pb, pa), &n)) else {
+                continue;
+            };
+            let q = Quadric::from_plane(m, -dot(&m, pa));
+            for v in [a, b] {
+                quadrics[v as usize] = quadrics[v as usize].add(&q);
+            }
+        }
+
+        let mut mesh = Self {
+            positions,
+            locked,
+            triangles: triangles.to_vec(),
+            alive: vec![true; triangles.len()],
+            vertex_triangles,
+            quadrics,
+            removed: vec![false; n],
+            versions: vec![0; n],
+            heap: BinaryHeap::new(),
+        };
+        for &(a, b) in edges.keys() {
+            mesh.push_candidates(a, b);
+        }
+        mesh
+    }
+
+    fn push_candidates(&mut self, a: u32, b: u32) {
+        for (from, to) in [(a, b), (b, a)] {
+            if self.locked[from as usize] {
+                continue;
+            }
+            let q = self.quadrics[from as usize].add(&self.quadrics[to as usize]);
+            self.heap.push(Collapse {
+                cost: q.error(&self.positions[to as usize]),
+                from,
+                to,
+                versions: (self.versions[from as usize], self.versions[to as usize]),
+            });
+        }
+    }
+
+    fn decimate(&mut self, max_cost: f64) {
+        while let Some(collapse) = self.heap.pop() {
+            let Collapse {
+                cost,
+                from,
+                to,
+                versions,
+            } = collapse;
+            let (f, t) = (from as usize, to as usize);
+            if self.removed[f]
+                || self.removed[t]
+                || versions != (self.versions[f], self.versions[t])
+            {
+                continue;
+            }
+            if cost > max_cost {
+                break;
+            }
+            if self.can_collapse(from, to) {
+                self.collapse(from, to);
+            }
+        }
+    }
+
+    fn alive_triangles(&self, v: u32) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_triangles[v as usize]
+            .iter()
+            .copied()
+            .filter(|&ti| self.alive[ti])
+    }
+
+    fn neighbors(&self, v: u32) -> Vec<u32> {
+        let mut neighbors: Vec<u32> = self
+            .alive_triangles(v)
+            .flat_map(|ti| self.triangles[ti])
+            .filter(|&u| u != v)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    fn can_collapse(&self, from: u32, to: u32) -> bool {
+        // Link condition: the common neighbours must be the apexes of the triangles sharing the edge
+        let shared = self
+            .alive_triangles(from)
+            .filter(|&ti| self.triangles[ti].contains(&to))
+            .count();
+        let to_neighbors = self.neighbors(to);
+        let common = self
+            .neighbors(from)
+            .iter()
+            .filter(|u| to_neighbors.binary_search(u).is_ok())
+            .count();
+        if shared == 0 || common > shared {
+            return false;
+        }
+
+        // The remaining triangles must not flip or degenerate
+        for ti in self.alive_triangles(from) {
+            let tri = self.triangles[ti];
+            if tri.contains(&to) {
+                continue;
+            }
+            let moved = tri.map(|v| if v == from { to } else { v });
+            let (Some(old), Some(new)) = (
+                triangle_unit_normal(self.positions, &tri),
+                triangle_unit_normal(self.positions, &moved),
+            ) else {
+                return false;
+            };
+            if dot(&old, &new) <= 0.0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn collapse(&mut self, from: u32, to: u32) {
+        let (f, t) = (from as usize, to as usize);
+        for ti in std::mem::take(&mut self.vertex_triangles[f]) {
+            if !self.alive[ti] {
+                continue;
+            }
+            let tri = &mut self.triangles[ti];
+            if tri.contains(&to) {
+                self.alive[ti] = false;
+            } else {
+                for v in tri.iter_mut().filter(|v| **v == from) {
+                    *v = to;
+                }
+                self.vertex_triangles[t].push(ti);
+            }
+        }
+        let alive = &self.alive;
+        self.vertex_triangles[t].retain(|&ti| alive[ti]);
+
+        self.removed[f] = true;
+        self.quadrics[t] = self.quadrics[t].add(&self.quadrics[f]);
+        self.versions[t] += 1;
+        for u in self.neighbors(to) {
+            self.push_candidates(to, u);
+        }
+    }
+}
+
+fn triangle_unit_normal(positions: &[[f64; 3]], tri: &[u32; 3]) -> Option<[f64; 3]> {
+    let [a, b, c] = tri.map(|v| &positions[v as usize]);
+    normalize(cross(&sub(b, a), &sub(c, a)))
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f64; 3]) -> Option<[f64; 3]> {
+    let len = dot(&v, &v).sqrt();
+    if len == 0.0 || !len.is_finite() {
+        return None;
+    }
+    Some([v[0] / len, v[1] / len, v[2] / len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flat grid of (n x n) vertices on the XY plane
+    fn grid(n: u32) -> (Vec<[f64; 3]>, Vec<[u32; 3]>) {
+        let positions = (0..n * n)
+            .map(|i| [(i % n) as f64, (i / n) as f64, 0.])
+            .collect();
+        let mut triangles = vec![];
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let v = y * n + x;
+                triangles.push([v, v + 1, v + n + 1]);
+                triangles.push([v, v + n + 1, v + n]);
+            }
+        }
+        (positions, triangles)
+    }
+
+    fn total_area(positions: &[[f64; 3]], triangles: &[(usize, [u32; 3])]) -> f64 {
+        triangles
+            .iter()
+            .map(|(_, tri)| {
+                let [a, b, c] = tri.map(|v| &positions[v as usize]);
+                let n = cross(&sub(b, a), &sub(c, a));
+                dot(&n, &n).sqrt() / 2.
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_flat_grid() {
+        let (positions, triangles) = grid(4);
+        let locked = vec![false; positions.len()];
+
+        let result = decimate_mesh(&positions, &triangles, &locked, 0.0);
+        assert_eq!(result.len(), triangles.len());
+
+        // The interior vertices and the collinear border vertices are removed
+        let result = decimate_mesh(&positions, &triangles, &locked, 0.01);
+        assert_eq!(result.len(), 2);
+        assert!((total_area(&positions, &result) - 9.).abs() < 1e-9);
+        for (ti, tri) in &result {
+            // Orientation is preserved
+            let [a, b, c] = tri.map(|v| &positions[v as usize]);
+            assert!(cross(&sub(b, a), &sub(c, a))[2] > 0.);
+            assert!(*ti < triangles.len());
+        }
+    }
+
+    #[test]
+    fn test_locked_vertices() {
+        let (positions, triangles) = grid(4);
+        let locked: Vec<bool> = positions
+            .iter()
+            .map(|p| p[0] == 0. || p[0] == 3. || p[1] == 0. || p[1] == 3.)
+            .collect();
+        let result = decimate_mesh(&positions, &triangles, &locked, 0.01);
+        // Only the four interior vertices can be removed
+        assert_eq!(result.len(), 10);
+        assert!((total_area(&positions, &result) - 9.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_error_budget() {
+        // A square pyramid without its base
+        let positions = vec![
+            [0., 0., 0.],
+            [2., 0., 0.],
+            [2., 2., 0.],
+            [0., 2., 0.],
+            [1., 1., 0.5],
+        ];
+        let triangles = vec![[0, 1, 4], [1, 2, 4], [2, 3, 4], [3, 0, 4]];
+        let locked = vec![true, true, true, true, false];
+
+        let result = decimate_mesh(&positions, &triangles, &locked, 0.1);
+        assert_eq!(result.len(), 4);
+
+        let result = decimate_mesh(&positions, &triangles, &locked, 2.0);
+        assert_eq!(result.len(), 2);
+    }
+}
@@ -0,0 +1,95 @@
+//! Segment intersection tests for 2D rings.
+
+/// Orientation of the triplet (a, b, c): positive if counter-clockwise, negative if clockwise.
+fn orient(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+/// Returns true if `p` lies on the segment (a, b), assuming the three points are collinear.
+fn on_segment(a: [f64; 2], b: [f64; 2], p: [f64; 2]) -> bool {
+    p[0] >= a[0].min(b[0])
+        && p[0] <= a[0].max(b[0])
+        && p[1] >= a[1].min(b[1])
+        && p[1] <= a[1].max(b[1])
+}
+
+/// Returns true if the closed segments (a1, a2) and (b1, b2) intersect or touch.
+pub fn segments_intersect(a1: [f64; 2], a2: [f64; 2], b1: [f64; 2], b2: [f64; 2]) -> bool {
+    let d1 = orient(b1, b2, a1);
+    let d2 = orient(b1, b2, a2);
+    let d3 = orient(a1, a2, b1);
+    let d4 = orient(a1, a2, b2);
+
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0))
+        && ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0))
+    {
+        return true;
+    }
+
+    (d1 == 0.0 && on_segment(b1, b2, a1))
+        || (d2 == 0.0 && on_segment(b1, b2, a2))
+        || (d3 == 0.0 && on_segment(a1, a2, b1))
+        || (d4 == 0.0 && on_segment(a1, a2, b2))
+}
+
+/// Returns true if the (implicitly closed) ring intersects or touches itself.
+///
+/// Adjacent edges are allowed to share their common vertex. This is a brute-force O(n^2) test,
+/// which is sufficient for the typical size of building surfaces.
+pub fn ring_self_intersects(ring: &[[f64; 2]]) -> bool {
+    let n = ring.len();
+    if n < 4 {
+        // A triangle can only be degenerate, not self-intersecting
+        return false;
+    }
+    for i in 0..n {
+        let (a1, a2) = (ring[i], ring[(i + 1) % n]);
+        for j in (i + 2)..n {
+            if i == 0 && j == n - 1 {
+                // first and last edges are adjacent
+                continue;
+            }
+            let (b1, b2) = (ring[j], ring[(j + 1) % n]);
+            if segments_intersect(a1, a2, b1, b2) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments() {
+        assert!(segments_intersect([0., 0.], [2., 2.], [0., 2.], [2., 0.]));
+        assert!(!segments_intersect([0., 0.], [1., 0.], [0., 1.], [1., 1.]));
+        // touching at an endpoint
+        assert!(segments_intersect([0., 0.], [1., 0.], [1., 0.], [2., 1.]));
+        // collinear and overlapping
+        assert!(segments_intersect([0., 0.], [2., 0.], [1., 0.], [3., 0.]));
+        // collinear and disjoint
+        assert!(!segments_intersect([0., 0.], [1., 0.], [2., 0.], [3., 0.]));
+    }
+
+    #[test]
+    fn simple_ring() {
+        let ring = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+        assert!(!ring_self_intersects(&ring));
+    }
+
+    #[test]
+    fn bowtie_ring() {
+        let ring = [[0., 0.], [1., 1.], [1., 0.], [0., 1.]];
+        assert!(ring_self_intersects(&ring));
+    }
+
+    #[test]
+    fn ring_touching_itself() {
+        // the vertex (1, 0) touches the edge (0, 0)-(2, 0)
+        let ring = [[0., 0.], [2., 0.], [2., 2.], [1., 0.], [0., 2.]];
+        assert!(ring_self_intersects(&ring));
+    }
+}
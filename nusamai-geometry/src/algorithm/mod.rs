@@ -0,0 +1,7 @@
+//! Geometric algorithms that operate on plain coordinate slices.
+
+pub mod clean;
+pub mod decimate;
+pub mod intersection;
+pub mod plane;
+pub mod simplify;
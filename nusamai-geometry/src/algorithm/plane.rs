@@ -0,0 +1,113 @@
+//! Plane fitting and projection for (nearly) planar 3D rings.
+
+/// Computes the normal vector of a ring using Newell's method.
+///
+/// The ring is treated as implicitly closed. The magnitude of the result is twice the area of the ring.
+pub fn newell_normal(ring: &[[f64; 3]]) -> [f64; 3] {
+    let mut n = [0.0; 3];
+    for (i, a) in ring.iter().enumerate() {
+        let b = &ring[(i + 1) % ring.len()];
+        n[0] += (a[1] - b[1]) * (a[2] + b[2]);
+        n[1] += (a[2] - b[2]) * (a[0] + b[0]);
+        n[2] += (a[0] - b[0]) * (a[1] + b[1]);
+    }
+    n
+}
+
+/// Returns the unit normal vector of a ring, or `None` if the ring is degenerate.
+pub fn unit_normal(ring: &[[f64; 3]]) -> Option<[f64; 3]> {
+    let n = newell_normal(ring);
+    let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if len == 0.0 || !len.is_finite() {
+        return None;
+    }
+    Some([n[0] / len, n[1] / len, n[2] / len])
+}
+
+/// Calculates the maximum distance of the vertices from the best-fit plane of the ring.
+///
+/// The plane passes through the centroid of the vertices and is oriented by the Newell normal.
+/// Returns `None` if the ring is degenerate (e.g. collinear points).
+pub fn max_distance_to_plane(ring: &[[f64; 3]]) -> Option<f64> {
+    let n = unit_normal(ring)?;
+    let inv = 1.0 / ring.len() as f64;
+    let c = ring.iter().fold([0.0; 3], |acc, v| {
+        [
+            acc[0] + v[0] * inv,
+            acc[1] + v[1] * inv,
+            acc[2] + v[2] * inv,
+        ]
+    });
+    let max_dist = ring
+        .iter()
+        .map(|v| ((v[0] - c[0]) * n[0] + (v[1] - c[1]) * n[1] + (v[2] - c[2]) * n[2]).abs())
+        .fold(0.0, f64::max);
+    Some(max_dist)
+}
+
+/// Projects 3D points onto the axis-aligned plane that is most parallel to the given normal.
+///
+/// The orientation of the rings is preserved, i.e. a counter-clockwise ring viewed
+/// from the direction of `normal` is counter-clockwise in the projected plane.
+pub fn project_to_2d(points: &[[f64; 3]], normal: [f64; 3]) -> Vec<[f64; 2]> {
+    let [ax, ay, az] = [normal[0].abs(), normal[1].abs(), normal[2].abs()];
+    if az >= ax && az >= ay {
+        // drop Z
+        let s = normal[2].signum();
+        points.iter().map(|v| [v[0] * s, v[1]]).collect()
+    } else if ay >= ax {
+        // drop Y
+        let s = normal[1].signum();
+        points.iter().map(|v| [v[2] * s, v[0]]).collect()
+    } else {
+        // drop X
+        let s = normal[0].signum();
+        points.iter().map(|v| [v[1] * s, v[2]]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LineString2;
+
+    #[test]
+    fn normal_of_square() {
+        let ring = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+        assert_eq!(newell_normal(&ring), [0., 0., 2.]);
+        assert_eq!(unit_normal(&ring), Some([0., 0., 1.]));
+        assert_eq!(max_distance_to_plane(&ring), Some(0.));
+    }
+
+    #[test]
+    fn degenerate_ring() {
+        let ring = [[0., 0., 0.], [1., 1., 1.], [2., 2., 2.]];
+        assert_eq!(unit_normal(&ring), None);
+        assert_eq!(max_distance_to_plane(&ring), None);
+    }
+
+    #[test]
+    fn non_planar_ring() {
+        let ring = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.2], [0., 1., 0.]];
+        let dist = max_distance_to_plane(&ring).unwrap();
+        assert!((dist - 0.05).abs() < 1e-2);
+    }
+
+    #[test]
+    fn projection_keeps_orientation() {
+        let rings = [
+            // facing +Z, -Z, +Y, -Y, +X, -X
+            vec![[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]],
+            vec![[0., 0., 0.], [0., 1., 0.], [1., 1., 0.], [1., 0., 0.]],
+            vec![[0., 0., 0.], [0., 0., 1.], [1., 0., 1.], [1., 0., 0.]],
+            vec![[0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.]],
+            vec![[0., 0., 0.], [0., 1., 0.], [0., 1., 1.], [0., 0., 1.]],
+            vec![[0., 0., 0.], [0., 0., 1.], [0., 1., 1.], [0., 1., 0.]],
+        ];
+        for ring in rings {
+            let normal = newell_normal(&ring);
+            let projected = LineString2::from_raw(project_to_2d(&ring, normal).into());
+            assert!(projected.is_ccw());
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Line and polygon simplification
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use super::{
+    intersection::{ring_self_intersects, segments_intersect},
+    plane::{project_to_2d, unit_normal},
+};
+
+/// Simplification algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SimplifyMethod {
+    /// Douglas–Peucker: removes points closer than the tolerance to the simplified line
+    #[default]
+    DouglasPeucker,
+    /// Visvalingam–Whyatt: removes points whose effective area is smaller than the square of the tolerance
+    Visvalingam,
+}
+
+/// Simplifies an open line and returns the indices of the retained points.
+///
+/// The first and last points are always retained.
+pub fn simplify_line(points: &[[f64; 3]], tolerance: f64, method: SimplifyMethod) -> Vec<usize> {
+    if points.len() <= 2 || tolerance <= 0.0 {
+        return (0..points.len()).collect();
+    }
+    match method {
+        SimplifyMethod::DouglasPeucker => {
+            let mut keep = vec![false; points.len()];
+            keep[0] = true;
+            keep[points.len() - 1] = true;
+            douglas_peucker(points, 0, points.len() - 1, tolerance, &mut keep);
+            collect_kept(&keep)
+        }
+        SimplifyMethod::Visvalingam => visvalingam(points, tolerance * tolerance, false, 2),
+    }
+}
+
+/// Simplifies an (implicitly closed) ring and returns the indices of the retained points.
+///
+/// At least three points are retained.
+pub fn simplify_ring(ring: &[[f64; 3]], tolerance: f64, method: SimplifyMethod) -> Vec<usize> {
+    if ring.len() <= 3 || tolerance <= 0.0 {
+        return (0..ring.len()).collect();
+    }
+    match method {
+        SimplifyMethod::DouglasPeucker => {
+            // Split the ring at the first point and the point farthest from it
+            let far = (1..ring.len())
+                .max_by(|&a, &b| {
+                    dist2(&ring[0], &ring[a])
+                        .partial_cmp(&dist2(&ring[0], &ring[b]))
+                        .unwrap_or(Ordering::Equal)
+                })
+                .unwrap();
+            let mut closed = ring.to_vec();
+            closed.push(ring[0]);
+            let mut keep = vec![false; closed.len()];
+            keep[0] = true;
+            keep[far] = true;
+            douglas_peucker(&closed, 0, far, tolerance, &mut keep);
+            douglas_peucker(&closed, far, closed.len() - 1, tolerance, &mut keep);
+            keep.pop();
+            if keep.iter().filter(|&&k| k).count() < 3 {
+                // Retain the point farthest from the chord to keep a triangle
+                let third = (1..ring.len())
+                    .filter(|&i| i != far)
+                    .max_by(|&a, &b| {
+                        segment_distance(&ring[a], &ring[0], &ring[far])
+                            .partial_cmp(&segment_distance(&ring[b], &ring[0], &ring[far]))
+                            .unwrap_or(Ordering::Equal)
+                    })
+                    .unwrap();
+                keep[third] = true;
+            }
+            collect_kept(&keep)
+        }
+        SimplifyMethod::Visvalingam => visvalingam(ring, tolerance * tolerance, true, 3),
+    }
+}
+
+/// Simplifies the rings of a polygon while preserving its topology.
+///
+/// Each ring falls back to its original points if the simplified ring collapses,
+/// self-intersects or intersects another ring of the polygon.
+pub fn simplify_polygon(
+    rings: &[Vec<[f64; 3]>],
+    tolerance: f64,
+    method: SimplifyMethod,
+) -> Vec<Vec<usize>> {
+    let mut indices: Vec<Vec<usize>> = rings
+        .iter()
+        .map(|ring| simplify_ring(ring, tolerance, method))
+        .collect();
+
+    let Some(normal) = rings.first().and_then(|ext| unit_normal(ext)) else {
+        // Degenerate polygon, keep as it is
+        return rings.iter().map(|ring| (0..ring.len()).collect()).collect();
+    };
+    let project = |ring: &[[f64; 3]], idx: &[usize]| {
+        let points: Vec<_> = idx.iter().map(|&i| ring[i]).collect();
+        project_to_2d(&points, normal)
+    };
+
+    let mut projected: Vec<Vec<[f64; 2]>> = Vec::with_capacity(rings.len());
+    for (ring, idx) in rings.iter().zip(indices.iter_mut()) {
+        let mut proj = project(ring, idx);
+        if idx.len() != ring.len() && ring_self_intersects(&proj) {
+            *idx = (0..ring.len()).collect();
+            proj = project(ring, idx);
+        }
+        projected.push(proj);
+    }
+
+    // Revert the pairs of rings that intersect each other
+    for i in 0..rings.len() {
+        for j in (i + 1)..rings.len() {
+            if rings_intersect(&projected[i], &projected[j]) {
+                for k in [i, j] {
+                    if indices[k].len() != rings[k].len() {
+                        indices[k] = (0..rings[k].len()).collect();
+                        projected[k] = project(&rings[k], &indices[k]);
+                    }
+                }
+            }
+        }
+    }
+
+    indices
+}
+
+fn rings_intersect(a: &[[f64; 2]], b: &[[f64; 2]]) -> bool {
+    edges(a).any(|(a1, a2)| edges(b).any(|(b1, b2)| segments_intersect(a1, a2, b1, b2)))
+}
+
+fn edges(ring: &[[f64; 2]]) -> impl Iterator<Item = ([f64; 2], [f64; 2])> + '_ {
+    let n = ring.len();
+    (0..n).map(move |i| (ring[i], ring[(i + 1) % n]))
+}
+
+fn collect_kept(keep: &[bool]) -> Vec<usize> {
+    keep.iter()
+        .enumerate()
+        .filter_map(|(i, &k)| k.then_some(i))
+        .collect()
+}
+
+fn douglas_peucker(
+    points: &[[f64; 3]],
+    first: usize,
+    last: usize,
+    tolerance: f64,
+    keep: &mut [bool],
+) {
+    if last <= first + 1 {
+        return;
+    }
+    let (mut max_dist, mut max_idx) = (0.0, first);
+    for i in (first + 1)..last {
+        let d = segment_distance(&points[i], &points[first], &points[last]);
+        if d > max_dist {
+            max_dist = d;
+            max_idx = i;
+        }
+    }
+    if max_dist > tolerance {
+        keep[max_idx] = true;
+        douglas_peucker(points, first, max_idx, tolerance, keep);
+        douglas_peucker(points, max_idx, last, tolerance, keep);
+    }
+}
+
+#[derive(PartialEq)]
+struct Candidate {
+    area: f64,
+    index: usize,
+    version: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Min-heap by area
+        other
+            .area
+            .partial_cmp(&self.area)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn visvalingam(points: &[[f64; 3]], min_area: f64, closed: bool, min_points: usize) -> Vec<usize> {
+    let n = points.len();
+    let mut prev: Vec<usize> = (0..n).map(|i| (i + n - 1) % n).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| (i + 1) % n).collect();
+    let mut removed = vec![false; n];
+    let mut versions = vec![0u32; n];
+    let removable = |i: usize| closed || (i != 0 && i != n - 1);
+
+    let mut heap = BinaryHeap::new();
+    for i in (0..n).filter(|&i| removable(i)) {
+        heap.push(Candidate {
+            area: triangle_area(&points[prev[i]], &points[i], &points[next[i]]),
+            index: i,
+            version: 0,
+        });
+    }
+
+    let mut remaining = n;
+    while let Some(Candidate {
+        area,
+        index,
+        version,
+    }) = heap.pop()
+    {
+        if removed[index] || version != versions[index] {
+            continue;
+        }
+        if area >= min_area || remaining <= min_points {
+            break;
+        }
+        removed[index] = true;
+        remaining -= 1;
+        let (p, q) = (prev[index], next[index]);
+        next[p] = q;
+        prev[q] = p;
+        for j in [p, q] {
+            if removable(j) {
+                versions[j] += 1;
+                heap.push(Candidate {
+                    area: triangle_area(&points[prev[j]], &points[j], &points[next[j]]),
+                    index: j,
+                    version: versions[j],
+                });
+            }
+        }
+    }
+
+    (0..n).filter(|&i| !removed[i]).collect()
+}
+
+fn sub(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn dist2(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let d = sub(a, b);
+    dot(&d, &d)
+}
+
+/// Distance from the point `p` to the segment (a, b)
+fn segment_distance(p: &[f64; 3], a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let ab = sub(b, a);
+    let len2 = dot(&ab, &ab);
+    if len2 == 0.0 {
+        return dist2(p, a).sqrt();
+    }
+    let t = (dot(&sub(p, a), &ab) / len2).clamp(0.0, 1.0);
+    let proj = [a[0] + ab[0] * t, a[1] + ab[1] * t, a[2] + ab[2] * t];
+    dist2(p, &proj).sqrt()
+}
+
+fn triangle_area(a: &[f64; 3], b: &[f64; 3], c: &[f64; 3]) -> f64 {
+    let (u, v) = (sub(b, a), sub(c, a));
+    let cross = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    dot(&cross, &cross).sqrt() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simplify_line() {
+        let line = [
+            [0., 0., 0.],
+            [1., 0.05, 0.],
+            [2., -0.05, 0.],
+            [3., 2., 0.],
+            [4., 4., 0.],
+        ];
+        let dp = SimplifyMethod::DouglasPeucker;
+        assert_eq!(simplify_line(&line, 0.1, dp), [0, 2, 4]);
+        assert_eq!(simplify_line(&line, 0.0, dp), [0, 1, 2, 3, 4]);
+        let vw = SimplifyMethod::Visvalingam;
+        assert_eq!(simplify_line(&line, 0.5, vw), [0, 2, 4]);
+        assert_eq!(simplify_line(&line, 0.1, vw), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_simplify_ring() {
+        // Square with nearly collinear points on its edges
+        let ring = [
+            [0., 0., 0.],
+            [5., 0.01, 0.],
+            [10., 0., 0.],
+            [10., 10., 0.],
+            [5., 10.02, 0.],
+            [0., 10., 0.],
+        ];
+        for method in [SimplifyMethod::DouglasPeucker, SimplifyMethod::Visvalingam] {
+            assert_eq!(simplify_ring(&ring, 0.5, method), [0, 2, 3, 5]);
+            // Never collapses below a triangle
+            assert_eq!(simplify_ring(&ring, 100.0, method).len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_simplify_polygon_topology() {
+        // Removing the peak of the exterior would cut through the hole
+        let exterior = vec![
+            [0., 0., 0.],
+            [10., 0., 0.],
+            [10., 10., 0.],
+            [5., 11., 0.],
+            [0., 10., 0.],
+        ];
+        let hole = vec![
+            [4.5, 9.5, 0.],
+            [4.5, 10.5, 0.],
+            [5.5, 10.5, 0.],
+            [5.5, 9.5, 0.],
+        ];
+
+        let indices = simplify_polygon(
+            std::slice::from_ref(&exterior),
+            2.0,
+            SimplifyMethod::DouglasPeucker,
+        );
+        assert_eq!(indices[0], [0, 1, 2, 4]);
+
+        let indices = simplify_polygon(
+            &[exterior.clone(), hole.clone()],
+            2.0,
+            SimplifyMethod::DouglasPeucker,
+        );
+        assert_eq!(indices[0].len(), exterior.len());
+        assert_eq!(indices[1].len(), hole.len());
+    }
+}
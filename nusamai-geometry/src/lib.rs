@@ -1,3 +1,4 @@
+pub mod algorithm;
 mod compact;
 
 pub use compact::*;
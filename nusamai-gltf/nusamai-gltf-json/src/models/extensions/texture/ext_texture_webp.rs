@@ -0,0 +1,14 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// EXT_texture_webp: allows a texture to use an image in the WebP format.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct ExtTextureWebp {
+    /// The index of the image which points to a WebP image.
+    pub source: u32,
+
+    #[serde(flatten)]
+    pub others: HashMap<String, Value>,
+}
@@ -0,0 +1,16 @@
+pub mod ext_texture_webp;
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Texture {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "EXT_texture_webp")]
+    pub ext_texture_webp: Option<ext_texture_webp::ExtTextureWebp>,
+
+    #[serde(flatten)]
+    pub others: HashMap<String, Value>,
+}
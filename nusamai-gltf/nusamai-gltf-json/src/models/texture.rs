@@ -1,8 +1,8 @@
-use std::collections::HashMap;
-
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::extensions;
+
 /// A texture and its sampler.
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
 #[serde[rename_all = "camelCase"]]
@@ -22,16 +22,9 @@ pub struct Texture {
 
     /// JSON object with extension-specific objects.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub extensions: Option<TextureExtensions>,
+    pub extensions: Option<extensions::texture::Texture>,
 
     /// Application-specific data.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extras: Option<Value>,
 }
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-#[serde(rename_all = "camelCase")]
-pub struct TextureExtensions {
-    #[serde(flatten)]
-    others: HashMap<String, Value>,
-}
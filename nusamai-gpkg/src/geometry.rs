@@ -4,7 +4,7 @@
 
 use std::io::Write;
 
-use nusamai_geometry::{Coord, MultiPolygon, Polygon};
+use nusamai_geometry::{Coord, MultiLineString, MultiPoint, MultiPolygon, Polygon};
 
 #[repr(u8)]
 pub enum WkbByteOrder {
@@ -56,16 +56,36 @@ fn write_geometry_header<W: Write>(writer: &mut W, srs_id: i32) -> std::io::Resu
     Ok(())
 }
 
-fn write_polygon_body<W: Write, T: Coord>(
+fn write_geometry_type<W: Write>(
     writer: &mut W,
-    poly: &Polygon<T>,
-    mapping: impl Fn(T) -> [f64; 3],
+    geometry_type: WkbGeometryType,
+    has_z: bool,
 ) -> std::io::Result<()> {
     // Byte order: Little endian (1)
     writer.write_all(&[WkbByteOrder::LittleEndian as u8])?;
 
-    // Geometry type: wkbPolygonZ (1003)
-    writer.write_all(&(WkbGeometryType::PolygonZ as u32).to_le_bytes())?;
+    // Geometry type: the Z variants are offset by 1000 (e.g. wkbPolygonZ = 1003)
+    let geometry_type = geometry_type as u32 + if has_z { 1000 } else { 0 };
+    writer.write_all(&geometry_type.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_coord<W: Write>(writer: &mut W, [x, y, z]: [f64; 3], has_z: bool) -> std::io::Result<()> {
+    writer.write_all(&f64::to_le_bytes(x))?;
+    writer.write_all(&f64::to_le_bytes(y))?;
+    if has_z {
+        writer.write_all(&f64::to_le_bytes(z))?;
+    }
+    Ok(())
+}
+
+fn write_polygon_body<W: Write, T: Coord>(
+    writer: &mut W,
+    poly: &Polygon<T>,
+    mapping: impl Fn(T) -> [f64; 3],
+    has_z: bool,
+) -> std::io::Result<()> {
+    write_geometry_type(writer, WkbGeometryType::Polygon, has_z)?;
 
     // numRings
     writer.write_all(&(poly.rings().count() as u32).to_le_bytes())?;
@@ -75,23 +95,22 @@ fn write_polygon_body<W: Write, T: Coord>(
         writer.write_all(&(ring.iter_closed().count() as u32).to_le_bytes())?;
 
         for idx in ring.iter_closed() {
-            let [x, y, z] = mapping(idx);
-            writer.write_all(&f64::to_le_bytes(x))?;
-            writer.write_all(&f64::to_le_bytes(y))?;
-            writer.write_all(&f64::to_le_bytes(z))?;
+            write_coord(writer, mapping(idx), has_z)?;
         }
     }
     Ok(())
 }
 
+/// Write a MultiPolygon (or MultiPolygonZ if `has_z` is true)
 pub fn write_indexed_multipolygon<W: Write>(
     writer: &mut W,
     vertices: &[[f64; 3]],
     mpoly: &MultiPolygon<u32>,
     srs_id: i32,
+    has_z: bool,
 ) -> std::io::Result<()> {
     write_geometry_header(writer, srs_id)?;
-    write_multipolygon_body(writer, mpoly, |idx| vertices[idx as usize])?;
+    write_multipolygon_body(writer, mpoly, |idx| vertices[idx as usize], has_z)?;
     Ok(())
 }
 
@@ -99,23 +118,68 @@ fn write_multipolygon_body<W: Write, T: Coord>(
     writer: &mut W,
     mpoly: &MultiPolygon<T>,
     mapping: impl Fn(T) -> [f64; 3],
+    has_z: bool,
 ) -> std::io::Result<()> {
-    // Byte order: Little endian (1)
-    writer.write_all(&[WkbByteOrder::LittleEndian as u8])?;
-
-    // Geometry type: wkbMultiPolygonZ (1006)
-    writer.write_all(&(WkbGeometryType::MultiPolygonZ as u32).to_le_bytes())?;
+    write_geometry_type(writer, WkbGeometryType::MultiPolygon, has_z)?;
 
     // numPolygons
     writer.write_all(&(mpoly.len() as u32).to_le_bytes())?;
 
     for poly in mpoly {
-        write_polygon_body(writer, &poly, &mapping)?;
+        write_polygon_body(writer, &poly, &mapping, has_z)?;
     }
 
     Ok(())
 }
 
+/// Write a MultiLineString (or MultiLineStringZ if `has_z` is true)
+pub fn write_indexed_multilinestring<W: Write>(
+    writer: &mut W,
+    vertices: &[[f64; 3]],
+    mls: &MultiLineString<u32>,
+    srs_id: i32,
+    has_z: bool,
+) -> std::io::Result<()> {
+    write_geometry_header(writer, srs_id)?;
+    write_geometry_type(writer, WkbGeometryType::MultiLineString, has_z)?;
+
+    // numLineStrings
+    writer.write_all(&(mls.len() as u32).to_le_bytes())?;
+
+    for ls in mls {
+        write_geometry_type(writer, WkbGeometryType::LineString, has_z)?;
+
+        // numPoints
+        writer.write_all(&(ls.len() as u32).to_le_bytes())?;
+
+        for idx in &ls {
+            write_coord(writer, vertices[idx as usize], has_z)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a MultiPoint (or MultiPointZ if `has_z` is true)
+pub fn write_indexed_multipoint<W: Write>(
+    writer: &mut W,
+    vertices: &[[f64; 3]],
+    mpoint: &MultiPoint<u32>,
+    srs_id: i32,
+    has_z: bool,
+) -> std::io::Result<()> {
+    write_geometry_header(writer, srs_id)?;
+    write_geometry_type(writer, WkbGeometryType::MultiPoint, has_z)?;
+
+    // numPoints
+    writer.write_all(&(mpoint.len() as u32).to_le_bytes())?;
+
+    for idx in mpoint {
+        write_geometry_type(writer, WkbGeometryType::Point, has_z)?;
+        write_coord(writer, vertices[idx as usize], has_z)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,7 +205,7 @@ mod tests {
         mpoly.add_interior([4, 5, 6, 7, 4]);
 
         let mut bytes = Vec::new();
-        write_indexed_multipolygon(&mut bytes, &vertices, &mpoly, 1234).unwrap();
+        write_indexed_multipolygon(&mut bytes, &vertices, &mpoly, 1234, true).unwrap();
 
         assert_eq!(bytes.len(), 274);
 
@@ -226,4 +290,79 @@ mod tests {
         assert_eq!(bytes[258..=265].to_vec(), &1_f64.to_le_bytes());
         assert_eq!(bytes[266..=273].to_vec(), &111_f64.to_le_bytes());
     }
+
+    #[test]
+    fn test_multipolygon_to_bytes_2d() {
+        let vertices: Vec<[f64; 3]> = vec![[0., 0., 111.], [5., 0., 111.], [5., 5., 111.]];
+
+        let mut mpoly = MultiPolygon::<u32>::new();
+        mpoly.add_exterior([0, 1, 2, 0]);
+
+        let mut bytes = Vec::new();
+        write_indexed_multipolygon(&mut bytes, &vertices, &mpoly, 1234, false).unwrap();
+
+        // header (8) + multipolygon (9) + polygon (9) + ring (4 + 4 points * 16)
+        assert_eq!(bytes.len(), 8 + 9 + 9 + 4 + 4 * 16);
+
+        // Geometry type: wkbMultiPolygon (6)
+        assert_eq!(bytes[9..=12].to_vec(), &6_u32.to_le_bytes());
+        // Geometry type: wkbPolygon (3)
+        assert_eq!(bytes[18..=21].to_vec(), &3_u32.to_le_bytes());
+
+        // 2nd point (no Z)
+        assert_eq!(bytes[46..=53].to_vec(), &5_f64.to_le_bytes());
+        assert_eq!(bytes[54..=61].to_vec(), &0_f64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_multilinestring_to_bytes() {
+        let vertices: Vec<[f64; 3]> = vec![[0., 0., 111.], [5., 0., 222.], [5., 5., 333.]];
+
+        let mut mls = MultiLineString::<u32>::new();
+        mls.add_linestring([0, 1, 2]);
+        mls.add_linestring([2, 0]);
+
+        let mut bytes = Vec::new();
+        write_indexed_multilinestring(&mut bytes, &vertices, &mls, 1234, true).unwrap();
+
+        // header (8) + multilinestring (9) + linestrings (9 + 3 points * 24, 9 + 2 points * 24)
+        assert_eq!(bytes.len(), 8 + 9 + (9 + 3 * 24) + (9 + 2 * 24));
+
+        // Geometry type: wkbMultiLineStringZ (1005)
+        assert_eq!(bytes[9..=12].to_vec(), &1005_u32.to_le_bytes());
+        // numLineStrings
+        assert_eq!(bytes[13..=16].to_vec(), &2_u32.to_le_bytes());
+
+        // 1st linestring
+        // Geometry type: wkbLineStringZ (1002)
+        assert_eq!(bytes[18..=21].to_vec(), &1002_u32.to_le_bytes());
+        // numPoints
+        assert_eq!(bytes[22..=25].to_vec(), &3_u32.to_le_bytes());
+        // 2nd point
+        assert_eq!(bytes[50..=57].to_vec(), &5_f64.to_le_bytes());
+        assert_eq!(bytes[58..=65].to_vec(), &0_f64.to_le_bytes());
+        assert_eq!(bytes[66..=73].to_vec(), &222_f64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_multipoint_to_bytes() {
+        let vertices: Vec<[f64; 3]> = vec![[1., 2., 3.], [4., 5., 6.]];
+
+        let mut mpoint = MultiPoint::<u32>::new();
+        mpoint.push(1);
+
+        for (has_z, geometry_type, point_type, len) in
+            [(true, 1004_u32, 1001_u32, 24), (false, 4, 1, 16)]
+        {
+            let mut bytes = Vec::new();
+            write_indexed_multipoint(&mut bytes, &vertices, &mpoint, 1234, has_z).unwrap();
+
+            assert_eq!(bytes.len(), 8 + 9 + 5 + len);
+            assert_eq!(bytes[9..=12].to_vec(), &geometry_type.to_le_bytes());
+            assert_eq!(bytes[13..=16].to_vec(), &1_u32.to_le_bytes());
+            assert_eq!(bytes[18..=21].to_vec(), &point_type.to_le_bytes());
+            assert_eq!(bytes[22..=29].to_vec(), &4_f64.to_le_bytes());
+            assert_eq!(bytes[30..=37].to_vec(), &5_f64.to_le_bytes());
+        }
+    }
 }
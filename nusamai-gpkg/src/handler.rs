@@ -198,7 +198,7 @@ impl<'c> GpkgTransaction<'c> {
 
         // Create the table
         let mut query_string = format!("CREATE TABLE \"{}\" (", table_info.name);
-        if table_info.has_geometry {
+        if table_info.has_geometry() {
             query_string.push_str("fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL");
             query_string.push_str(", id TEXT NOT NULL");
             query_string.push_str(", geometry BLOB NOT NULL");
@@ -216,7 +216,7 @@ impl<'c> GpkgTransaction<'c> {
             "INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES (?, ?, ?, ?);",
         )
         .bind(table_info.name.as_str())
-        .bind(if table_info.has_geometry {
+        .bind(if table_info.has_geometry() {
             "features"
         } else {
             "attributes"
@@ -227,34 +227,112 @@ impl<'c> GpkgTransaction<'c> {
         .await?;
 
         // Add the table to `gpkg_geometry_columns`
-        if table_info.has_geometry {
+        if let Some(geometry) = &table_info.geometry {
             sqlx::query(
                 "INSERT INTO gpkg_geometry_columns (table_name, column_name, geometry_type_name, srs_id, z, m) VALUES (?, ?, ?, ?, ?, ?);"
             ).bind(table_info.name.as_str())
             .bind("geometry")
-            .bind("MULTIPOLYGON") // Fixed for now - TODO: Change according to the data
+            .bind(geometry.geometry_type.as_str())
             .bind(srs_id)
-            .bind(1)
+            .bind(geometry.has_z as i8) // 0: prohibited, 1: mandatory
             .bind(0).execute(&mut *executor).await?;
         }
 
-        // TODO: add MIME type to `gpkg_data_columns`
+        // Describe the columns in `gpkg_data_columns`
+        if table_info
+            .columns
+            .iter()
+            .any(|column| column.is_documented())
+        {
+            self.add_data_columns(table_info).await?;
+        }
 
         Ok(())
     }
 
-    /// Add a record to the feature table
-    // TODO: handle MultiLineString, MultiPoint (currently only MultiPolygonZ is supported)
+    /// Add the titles, descriptions and MIME types of the columns to `gpkg_data_columns` (`gpkg_schema` extension)
+    async fn add_data_columns(&mut self, table_info: &TableInfo) -> Result<(), GpkgError> {
+        self.add_extension(
+            Some("gpkg_data_columns"),
+            None,
+            "gpkg_schema",
+            "http://www.geopackage.org/spec120/#extension_schema",
+            "read-write",
+        )
+        .await?;
+
+        let executor = self.tx.acquire().await.unwrap();
+        sqlx::query(include_str!("sql/data_columns.sql"))
+            .execute(&mut *executor)
+            .await?;
+        for column in table_info.columns.iter().filter(|c| c.is_documented()) {
+            sqlx::query(
+                "INSERT INTO gpkg_data_columns (table_name, column_name, name, title, description, mime_type) VALUES (?, ?, ?, ?, ?, ?);",
+            )
+            .bind(table_info.name.as_str())
+            .bind(column.name.as_str())
+            .bind(column.name.as_str())
+            .bind(column.title.as_deref())
+            .bind(column.description.as_deref())
+            .bind(column.mime_type.as_deref())
+            .execute(&mut *executor)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Add a metadata document describing the whole GeoPackage (`gpkg_metadata` extension), and return its id
+    pub async fn add_metadata(
+        &mut self,
+        md_standard_uri: &str,
+        mime_type: &str,
+        metadata: &str,
+    ) -> Result<i64, GpkgError> {
+        for table_name in ["gpkg_metadata", "gpkg_metadata_reference"] {
+            self.add_extension(
+                Some(table_name),
+                None,
+                "gpkg_metadata",
+                "http://www.geopackage.org/spec120/#extension_metadata",
+                "read-write",
+            )
+            .await?;
+        }
+
+        let executor = self.tx.acquire().await.unwrap();
+        sqlx::query(include_str!("sql/metadata.sql"))
+            .execute(&mut *executor)
+            .await?;
+        let md_file_id = sqlx::query(
+            "INSERT INTO gpkg_metadata (md_scope, md_standard_uri, mime_type, metadata) VALUES ('dataset', ?, ?, ?);",
+        )
+        .bind(md_standard_uri)
+        .bind(mime_type)
+        .bind(metadata)
+        .execute(&mut *executor)
+        .await?
+        .last_insert_rowid();
+        sqlx::query(
+            "INSERT INTO gpkg_metadata_reference (reference_scope, md_file_id) VALUES ('geopackage', ?);",
+        )
+        .bind(md_file_id)
+        .execute(&mut *executor)
+        .await?;
+
+        Ok(md_file_id)
+    }
+
+    /// Add a record to the feature table, and return its `fid`
     pub async fn insert_feature(
         &mut self,
         table_name: &str,
         id: &str,
         bytes: &[u8],
         attributes: &IndexMap<String, String>,
-    ) -> Result<(), GpkgError> {
+    ) -> Result<i64, GpkgError> {
         let executor = self.tx.acquire().await.unwrap();
 
-        if attributes.is_empty() {
+        let result = if attributes.is_empty() {
             let query_string = format!(
                 "INSERT INTO \"{}\" (id, geometry) VALUES (?, ?)",
                 table_name
@@ -263,7 +341,7 @@ impl<'c> GpkgTransaction<'c> {
                 .bind(id)
                 .bind(bytes)
                 .execute(&mut *executor)
-                .await?;
+                .await?
         } else {
             let query_string = format!(
                 "INSERT INTO \"{}\" (id, geometry, {}) VALUES (?, ?, {})",
@@ -279,36 +357,211 @@ impl<'c> GpkgTransaction<'c> {
             for value in attributes.values() {
                 query = query.bind(value);
             }
-            query.execute(&mut *executor).await?;
-        }
+            query.execute(&mut *executor).await?
+        };
 
-        Ok(())
+        Ok(result.last_insert_rowid())
     }
 
-    /// Add a record to the attribute table
+    /// Add a record to the attribute table, and return its `id`
     pub async fn insert_attribute(
         &mut self,
         table_name: &str,
         attributes: &IndexMap<String, String>,
-    ) -> Result<(), GpkgError> {
-        let query_string = format!(
-            "INSERT INTO \"{}\" ({}) VALUES ({})",
-            table_name,
-            attributes
-                .keys()
-                .map(|key| key.to_string())
-                .collect::<Vec<_>>()
-                .join(", "),
-            vec!["?"; attributes.len()].join(", ")
-        );
+    ) -> Result<i64, GpkgError> {
+        let query_string = if attributes.is_empty() {
+            format!("INSERT INTO \"{}\" DEFAULT VALUES", table_name)
+        } else {
+            format!(
+                "INSERT INTO \"{}\" ({}) VALUES ({})",
+                table_name,
+                attributes
+                    .keys()
+                    .map(|key| format!("\"{}\"", key))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                vec!["?"; attributes.len()].join(", ")
+            )
+        };
         let mut query = sqlx::query(&query_string);
         for value in attributes.values() {
             query = query.bind(value);
         }
 
         let executor: &mut SqliteConnection = self.tx.acquire().await.unwrap();
-        query.execute(&mut *executor).await?;
+        let result = query.execute(&mut *executor).await?;
+
+        Ok(result.last_insert_rowid())
+    }
+
+    /// Register an extension in `gpkg_extensions` (the table is created if it does not exist)
+    pub async fn add_extension(
+        &mut self,
+        table_name: Option<&str>,
+        column_name: Option<&str>,
+        extension_name: &str,
+        definition: &str,
+        scope: &str,
+    ) -> Result<(), GpkgError> {
+        let executor = self.tx.acquire().await.unwrap();
+
+        sqlx::query(include_str!("sql/extensions.sql"))
+            .execute(&mut *executor)
+            .await?;
+        // Note: the UNIQUE constraint does not prevent the duplicates with NULL columns
+        sqlx::query(
+            "INSERT INTO gpkg_extensions (table_name, column_name, extension_name, definition, scope) SELECT ?1, ?2, ?3, ?4, ?5 WHERE NOT EXISTS (SELECT 1 FROM gpkg_extensions WHERE table_name IS ?1 AND column_name IS ?2 AND extension_name = ?3);",
+        )
+        .bind(table_name)
+        .bind(column_name)
+        .bind(extension_name)
+        .bind(definition)
+        .bind(scope)
+        .execute(&mut *executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Relate the records of an attribute table to the records of the base table, with the Related Tables Extension
+    ///
+    /// The mapping table holds the pairs of the primary keys (`base_id`, `related_id`).
+    pub async fn add_relation(
+        &mut self,
+        base_table: &TableInfo,
+        related_table: &TableInfo,
+        mapping_table_name: &str,
+    ) -> Result<(), GpkgError> {
+        const EXTENSION_NAME: &str = "gpkg_related_tables";
+        const DEFINITION: &str = "http://docs.opengeospatial.org/is/18-000/18-000.html";
+
+        self.add_extension(
+            Some("gpkgext_relations"),
+            None,
+            EXTENSION_NAME,
+            DEFINITION,
+            "read-write",
+        )
+        .await?;
+        self.add_extension(
+            Some(mapping_table_name),
+            None,
+            EXTENSION_NAME,
+            DEFINITION,
+            "read-write",
+        )
+        .await?;
+
+        let executor = self.tx.acquire().await.unwrap();
+
+        sqlx::query(include_str!("sql/relations.sql"))
+            .execute(&mut *executor)
+            .await?;
+
+        // Create the mapping table
+        let query_string = format!(
+            "CREATE TABLE \"{}\" (base_id INTEGER NOT NULL REFERENCES \"{}\"({}), related_id INTEGER NOT NULL REFERENCES \"{}\"({}));",
+            mapping_table_name,
+            base_table.name,
+            base_table.primary_column(),
+            related_table.name,
+            related_table.primary_column(),
+        );
+        sqlx::query(&query_string).execute(&mut *executor).await?;
+
+        sqlx::query(
+            "INSERT INTO gpkgext_relations (base_table_name, base_primary_column, related_table_name, related_primary_column, relation_name, mapping_table_name) VALUES (?, ?, ?, ?, ?, ?);",
+        )
+        .bind(base_table.name.as_str())
+        .bind(base_table.primary_column())
+        .bind(related_table.name.as_str())
+        .bind(related_table.primary_column())
+        .bind(if related_table.has_geometry() {
+            "features"
+        } else {
+            "attributes"
+        })
+        .bind(mapping_table_name)
+        .execute(&mut *executor)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Set up the R-tree spatial index of a features table (`gpkg_rtree_index` extension)
+    ///
+    /// The entries are to be added with `insert_rtree_entry` and the triggers with `add_rtree_triggers`.
+    pub async fn add_rtree_index(&mut self, table_info: &TableInfo) -> Result<(), GpkgError> {
+        self.add_extension(
+            Some(&table_info.name),
+            Some("geometry"),
+            "gpkg_rtree_index",
+            "http://www.geopackage.org/spec120/#extension_rtree",
+            "write-only",
+        )
+        .await?;
 
+        let executor = self.tx.acquire().await.unwrap();
+        sqlx::query(&format!(
+            "CREATE VIRTUAL TABLE \"rtree_{}_geometry\" USING rtree(id, minx, maxx, miny, maxy);",
+            table_info.name
+        ))
+        .execute(&mut *executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Add the bounding box of a feature to the R-tree spatial index (min_x, min_y, max_x, max_y)
+    pub async fn insert_rtree_entry(
+        &mut self,
+        table_name: &str,
+        fid: i64,
+        (min_x, min_y, max_x, max_y): (f64, f64, f64, f64),
+    ) -> Result<(), GpkgError> {
+        let executor = self.tx.acquire().await.unwrap();
+        sqlx::query(&format!(
+            "INSERT INTO \"rtree_{}_geometry\" (id, minx, maxx, miny, maxy) VALUES (?, ?, ?, ?, ?);",
+            table_name
+        ))
+        .bind(fid)
+        .bind(min_x)
+        .bind(max_x)
+        .bind(min_y)
+        .bind(max_y)
+        .execute(&mut *executor)
+        .await?;
+        Ok(())
+    }
+
+    /// Create the triggers that keep the R-tree spatial index up to date
+    ///
+    /// This must be done after all the features are inserted, because the triggers use the SQL functions
+    /// (`ST_MinX` etc.) that are provided by the GeoPackage readers, not by SQLite itself.
+    pub async fn add_rtree_triggers(&mut self, table_name: &str) -> Result<(), GpkgError> {
+        let executor = self.tx.acquire().await.unwrap();
+        let query_string = include_str!("sql/rtree_triggers.sql")
+            .replace("<t>", table_name)
+            .replace("<c>", "geometry")
+            .replace("<i>", "fid");
+        sqlx::query(&query_string).execute(&mut *executor).await?;
+        Ok(())
+    }
+
+    /// Add a pair of related records to the mapping table
+    pub async fn insert_relation(
+        &mut self,
+        mapping_table_name: &str,
+        base_id: i64,
+        related_id: i64,
+    ) -> Result<(), GpkgError> {
+        let executor = self.tx.acquire().await.unwrap();
+        sqlx::query(&format!(
+            "INSERT INTO \"{}\" (base_id, related_id) VALUES (?, ?);",
+            mapping_table_name
+        ))
+        .bind(base_id)
+        .bind(related_id)
+        .execute(&mut *executor)
+        .await?;
         Ok(())
     }
 
@@ -334,7 +587,7 @@ impl<'c> GpkgTransaction<'c> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::table::ColumnInfo;
+    use crate::table::{ColumnInfo, GeometryColumnInfo, GeometryTypeName};
 
     #[tokio::test]
     async fn test_init_connect() {
@@ -371,26 +624,37 @@ mod tests {
                 name: "attr1".into(),
                 data_type: "TEXT".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr2".into(),
                 data_type: "INTEGER".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr3".into(),
                 data_type: "REAL".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr4".into(),
                 data_type: "BOOLEAN".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
         ];
         let table_info = TableInfo {
             name: table_name.into(),
-            has_geometry: true,
+            geometry: Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiPolygon,
+                has_z: true,
+            }),
             columns,
         };
 
@@ -460,10 +724,12 @@ mod tests {
             name: "attr1".into(),
             data_type: "TEXT".into(),
             mime_type: None,
+            title: None,
+            description: None,
         }];
         let table_info = TableInfo {
             name: table_name.into(),
-            has_geometry: false, // No geometry
+            geometry: None, // No geometry
             columns,
         };
 
@@ -522,26 +788,37 @@ mod tests {
                 name: "attr1".into(),
                 data_type: "TEXT".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr2".into(),
                 data_type: "INTEGER".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr3".into(),
                 data_type: "REAL".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr4".into(),
                 data_type: "BOOLEAN".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
         ];
         let table_info = TableInfo {
             name: table_name.into(),
-            has_geometry: true,
+            geometry: Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiPolygon,
+                has_z: true,
+            }),
             columns,
         };
         tx.add_table(&table_info, srs_id).await.unwrap();
@@ -584,26 +861,34 @@ mod tests {
                 name: "attr1".into(),
                 data_type: "TEXT".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr2".into(),
                 data_type: "INTEGER".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr3".into(),
                 data_type: "REAL".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
             ColumnInfo {
                 name: "attr4".into(),
                 data_type: "BOOLEAN".into(),
                 mime_type: None,
+                title: None,
+                description: None,
             },
         ];
         let table_info = TableInfo {
             name: table_name.into(),
-            has_geometry: false, // No geometry
+            geometry: None, // No geometry
             columns,
         };
         tx.add_table(&table_info, srs_id).await.unwrap();
@@ -629,6 +914,267 @@ mod tests {
         assert!(row.get::<bool, &str>("attr4"));
     }
 
+    #[tokio::test]
+    async fn test_add_relation() {
+        let mut handler = GpkgHandler::from_url(&Url::parse("sqlite::memory:").unwrap())
+            .await
+            .unwrap();
+        let mut tx: GpkgTransaction<'_> = handler.begin().await.unwrap();
+
+        let srs_id = 4326;
+        let base_table = TableInfo {
+            name: "base".into(),
+            geometry: Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiPolygon,
+                has_z: true,
+            }),
+            columns: vec![],
+        };
+        let related_table = TableInfo {
+            name: "related".into(),
+            geometry: None,
+            columns: vec![],
+        };
+        tx.add_table(&base_table, srs_id).await.unwrap();
+        tx.add_table(&related_table, srs_id).await.unwrap();
+        tx.add_relation(&base_table, &related_table, "base_related")
+            .await
+            .unwrap();
+
+        let base_id = tx
+            .insert_feature("base", "id_1", &[0, 1, 2, 3], &IndexMap::new())
+            .await
+            .unwrap();
+        for _ in 0..2 {
+            let related_id = tx
+                .insert_attribute("related", &IndexMap::new())
+                .await
+                .unwrap();
+            tx.insert_relation("base_related", base_id, related_id)
+                .await
+                .unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        let table_names = handler.table_names().await;
+        assert_eq!(
+            table_names,
+            vec![
+                "base",
+                "base_related",
+                "gpkg_contents",
+                "gpkg_extensions",
+                "gpkg_geometry_columns",
+                "gpkg_spatial_ref_sys",
+                "gpkgext_relations",
+                "related",
+            ]
+        );
+
+        let relations = handler.fetch_rows("gpkgext_relations").await.unwrap();
+        assert_eq!(relations.len(), 1);
+        let relation = relations.first().unwrap();
+        assert_eq!(relation.get::<String, &str>("base_table_name"), "base");
+        assert_eq!(relation.get::<String, &str>("base_primary_column"), "fid");
+        assert_eq!(
+            relation.get::<String, &str>("related_table_name"),
+            "related"
+        );
+        assert_eq!(relation.get::<String, &str>("related_primary_column"), "id");
+        assert_eq!(relation.get::<String, &str>("relation_name"), "attributes");
+        assert_eq!(
+            relation.get::<String, &str>("mapping_table_name"),
+            "base_related"
+        );
+
+        let extensions = handler.fetch_rows("gpkg_extensions").await.unwrap();
+        assert_eq!(
+            extensions
+                .iter()
+                .map(|row| row.get::<String, &str>("table_name"))
+                .collect::<Vec<_>>(),
+            vec!["gpkgext_relations", "base_related"]
+        );
+
+        let mappings = handler.fetch_rows("base_related").await.unwrap();
+        assert_eq!(
+            mappings
+                .iter()
+                .map(|row| (
+                    row.get::<i64, &str>("base_id"),
+                    row.get::<i64, &str>("related_id")
+                ))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (1, 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rtree_index() {
+        let mut handler = GpkgHandler::from_url(&Url::parse("sqlite::memory:").unwrap())
+            .await
+            .unwrap();
+
+        let srs_id = 4326;
+        let table_name = "lines";
+        let table_info = TableInfo {
+            name: table_name.into(),
+            geometry: Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiLineString,
+                has_z: false,
+            }),
+            columns: vec![],
+        };
+
+        let mut tx = handler.begin().await.unwrap();
+        tx.add_table(&table_info, srs_id).await.unwrap();
+        tx.add_rtree_index(&table_info).await.unwrap();
+        let fid = tx
+            .insert_feature(table_name, "id_1", &[0, 1, 2, 3], &IndexMap::new())
+            .await
+            .unwrap();
+        tx.insert_rtree_entry(table_name, fid, (1.0, 2.0, 3.0, 4.0))
+            .await
+            .unwrap();
+        tx.add_rtree_triggers(table_name).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let gpkg_geometry_columns = handler.gpkg_geometry_columns().await.unwrap();
+        assert_eq!(
+            gpkg_geometry_columns,
+            vec![(
+                table_name.into(),
+                "geometry".into(),
+                "MULTILINESTRING".into(),
+                srs_id as i32,
+                0,
+                0
+            )]
+        );
+
+        let extensions = handler.fetch_rows("gpkg_extensions").await.unwrap();
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(
+            extensions[0].get::<String, &str>("extension_name"),
+            "gpkg_rtree_index"
+        );
+        assert_eq!(extensions[0].get::<String, &str>("column_name"), "geometry");
+
+        let entries = handler.fetch_rows("rtree_lines_geometry").await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].get::<i64, &str>("id"), fid);
+        assert_eq!(entries[0].get::<f64, &str>("minx"), 1.0);
+        assert_eq!(entries[0].get::<f64, &str>("maxy"), 4.0);
+
+        let triggers: Vec<String> =
+            sqlx::query("SELECT name FROM sqlite_schema WHERE type = 'trigger' ORDER BY name;")
+                .fetch_all(&handler.pool)
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+        assert_eq!(
+            triggers,
+            vec![
+                "rtree_lines_geometry_delete",
+                "rtree_lines_geometry_insert",
+                "rtree_lines_geometry_update1",
+                "rtree_lines_geometry_update2",
+                "rtree_lines_geometry_update3",
+                "rtree_lines_geometry_update4",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_schema_documentation() {
+        let mut handler = GpkgHandler::from_url(&Url::parse("sqlite::memory:").unwrap())
+            .await
+            .unwrap();
+
+        let table_info = TableInfo {
+            name: "documented".into(),
+            geometry: None,
+            columns: vec![
+                ColumnInfo {
+                    name: "attr1".into(),
+                    data_type: "REAL".into(),
+                    mime_type: None,
+                    title: Some("uro:attr1".into()),
+                    description: Some("gml:MeasureType (uom: m)".into()),
+                },
+                ColumnInfo {
+                    name: "attr2".into(),
+                    data_type: "TEXT".into(),
+                    mime_type: None,
+                    title: None,
+                    description: None,
+                },
+            ],
+        };
+
+        let mut tx = handler.begin().await.unwrap();
+        tx.add_table(&table_info, 4326).await.unwrap();
+        tx.add_table(
+            &TableInfo {
+                name: "documented_2".into(),
+                ..table_info
+            },
+            4326,
+        )
+        .await
+        .unwrap();
+        let md_file_id = tx
+            .add_metadata("https://example.com/", "application/json", "{}")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        // Only the documented columns are described
+        let data_columns = handler.fetch_rows("gpkg_data_columns").await.unwrap();
+        assert_eq!(data_columns.len(), 2);
+        assert_eq!(data_columns[0].get::<String, &str>("column_name"), "attr1");
+        assert_eq!(data_columns[0].get::<String, &str>("title"), "uro:attr1");
+        assert_eq!(
+            data_columns[0].get::<String, &str>("description"),
+            "gml:MeasureType (uom: m)"
+        );
+
+        let metadata = handler.fetch_rows("gpkg_metadata").await.unwrap();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(metadata[0].get::<i64, &str>("id"), md_file_id);
+        assert_eq!(metadata[0].get::<String, &str>("md_scope"), "dataset");
+        assert_eq!(
+            metadata[0].get::<String, &str>("mime_type"),
+            "application/json"
+        );
+
+        let references = handler.fetch_rows("gpkg_metadata_reference").await.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(
+            references[0].get::<String, &str>("reference_scope"),
+            "geopackage"
+        );
+        assert_eq!(references[0].get::<i64, &str>("md_file_id"), md_file_id);
+
+        let extensions: Vec<(String, String)> = handler
+            .fetch_rows("gpkg_extensions")
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get("table_name"), row.get("extension_name")))
+            .collect();
+        assert_eq!(
+            extensions,
+            vec![
+                ("gpkg_data_columns".into(), "gpkg_schema".into()),
+                ("gpkg_metadata".into(), "gpkg_metadata".into()),
+                ("gpkg_metadata_reference".into(), "gpkg_metadata".into()),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn test_bbox() {
         let mut handler = GpkgHandler::from_url(&Url::parse("sqlite::memory:").unwrap())
@@ -639,7 +1185,10 @@ mod tests {
         let table_name = "mpoly3d";
         let table_info = TableInfo {
             name: table_name.into(),
-            has_geometry: true,
+            geometry: Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiPolygon,
+                has_z: true,
+            }),
             columns: vec![],
         };
 
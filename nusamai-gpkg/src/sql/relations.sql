@@ -0,0 +1,10 @@
+-- https://docs.ogc.org/is/18-000/18-000.html (GeoPackage Related Tables Extension)
+CREATE TABLE IF NOT EXISTS gpkgext_relations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    base_table_name TEXT NOT NULL,
+    base_primary_column TEXT NOT NULL DEFAULT 'id',
+    related_table_name TEXT NOT NULL,
+    related_primary_column TEXT NOT NULL DEFAULT 'id',
+    relation_name TEXT NOT NULL,
+    mapping_table_name TEXT NOT NULL UNIQUE
+);
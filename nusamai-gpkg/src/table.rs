@@ -1,7 +1,8 @@
 #[derive(Debug, PartialEq)]
 pub struct TableInfo {
     pub name: String,
-    pub has_geometry: bool,
+    /// The geometry column of the features table (`None` for the attributes table)
+    pub geometry: Option<GeometryColumnInfo>,
     pub columns: Vec<ColumnInfo>,
 }
 
@@ -10,4 +11,55 @@ pub struct ColumnInfo {
     pub name: String,
     pub data_type: String,
     pub mime_type: Option<String>,
+    /// Human-readable name of the column (`gpkg_data_columns.title`)
+    pub title: Option<String>,
+    /// Description of the column values (`gpkg_data_columns.description`)
+    pub description: Option<String>,
+}
+
+impl ColumnInfo {
+    /// Whether the column has any information to be put in `gpkg_data_columns`
+    pub fn is_documented(&self) -> bool {
+        self.mime_type.is_some() || self.title.is_some() || self.description.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeometryColumnInfo {
+    pub geometry_type: GeometryTypeName,
+    /// Whether the geometries have Z coordinates
+    pub has_z: bool,
+}
+
+/// Geometry type of the geometry column (as in `gpkg_geometry_columns.geometry_type_name`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeometryTypeName {
+    MultiPoint,
+    MultiLineString,
+    MultiPolygon,
+}
+
+impl GeometryTypeName {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MultiPoint => "MULTIPOINT",
+            Self::MultiLineString => "MULTILINESTRING",
+            Self::MultiPolygon => "MULTIPOLYGON",
+        }
+    }
+}
+
+impl TableInfo {
+    pub fn has_geometry(&self) -> bool {
+        self.geometry.is_some()
+    }
+
+    /// Name of the primary key column of the table
+    pub fn primary_column(&self) -> &'static str {
+        if self.has_geometry() {
+            "fid"
+        } else {
+            "id"
+        }
+    }
 }
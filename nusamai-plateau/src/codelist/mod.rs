@@ -1,4 +1,4 @@
 mod resolver;
 pub mod xml;
 
-pub use resolver::Resolver;
+pub use resolver::{MissingCodelistPolicy, Resolver};
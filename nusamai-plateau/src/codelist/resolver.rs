@@ -1,20 +1,56 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Mutex};
 
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use nusamai_citygml::{codelist::CodeResolver, ParseError};
 use stretto::Cache;
 use url::Url;
 
 use super::xml::{parse_dictionary, Definition};
 
+/// How to handle codelist files that are missing or cannot be read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingCodelistPolicy {
+    /// Warn (once per file) and keep the raw code as the value
+    #[default]
+    Warn,
+    /// Fail with `ParseError::CodelistError`
+    Error,
+}
+
 pub struct Resolver {
     cache: Cache<PathBuf, HashMap<String, Definition>>,
+    policy: MissingCodelistPolicy,
+    /// Codelists (URLs) that have already been reported as unavailable
+    unavailable: Mutex<HashSet<String>>,
 }
 
 impl Resolver {
     pub fn new() -> Self {
+        Self::with_policy(MissingCodelistPolicy::default())
+    }
+
+    pub fn with_policy(policy: MissingCodelistPolicy) -> Self {
         Self {
             cache: Cache::new(12960, 100000).unwrap(),
+            policy,
+            unavailable: Default::default(),
+        }
+    }
+
+    /// Applies the policy to an unavailable codelist
+    fn handle_unavailable(
+        &self,
+        key: String,
+        err: ParseError,
+    ) -> Result<Option<String>, ParseError> {
+        match self.policy {
+            MissingCodelistPolicy::Error => Err(err),
+            MissingCodelistPolicy::Warn => {
+                if self.unavailable.lock().unwrap().insert(key) {
+                    log::warn!("{}; keeping the raw codes", err);
+                }
+                Ok(None)
+            }
         }
     }
 }
@@ -39,16 +75,18 @@ impl CodeResolver for Resolver {
         code: &str,
     ) -> Result<Option<String>, nusamai_citygml::ParseError> {
         let Ok(abs_url) = base_url.join(code_space) else {
-            return Err(ParseError::CodelistError(format!(
+            let err = ParseError::CodelistError(format!(
                 "failed to join url: {:?} + {:?}",
                 base_url, code_space
-            )));
+            ));
+            return self.handle_unavailable(code_space.to_string(), err);
         };
         let Ok(path) = abs_url.to_file_path() else {
-            return Err(ParseError::CodelistError(format!(
+            let err = ParseError::CodelistError(format!(
                 "failed to convert url to file path: {:?}",
                 abs_url,
-            )));
+            ));
+            return self.handle_unavailable(abs_url.into(), err);
         };
         if let Some(dict) = self.cache.get(&path) {
             // found in cache
@@ -56,14 +94,19 @@ impl CodeResolver for Resolver {
             Ok(v)
         } else {
             // not found in cache
+            if self.unavailable.lock().unwrap().contains(abs_url.as_str()) {
+                // already reported
+                return Ok(None);
+            }
             let Ok(file) = std::fs::File::open(&path) else {
-                return Err(ParseError::CodelistError(format!(
-                    "failed to open file: {:?}",
-                    path
-                )));
+                let err = ParseError::CodelistError(format!("failed to open file: {:?}", path));
+                return self.handle_unavailable(abs_url.into(), err);
             };
             let reader = std::io::BufReader::with_capacity(128 * 1024, file);
-            let definitions = parse_dictionary(reader)?;
+            let definitions = match parse_dictionary(reader) {
+                Ok(definitions) => definitions,
+                Err(err) => return self.handle_unavailable(abs_url.into(), err),
+            };
 
             let v = definitions.get(code).map(|d| d.value().to_string());
             let cost = definitions.len() as i64;
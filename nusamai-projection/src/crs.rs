@@ -50,6 +50,9 @@ pub const EPSG_JGD2011_JPRECT_XVII: EpsgCode = 6685;
 pub const EPSG_JGD2011_JPRECT_XVIII: EpsgCode = 6686;
 pub const EPSG_JGD2011_JPRECT_XIX: EpsgCode = 6687;
 
+/// JGD2000
+pub const EPSG_JGD2000_GEOGRAPHIC_2D: EpsgCode = 4612;
+
 // JGD2000 / Japan Plane Rectangular CS
 pub const EPSG_JGD2000_JPRECT_I: EpsgCode = 2443;
 pub const EPSG_JGD2000_JPRECT_II: EpsgCode = 2444;
@@ -71,6 +74,9 @@ pub const EPSG_JGD2000_JPRECT_XVII: EpsgCode = 2459;
 pub const EPSG_JGD2000_JPRECT_XVIII: EpsgCode = 2460;
 pub const EPSG_JGD2000_JPRECT_XIX: EpsgCode = 2461;
 
+/// Tokyo
+pub const EPSG_TOKYO_GEOGRAPHIC_2D: EpsgCode = 4301;
+
 // Tokyo / Japan Plane Rectangular CS
 pub const EPSG_TOKYO_JPRECT_I: EpsgCode = 30161;
 pub const EPSG_TOKYO_JPRECT_II: EpsgCode = 30162;
@@ -91,3 +97,21 @@ pub const EPSG_TOKYO_JPRECT_XVI: EpsgCode = 30176;
 pub const EPSG_TOKYO_JPRECT_XVII: EpsgCode = 30177;
 pub const EPSG_TOKYO_JPRECT_XVIII: EpsgCode = 30178;
 pub const EPSG_TOKYO_JPRECT_XIX: EpsgCode = 30179;
+
+// JGD2011 / UTM zone 51N - 55N
+// Note: JGD2011 does not define zone 56N
+pub const EPSG_JGD2011_UTM_51N: EpsgCode = 6688;
+pub const EPSG_JGD2011_UTM_52N: EpsgCode = 6689;
+pub const EPSG_JGD2011_UTM_53N: EpsgCode = 6690;
+pub const EPSG_JGD2011_UTM_54N: EpsgCode = 6691;
+pub const EPSG_JGD2011_UTM_55N: EpsgCode = 6692;
+
+// WGS 84 / UTM zone 1N - 60N (only the zones around Japan are named)
+pub const EPSG_WGS84_UTM_1N: EpsgCode = 32601;
+pub const EPSG_WGS84_UTM_51N: EpsgCode = 32651;
+pub const EPSG_WGS84_UTM_52N: EpsgCode = 32652;
+pub const EPSG_WGS84_UTM_53N: EpsgCode = 32653;
+pub const EPSG_WGS84_UTM_54N: EpsgCode = 32654;
+pub const EPSG_WGS84_UTM_55N: EpsgCode = 32655;
+pub const EPSG_WGS84_UTM_56N: EpsgCode = 32656;
+pub const EPSG_WGS84_UTM_60N: EpsgCode = 32660;
@@ -0,0 +1,260 @@
+//! Horizontal datum shift with the GSI grid files (TKY2JGD, PatchJGD)
+//!
+//! The `.par` files list the shifts (in arc-seconds, and optionally the height shift in meters,
+//! or only the height shift for PatchJGD_H) at the south-west corners of the third-level (1 km) meshes. The shift of a point is
+//! bilinearly interpolated from the four corners of the mesh containing it.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use crate::error::TransformError;
+
+/// Mesh size in degrees (30" in latitude, 45" in longitude)
+const MESH_LAT: f64 = 30. / 3600.;
+const MESH_LNG: f64 = 45. / 3600.;
+
+/// Shift grid loaded from a GSI `.par` file
+#[derive(Debug, Default)]
+pub struct ParGrid {
+    /// (dlat [sec], dlng [sec], dh [m]) by the (lat, lng) mesh index
+    nodes: HashMap<(u32, u32), [f64; 3]>,
+}
+
+impl ParGrid {
+    /// Reads a `.par` file.
+    ///
+    /// Each data line has a mesh code followed by dB and dL (and optionally dH), or only dH (PatchJGD_H).
+    /// Header lines are skipped.
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut nodes = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            let Some(code) = tokens
+                .next()
+                .filter(|t| t.len() == 8)
+                .and_then(|t| t.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let values = tokens
+                .map(|t| t.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let shift = match values[..] {
+                [dh] => [0., 0., dh],
+                [dlat, dlng] => [dlat, dlng, 0.],
+                [dlat, dlng, dh] => [dlat, dlng, dh],
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid line for mesh {code}"),
+                    ))
+                }
+            };
+            nodes.insert(mesh_index(code), shift);
+        }
+        Ok(Self { nodes })
+    }
+
+    /// Loads a `.par` file.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Adds the shifts of another grid (e.g. the height shifts of PatchJGD_H to the horizontal shifts of PatchJGD).
+    pub fn merge(&mut self, other: ParGrid) {
+        for (index, shift) in other.nodes {
+            let node = self.nodes.entry(index).or_default();
+            (0..3).for_each(|k| node[k] += shift[k]);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns the interpolated (dlng, dlat) in degrees and dh in meters.
+    ///
+    /// Returns `None` if a corner of the mesh is missing, unless `missing_as_zero` is set.
+    pub fn shift(&self, lng: f64, lat: f64, missing_as_zero: bool) -> Option<(f64, f64, f64)> {
+        let y = lat / MESH_LAT;
+        let x = (lng - 100.) / MESH_LNG;
+        if y < 0. || x < 0. {
+            return missing_as_zero.then_some((0., 0., 0.));
+        }
+        let (i, j) = (y.floor() as u32, x.floor() as u32);
+        let (fy, fx) = (y - y.floor(), x - x.floor());
+
+        let mut shift = [0.; 3];
+        for (di, dj, w) in [
+            (0, 0, (1. - fy) * (1. - fx)),
+            (0, 1, (1. - fy) * fx),
+            (1, 0, fy * (1. - fx)),
+            (1, 1, fy * fx),
+        ] {
+            match self.nodes.get(&(i + di, j + dj)) {
+                Some(node) => (0..3).for_each(|k| shift[k] += w * node[k]),
+                None if missing_as_zero => {}
+                None => return None,
+            }
+        }
+        Some((shift[1] / 3600., shift[0] / 3600., shift[2]))
+    }
+}
+
+/// Converts a third-level mesh code into the (lat, lng) mesh index.
+fn mesh_index(code: u32) -> (u32, u32) {
+    let (p, u) = (code / 1_000_000, code / 10_000 % 100);
+    let (q, v) = (code / 1000 % 10, code / 100 % 10);
+    let (r, w) = (code / 10 % 10, code % 10);
+    (p * 80 + q * 10 + r, u * 80 + v * 10 + w)
+}
+
+/// Conversion from the old Japanese datums into JGD2011
+///
+/// - Tokyo datum -> JGD2000 with TKY2JGD
+/// - JGD2000 -> JGD2011 with PatchJGD (the shift is zero outside the grid)
+#[derive(Debug, Default)]
+pub struct JgdDatumShift {
+    tky2jgd: Option<ParGrid>,
+    patchjgd: Option<ParGrid>,
+}
+
+impl JgdDatumShift {
+    pub fn new(tky2jgd: Option<ParGrid>, patchjgd: Option<ParGrid>) -> Self {
+        Self { tky2jgd, patchjgd }
+    }
+
+    /// Converts Tokyo datum (lng, lat) into JGD2011. The TKY2JGD grid is required.
+    pub fn tokyo_to_jgd2011(
+        &self,
+        lng: f64,
+        lat: f64,
+        height: f64,
+    ) -> Result<(f64, f64, f64), TransformError> {
+        let grid = self.tky2jgd.as_ref().ok_or(TransformError::MissingGrid)?;
+        let (dlng, dlat, _) = grid
+            .shift(lng, lat, false)
+            .ok_or(TransformError::OutsideGrid)?;
+        Ok(self.jgd2000_to_jgd2011(lng + dlng, lat + dlat, height))
+    }
+
+    /// Converts JGD2000 (lng, lat, height) into JGD2011.
+    ///
+    /// Without the PatchJGD grid, JGD2000 is regarded as JGD2011.
+    pub fn jgd2000_to_jgd2011(&self, lng: f64, lat: f64, height: f64) -> (f64, f64, f64) {
+        match self.patchjgd.as_ref().and_then(|g| g.shift(lng, lat, true)) {
+            Some((dlng, dlat, dh)) => (lng + dlng, lat + dlat, height + dh),
+            None => (lng, lat, height),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_code() {
+        // Tokyo station (35.6812, 139.7671) is in the mesh 53394611
+        let (i, j) = mesh_index(53394611);
+        assert_eq!(i, (35.6812 / MESH_LAT).floor() as u32);
+        assert_eq!(j, ((139.7671 - 100.) / MESH_LNG).floor() as u32);
+    }
+
+    #[test]
+    fn interpolation() {
+        let par = "JGD2000 TKY2JGD Ver.2.1.2\n\
+                   MeshCode   dB(sec)   dL(sec)\n\
+                   53394611   10.0   -10.0\n\
+                   53394612   12.0   -10.0\n\
+                   53394621   10.0   -14.0\n\
+                   53394622   12.0   -14.0\n";
+        let grid = ParGrid::from_reader(par.as_bytes()).unwrap();
+        assert_eq!(grid.len(), 4);
+
+        // (almost) the south-west corner of the mesh 53394611
+        let (lat0, lng0) = (4281. * MESH_LAT + 1e-12, 100. + 3181. * MESH_LNG + 1e-12);
+        let (dlng, dlat, dh) = grid.shift(lng0, lat0, false).unwrap();
+        assert!((dlat * 3600. - 10.).abs() < 1e-6);
+        assert!((dlng * 3600. + 10.).abs() < 1e-6);
+        assert_eq!(dh, 0.);
+
+        // center of the mesh
+        let (dlng, dlat, _) = grid
+            .shift(lng0 + MESH_LNG / 2., lat0 + MESH_LAT / 2., false)
+            .unwrap();
+        assert!((dlat * 3600. - 11.).abs() < 1e-9);
+        assert!((dlng * 3600. + 12.).abs() < 1e-9);
+
+        // the next mesh lacks the corners
+        assert!(grid.shift(lng0 + MESH_LNG * 1.5, lat0, false).is_none());
+        assert!(grid.shift(lng0 + MESH_LNG * 1.5, lat0, true).is_some());
+
+        let shift = JgdDatumShift::new(Some(grid), None);
+        let (lng, lat, height) = shift.tokyo_to_jgd2011(lng0, lat0, 5.).unwrap();
+        assert!((lng - (lng0 - 10. / 3600.)).abs() < 1e-9);
+        assert!((lat - (lat0 + 10. / 3600.)).abs() < 1e-9);
+        assert_eq!(height, 5.);
+        assert!(matches!(
+            shift.tokyo_to_jgd2011(130., 33., 0.),
+            Err(TransformError::OutsideGrid)
+        ));
+    }
+
+    #[test]
+    fn patchjgd() {
+        let par = "MeshCode   dB(sec)   dL(sec)   dH(m)\n\
+                   53394611   0.1   -0.2   -0.5\n\
+                   53394612   0.1   -0.2   -0.5\n\
+                   53394621   0.1   -0.2   -0.5\n\
+                   53394622   0.1   -0.2   -0.5\n";
+        let shift = JgdDatumShift::new(None, Some(ParGrid::from_reader(par.as_bytes()).unwrap()));
+        let (_, _, height) = shift.jgd2000_to_jgd2011(139.7671, 35.6812, 10.);
+        assert!((height - 9.5).abs() < 1e-9);
+
+        // zero outside the grid
+        assert_eq!(shift.jgd2000_to_jgd2011(130., 33., 1.), (130., 33., 1.));
+
+        // Tokyo datum needs the TKY2JGD grid
+        assert!(matches!(
+            shift.tokyo_to_jgd2011(139.7671, 35.6812, 0.),
+            Err(TransformError::MissingGrid)
+        ));
+
+        assert!(ParGrid::from_reader("53394611 0.1 0.2 0.3 0.4\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn patchjgd_h() {
+        // Horizontal shifts and the height shifts in separate files
+        let par = "MeshCode   dB(sec)   dL(sec)\n\
+                   53394611   0.1   -0.2\n\
+                   53394612   0.1   -0.2\n\
+                   53394621   0.1   -0.2\n\
+                   53394622   0.1   -0.2\n";
+        let par_h = "MeshCode   dH(m)\n\
+                     53394611   -0.5\n\
+                     53394612   -0.5\n\
+                     53394621   -0.5\n\
+                     53394622   -0.5\n";
+        let mut grid = ParGrid::from_reader(par.as_bytes()).unwrap();
+        grid.merge(ParGrid::from_reader(par_h.as_bytes()).unwrap());
+        assert_eq!(grid.len(), 4);
+
+        let shift = JgdDatumShift::new(None, Some(grid));
+        let (lng, lat, height) = shift.jgd2000_to_jgd2011(139.7671, 35.6812, 10.);
+        assert!((lng - (139.7671 - 0.2 / 3600.)).abs() < 1e-9);
+        assert!((lat - (35.6812 + 0.1 / 3600.)).abs() < 1e-9);
+        assert!((height - 9.5).abs() < 1e-9);
+    }
+}
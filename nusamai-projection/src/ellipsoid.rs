@@ -68,3 +68,9 @@ pub fn wgs84() -> Ellipsoid {
 pub fn grs80() -> Ellipsoid {
     Ellipsoid::new(6378137., 298.257222101)
 }
+
+/// Bessel 1841 Ellipsoid (Tokyo datum)
+#[inline]
+pub fn bessel() -> Ellipsoid {
+    Ellipsoid::new(6377397.155, 299.1528128)
+}
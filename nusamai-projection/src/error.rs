@@ -1,7 +1,15 @@
 use thiserror::Error;
 
+use crate::crs::EpsgCode;
+
 #[derive(Error, Debug)]
 pub enum TransformError {
     #[error("outside projection domain")]
     OutsideProjectionDomain,
+    #[error("outside the datum shift grid")]
+    OutsideGrid,
+    #[error("datum shift grid is not loaded")]
+    MissingGrid,
+    #[error("unsupported CRS: EPSG:{0}")]
+    UnsupportedCrs(EpsgCode),
 }
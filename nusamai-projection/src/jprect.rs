@@ -1,6 +1,10 @@
 //! Japan Plane Rectangular Coordinate Systems
 
-use crate::{crs::*, ellipsoid::grs80, etmerc::ExtendedTransverseMercatorProjection};
+use crate::{
+    crs::*,
+    ellipsoid::{grs80, Ellipsoid},
+    etmerc::ExtendedTransverseMercatorProjection,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum JPRZone {
@@ -48,8 +52,16 @@ impl JPRZoneParams {
 impl JPRZone {
     /// Gets the transverse mercator projection for the zone.
     pub fn projection(&self) -> ExtendedTransverseMercatorProjection {
+        self.projection_with_ellipsoid(&grs80())
+    }
+
+    /// Gets the transverse mercator projection for the zone on the given ellipsoid (e.g. Bessel for the Tokyo datum).
+    pub fn projection_with_ellipsoid(
+        &self,
+        ellips: &Ellipsoid,
+    ) -> ExtendedTransverseMercatorProjection {
         let params = self.params();
-        ExtendedTransverseMercatorProjection::new(params.lng0(), params.lat0(), JPR_K, &grs80())
+        ExtendedTransverseMercatorProjection::new(params.lng0(), params.lat0(), JPR_K, ellips)
     }
 
     /// Get the zone from the zone number.
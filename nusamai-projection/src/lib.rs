@@ -1,7 +1,9 @@
 pub mod cartesian;
 pub mod crs;
+pub mod datumshift;
 pub mod ellipsoid;
 pub mod error;
 pub mod etmerc;
 pub mod jprect;
+pub mod utm;
 pub mod vshift;
@@ -0,0 +1,170 @@
+//! Universal Transverse Mercator (northern hemisphere)
+
+use crate::{
+    crs::*,
+    ellipsoid::{grs80, wgs84},
+    error::TransformError,
+    etmerc::ExtendedTransverseMercatorProjection,
+};
+
+const UTM_K: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.;
+
+/// Geodetic datum of the UTM zone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UtmDatum {
+    /// JGD2011 (GRS80 ellipsoid)
+    Jgd2011,
+    /// WGS 84 (WGS84 ellipsoid)
+    Wgs84,
+}
+
+/// UTM zone in the northern hemisphere
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UtmZone {
+    number: u8,
+    datum: UtmDatum,
+}
+
+impl UtmZone {
+    /// Get the zone from the zone number (1 - 60).
+    pub const fn from_number(number: u8, datum: UtmDatum) -> Option<Self> {
+        match number {
+            1..=60 => Some(UtmZone { number, datum }),
+            _ => None,
+        }
+    }
+
+    /// Get the zone from the EPSG code.
+    pub const fn from_epsg(epsg: EpsgCode) -> Option<Self> {
+        match epsg {
+            EPSG_JGD2011_UTM_51N..=EPSG_JGD2011_UTM_55N => Some(UtmZone {
+                number: (epsg - EPSG_JGD2011_UTM_51N) as u8 + 51,
+                datum: UtmDatum::Jgd2011,
+            }),
+            EPSG_WGS84_UTM_1N..=EPSG_WGS84_UTM_60N => Some(UtmZone {
+                number: (epsg - EPSG_WGS84_UTM_1N) as u8 + 1,
+                datum: UtmDatum::Wgs84,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Gets the zone number.
+    pub const fn zone_number(&self) -> u8 {
+        self.number
+    }
+
+    /// Gets the datum.
+    pub const fn datum(&self) -> UtmDatum {
+        self.datum
+    }
+
+    /// Central longitude
+    pub fn lng0(&self) -> f64 {
+        self.number as f64 * 6. - 183.
+    }
+
+    /// Gets the EPSG code of the zone, if it is defined.
+    pub const fn epsg(&self) -> Option<EpsgCode> {
+        match (self.datum, self.number) {
+            (UtmDatum::Jgd2011, 51..=55) => {
+                Some(self.number as EpsgCode - 51 + EPSG_JGD2011_UTM_51N)
+            }
+            (UtmDatum::Jgd2011, _) => None,
+            (UtmDatum::Wgs84, _) => Some(self.number as EpsgCode - 1 + EPSG_WGS84_UTM_1N),
+        }
+    }
+
+    /// Gets the projection for the zone.
+    pub fn projection(&self) -> UtmProjection {
+        let ellips = match self.datum {
+            UtmDatum::Jgd2011 => grs80(),
+            UtmDatum::Wgs84 => wgs84(),
+        };
+        UtmProjection {
+            tmerc: ExtendedTransverseMercatorProjection::new(self.lng0(), 0., UTM_K, &ellips),
+        }
+    }
+}
+
+/// Transverse mercator projection with the UTM false easting
+#[derive(Debug, Clone)]
+pub struct UtmProjection {
+    tmerc: ExtendedTransverseMercatorProjection,
+}
+
+impl UtmProjection {
+    pub fn project_forward(
+        &self,
+        lng: f64,
+        lat: f64,
+        z: f64,
+    ) -> Result<(f64, f64, f64), TransformError> {
+        let (x, y, z) = self.tmerc.project_forward(lng, lat, z)?;
+        Ok((x + UTM_FALSE_EASTING, y, z))
+    }
+
+    pub fn project_inverse(
+        &self,
+        x: f64,
+        y: f64,
+        z: f64,
+    ) -> Result<(f64, f64, f64), TransformError> {
+        self.tmerc.project_inverse(x - UTM_FALSE_EASTING, y, z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project() {
+        // reference values computed with the Krüger series (6th order)
+        let proj = UtmZone::from_epsg(EPSG_JGD2011_UTM_54N)
+            .unwrap()
+            .projection();
+        let (x, y, z) = proj.project_forward(139.7671, 35.6812, 10.).unwrap();
+        assert!((x - 388433.3746203965).abs() < 1e-6);
+        assert!((y - 3949290.013429536).abs() < 1e-6);
+        assert_eq!(z, 10.);
+
+        let (lng, lat, _) = proj.project_inverse(x, y, 0.).unwrap();
+        assert!((lng - 139.7671).abs() < 1e-10);
+        assert!((lat - 35.6812).abs() < 1e-10);
+
+        let proj = UtmZone::from_epsg(EPSG_WGS84_UTM_52N).unwrap().projection();
+        let (x, y, _) = proj.project_forward(130.0, 33.0, 0.).unwrap();
+        assert!((x - 593417.778).abs() < 1e-2);
+        assert!((y - 3651730.974).abs() < 1e-2);
+
+        // central meridian on the equator
+        let (x, y, _) = proj.project_forward(129.0, 0., 0.).unwrap();
+        assert!((x - 500_000.).abs() < 1e-9);
+        assert!(y.abs() < 1e-9);
+    }
+
+    #[test]
+    fn zones() {
+        let zone = UtmZone::from_epsg(EPSG_JGD2011_UTM_51N).unwrap();
+        assert_eq!(zone.zone_number(), 51);
+        assert_eq!(zone.datum(), UtmDatum::Jgd2011);
+        assert_eq!(zone.lng0(), 123.);
+        assert_eq!(zone.epsg(), Some(EPSG_JGD2011_UTM_51N));
+
+        let zone = UtmZone::from_epsg(EPSG_WGS84_UTM_56N).unwrap();
+        assert_eq!(zone.zone_number(), 56);
+        assert_eq!(zone.datum(), UtmDatum::Wgs84);
+        assert_eq!(zone.lng0(), 153.);
+        assert_eq!(zone.epsg(), Some(EPSG_WGS84_UTM_56N));
+
+        // JGD2011 has no zone 56
+        let zone = UtmZone::from_number(56, UtmDatum::Jgd2011).unwrap();
+        assert_eq!(zone.epsg(), None);
+
+        assert_eq!(UtmZone::from_number(0, UtmDatum::Wgs84), None);
+        assert_eq!(UtmZone::from_number(61, UtmDatum::Wgs84), None);
+        assert_eq!(UtmZone::from_epsg(EPSG_JGD2011_JPRECT_I), None);
+    }
+}
@@ -1,3 +1,9 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
 use japan_geoid::{gsi::MemoryGrid, Geoid};
 
 /// Convert from JGD 2011 Geograhpic 3D (EPSG:6697) to WGS84 Geograhpic 3D (EPSG:4979)
@@ -13,11 +19,38 @@ impl Jgd2011ToWgs84 {
         }
     }
 
+    /// Create a new instance with the given geoid model.
+    pub fn from_geoid(geoid: MemoryGrid<'static>) -> Self {
+        Self { geoid }
+    }
+
+    /// Create a new instance with the geoid model loaded from a file (e.g. JPGEO2024 or a local geoid).
+    ///
+    /// Files with the `.asc` extension are read as GSI ASCII grids, and others as binary grids.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let is_ascii = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("asc"));
+        let geoid = match is_ascii {
+            true => MemoryGrid::from_ascii_reader(&mut reader)?,
+            false => MemoryGrid::from_binary_reader(&mut reader)?,
+        };
+        Ok(Self::from_geoid(geoid))
+    }
+
     /// JGD2011 Geographic 3D (EPSG:6697) to WGS84 Geographic 3D (EPSG:4979)
     pub fn convert(&self, lng: f64, lat: f64, height: f64) -> (f64, f64, f64) {
         let ellipsoid_height = self.geoid.get_height(lng, lat) + height;
         (lng, lat, ellipsoid_height)
     }
+
+    /// WGS84 Geographic 3D (EPSG:4979) to JGD2011 Geographic 3D (EPSG:6697)
+    pub fn convert_inverse(&self, lng: f64, lat: f64, ellipsoid_height: f64) -> (f64, f64, f64) {
+        let height = ellipsoid_height - self.geoid.get_height(lng, lat);
+        (lng, lat, height)
+    }
 }
 
 impl Default for Jgd2011ToWgs84 {
@@ -39,5 +72,25 @@ mod tests {
         // (lng, lat) must not change.
         assert_eq!(lng_jgd, lng_wgs);
         assert_eq!(lat_jgd, lat_wgs);
+
+        let (lng, lat, height) = jgd_to_wgs.convert_inverse(lng_wgs, lat_wgs, ellips_height);
+        assert_eq!((lng, lat), (lng_jgd, lat_jgd));
+        assert!((height - elevation).abs() < 1e-8);
+    }
+
+    #[test]
+    fn load_binary_file() {
+        let path = std::env::temp_dir().join("nusamai-projection-test-geoid.bin");
+        let embedded = japan_geoid::gsi::load_embedded_gsigeo2011();
+        embedded
+            .to_binary_writer(&mut std::io::BufWriter::new(File::create(&path).unwrap()))
+            .unwrap();
+
+        let jgd_to_wgs = Jgd2011ToWgs84::from_file(&path).unwrap();
+        let (_, _, ellips_height) = jgd_to_wgs.convert(138.2839817085188, 37.12378643088312, 0.);
+        assert!((ellips_height - 39.47387115961899).abs() < 1e-8);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(Jgd2011ToWgs84::from_file("nonexistent.asc").is_err());
     }
 }
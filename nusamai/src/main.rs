@@ -9,15 +9,20 @@ use std::{
 use clap::Parser;
 use nusamai::{
     pipeline::Canceller,
-    sink::{DataRequirements, DataSink, DataSinkProvider},
+    sink::{DataSink, DataSinkProvider},
     source::{citygml::CityGmlSourceProvider, DataSource, DataSourceProvider},
     transformer::{
-        self, MappingRules, MultiThreadTransformer, NusamaiTransformBuilder, TransformBuilder,
+        self, transform::ProjectionTransform, LodFilterMode, MappingRules, MultiThreadTransformer,
+        NusamaiTransformBuilder, TransformBuilder,
     },
     BUILTIN_SINKS,
 };
 use nusamai_citygml::CityGmlElement;
 use nusamai_plateau::models::TopLevelCityObject;
+use nusamai_projection::{
+    datumshift::{JgdDatumShift, ParGrid},
+    vshift::Jgd2011ToWgs84,
+};
 
 #[derive(clap::Parser)]
 #[command(author, version, about, long_about = None)]
@@ -42,6 +47,27 @@ struct Args {
     #[arg(long)]
     rules: Option<String>,
 
+    /// Specify the geoid model file (GSI ASCII `.asc` or binary) for the height conversion (default: GSIGEO2011)
+    #[arg(long)]
+    geoid: Option<String>,
+
+    /// Keep the orthometric heights in WGS84 outputs, instead of converting them to ellipsoidal heights
+    #[arg(long, conflicts_with = "geoid")]
+    keep_orthometric_height: bool,
+
+    /// Specify the TKY2JGD grid file (`TKY2JGD.par`) for the Tokyo datum inputs
+    #[arg(long)]
+    tky2jgd: Option<String>,
+
+    /// Specify the PatchJGD grid file (`.par`) for the JGD2000 and Tokyo datum inputs
+    /// (may be repeated to add the height shifts of PatchJGD_H)
+    #[arg(long)]
+    patchjgd: Vec<String>,
+
+    /// Select the LODs to output: `highest`, `lowest`, `all` (one feature per LOD) or a LOD number (default: chosen by the output format)
+    #[arg(long, value_parser = parse_lod_mode)]
+    lod: Option<LodFilterMode>,
+
     /// Output schema
     #[arg(long)]
     schema: Option<String>,
@@ -62,6 +88,20 @@ fn parse_key_val(s: &str) -> Result<(String, String), String> {
     Ok((s[..pos].into(), s[pos + 1..].into()))
 }
 
+fn parse_lod_mode(s: &str) -> Result<LodFilterMode, String> {
+    match s {
+        "highest" => Ok(LodFilterMode::Highest),
+        "lowest" => Ok(LodFilterMode::Lowest),
+        "all" => Ok(LodFilterMode::All),
+        _ => match s.parse::<u8>() {
+            Ok(lod) if lod <= 4 => Ok(LodFilterMode::Exact(lod)),
+            _ => Err(format!(
+                "invalid LOD mode `{s}`: expected highest, lowest, all or 0-4"
+            )),
+        },
+    }
+}
+
 fn parse_non_empty(s: &str) -> Result<String, String> {
     if s.is_empty() {
         Err("value must not be empty".into())
@@ -162,6 +202,10 @@ fn main() -> ExitCode {
         "kml" => 6697, // temporary hack for KML output
         _ => args.epsg,
     });
+    if let Err(err) = ProjectionTransform::check_output_epsg(requirements.output_epsg) {
+        log::error!("Invalid output CRS: {}", err);
+        return ExitCode::FAILURE;
+    }
 
     let mapping_rules = match &args.rules {
         Some(rules_path) => {
@@ -178,6 +222,40 @@ fn main() -> ExitCode {
         None => None,
     };
 
+    let jgd2wgs = match &args.geoid {
+        Some(geoid_path) => match Jgd2011ToWgs84::from_file(geoid_path) {
+            Ok(jgd2wgs) => jgd2wgs,
+            Err(err) => {
+                log::error!("Error loading geoid model {}: {:?}", geoid_path, err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Jgd2011ToWgs84::default(),
+    };
+
+    let load_grid = |path: &Option<String>| path.as_ref().map(ParGrid::from_file).transpose();
+    let load_grids = |paths: &[String]| {
+        paths
+            .iter()
+            .try_fold(None, |merged: Option<ParGrid>, path| {
+                let grid = ParGrid::from_file(path)?;
+                Ok::<_, std::io::Error>(Some(match merged {
+                    Some(mut merged) => {
+                        merged.merge(grid);
+                        merged
+                    }
+                    None => grid,
+                }))
+            })
+    };
+    let datum_shift = match (load_grid(&args.tky2jgd), load_grids(&args.patchjgd)) {
+        (Ok(tky2jgd), Ok(patchjgd)) => JgdDatumShift::new(tky2jgd, patchjgd),
+        (Err(err), _) | (_, Err(err)) => {
+            log::error!("Error loading datum shift grid: {:?}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
     let source = {
         // glob input file patterns
         let mut filenames = vec![];
@@ -216,14 +294,17 @@ fn main() -> ExitCode {
         source
     };
 
-    run(
-        &args,
-        source,
-        requirements,
-        mapping_rules,
-        sink,
-        &mut canceller,
-    );
+    let transform_builder = {
+        let mut request = transformer::Request::from(requirements);
+        request.set_mapping_rules(mapping_rules);
+        request.keep_orthometric_height = args.keep_orthometric_height;
+        if let Some(mode) = args.lod {
+            request.lod_filter.mode = mode;
+        }
+        NusamaiTransformBuilder::with_geoid(request, jgd2wgs).with_datum_shift(datum_shift)
+    };
+
+    run(&args, source, transform_builder, sink, &mut canceller);
 
     ExitCode::SUCCESS
 }
@@ -231,8 +312,7 @@ fn main() -> ExitCode {
 fn run(
     args: &Args,
     source: Box<dyn DataSource>,
-    requirements: DataRequirements,
-    mapping_rules: Option<MappingRules>,
+    transform_builder: NusamaiTransformBuilder,
     sink: Box<dyn DataSink>,
     canceller: &mut Arc<Mutex<Canceller>>,
 ) {
@@ -240,12 +320,6 @@ fn run(
 
     // Prepare the transformer for the pipeline and transform the schema
     let (transformer, schema) = {
-        let request = {
-            let mut request = transformer::Request::from(requirements);
-            request.set_mapping_rules(mapping_rules);
-            request
-        };
-        let transform_builder = NusamaiTransformBuilder::new(request);
         let mut schema = nusamai_citygml::schema::Schema::default();
         TopLevelCityObject::collect_schema(&mut schema);
         transform_builder.transform_schema(&mut schema);
@@ -0,0 +1,409 @@
+//! Texture atlas packing
+//!
+//! The regions of the texture images used by a mesh are cropped and packed into a few atlas images,
+//! so that the mesh needs fewer materials (draw calls) and images (requests).
+
+use std::{
+    cmp::Reverse,
+    fs,
+    hash::Hash,
+    io::{self, BufWriter},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ahash::{HashMap, HashSet, RandomState};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, GenericImageView, RgbaImage};
+use indexmap::IndexSet;
+use url::Url;
+
+use crate::pipeline::Feedback;
+
+/// Maximum width and height of an atlas image
+const MAX_ATLAS_SIZE: u32 = 4096;
+/// Margin (in pixels) kept around each region, to avoid bleeding with the texture filtering
+const PADDING: u32 = 2;
+/// JPEG quality of the atlas images without transparency
+const JPEG_QUALITY: u8 = 90;
+/// Tolerance for the texture coordinates slightly outside [0, 1]
+const UV_TOLERANCE: f64 = 1e-3;
+
+static ATLAS_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// (page, x, y) of a packed rectangle
+type Position = (usize, u32, u32);
+
+/// Ranges of the texture coordinates used for each texture image
+///
+/// The wrapping (repeated) textures, with coordinates outside [0, 1], cannot be packed and are left out.
+#[derive(Default)]
+pub(crate) struct UvUsage {
+    /// [min_u, min_v, max_u, max_v] by the image URL
+    bounds: HashMap<Url, [f64; 4]>,
+    /// Images with the texture coordinates outside [0, 1]
+    wrapping: HashSet<Url>,
+}
+
+impl UvUsage {
+    pub fn add(&mut self, uri: &Url, [u, v]: [f64; 2]) {
+        let range = -UV_TOLERANCE..=1. + UV_TOLERANCE;
+        if !range.contains(&u) || !range.contains(&v) {
+            self.wrapping.insert(uri.clone());
+        }
+        let (u, v) = (u.clamp(0., 1.), v.clamp(0., 1.));
+        let b = self
+            .bounds
+            .entry(uri.clone())
+            .or_insert([f64::MAX, f64::MAX, f64::MIN, f64::MIN]);
+        *b = [b[0].min(u), b[1].min(v), b[2].max(u), b[3].max(v)];
+    }
+}
+
+/// Location of a texture region in an atlas image
+#[derive(Debug, Clone)]
+pub(crate) struct Placement {
+    /// URL of the atlas image
+    pub page_uri: Url,
+    /// Size of the original image (in pixels)
+    image_size: [f64; 2],
+    /// Offset from the original image to the atlas image (in pixels)
+    offset: [f64; 2],
+    /// Size of the atlas image (in pixels)
+    page_size: [f64; 2],
+}
+
+impl Placement {
+    /// Maps texture coordinates of the original image into the atlas image.
+    pub fn map_uv(&self, [u, v]: [f64; 2]) -> [f64; 2] {
+        let u = u.clamp(0., 1.) * self.image_size[0] + self.offset[0];
+        let v = v.clamp(0., 1.) * self.image_size[1] + self.offset[1];
+        [u / self.page_size[0], v / self.page_size[1]]
+    }
+
+    /// Maps the texture coordinates of a vertex `[x, y, z, nx, ny, nz, u, v, feature_id]` (as f32 bits).
+    pub fn map_vertex(&self, mut vbits: [u32; 9]) -> [u32; 9] {
+        let uv = [
+            f32::from_bits(vbits[6]) as f64,
+            f32::from_bits(vbits[7]) as f64,
+        ];
+        let [u, v] = self.map_uv(uv);
+        vbits[6] = (u as f32).to_bits();
+        vbits[7] = (v as f32).to_bits();
+        vbits
+    }
+}
+
+/// Material of the meshes, whose texture image can be replaced with an atlas image
+pub(crate) trait AtlasMaterial: Eq + Hash {
+    fn texture_uri(&self) -> Option<&Url>;
+    /// Returns the material with the texture image replaced
+    fn with_texture_uri(self, uri: Url) -> Self;
+}
+
+/// Primitive of the meshes, which has the indices of its vertices
+pub(crate) trait AtlasPrimitive: Default {
+    fn indices(&self) -> &[u32];
+    /// Adds the vertices (with the indices mapped) and the features of another primitive
+    fn merge(&mut self, other: Self, map_index: impl FnMut(u32) -> u32);
+}
+
+/// Vertices `[x, y, z, nx, ny, nz, u, v, feature_id]` (as f32 bits)
+pub(crate) type Vertices = IndexSet<[u32; 9], RandomState>;
+
+/// Packs the textures of the primitives into atlases, and remaps their materials and texture coordinates.
+///
+/// The atlas images are kept until the returned `TextureAtlas` is dropped.
+#[allow(clippy::type_complexity)]
+pub(crate) fn apply_texture_atlas<M: AtlasMaterial, P: AtlasPrimitive>(
+    feedback: &Feedback,
+    vertices: Vertices,
+    primitives: HashMap<M, P>,
+) -> io::Result<(Vertices, HashMap<M, P>, TextureAtlas)> {
+    let mut usage = UvUsage::default();
+    for (mat, primitive) in &primitives {
+        if let Some(uri) = mat.texture_uri() {
+            for &idx in primitive.indices() {
+                let vbits = vertices[idx as usize];
+                let uv = [vbits[6], vbits[7]].map(|c| f32::from_bits(c) as f64);
+                usage.add(uri, uv);
+            }
+        }
+    }
+    let atlas = TextureAtlas::build(feedback, usage)?;
+    if atlas.is_empty() {
+        return Ok((vertices, primitives, atlas));
+    }
+
+    let mut new_vertices = Vertices::default();
+    let mut new_primitives: HashMap<M, P> = HashMap::default();
+    for (mat, primitive) in primitives {
+        let placement = mat.texture_uri().and_then(|uri| atlas.placement(uri));
+        let mat = match placement {
+            Some(placement) => mat.with_texture_uri(placement.page_uri.clone()),
+            None => mat,
+        };
+        new_primitives
+            .entry(mat)
+            .or_default()
+            .merge(primitive, |idx| {
+                let vbits = vertices[idx as usize];
+                let vbits = match placement {
+                    Some(placement) => placement.map_vertex(vbits),
+                    None => vbits,
+                };
+                new_vertices.insert_full(vbits).0 as u32
+            });
+    }
+    Ok((new_vertices, new_primitives, atlas))
+}
+
+/// Atlas images written to temporary files, which are removed on drop
+#[derive(Default)]
+pub(crate) struct TextureAtlas {
+    placements: HashMap<Url, Placement>,
+    files: Vec<PathBuf>,
+}
+
+struct Region {
+    uri: Url,
+    image: DynamicImage,
+    image_size: [u32; 2],
+    /// Top-left corner of the region in the original image
+    origin: [u32; 2],
+}
+
+impl TextureAtlas {
+    /// Packs the used regions of the images into atlas images.
+    ///
+    /// The images that cannot be loaded or that are too large are left out, so they are used as they are.
+    pub fn build(feedback: &Feedback, usage: UvUsage) -> io::Result<Self> {
+        // Nothing to gain with a single image
+        if usage.bounds.len() < 2 {
+            return Ok(Self::default());
+        }
+
+        let mut usage: Vec<_> = usage
+            .bounds
+            .into_iter()
+            .filter(|(uri, _)| !usage.wrapping.contains(uri))
+            .collect();
+        usage.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        let mut regions = Vec::new();
+        for (uri, [min_u, min_v, max_u, max_v]) in usage {
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            let image = match image::open(&path) {
+                Ok(image) => image,
+                Err(err) => {
+                    feedback.warn(format!("Failed to load texture {:?}: {}", path, err));
+                    continue;
+                }
+            };
+            let (width, height) = image.dimensions();
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let crop = |min: f64, max: f64, size: u32| {
+                let start = ((min * size as f64).floor() as u32).saturating_sub(PADDING);
+                let end = ((max * size as f64).ceil() as u32 + PADDING).min(size);
+                (start.min(size - 1), end.max(start + 1).min(size))
+            };
+            let (x0, x1) = crop(min_u, max_u, width);
+            let (y0, y1) = crop(min_v, max_v, height);
+            if x1 - x0 > MAX_ATLAS_SIZE || y1 - y0 > MAX_ATLAS_SIZE {
+                continue;
+            }
+            regions.push(Region {
+                uri,
+                image: image.crop_imm(x0, y0, x1 - x0, y1 - y0),
+                image_size: [width, height],
+                origin: [x0, y0],
+            });
+        }
+        if regions.len() < 2 {
+            return Ok(Self::default());
+        }
+
+        let sizes: Vec<_> = regions
+            .iter()
+            .map(|r| [r.image.width(), r.image.height()])
+            .collect();
+        let (positions, page_sizes) = pack_shelves(&sizes, MAX_ATLAS_SIZE);
+
+        let mut atlas = Self::default();
+        for (page, &[page_width, page_height]) in page_sizes.iter().enumerate() {
+            let mut canvas = RgbaImage::new(page_width, page_height);
+            let mut has_alpha = false;
+            let members: Vec<_> = regions
+                .iter()
+                .zip(&positions)
+                .filter(|(_, (p, _, _))| *p == page)
+                .collect();
+            for (region, &(_, x, y)) in &members {
+                has_alpha |= region.image.color().has_alpha();
+                image::imageops::replace(&mut canvas, &region.image.to_rgba8(), x as i64, y as i64);
+            }
+
+            let path = std::env::temp_dir().join(format!(
+                "nusamai-atlas-{}-{}.{}",
+                std::process::id(),
+                ATLAS_COUNTER.fetch_add(1, Ordering::Relaxed),
+                if has_alpha { "png" } else { "jpg" }
+            ));
+            atlas.files.push(path.clone());
+            write_image(canvas, has_alpha, &path)?;
+            let page_uri = Url::from_file_path(&path)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid atlas path"))?;
+
+            for (region, &(_, x, y)) in members {
+                atlas.placements.insert(
+                    region.uri.clone(),
+                    Placement {
+                        page_uri: page_uri.clone(),
+                        image_size: region.image_size.map(|s| s as f64),
+                        offset: [
+                            x as f64 - region.origin[0] as f64,
+                            y as f64 - region.origin[1] as f64,
+                        ],
+                        page_size: [page_width as f64, page_height as f64],
+                    },
+                );
+            }
+        }
+        feedback.info(format!(
+            "Packed {} textures into {} atlas images",
+            regions.len(),
+            page_sizes.len()
+        ));
+        Ok(atlas)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placements.is_empty()
+    }
+
+    /// Returns where the image is packed, if it is.
+    pub fn placement(&self, uri: &Url) -> Option<&Placement> {
+        self.placements.get(uri)
+    }
+}
+
+impl Drop for TextureAtlas {
+    fn drop(&mut self) {
+        for path in &self.files {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn write_image(canvas: RgbaImage, has_alpha: bool, path: &PathBuf) -> io::Result<()> {
+    let to_io_error = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+    if has_alpha {
+        canvas.save(path).map_err(to_io_error)
+    } else {
+        let writer = BufWriter::new(fs::File::create(path)?);
+        let encoder = JpegEncoder::new_with_quality(writer, JPEG_QUALITY);
+        DynamicImage::ImageRgba8(canvas)
+            .to_rgb8()
+            .write_with_encoder(encoder)
+            .map_err(to_io_error)
+    }
+}
+
+/// Packs the rectangles ([width, height], each within `max_size`) into pages with the shelf algorithm.
+///
+/// Returns the position of each rectangle, and the sizes of the pages.
+fn pack_shelves(sizes: &[[u32; 2]], max_size: u32) -> (Vec<Position>, Vec<[u32; 2]>) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (Reverse(sizes[i][1]), Reverse(sizes[i][0]), i));
+
+    let mut positions = vec![(0, 0, 0); sizes.len()];
+    let mut pages: Vec<[u32; 2]> = Vec::new();
+    let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+    for i in order {
+        let [width, height] = sizes[i];
+        if pages.is_empty() {
+            pages.push([0, 0]);
+        }
+        if shelf_x + width > max_size {
+            // next shelf
+            (shelf_x, shelf_y, shelf_height) = (0, shelf_y + shelf_height, 0);
+        }
+        if shelf_y + height > max_size {
+            // next page
+            pages.push([0, 0]);
+            (shelf_x, shelf_y, shelf_height) = (0, 0, 0);
+        }
+        let page = pages.len() - 1;
+        positions[i] = (page, shelf_x, shelf_y);
+        shelf_x += width;
+        shelf_height = shelf_height.max(height);
+        pages[page] = [
+            pages[page][0].max(shelf_x),
+            pages[page][1].max(shelf_y + height),
+        ];
+    }
+    (positions, pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::pipeline::feedback;
+
+    #[test]
+    fn shelves() {
+        let (positions, pages) = pack_shelves(&[[30, 10], [60, 40], [50, 20], [100, 100]], 100);
+        assert_eq!(positions, [(1, 50, 40), (1, 0, 0), (1, 0, 40), (0, 0, 0)]);
+        assert_eq!(pages, [[100, 100], [80, 60]]);
+    }
+
+    #[test]
+    fn build_atlas() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let dir = std::env::temp_dir().join(format!("nusamai-atlas-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut usage = UvUsage::default();
+        let mut uris = Vec::new();
+        for (i, color) in [[255, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 255]]
+            .into_iter()
+            .enumerate()
+        {
+            let path = dir.join(format!("{i}.png"));
+            RgbaImage::from_pixel(100, 50, Rgba(color))
+                .save(&path)
+                .unwrap();
+            uris.push(Url::from_file_path(&path).unwrap());
+        }
+        // only the left half of the first image, the whole of the second,
+        // and the wrapping coordinates of the third (left out)
+        usage.add(&uris[0], [0.0, 0.0]);
+        usage.add(&uris[0], [0.5, 1.0]);
+        usage.add(&uris[1], [0.0, 0.0]);
+        usage.add(&uris[1], [1.0, 1.0]);
+        usage.add(&uris[2], [-1.0, 0.0]);
+        usage.add(&uris[2], [2.0, 1.0]);
+
+        let atlas = TextureAtlas::build(&feedback, usage).unwrap();
+        let first = atlas.placement(&uris[0]).unwrap();
+        let second = atlas.placement(&uris[1]).unwrap();
+        assert!(atlas.placement(&uris[2]).is_none());
+        assert_eq!(first.page_uri, second.page_uri);
+        // the first image is cropped to 52 x 50 pixels, placed next to the second one (100 x 50)
+        assert_eq!(first.page_size, [152., 50.]);
+        assert_eq!(second.map_uv([0., 0.]), [0., 0.]);
+        assert_eq!(second.map_uv([1., 1.]), [100. / 152., 1.]);
+        assert_eq!(first.map_uv([0.5, 0.5]), [150. / 152., 0.5]);
+
+        let page_path = first.page_uri.to_file_path().unwrap();
+        let page = image::open(&page_path).unwrap();
+        assert_eq!(page.get_pixel(120, 25), Rgba([255, 0, 0, 255]));
+        drop(atlas);
+        assert!(!page_path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,132 @@
+//! Mesh decimation for the tiles of coarser zoom levels
+
+use ahash::{HashMap, HashMapExt};
+use indexmap::IndexSet;
+use nusamai_geometry::algorithm::decimate::decimate_mesh;
+
+/// A triangle of a feature, ready to be written to a glTF primitive
+#[derive(Clone)]
+pub struct Triangle {
+    // corners [x, y, z, u, v]
+    pub corners: [[f64; 5]; 3],
+    pub normal: [f64; 3],
+    pub material_id: u32,
+}
+
+/// Simplifies the triangles of a feature within the given error (in metres).
+///
+/// Corners are shared only if they have the same position, texture coordinates and material,
+/// and the vertices on the seams between different materials or texture coordinates are kept
+/// in place so that the textures are not distorted. The triangles keep their original normals.
+pub fn decimate_triangles(triangles: &[Triangle], max_error: f64) -> Vec<Triangle> {
+    let mut vertices: IndexSet<[u64; 6], ahash::RandomState> = IndexSet::default();
+    let mut positions: HashMap<[u64; 3], u32> = HashMap::new();
+    let mut locked: Vec<bool> = Vec::new();
+
+    let indexed: Vec<[u32; 3]> = triangles
+        .iter()
+        .map(|tri| {
+            tri.corners.map(|[x, y, z, u, v]| {
+                let key = [x, y, z, u, v].map(f64::to_bits);
+                let (index, inserted) = vertices.insert_full([
+                    key[0],
+                    key[1],
+                    key[2],
+                    key[3],
+                    key[4],
+                    tri.material_id as u64,
+                ]);
+                if inserted {
+                    locked.push(false);
+                    let first = *positions
+                        .entry([key[0], key[1], key[2]])
+                        .or_insert(index as u32);
+                    if first != index as u32 {
+                        // The position is shared by different attributes (a seam)
+                        locked[first as usize] = true;
+                        locked[index] = true;
+                    }
+                }
+                index as u32
+            })
+        })
+        .collect();
+
+    let coords: Vec<[f64; 3]> = vertices
+        .iter()
+        .map(|&[x, y, z, ..]| [x, y, z].map(f64::from_bits))
+        .collect();
+
+    decimate_mesh(&coords, &indexed, &locked, max_error)
+        .into_iter()
+        .map(|(ti, tri)| {
+            let orig = &triangles[ti];
+            Triangle {
+                corners: tri.map(|idx| {
+                    let [x, y, z, u, v, _] = vertices[idx as usize];
+                    [x, y, z, u, v].map(f64::from_bits)
+                }),
+                normal: orig.normal,
+                material_id: orig.material_id,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(x: f64, material_id: u32) -> [Triangle; 2] {
+        let c = |dx: f64, y: f64| [x + dx, y, 0., (x + dx) / 8., y];
+        let normal = [0., 0., 1.];
+        [
+            Triangle {
+                corners: [c(0., 0.), c(1., 0.), c(1., 1.)],
+                normal,
+                material_id,
+            },
+            Triangle {
+                corners: [c(0., 0.), c(1., 1.), c(0., 1.)],
+                normal,
+                material_id,
+            },
+        ]
+    }
+
+    #[test]
+    fn keep_material_seams() {
+        // Two strips of coplanar quads with different materials
+        let triangles: Vec<Triangle> = (0..4)
+            .flat_map(|i| quad(i as f64, 0))
+            .chain((4..8).flat_map(|i| quad(i as f64, 1)))
+            .collect();
+
+        let result = decimate_triangles(&triangles, 0.01);
+        assert!(result.len() < triangles.len());
+        let area: f64 = result
+            .iter()
+            .map(|tri| {
+                let [a, b, c] = tri.corners;
+                ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) / 2.
+            })
+            .sum();
+        assert!((area - 8.).abs() < 1e-9);
+
+        // The seam at x = 4 is kept on both sides
+        for material_id in [0, 1] {
+            assert!(result
+                .iter()
+                .filter(|tri| tri.material_id == material_id)
+                .any(|tri| tri.corners.iter().any(|c| c[0] == 4.)));
+        }
+        for tri in &result {
+            for c in &tri.corners {
+                match tri.material_id {
+                    0 => assert!(c[0] <= 4.),
+                    _ => assert!(c[0] >= 4.),
+                }
+            }
+        }
+    }
+}
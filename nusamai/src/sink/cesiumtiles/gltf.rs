@@ -5,7 +5,12 @@ use byteorder::{ByteOrder, LittleEndian};
 use indexmap::IndexSet;
 use nusamai_gltf_json::extensions::mesh::ext_mesh_features;
 
-use super::{material, metadata::MetadataEncoder};
+use super::{
+    atlas::AtlasPrimitive,
+    material,
+    metadata::MetadataEncoder,
+    texture::{apply_webp_extension, TextureOptions},
+};
 use crate::pipeline::{feedback, PipelineError};
 
 #[derive(Default)]
@@ -14,8 +19,21 @@ pub struct PrimitiveInfo {
     pub feature_ids: HashSet<u32>,
 }
 
+impl AtlasPrimitive for PrimitiveInfo {
+    fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    fn merge(&mut self, other: Self, map_index: impl FnMut(u32) -> u32) {
+        self.feature_ids.extend(other.feature_ids);
+        self.indices
+            .extend(other.indices.into_iter().map(map_index));
+    }
+}
+
 pub type Primitives = HashMap<material::Material, PrimitiveInfo>;
 
+#[allow(clippy::too_many_arguments)]
 pub fn write_gltf_glb<W: Write>(
     feedback: &feedback::Feedback,
     writer: W,
@@ -24,6 +42,7 @@ pub fn write_gltf_glb<W: Write>(
     primitives: Primitives,
     num_features: usize,
     metadata_encoder: MetadataEncoder,
+    texture_options: &TextureOptions,
 ) -> Result<(), PipelineError> {
     use nusamai_gltf_json::*;
 
@@ -198,7 +217,7 @@ pub fn write_gltf_glb<W: Write>(
         .map(|material| material.to_gltf(&mut texture_set))
         .collect();
 
-    let gltf_textures: Vec<_> = texture_set
+    let mut gltf_textures: Vec<_> = texture_set
         .into_iter()
         .map(|t| t.to_gltf(&mut image_set))
         .collect();
@@ -207,10 +226,25 @@ pub fn write_gltf_glb<W: Write>(
         .into_iter()
         .map(|img| {
             feedback.ensure_not_canceled()?;
-            Ok(img.to_gltf(feedback, &mut gltf_buffer_views, &mut bin_content)?)
+            Ok(img.to_gltf(
+                feedback,
+                &mut gltf_buffer_views,
+                &mut bin_content,
+                texture_options,
+            )?)
         })
         .collect::<Result<Vec<Image>, PipelineError>>()?;
 
+    let mut extensions_used = vec![
+        "EXT_mesh_features".to_string(),
+        "EXT_structural_metadata".to_string(),
+    ];
+    let mut extensions_required = vec![];
+    if apply_webp_extension(&mut gltf_textures, &gltf_images) {
+        extensions_used.push("EXT_texture_webp".to_string());
+        extensions_required.push("EXT_texture_webp".to_string());
+    }
+
     let mut gltf_meshes = vec![];
     if !gltf_primitives.is_empty() {
         gltf_meshes.push(Mesh {
@@ -255,10 +289,8 @@ pub fn write_gltf_glb<W: Write>(
             ..Default::default()
         }
         .into(),
-        extensions_used: vec![
-            "EXT_mesh_features".to_string(),
-            "EXT_structural_metadata".to_string(),
-        ],
+        extensions_used,
+        extensions_required,
         ..Default::default()
     };
 
@@ -280,6 +280,7 @@ impl Class {
                             serde_json::Number::from(UINT64_NO_DATA),
                         )),
                     },
+                    description: prop.uom.as_ref().map(|uom| format!("unit: {uom}")),
                     ..Default::default()
                 },
             );
@@ -445,6 +446,8 @@ struct Property {
     used: bool,
     array_offsets: Vec<u32>,
     string_offsets: Vec<u32>,
+    /// Unit of measure of the values, if known
+    uom: Option<String>,
 }
 
 impl Property {
@@ -465,6 +468,7 @@ impl Property {
             used: false,
             string_offsets,
             array_offsets,
+            uom: None,
         }
     }
 }
@@ -489,7 +493,9 @@ impl From<&Attribute> for Property {
             TypeRef::Unknown => unreachable!(),
         };
         let is_array = attr.max_occurs != Some(1);
-        Property::new(type_, is_array)
+        let mut prop = Property::new(type_, is_array);
+        prop.uom = attr.uom.clone();
+        prop
     }
 }
 
@@ -1,10 +1,13 @@
 //! 3D Tiles sink
 
+pub(crate) mod atlas;
+mod decimate;
 mod gltf;
 mod material;
 pub(crate) mod metadata;
 mod slice;
 mod sort;
+pub(crate) mod texture;
 mod tiling;
 pub(crate) mod utils;
 
@@ -17,6 +20,8 @@ use std::{
 };
 
 use ahash::RandomState;
+use atlas::{apply_texture_atlas, TextureAtlas};
+use decimate::{decimate_triangles, Triangle};
 use earcut::{utils3d::project3d_to_2d, Earcut};
 use ext_sort::{buffer::mem::MemoryLimitedBufferBuilder, ExternalSorter, ExternalSorterBuilder};
 use gltf::write_gltf_glb;
@@ -29,6 +34,7 @@ use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use slice::{slice_to_tiles, SlicedFeature};
 use sort::BincodeExternalChunk;
+use texture::{MaxSizeByZoom, TextureOptions, DEFAULT_JPEG_QUALITY};
 use tiling::{TileContent, TileTree};
 
 use crate::{
@@ -62,6 +68,59 @@ impl DataSinkProvider for CesiumTilesSinkProvider {
                 }),
             },
         );
+        params.define(
+            "texture_atlas".into(),
+            ParameterEntry {
+                description: "Pack the textures of each tile into atlas images".into(),
+                required: false,
+                parameter: ParameterType::Boolean(BooleanParameter { value: Some(false) }),
+            },
+        );
+        params.define(
+            "texture_format".into(),
+            ParameterEntry {
+                description: "Convert the textures into the format (jpeg, png or lossless webp)"
+                    .into(),
+                required: false,
+                parameter: ParameterType::String(StringParameter { value: None }),
+            },
+        );
+        params.define(
+            "texture_quality".into(),
+            ParameterEntry {
+                description: format!(
+                    "Quality of the JPEG textures (1-100, default {DEFAULT_JPEG_QUALITY}). Cannot be set for WebP, which is lossless"
+                ),
+                required: false,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: None,
+                    min: Some(1),
+                    max: Some(100),
+                }),
+            },
+        );
+        params.define(
+            "texture_max_size".into(),
+            ParameterEntry {
+                description: "Downscale the textures to fit within the size (in pixels)".into(),
+                required: false,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: None,
+                    min: Some(1),
+                    max: None,
+                }),
+            },
+        );
+        params.define(
+            "texture_max_size_by_zoom".into(),
+            ParameterEntry {
+                description:
+                    "Maximum texture size for each zoom level, e.g. \"12=256,16=1024\" (overrides texture_max_size)"
+                        .into(),
+                required: false,
+                parameter: ParameterType::String(StringParameter { value: None }),
+            },
+        );
         // TODO: min Zoom
         // TODO: max Zoom
         params
@@ -69,15 +128,51 @@ impl DataSinkProvider for CesiumTilesSinkProvider {
 
     fn create(&self, params: &Parameters) -> Box<dyn DataSink> {
         let output_path = get_parameter_value!(params, "@output", FileSystemPath);
+        let texture_atlas = get_parameter_value!(params, "texture_atlas", Boolean).unwrap_or(false);
+        let texture_format = get_parameter_value!(params, "texture_format", String);
+        let texture_quality = get_parameter_value!(params, "texture_quality", Integer);
+        let texture_max_size = get_parameter_value!(params, "texture_max_size", Integer);
+        let texture_max_size_by_zoom =
+            get_parameter_value!(params, "texture_max_size_by_zoom", String);
 
         Box::<CesiumTilesSink>::new(CesiumTilesSink {
             output_path: output_path.as_ref().unwrap().into(),
+            texture_atlas,
+            texture_format: texture_format.clone(),
+            texture_quality: *texture_quality,
+            texture_max_size: *texture_max_size,
+            texture_max_size_by_zoom: texture_max_size_by_zoom.clone(),
         })
     }
 }
 
 struct CesiumTilesSink {
     output_path: PathBuf,
+    /// Whether to pack the textures into atlas images
+    texture_atlas: bool,
+    // Texture encoding parameters (validated when the sink runs)
+    texture_format: Option<String>,
+    texture_quality: Option<i64>,
+    texture_max_size: Option<i64>,
+    texture_max_size_by_zoom: Option<String>,
+}
+
+/// How the textures of the tiles are written
+struct TextureSettings {
+    /// Whether to pack the textures into atlas images
+    atlas: bool,
+    options: TextureOptions,
+    max_size_by_zoom: MaxSizeByZoom,
+}
+
+impl TextureSettings {
+    /// Returns the encoding options for the tiles of the zoom level.
+    fn options_for_zoom(&self, zoom: u8) -> TextureOptions {
+        TextureOptions {
+            max_size: self.max_size_by_zoom.get(zoom).or(self.options.max_size),
+            ..self.options.clone()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, deepsize::DeepSizeOf)]
@@ -99,6 +194,22 @@ impl DataSink for CesiumTilesSink {
     }
 
     fn run(&mut self, upstream: Receiver, feedback: &Feedback, schema: &Schema) -> Result<()> {
+        let texture_settings = TextureSettings {
+            atlas: self.texture_atlas,
+            options: TextureOptions::from_parameters(
+                self.texture_format.as_deref(),
+                self.texture_quality,
+                self.texture_max_size,
+            )
+            .map_err(PipelineError::Other)?,
+            max_size_by_zoom: self
+                .texture_max_size_by_zoom
+                .as_deref()
+                .unwrap_or_default()
+                .parse()
+                .map_err(PipelineError::Other)?,
+        };
+
         let (sender_sliced, receiver_sliced) = mpsc::sync_channel(2000);
         let (sender_sorted, receiver_sorted) = mpsc::sync_channel(2000);
 
@@ -141,6 +252,7 @@ impl DataSink for CesiumTilesSink {
             // Group sorted features and write them into tiles
             {
                 let output_path = &self.output_path;
+                let texture_settings = &texture_settings;
                 s.spawn(move || {
                     // Run in a separate thread pool to avoid deadlocks
                     let pool = rayon::ThreadPoolBuilder::new()
@@ -154,6 +266,8 @@ impl DataSink for CesiumTilesSink {
                             receiver_sorted,
                             tile_id_conv,
                             schema,
+                            max_zoom,
+                            texture_settings,
                         ) {
                             feedback.fatal_error(error);
                         }
@@ -253,6 +367,8 @@ fn tile_writing_stage(
     receiver_sorted: mpsc::Receiver<((u64, String), Vec<SerializedSlicedFeature>)>,
     tile_id_conv: TileIdMethod,
     schema: &Schema,
+    max_zoom: u8,
+    texture_settings: &TextureSettings,
 ) -> Result<()> {
     let ellipsoid = nusamai_projection::ellipsoid::wgs84();
     let contents: Arc<Mutex<Vec<TileContent>>> = Default::default();
@@ -266,7 +382,7 @@ fn tile_writing_stage(
             feedback.ensure_not_canceled()?;
 
             // Tile information
-            let (mut content, translation) = {
+            let (mut content, translation, geom_error) = {
                 let zxy = tile_id_conv.id_to_zxy(tile_id);
                 let (tile_zoom, tile_x, tile_y) = zxy;
                 let (min_lat, max_lat) = tiling::y_slice_range(tile_zoom, tile_y);
@@ -301,9 +417,12 @@ fn tile_writing_stage(
                     max_height: f64::MIN,
                 };
 
-                (content, translation)
+                (content, translation, geom_error)
             };
 
+            // Simplify the meshes of the coarser zoom levels within the geometric error
+            let decimation_error = (content.zxy.0 < max_zoom).then_some(geom_error);
+
             let mut earcutter = Earcut::new();
             let mut buf3d: Vec<[f64; 3]> = Vec::new();
             let mut buf2d: Vec<[f64; 2]> = Vec::new(); // 2d-projected [x, y]
@@ -358,16 +477,13 @@ fn tile_writing_stage(
                 }
 
                 // Triangulation, etc.
+                let mut triangles: Vec<Triangle> = Vec::new();
                 for (poly, orig_mat_id) in feature.polygons.iter().zip_eq(feature.polygon_material_ids.iter()) {
                     let num_outer_points = match poly.hole_indices().first() {
                         Some(&v) => v as usize,
                         None => poly.raw_coords().len(),
                     };
 
-                    let mat = feature.materials[*orig_mat_id as usize].clone();
-                    let primitive = primitives.entry(mat).or_default();
-                    primitive.feature_ids.insert(feature_id as u32);
-
                     if let Some((nx, ny, nz)) = calculate_normal(
                         poly.exterior().iter().map(|v| [v[0], v[1], v[2]])
                     ) {
@@ -379,35 +495,59 @@ fn tile_writing_stage(
                             earcutter.earcut(buf2d.iter().cloned(), poly.hole_indices(),  &mut index_buf);
 
                             // collect triangles
-                            primitive.indices.extend(index_buf.iter().map(|&idx| {
-                                let [x, y, z, u, v] = poly.raw_coords()[idx as usize];
-                                let vbits = [
-                                    (x as f32).to_bits(),
-                                    (y as f32).to_bits(),
-                                    (z as f32).to_bits(),
-                                    (nx as f32).to_bits(),
-                                    (ny as f32).to_bits(),
-                                    (nz as f32).to_bits(),
-                                    (u as f32).to_bits(),
-                                    (v as f32).to_bits(),
-                                    (feature_id as f32).to_bits(), // UNSIGNED_INT can't be used for vertex attribute
-                                ];
-                                let (index, _) = vertices.insert_full(vbits);
-                                index as u32
+                            triangles.extend(index_buf.chunks_exact(3).map(|idx| Triangle {
+                                corners: [0, 1, 2].map(|i| poly.raw_coords()[idx[i] as usize]),
+                                normal: [nx, ny, nz],
+                                material_id: *orig_mat_id,
                             }));
                         }
                     }
                 }
 
+                if let Some(max_error) = decimation_error {
+                    triangles = decimate_triangles(&triangles, max_error);
+                }
+
+                for tri in &triangles {
+                    let mat = feature.materials[tri.material_id as usize].clone();
+                    let primitive = primitives.entry(mat).or_default();
+                    primitive.feature_ids.insert(feature_id as u32);
+
+                    let [nx, ny, nz] = tri.normal;
+                    primitive.indices.extend(tri.corners.iter().map(|&[x, y, z, u, v]| {
+                        let vbits = [
+                            (x as f32).to_bits(),
+                            (y as f32).to_bits(),
+                            (z as f32).to_bits(),
+                            (nx as f32).to_bits(),
+                            (ny as f32).to_bits(),
+                            (nz as f32).to_bits(),
+                            (u as f32).to_bits(),
+                            (v as f32).to_bits(),
+                            (feature_id as f32).to_bits(), // UNSIGNED_INT can't be used for vertex attribute
+                        ];
+                        let (index, _) = vertices.insert_full(vbits);
+                        index as u32
+                    }));
+                }
+
                 feature_id += 1;
             }
 
+            // Pack the textures into atlases (kept until the glb is written)
+            let (vertices, primitives, _atlas) = if texture_settings.atlas {
+                apply_texture_atlas(feedback, vertices, primitives)?
+            } else {
+                (vertices, primitives, TextureAtlas::default())
+            };
+
             // Write to file
             let path_glb = output_path.join(Path::new(&content.content_path));
             if let Some(dir) = path_glb.parent() {
                 fs::create_dir_all(dir)?;
             }
 
+            let texture_options = texture_settings.options_for_zoom(content.zxy.0);
             contents.lock().unwrap().push(content);
 
             let mut file = std::fs::File::create(path_glb)?;
@@ -419,6 +559,7 @@ fn tile_writing_stage(
                 primitives,
                 feature_id, // number of features
                 metadata_encoder,
+                &texture_options,
             )?;
 
             Ok::<(), PipelineError>(())
@@ -0,0 +1,356 @@
+//! Texture image encoding
+//!
+//! The texture images are embedded into the glTF files, optionally downscaled and converted into
+//! a format that web browsers can display.
+
+use std::{
+    io::{self, Cursor},
+    path::Path,
+    str::FromStr,
+    time::Instant,
+};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, GenericImageView,
+};
+use nusamai_gltf_json::{extensions, Image, MimeType, Texture};
+
+use crate::pipeline::Feedback;
+
+/// Default JPEG quality of the re-encoded images
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// Image format of the embedded textures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    Jpeg,
+    Png,
+    /// Lossless WebP (requires the `EXT_texture_webp` extension)
+    Webp,
+}
+
+impl FromStr for TextureFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            "webp" => Ok(Self::Webp),
+            _ => Err(format!(
+                "Unknown texture format: {s:?} (expected jpeg, png or webp)"
+            )),
+        }
+    }
+}
+
+impl TextureFormat {
+    fn mime_type(&self) -> MimeType {
+        match self {
+            Self::Jpeg => MimeType::ImageJpeg,
+            Self::Png => MimeType::ImagePng,
+            Self::Webp => MimeType::ImageWebp,
+        }
+    }
+}
+
+/// How the texture images are embedded
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureOptions {
+    /// Target format. If `None`, JPEG images are kept as JPEG and the others are converted into PNG.
+    pub format: Option<TextureFormat>,
+    /// Quality of JPEG encoding (1-100). WebP is always encoded losslessly.
+    pub quality: u8,
+    /// Maximum width and height (in pixels). Larger images are downscaled, keeping the aspect ratio.
+    pub max_size: Option<u32>,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            quality: DEFAULT_JPEG_QUALITY,
+            max_size: None,
+        }
+    }
+}
+
+impl TextureOptions {
+    /// Makes the options from the sink parameters.
+    pub fn from_parameters(
+        format: Option<&str>,
+        quality: Option<i64>,
+        max_size: Option<i64>,
+    ) -> Result<Self, String> {
+        let format = format
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
+            .transpose()?;
+        if format == Some(TextureFormat::Webp) && quality.is_some() {
+            return Err(
+                "The texture quality cannot be set for WebP, which is encoded losslessly".into(),
+            );
+        }
+        Ok(Self {
+            format,
+            quality: quality.map_or(DEFAULT_JPEG_QUALITY, |q| q.clamp(1, 100) as u8),
+            max_size: max_size.map(|s| s.clamp(1, u32::MAX as i64) as u32),
+        })
+    }
+}
+
+/// Maximum texture sizes for each zoom level, given as `"<zoom>=<size>,..."` (e.g. `"12=256,16=1024"`)
+///
+/// A tile uses the entry of the highest zoom level that is not greater than its own zoom level.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaxSizeByZoom(Vec<(u8, u32)>);
+
+impl FromStr for MaxSizeByZoom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = s
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let invalid = || format!("Invalid texture size for a zoom level: {entry:?}");
+                let (zoom, size) = entry.split_once('=').ok_or_else(invalid)?;
+                let zoom = zoom.trim().parse::<u8>().map_err(|_| invalid())?;
+                let size = size.trim().parse::<u32>().map_err(|_| invalid())?;
+                if size == 0 {
+                    return Err(invalid());
+                }
+                Ok((zoom, size))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        entries.sort_by_key(|&(zoom, _)| zoom);
+        Ok(Self(entries))
+    }
+}
+
+impl MaxSizeByZoom {
+    /// Returns the maximum texture size for the zoom level, if specified.
+    pub fn get(&self, zoom: u8) -> Option<u32> {
+        self.0
+            .iter()
+            .rev()
+            .find(|&&(z, _)| z <= zoom)
+            .map(|&(_, size)| size)
+    }
+}
+
+/// Loads an image file and encodes it for embedding into glTF.
+pub fn load_image(
+    feedback: &Feedback,
+    path: &Path,
+    options: &TextureOptions,
+) -> io::Result<(Vec<u8>, MimeType)> {
+    let to_io_error = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let is_jpeg = match path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("jpg" | "jpeg") => true,
+        Some("tif" | "tiff" | "png") => false,
+        _ => {
+            let err = format!("Unsupported image format: {:?}", path);
+            log::error!("{}", err);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+    };
+    let format = options.format.unwrap_or(if is_jpeg {
+        TextureFormat::Jpeg
+    } else {
+        TextureFormat::Png
+    });
+
+    if is_jpeg && format == TextureFormat::Jpeg {
+        let fits = match options.max_size {
+            Some(max_size) => {
+                let (width, height) = image::image_dimensions(path).map_err(to_io_error)?;
+                width <= max_size && height <= max_size
+            }
+            None => true,
+        };
+        if fits {
+            feedback.info(format!("Embedding a jpeg as is: {:?}", path));
+            return Ok((std::fs::read(path)?, MimeType::ImageJpeg));
+        }
+    }
+
+    feedback.info(format!("Decoding image: {:?}", path));
+    let t = Instant::now();
+    let image = image::open(path).map_err(to_io_error)?;
+    feedback.info(format!("Image decoding took {:?}", t.elapsed()));
+
+    let image = match options.max_size {
+        Some(max_size) => downscale(image, max_size),
+        None => image,
+    };
+    Ok((
+        encode_image(&image, format, options.quality)?,
+        format.mime_type(),
+    ))
+}
+
+/// Downscales the image to fit within `max_size` x `max_size`, keeping the aspect ratio.
+fn downscale(image: DynamicImage, max_size: u32) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    if width <= max_size && height <= max_size {
+        image
+    } else {
+        image.resize(max_size, max_size, FilterType::Triangle)
+    }
+}
+
+fn encode_image(image: &DynamicImage, format: TextureFormat, quality: u8) -> io::Result<Vec<u8>> {
+    let to_io_error = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+    let mut writer = Cursor::new(Vec::new());
+    match format {
+        TextureFormat::Jpeg => {
+            // JPEG has no alpha channel
+            let encoder = JpegEncoder::new_with_quality(&mut writer, quality);
+            image.to_rgb8().write_with_encoder(encoder)
+        }
+        TextureFormat::Png => image.write_with_encoder(PngEncoder::new(&mut writer)),
+        TextureFormat::Webp => {
+            // The WebP encoder supports 8-bit colors only
+            let encoder = WebPEncoder::new_lossless(&mut writer);
+            if image.color().has_alpha() {
+                image.to_rgba8().write_with_encoder(encoder)
+            } else {
+                image.to_rgb8().write_with_encoder(encoder)
+            }
+        }
+    }
+    .map_err(to_io_error)?;
+    Ok(writer.into_inner())
+}
+
+/// Makes the textures refer to their WebP images through the `EXT_texture_webp` extension.
+///
+/// Returns whether any texture uses the extension.
+pub fn apply_webp_extension(textures: &mut [Texture], images: &[Image]) -> bool {
+    let mut used = false;
+    for texture in textures {
+        let Some(source) = texture.source else {
+            continue;
+        };
+        if images[source as usize].mime_type == Some(MimeType::ImageWebp) {
+            texture.source = None;
+            texture.extensions = Some(extensions::texture::Texture {
+                ext_texture_webp: Some(extensions::texture::ext_texture_webp::ExtTextureWebp {
+                    source,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+            used = true;
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::pipeline::feedback;
+
+    #[test]
+    fn parse_options() {
+        let options = TextureOptions::from_parameters(Some("JPG"), Some(70), Some(512)).unwrap();
+        assert_eq!(options.format, Some(TextureFormat::Jpeg));
+        assert_eq!(options.quality, 70);
+        assert_eq!(options.max_size, Some(512));
+        let options = TextureOptions::from_parameters(Some("WebP"), None, None).unwrap();
+        assert_eq!(options.format, Some(TextureFormat::Webp));
+        assert!(TextureOptions::from_parameters(Some("webp"), Some(70), None).is_err());
+        assert_eq!(
+            TextureOptions::from_parameters(Some(""), None, None).unwrap(),
+            TextureOptions::default()
+        );
+        assert!(TextureOptions::from_parameters(Some("gif"), None, None).is_err());
+    }
+
+    #[test]
+    fn max_size_by_zoom() {
+        let sizes: MaxSizeByZoom = "16=1024, 12=256".parse().unwrap();
+        assert_eq!(sizes.get(11), None);
+        assert_eq!(sizes.get(12), Some(256));
+        assert_eq!(sizes.get(15), Some(256));
+        assert_eq!(sizes.get(18), Some(1024));
+        assert_eq!("".parse::<MaxSizeByZoom>().unwrap().get(18), None);
+        assert!("12:256".parse::<MaxSizeByZoom>().is_err());
+        assert!("12=0".parse::<MaxSizeByZoom>().is_err());
+    }
+
+    #[test]
+    fn resize_and_encode() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let path = std::env::temp_dir().join(format!("nusamai-texture-{}.png", std::process::id()));
+        RgbaImage::from_pixel(400, 100, Rgba([0, 128, 255, 255]))
+            .save(&path)
+            .unwrap();
+
+        // PNG is kept as PNG by default
+        let (content, mime_type) = load_image(&feedback, &path, &Default::default()).unwrap();
+        assert_eq!(mime_type, MimeType::ImagePng);
+        assert_eq!(
+            image::load_from_memory(&content).unwrap().dimensions(),
+            (400, 100)
+        );
+
+        for (format, mime_type) in [
+            (TextureFormat::Jpeg, MimeType::ImageJpeg),
+            (TextureFormat::Webp, MimeType::ImageWebp),
+        ] {
+            let options = TextureOptions {
+                format: Some(format),
+                max_size: Some(200),
+                ..Default::default()
+            };
+            let (content, actual) = load_image(&feedback, &path, &options).unwrap();
+            assert_eq!(actual, mime_type);
+            let decoded = image::load_from_memory(&content).unwrap();
+            assert_eq!(decoded.dimensions(), (200, 50));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn webp_extension() {
+        let mut textures = vec![
+            Texture {
+                source: Some(0),
+                ..Default::default()
+            },
+            Texture {
+                source: Some(1),
+                ..Default::default()
+            },
+        ];
+        let images = vec![
+            Image {
+                mime_type: Some(MimeType::ImageJpeg),
+                ..Default::default()
+            },
+            Image {
+                mime_type: Some(MimeType::ImageWebp),
+                ..Default::default()
+            },
+        ];
+        assert!(apply_webp_extension(&mut textures, &images));
+        assert_eq!(textures[0].source, Some(0));
+        assert_eq!(textures[1].source, None);
+        let ext = textures[1].extensions.as_ref().unwrap();
+        assert_eq!(ext.ext_texture_webp.as_ref().unwrap().source, 1);
+    }
+}
@@ -11,7 +11,10 @@ use nusamai_gltf_json::extensions::mesh::ext_mesh_features;
 use super::{material, Primitives};
 use crate::{
     pipeline::{feedback, PipelineError},
-    sink::cesiumtiles::metadata,
+    sink::cesiumtiles::{
+        metadata,
+        texture::{apply_webp_extension, TextureOptions},
+    },
 };
 
 pub fn write_gltf_glb<W: Write>(
@@ -21,6 +24,7 @@ pub fn write_gltf_glb<W: Write>(
     vertices: impl IntoIterator<Item = [u32; 9]>,
     primitives: Primitives,
     metadata_encoder: metadata::MetadataEncoder,
+    texture_options: &TextureOptions,
 ) -> Result<(), PipelineError> {
     use nusamai_gltf_json::*;
 
@@ -195,7 +199,7 @@ pub fn write_gltf_glb<W: Write>(
         .map(|material| material.to_gltf(&mut texture_set))
         .collect();
 
-    let gltf_textures: Vec<_> = texture_set
+    let mut gltf_textures: Vec<_> = texture_set
         .into_iter()
         .map(|t| t.to_gltf(&mut image_set))
         .collect();
@@ -204,10 +208,25 @@ pub fn write_gltf_glb<W: Write>(
         .into_iter()
         .map(|img| {
             feedback.ensure_not_canceled()?;
-            Ok(img.to_gltf(feedback, &mut gltf_buffer_views, &mut bin_content)?)
+            Ok(img.to_gltf(
+                feedback,
+                &mut gltf_buffer_views,
+                &mut bin_content,
+                texture_options,
+            )?)
         })
         .collect::<Result<Vec<Image>, PipelineError>>()?;
 
+    let mut extensions_used = vec![
+        "EXT_mesh_features".to_string(),
+        "EXT_structural_metadata".to_string(),
+    ];
+    let mut extensions_required = vec![];
+    if apply_webp_extension(&mut gltf_textures, &gltf_images) {
+        extensions_used.push("EXT_texture_webp".to_string());
+        extensions_required.push("EXT_texture_webp".to_string());
+    }
+
     let mut gltf_meshes = vec![];
     if !gltf_primitives.is_empty() {
         gltf_meshes.push(Mesh {
@@ -252,11 +271,8 @@ pub fn write_gltf_glb<W: Write>(
             ..Default::default()
         }
         .into(),
-        extensions_used: vec![
-            "EXT_mesh_features".to_string(),
-            "EXT_structural_metadata".to_string(),
-            "EXT_texture_webp".to_string(),
-        ],
+        extensions_used,
+        extensions_required,
         ..Default::default()
     };
 
@@ -1,13 +1,19 @@
 //! Material mangement
 
-use std::{hash::Hash, path::Path, time::Instant};
+use std::hash::Hash;
 
 use indexmap::IndexSet;
-use nusamai_gltf_json::{BufferView, MimeType};
+use nusamai_gltf_json::BufferView;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::pipeline::Feedback;
+use crate::{
+    pipeline::Feedback,
+    sink::cesiumtiles::{
+        atlas::AtlasMaterial,
+        texture::{load_image, TextureOptions},
+    },
+};
 
 #[derive(Debug, Serialize, Clone, PartialEq, Deserialize)]
 pub struct Material {
@@ -25,6 +31,19 @@ impl Hash for Material {
     }
 }
 
+impl AtlasMaterial for Material {
+    fn texture_uri(&self) -> Option<&Url> {
+        self.base_texture.as_ref().map(|texture| &texture.uri)
+    }
+
+    fn with_texture_uri(self, uri: Url) -> Self {
+        Material {
+            base_texture: Some(Texture { uri }),
+            ..self
+        }
+    }
+}
+
 impl Material {
     pub fn to_gltf(
         &self,
@@ -84,10 +103,10 @@ impl Image {
         feedback: &Feedback,
         buffer_views: &mut Vec<BufferView>,
         bin_content: &mut Vec<u8>,
+        options: &TextureOptions,
     ) -> std::io::Result<nusamai_gltf_json::Image> {
         if let Ok(path) = self.uri.to_file_path() {
-            // NOTE: temporary implementation
-            let (content, mime_type) = load_image(feedback, &path)?;
+            let (content, mime_type) = load_image(feedback, &path, options)?;
 
             buffer_views.push(BufferView {
                 byte_offset: bin_content.len() as u32,
@@ -111,44 +130,6 @@ impl Image {
     }
 }
 
-// NOTE: temporary implementation
-fn load_image(feedback: &Feedback, path: &Path) -> std::io::Result<(Vec<u8>, MimeType)> {
-    if let Some(ext) = path.extension() {
-        match ext.to_ascii_lowercase().to_str() {
-            Some("tif" | "tiff" | "png") => {
-                feedback.info(format!("Decoding image: {:?}", path));
-                let t = Instant::now();
-                let image = image::open(path)
-                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
-                feedback.debug(format!("Image decoding took {:?}", t.elapsed()));
-
-                let t = Instant::now();
-                let mut writer = std::io::Cursor::new(Vec::new());
-                // let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut writer);
-                let encoder = image::codecs::png::PngEncoder::new(&mut writer);
-                image
-                    .write_with_encoder(encoder)
-                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
-                log::debug!("Image encoding took {:?}", t.elapsed());
-
-                Ok((writer.into_inner(), MimeType::ImagePng))
-            }
-            Some("jpg" | "jpeg") => {
-                feedback.info(format!("Embedding a jpeg as is: {:?}", path));
-                Ok((std::fs::read(path)?, MimeType::ImageJpeg))
-            }
-            _ => {
-                let err = format!("Unsupported image format: {:?}", path);
-                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
-            }
-        }
-    } else {
-        let err = format!("Unsupported image format: {:?}", path);
-        log::error!("{}", err);
-        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
-    }
-}
-
 fn to_f64x4(c: [f32; 4]) -> [f64; 4] {
     [
         f64::from(c[0]),
@@ -4,7 +4,11 @@ mod material;
 
 use std::{fs::File, io::BufWriter, path::PathBuf, sync::Mutex};
 
-use crate::sink::cesiumtiles::utils::calculate_normal;
+use crate::sink::cesiumtiles::{
+    atlas::{apply_texture_atlas, AtlasPrimitive, TextureAtlas},
+    texture::{TextureOptions, DEFAULT_JPEG_QUALITY},
+    utils::calculate_normal,
+};
 use ahash::{HashMap, HashSet, RandomState};
 use earcut::{utils3d::project3d_to_2d, Earcut};
 use gltf_writer::{write_3dtiles, write_gltf_glb};
@@ -48,20 +52,77 @@ impl DataSinkProvider for GltfSinkProvider {
                 }),
             },
         );
+        params.define(
+            "texture_atlas".into(),
+            ParameterEntry {
+                description: "Pack the textures of each mesh into atlas images".into(),
+                required: false,
+                parameter: ParameterType::Boolean(BooleanParameter { value: Some(false) }),
+            },
+        );
+        params.define(
+            "texture_format".into(),
+            ParameterEntry {
+                description: "Convert the textures into the format (jpeg, png or lossless webp)"
+                    .into(),
+                required: false,
+                parameter: ParameterType::String(StringParameter { value: None }),
+            },
+        );
+        params.define(
+            "texture_quality".into(),
+            ParameterEntry {
+                description: format!(
+                    "Quality of the JPEG textures (1-100, default {DEFAULT_JPEG_QUALITY}). Cannot be set for WebP, which is lossless"
+                ),
+                required: false,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: None,
+                    min: Some(1),
+                    max: Some(100),
+                }),
+            },
+        );
+        params.define(
+            "texture_max_size".into(),
+            ParameterEntry {
+                description: "Downscale the textures to fit within the size (in pixels)".into(),
+                required: false,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: None,
+                    min: Some(1),
+                    max: None,
+                }),
+            },
+        );
         params
     }
 
     fn create(&self, params: &Parameters) -> Box<dyn DataSink> {
         let output_path = get_parameter_value!(params, "@output", FileSystemPath);
+        let texture_atlas = get_parameter_value!(params, "texture_atlas", Boolean).unwrap_or(false);
+        let texture_format = get_parameter_value!(params, "texture_format", String);
+        let texture_quality = get_parameter_value!(params, "texture_quality", Integer);
+        let texture_max_size = get_parameter_value!(params, "texture_max_size", Integer);
 
         Box::<GltfSink>::new(GltfSink {
             output_path: output_path.as_ref().unwrap().into(),
+            texture_atlas,
+            texture_format: texture_format.clone(),
+            texture_quality: *texture_quality,
+            texture_max_size: *texture_max_size,
         })
     }
 }
 
 pub struct GltfSink {
     output_path: PathBuf,
+    /// Whether to pack the textures into atlas images
+    texture_atlas: bool,
+    // Texture encoding parameters (validated when the sink runs)
+    texture_format: Option<String>,
+    texture_quality: Option<i64>,
+    texture_max_size: Option<i64>,
 }
 
 pub struct BoundingVolume {
@@ -125,6 +186,18 @@ pub struct PrimitiveInfo {
     pub feature_ids: HashSet<u32>,
 }
 
+impl AtlasPrimitive for PrimitiveInfo {
+    fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    fn merge(&mut self, other: Self, map_index: impl FnMut(u32) -> u32) {
+        self.feature_ids.extend(other.feature_ids);
+        self.indices
+            .extend(other.indices.into_iter().map(map_index));
+    }
+}
+
 pub type Primitives = HashMap<material::Material, PrimitiveInfo>;
 
 impl DataSink for GltfSink {
@@ -138,6 +211,13 @@ impl DataSink for GltfSink {
     }
 
     fn run(&mut self, upstream: Receiver, feedback: &Feedback, schema: &Schema) -> Result<()> {
+        let texture_options = TextureOptions::from_parameters(
+            self.texture_format.as_deref(),
+            self.texture_quality,
+            self.texture_max_size,
+        )
+        .map_err(PipelineError::Other)?;
+
         let ellipsoid = nusamai_projection::ellipsoid::wgs84();
 
         let classified_features: Mutex<ClassifiedFeatures> = Default::default();
@@ -392,6 +472,13 @@ impl DataSink for GltfSink {
                     feature_id += 1;
                 }
 
+                // Pack the textures into atlases (kept until the glb is written)
+                let (vertices, primitives, _atlas) = if self.texture_atlas {
+                    apply_texture_atlas(feedback, vertices, primitives)?
+                } else {
+                    (vertices, primitives, TextureAtlas::default())
+                };
+
                 // Ensure that the parent directory exists
                 std::fs::create_dir_all(&self.output_path)?;
 
@@ -420,6 +507,7 @@ impl DataSink for GltfSink {
                     vertices,
                     primitives,
                     metadata_encoder,
+                    &texture_options,
                 )?;
 
                 Ok::<(), PipelineError>(())
@@ -1,55 +1,250 @@
 use indexmap::IndexMap;
-use nusamai_citygml::object::{Object, Value};
+use nusamai_citygml::object::{Object, ObjectStereotype, Value};
+use nusamai_gpkg::table::TableInfo;
+
+use super::table::{mapping_table_name, value_table_name};
+
+/// A record of an attribute table, related to the record of its parent (GeoPackage Related Tables Extension)
+#[derive(Debug, PartialEq)]
+pub struct RelatedRecord {
+    pub table_name: String,
+    pub mapping_table_name: String,
+    pub attributes: IndexMap<String, String>,
+    pub related: Vec<RelatedRecord>,
+}
 
 /// Prepare the attribute values for the GeoPackage
 pub fn prepare_object_attributes(obj: &Object) -> IndexMap<String, String> {
     let mut attributes = IndexMap::<String, String>::new();
 
+    for (attr_name, attr_value) in &obj.attributes {
+        prepare_value(attr_name, attr_value, &mut attributes);
+    }
+
+    attributes
+}
+
+fn prepare_value(attr_name: &str, attr_value: &Value, attributes: &mut IndexMap<String, String>) {
+    match attr_value {
+        Value::String(s) => {
+            attributes.insert(attr_name.into(), s.into());
+        }
+        Value::Code(c) => {
+            // value of the code
+            attributes.insert(attr_name.into(), c.value().into());
+        }
+        Value::Integer(i) => {
+            attributes.insert(attr_name.into(), i.to_string());
+        }
+        Value::NonNegativeInteger(i) => {
+            attributes.insert(attr_name.into(), i.to_string());
+        }
+        Value::Double(d) => {
+            attributes.insert(attr_name.into(), d.to_string());
+        }
+        Value::Measure(m) => {
+            attributes.insert(attr_name.into(), m.value().to_string());
+        }
+        Value::Boolean(b) => {
+            // 0 for false and 1 for true in SQLite
+            attributes.insert(attr_name.into(), if *b { "1".into() } else { "0".into() });
+        }
+        Value::Uri(u) => {
+            // value of the URI
+            attributes.insert(attr_name.into(), u.value().to_string());
+        }
+        Value::Date(d) => {
+            // Date represented as an ISO8601 string
+            attributes.insert(attr_name.into(), d.to_string());
+        }
+        Value::Point(_p) => {
+            // TODO: implement
+            // Point struct currently does not contain any data
+        }
+        Value::Array(_) | Value::Object(_) => {
+            // Jsonified in the earlier step, or stored in the related tables (see `prepare_related_attributes`)
+        }
+    };
+}
+
+/// Prepare the attribute values for the GeoPackage, separating nested data and multiple values into related records
+///
+/// Objects and arrays are stored as JSON if the table has a column for them (see `schema_to_table_infos`).
+pub fn prepare_related_attributes(
+    obj: &Object,
+    table: &TableInfo,
+    table_infos: &IndexMap<String, TableInfo>,
+) -> (IndexMap<String, String>, Vec<RelatedRecord>) {
+    let mut attributes = IndexMap::<String, String>::new();
+    let mut related = Vec::new();
+
     for (attr_name, attr_value) in &obj.attributes {
         match attr_value {
-            Value::String(s) => {
-                attributes.insert(attr_name.into(), s.into());
-            }
-            Value::Code(c) => {
-                // value of the code
-                attributes.insert(attr_name.into(), c.value().into());
-            }
-            Value::Integer(i) => {
-                attributes.insert(attr_name.into(), i.to_string());
-            }
-            Value::NonNegativeInteger(i) => {
-                attributes.insert(attr_name.into(), i.to_string());
-            }
-            Value::Double(d) => {
-                attributes.insert(attr_name.into(), d.to_string());
-            }
-            Value::Measure(m) => {
-                attributes.insert(attr_name.into(), m.value().to_string());
-            }
-            Value::Boolean(b) => {
-                // 0 for false and 1 for true in SQLite
-                attributes.insert(attr_name.into(), if *b { "1".into() } else { "0".into() });
-            }
-            Value::Uri(u) => {
-                // value of the URI
-                attributes.insert(attr_name.into(), u.value().to_string());
+            Value::Object(_) | Value::Array(_)
+                if table.columns.iter().any(|column| &column.name == attr_name) =>
+            {
+                attributes.insert(attr_name.into(), attr_value.to_attribute_json().to_string());
             }
-            Value::Date(d) => {
-                // Date represented as an ISO8601 string
-                attributes.insert(attr_name.into(), d.to_string());
+            Value::Object(_) | Value::Array(_) => {
+                let values = match attr_value {
+                    Value::Array(arr) => arr.as_slice(),
+                    value => std::slice::from_ref(value),
+                };
+                for value in values {
+                    match value {
+                        Value::Object(child) => {
+                            let Some(child_table) = table_infos.get(child.typename.as_ref()) else {
+                                continue;
+                            };
+                            // Child features are flattened in the earlier step
+                            if !matches!(child.stereotype, ObjectStereotype::Data) {
+                                continue;
+                            }
+                            let (attributes, grandchildren) =
+                                prepare_related_attributes(child, child_table, table_infos);
+                            related.push(RelatedRecord {
+                                table_name: child_table.name.clone(),
+                                mapping_table_name: mapping_table_name(
+                                    &table.name,
+                                    attr_name,
+                                    &child_table.name,
+                                ),
+                                attributes,
+                                related: grandchildren,
+                            });
+                        }
+                        value => {
+                            let table_name = value_table_name(&table.name, attr_name);
+                            if !table_infos.contains_key(&table_name) {
+                                continue;
+                            }
+                            let mut attributes = IndexMap::<String, String>::new();
+                            prepare_value("value", value, &mut attributes);
+                            related.push(RelatedRecord {
+                                mapping_table_name: mapping_table_name(
+                                    &table.name,
+                                    attr_name,
+                                    "value",
+                                ),
+                                table_name,
+                                attributes,
+                                related: vec![],
+                            });
+                        }
+                    }
+                }
             }
-            Value::Point(_p) => {
-                // TODO: implement
-                // Point struct currently does not contain any data
-            }
-            Value::Array(_arr) => {
-                // TODO: handle multiple values
-            }
-            Value::Object(_obj) => {
-                // TODO: handle nested objects
-            }
-        };
+            _ => prepare_value(attr_name, attr_value, &mut attributes),
+        }
     }
 
-    attributes
+    (attributes, related)
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::object::Map;
+    use nusamai_gpkg::table::ColumnInfo;
+
+    use super::*;
+
+    fn table(name: &str, columns: &[&str]) -> (String, TableInfo) {
+        (
+            name.into(),
+            TableInfo {
+                name: name.into(),
+                geometry: None,
+                columns: columns
+                    .iter()
+                    .map(|name| ColumnInfo {
+                        name: name.to_string(),
+                        data_type: "TEXT".into(),
+                        mime_type: None,
+                        title: None,
+                        description: None,
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    fn data(typename: &str, attributes: Map) -> Value {
+        Value::Object(Object {
+            typename: typename.to_string().into(),
+            stereotype: ObjectStereotype::Data,
+            attributes,
+        })
+    }
+
+    #[test]
+    fn test_prepare_related_attributes() {
+        let table_infos: IndexMap<String, TableInfo> = IndexMap::from([
+            table("feature", &["text", "generic"]),
+            table("feature_names", &["value"]),
+            table("detail", &["rank"]),
+            table("generic", &[]),
+        ]);
+
+        let mut attributes = Map::default();
+        attributes.insert("text".into(), Value::String("foo".into()));
+        attributes.insert(
+            "names".into(),
+            Value::Array(vec![Value::String("a".into()), Value::String("b".into())]),
+        );
+        attributes.insert(
+            "detail".into(),
+            data(
+                "detail",
+                Map::from_iter([("rank".into(), Value::Integer(1))]),
+            ),
+        );
+        attributes.insert(
+            "generic".into(),
+            data(
+                "generic",
+                Map::from_iter([("key".into(), Value::String("value".into()))]),
+            ),
+        );
+        let obj = Object {
+            typename: "feature".into(),
+            stereotype: ObjectStereotype::Feature {
+                id: "id_1".into(),
+                geometries: vec![],
+            },
+            attributes,
+        };
+
+        let (attributes, related) =
+            prepare_related_attributes(&obj, table_infos.get("feature").unwrap(), &table_infos);
+        assert_eq!(
+            attributes,
+            IndexMap::<String, String>::from([
+                ("text".into(), "foo".into()),
+                (
+                    "generic".into(),
+                    r#"{"key":"value","type":"generic"}"#.into()
+                ),
+            ])
+        );
+
+        let value_record = |value: &str| RelatedRecord {
+            table_name: "feature_names".into(),
+            mapping_table_name: "feature_names_value".into(),
+            attributes: IndexMap::<String, String>::from([("value".into(), value.into())]),
+            related: vec![],
+        };
+        assert_eq!(
+            related,
+            vec![
+                value_record("a"),
+                value_record("b"),
+                RelatedRecord {
+                    table_name: "detail".into(),
+                    mapping_table_name: "feature_detail_detail".into(),
+                    attributes: IndexMap::<String, String>::from([("rank".into(), "1".into())]),
+                    related: vec![],
+                },
+            ]
+        );
+    }
 }
@@ -1,4 +1,4 @@
-use nusamai_geometry::MultiPolygon;
+use nusamai_geometry::{MultiLineString, MultiPoint, MultiPolygon};
 
 pub struct Bbox {
     min_x: f64,
@@ -54,6 +54,30 @@ pub fn get_indexed_multipolygon_bbox(vertices: &[[f64; 3]], mpoly: &MultiPolygon
     bbox
 }
 
+// Get Bounding box of a MultiLineString
+pub fn get_indexed_multilinestring_bbox(vertices: &[[f64; 3]], mls: &MultiLineString<u32>) -> Bbox {
+    let mut bbox: Bbox = Default::default();
+
+    for ls in mls {
+        for point_idx in &ls {
+            let [x, y, _z] = vertices[point_idx as usize];
+            bbox.update(x, y);
+        }
+    }
+    bbox
+}
+
+// Get Bounding box of a MultiPoint
+pub fn get_indexed_multipoint_bbox(vertices: &[[f64; 3]], mpoint: &MultiPoint<u32>) -> Bbox {
+    let mut bbox: Bbox = Default::default();
+
+    for point_idx in mpoint {
+        let [x, y, _z] = vertices[point_idx as usize];
+        bbox.update(x, y);
+    }
+    bbox
+}
+
 #[cfg(test)]
 mod tests {
     use nusamai_projection::crs::EPSG_JGD2011_GEOGRAPHIC_3D;
@@ -117,4 +141,21 @@ mod tests {
 
         assert_eq!(bbox.to_tuple(), (10., 100., 20., 200.));
     }
+
+    #[test]
+    fn test_get_indexed_multilinestring_and_multipoint_bbox() {
+        let vertices: Vec<[f64; 3]> = vec![[10., 100., 111.], [30., 150., 111.], [20., 200., 111.]];
+
+        let mut mls = MultiLineString::<u32>::new();
+        mls.add_linestring([0, 1]);
+        mls.add_linestring([1, 2]);
+        let bbox = get_indexed_multilinestring_bbox(&vertices, &mls);
+        assert_eq!(bbox.to_tuple(), (10., 100., 30., 200.));
+
+        let mut mpoint = MultiPoint::<u32>::new();
+        mpoint.push(1);
+        mpoint.push(2);
+        let bbox = get_indexed_multipoint_bbox(&vertices, &mpoint);
+        assert_eq!(bbox.to_tuple(), (20., 150., 30., 200.));
+    }
 }
@@ -0,0 +1,74 @@
+//! Metadata document of the GeoPackage (`gpkg_metadata`)
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use nusamai_citygml::schema::Schema;
+use url::Url;
+
+/// `md_standard_uri` of the metadata document, whose format is defined by this converter
+pub const METADATA_STANDARD_URI: &str = "https://github.com/MIERUNE/plateau-gis-converter";
+
+pub const METADATA_MIME_TYPE: &str = "application/json";
+
+/// Make the metadata document recording where the data came from and how it is structured
+///
+/// The schema carries the original CityGML names of the attributes, so that the columns can be traced back to
+/// the PLATEAU specifications.
+pub fn metadata_document<'a>(
+    schema: &Schema,
+    source_urls: impl IntoIterator<Item = &'a Url>,
+    created: DateTime<Utc>,
+) -> String {
+    serde_json::json!({
+        "generator": {
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "created": created.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "sources": source_urls.into_iter().map(Url::as_str).collect::<Vec<_>>(),
+        "schema": schema,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use nusamai_citygml::schema::{Attribute, FeatureTypeDef, TypeDef, TypeRef};
+
+    use super::*;
+
+    #[test]
+    fn test_metadata_document() {
+        let mut schema = Schema::default();
+        let mut attributes = nusamai_citygml::schema::Map::default();
+        attributes.insert(
+            "measuredHeight".into(),
+            Attribute {
+                original_name: Some("bldg:measuredHeight".into()),
+                ..Attribute::new(TypeRef::Measure)
+            },
+        );
+        schema.types.insert(
+            "bldg:Building".into(),
+            TypeDef::Feature(FeatureTypeDef {
+                attributes,
+                additional_attributes: false,
+            }),
+        );
+        let source_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697_op.gml").unwrap();
+        let created = Utc.with_ymd_and_hms(2024, 4, 1, 12, 0, 0).unwrap();
+
+        let document: serde_json::Value =
+            serde_json::from_str(&metadata_document(&schema, [&source_url], created)).unwrap();
+        assert_eq!(document["created"], "2024-04-01T12:00:00Z");
+        assert_eq!(
+            document["sources"],
+            serde_json::json!(["file:///data/udx/bldg/53394611_bldg_6697_op.gml"])
+        );
+        assert_eq!(
+            document["schema"]["types"]["bldg:Building"]["attributes"]["measuredHeight"]
+                ["original_name"],
+            "bldg:measuredHeight"
+        );
+    }
+}
@@ -2,21 +2,36 @@
 
 mod attributes;
 mod bbox;
+mod metadata;
 mod table;
 
-use std::{collections::HashSet, path::PathBuf};
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 
-use attributes::prepare_object_attributes;
-use bbox::{get_indexed_multipolygon_bbox, Bbox};
+use attributes::{prepare_object_attributes, prepare_related_attributes, RelatedRecord};
+use bbox::{
+    get_indexed_multilinestring_bbox, get_indexed_multipoint_bbox, get_indexed_multipolygon_bbox,
+    Bbox,
+};
 use indexmap::IndexMap;
+use metadata::{metadata_document, METADATA_MIME_TYPE, METADATA_STANDARD_URI};
 use nusamai_citygml::{
-    object::{ObjectStereotype, Value},
+    object::{Object, ObjectStereotype, Value},
     schema::Schema,
     GeometryType,
 };
-use nusamai_gpkg::{geometry::write_indexed_multipolygon, GpkgHandler};
+use nusamai_gpkg::{
+    geometry::{
+        write_indexed_multilinestring, write_indexed_multipoint, write_indexed_multipolygon,
+    },
+    table::{GeometryTypeName, TableInfo},
+    GpkgError, GpkgHandler, GpkgTransaction,
+};
 use rayon::prelude::*;
-use table::schema_to_table_infos;
+use table::{feature_table_name, schema_to_table_infos, TableOptions};
 use url::Url;
 
 use crate::{
@@ -50,20 +65,45 @@ impl DataSinkProvider for GpkgSinkProvider {
                 }),
             },
         );
+        params.define(
+            "related_tables".into(),
+            ParameterEntry {
+                description: "Store nested data and multiple values in related attribute tables"
+                    .into(),
+                required: false,
+                parameter: ParameterType::Boolean(BooleanParameter { value: Some(false) }),
+            },
+        );
+        params.define(
+            "with_z".into(),
+            ParameterEntry {
+                description: "Write the geometries with Z coordinates (MULTIPOLYGONZ etc.)".into(),
+                required: false,
+                parameter: ParameterType::Boolean(BooleanParameter { value: Some(true) }),
+            },
+        );
         params
     }
 
     fn create(&self, params: &Parameters) -> Box<dyn DataSink> {
         let output_path = get_parameter_value!(params, "@output", FileSystemPath);
+        let related_tables =
+            get_parameter_value!(params, "related_tables", Boolean).unwrap_or(false);
+        let has_z = get_parameter_value!(params, "with_z", Boolean).unwrap_or(true);
 
         Box::<GpkgSink>::new(GpkgSink {
             output_path: output_path.as_ref().unwrap().into(),
+            table_options: TableOptions {
+                related_tables,
+                has_z,
+            },
         })
     }
 }
 
 pub struct GpkgSink {
     output_path: PathBuf,
+    table_options: TableOptions,
 }
 
 // An ephimeral container to wrap and pass the data in the pipeline
@@ -74,9 +114,11 @@ enum Record {
         geometry: Vec<u8>,
         bbox: Bbox,
         attributes: IndexMap<String, String>,
+        related: Vec<RelatedRecord>,
     },
     Attribute {
         attributes: IndexMap<String, String>,
+        related: Vec<RelatedRecord>,
     },
 }
 
@@ -105,16 +147,25 @@ impl GpkgSink {
                 .map_err(|e| PipelineError::Other(e.to_string()))?
         };
 
-        let table_infos = schema_to_table_infos(schema);
+        let created = chrono::Utc::now();
+        let table_infos = Arc::new(schema_to_table_infos(schema, &self.table_options));
         let mut created_tables = HashSet::<String>::new();
+        let mut created_relations = HashSet::<String>::new();
         let srs_id = schema.epsg.unwrap_or(0); // 0 means 'Undefined Geographic'
 
         let mut table_bboxes = IndexMap::<String, Bbox>::new();
+        let source_urls = Arc::new(Mutex::new(BTreeSet::<Url>::new()));
 
         let (sender, mut receiver) = tokio::sync::mpsc::channel(100);
 
         let producers = {
             let feedback = feedback.clone();
+            let table_infos = table_infos.clone();
+            let source_urls = source_urls.clone();
+            let TableOptions {
+                related_tables,
+                has_z,
+            } = self.table_options;
             tokio::task::spawn_blocking(move || {
                 upstream
                     .into_iter()
@@ -123,18 +174,34 @@ impl GpkgSink {
                         feedback.ensure_not_canceled()?;
 
                         let entity = parcel.entity;
+                        {
+                            let mut source_urls = source_urls.lock().unwrap();
+                            if !source_urls.contains(&entity.base_url) {
+                                source_urls.insert(entity.base_url.clone());
+                            }
+                        }
                         let geom_store = entity.geometry_store.read().unwrap();
 
                         let Value::Object(obj) = &entity.root else {
                             return Ok(());
                         };
 
+                        let prepare_attributes =
+                            |obj: &Object, table_name: &str| match table_infos.get(table_name) {
+                                Some(table) if related_tables => {
+                                    prepare_related_attributes(obj, table, &table_infos)
+                                }
+                                _ => (prepare_object_attributes(obj), vec![]),
+                            };
+
                         match &obj.stereotype {
                             ObjectStereotype::Feature {
                                 id: obj_id,
                                 geometries,
                             } => {
                                 let mut mpoly = nusamai_geometry::MultiPolygon::new();
+                                let mut mls = nusamai_geometry::MultiLineString::new();
+                                let mut mpoint = nusamai_geometry::MultiPoint::new();
 
                                 geometries.iter().for_each(|entry| match entry.ty {
                                     GeometryType::Solid
@@ -146,44 +213,73 @@ impl GpkgSink {
                                             mpoly.push(&idx_poly);
                                         }
                                     }
-                                    GeometryType::Curve => unimplemented!(),
-                                    GeometryType::Point => unimplemented!(),
+                                    GeometryType::Curve => {
+                                        for idx_ls in geom_store.multilinestring.iter_range(
+                                            entry.pos as usize..(entry.pos + entry.len) as usize,
+                                        ) {
+                                            mls.add_linestring(&idx_ls);
+                                        }
+                                    }
+                                    GeometryType::Point => {
+                                        for idx_point in geom_store.multipoint.iter_range(
+                                            entry.pos as usize..(entry.pos + entry.len) as usize,
+                                        ) {
+                                            mpoint.push(idx_point);
+                                        }
+                                    }
                                 });
 
-                                if mpoly.is_empty() {
-                                    return Ok(());
+                                // Each kind of geometry goes to its own table, with the same id and attributes
+                                let vertices = &geom_store.vertices;
+                                let mut features = Vec::with_capacity(1);
+                                if !mpoly.is_empty() {
+                                    let mut bytes = Vec::new();
+                                    write_indexed_multipolygon(
+                                        &mut bytes, vertices, &mpoly, 4326, has_z,
+                                    )?;
+                                    let bbox = get_indexed_multipolygon_bbox(vertices, &mpoly);
+                                    features.push((GeometryTypeName::MultiPolygon, bytes, bbox));
                                 }
-
-                                let mut bytes = Vec::new();
-                                if write_indexed_multipolygon(
-                                    &mut bytes,
-                                    &geom_store.vertices,
-                                    &mpoly,
-                                    4326,
-                                )
-                                .is_err()
-                                {
-                                    // TODO: fatal error
+                                if !mls.is_empty() {
+                                    let mut bytes = Vec::new();
+                                    write_indexed_multilinestring(
+                                        &mut bytes, vertices, &mls, 4326, has_z,
+                                    )?;
+                                    let bbox = get_indexed_multilinestring_bbox(vertices, &mls);
+                                    features.push((GeometryTypeName::MultiLineString, bytes, bbox));
+                                }
+                                if !mpoint.is_empty() {
+                                    let mut bytes = Vec::new();
+                                    write_indexed_multipoint(
+                                        &mut bytes, vertices, &mpoint, 4326, has_z,
+                                    )?;
+                                    let bbox = get_indexed_multipoint_bbox(vertices, &mpoint);
+                                    features.push((GeometryTypeName::MultiPoint, bytes, bbox));
                                 }
 
-                                let table_name = obj.typename.to_string();
-                                let record = Record::Feature {
-                                    obj_id: obj_id.clone(),
-                                    geometry: bytes,
-                                    bbox: get_indexed_multipolygon_bbox(
-                                        &geom_store.vertices,
-                                        &mpoly,
-                                    ),
-                                    attributes: prepare_object_attributes(obj),
-                                };
-                                if sender.blocking_send((table_name, record)).is_err() {
-                                    return Err(PipelineError::Canceled);
-                                };
+                                for (geometry_type, bytes, bbox) in features {
+                                    let table_name =
+                                        feature_table_name(&obj.typename, geometry_type);
+                                    let (attributes, related) =
+                                        prepare_attributes(obj, &table_name);
+                                    let record = Record::Feature {
+                                        obj_id: obj_id.clone(),
+                                        geometry: bytes,
+                                        bbox,
+                                        attributes,
+                                        related,
+                                    };
+                                    if sender.blocking_send((table_name, record)).is_err() {
+                                        return Err(PipelineError::Canceled);
+                                    };
+                                }
                             }
                             ObjectStereotype::Data => {
                                 let table_name = obj.typename.to_string();
+                                let (attributes, related) = prepare_attributes(obj, &table_name);
                                 let record = Record::Attribute {
-                                    attributes: prepare_object_attributes(obj),
+                                    attributes,
+                                    related,
                                 };
                                 if sender.blocking_send((table_name, record)).is_err() {
                                     return Err(PipelineError::Canceled);
@@ -210,32 +306,61 @@ impl GpkgSink {
         while let Some((table_name, record)) = receiver.recv().await {
             feedback.ensure_not_canceled()?;
 
+            let table_info = table_infos.get(&table_name).unwrap();
             if !created_tables.contains(&table_name) {
-                let tf = table_infos.get(&table_name).unwrap();
-                tx.add_table(tf, srs_id)
+                tx.add_table(table_info, srs_id)
                     .await
                     .map_err(|e| PipelineError::Other(e.to_string()))?;
+                if table_info.has_geometry() {
+                    tx.add_rtree_index(table_info)
+                        .await
+                        .map_err(|e| PipelineError::Other(e.to_string()))?;
+                }
                 created_tables.insert(table_name.clone());
             }
 
-            match record {
+            let (id, related) = match record {
                 Record::Feature {
                     obj_id,
                     geometry,
                     bbox,
                     attributes,
+                    related,
                 } => {
-                    tx.insert_feature(&table_name, &obj_id, &geometry, &attributes)
+                    let fid = tx
+                        .insert_feature(&table_name, &obj_id, &geometry, &attributes)
+                        .await
+                        .map_err(|e| PipelineError::Other(e.to_string()))?;
+                    tx.insert_rtree_entry(&table_name, fid, bbox.to_tuple())
                         .await
                         .map_err(|e| PipelineError::Other(e.to_string()))?;
                     table_bboxes.entry(table_name).or_default().merge(&bbox);
+                    (fid, related)
                 }
-                Record::Attribute { attributes } => {
-                    tx.insert_attribute(&table_name, &attributes)
+                Record::Attribute {
+                    attributes,
+                    related,
+                } => {
+                    let id = tx
+                        .insert_attribute(&table_name, &attributes)
                         .await
                         .map_err(|e| PipelineError::Other(e.to_string()))?;
+                    (id, related)
                 }
-            }
+            };
+
+            insert_related_records(
+                &mut tx,
+                &table_infos,
+                &mut created_tables,
+                &mut created_relations,
+                srs_id,
+                table_info,
+                id,
+                related,
+            )
+            .await
+            .map_err(|e| PipelineError::Other(e.to_string()))?;
         }
 
         for (table_name, bbox) in table_bboxes {
@@ -244,8 +369,18 @@ impl GpkgSink {
             tx.update_bbox(&table_name, bbox.to_tuple())
                 .await
                 .map_err(|e| PipelineError::Other(e.to_string()))?;
+            // The triggers are created after the insertions (see `add_rtree_triggers`)
+            tx.add_rtree_triggers(&table_name)
+                .await
+                .map_err(|e| PipelineError::Other(e.to_string()))?;
         }
 
+        // Record the source files and the schema, so that the columns can be traced back to the original data
+        let document = metadata_document(schema, source_urls.lock().unwrap().iter(), created);
+        tx.add_metadata(METADATA_STANDARD_URI, METADATA_MIME_TYPE, &document)
+            .await
+            .map_err(|e| PipelineError::Other(e.to_string()))?;
+
         tx.commit()
             .await
             .map_err(|e| PipelineError::Other(e.to_string()))?;
@@ -257,15 +392,74 @@ impl GpkgSink {
     }
 }
 
+/// Insert the records related to a record, creating the tables and relations as needed
+#[allow(clippy::too_many_arguments)]
+async fn insert_related_records(
+    tx: &mut GpkgTransaction<'_>,
+    table_infos: &IndexMap<String, TableInfo>,
+    created_tables: &mut HashSet<String>,
+    created_relations: &mut HashSet<String>,
+    srs_id: u16,
+    base_table: &TableInfo,
+    base_id: i64,
+    related: Vec<RelatedRecord>,
+) -> std::result::Result<(), GpkgError> {
+    let mut stack: Vec<_> = related
+        .into_iter()
+        .map(|record| (base_table, base_id, record))
+        .collect();
+
+    while let Some((base_table, base_id, record)) = stack.pop() {
+        let table_info = table_infos.get(&record.table_name).unwrap();
+        if !created_tables.contains(&record.table_name) {
+            tx.add_table(table_info, srs_id).await?;
+            created_tables.insert(record.table_name.clone());
+        }
+        if !created_relations.contains(&record.mapping_table_name) {
+            tx.add_relation(base_table, table_info, &record.mapping_table_name)
+                .await?;
+            created_relations.insert(record.mapping_table_name.clone());
+        }
+
+        let id = tx
+            .insert_attribute(&record.table_name, &record.attributes)
+            .await?;
+        tx.insert_relation(&record.mapping_table_name, base_id, id)
+            .await?;
+
+        stack.extend(
+            record
+                .related
+                .into_iter()
+                .rev()
+                .map(|child| (table_info, id, child)),
+        );
+    }
+    Ok(())
+}
+
 impl DataSink for GpkgSink {
     fn make_requirements(&self) -> DataRequirements {
-        DataRequirements {
-            tree_flattening: transformer::TreeFlatteningSpec::Flatten {
-                feature: transformer::FeatureFlatteningOption::AllExceptThematicSurfaces,
-                data: transformer::DataFlatteningOption::TopLevelOnly,
-                object: transformer::ObjectFlatteningOption::None,
-            },
-            ..Default::default()
+        if self.table_options.related_tables {
+            // Nested data and arrays are kept as they are, to be stored in the related tables
+            DataRequirements {
+                tree_flattening: transformer::TreeFlatteningSpec::Flatten {
+                    feature: transformer::FeatureFlatteningOption::AllExceptThematicSurfaces,
+                    data: transformer::DataFlatteningOption::None,
+                    object: transformer::ObjectFlatteningOption::None,
+                },
+                key_value: transformer::KeyValueSpec::None,
+                ..Default::default()
+            }
+        } else {
+            DataRequirements {
+                tree_flattening: transformer::TreeFlatteningSpec::Flatten {
+                    feature: transformer::FeatureFlatteningOption::AllExceptThematicSurfaces,
+                    data: transformer::DataFlatteningOption::TopLevelOnly,
+                    object: transformer::ObjectFlatteningOption::None,
+                },
+                ..Default::default()
+            }
         }
     }
 
@@ -1,26 +1,98 @@
 use indexmap::IndexMap;
-use nusamai_citygml::schema::{Attribute, Schema, TypeDef, TypeRef};
-use nusamai_gpkg::table::{ColumnInfo, TableInfo};
+use nusamai_citygml::schema::{Attribute, DataTypeDef, FeatureTypeDef, Schema, TypeDef, TypeRef};
+use nusamai_gpkg::table::{ColumnInfo, GeometryColumnInfo, GeometryTypeName, TableInfo};
+
+/// How the schema is mapped to the tables
+#[derive(Debug, Clone, Copy)]
+pub struct TableOptions {
+    /// Store nested data and multiple values in related tables instead of JSON columns.
+    /// The tables holding the multiple values are also prepared.
+    pub related_tables: bool,
+    /// Write the geometries with Z coordinates
+    pub has_z: bool,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            related_tables: false,
+            has_z: true,
+        }
+    }
+}
 
 /// Check the schema, and prepare the information for the SQLite table
 #[must_use]
-pub fn schema_to_table_infos(schema: &Schema) -> IndexMap<String, TableInfo> {
+pub fn schema_to_table_infos(
+    schema: &Schema,
+    options: &TableOptions,
+) -> IndexMap<String, TableInfo> {
     let mut table_infos = IndexMap::<String, TableInfo>::new();
 
     schema.types.iter().for_each(|(name, ty)| {
-        table_infos.insert(
-            name.clone(),
-            TableInfo {
-                name: name.clone(),
-                has_geometry: matches!(ty, TypeDef::Feature(_)),
-                columns: typedef_to_columns(ty),
-            },
-        );
+        // Features are stored in a table for each kind of geometry
+        let tables: Vec<(String, Option<GeometryColumnInfo>)> = match ty {
+            TypeDef::Feature(_) => FEATURE_GEOMETRY_TYPES
+                .iter()
+                .map(|&geometry_type| {
+                    (
+                        feature_table_name(name, geometry_type),
+                        Some(GeometryColumnInfo {
+                            geometry_type,
+                            has_z: options.has_z,
+                        }),
+                    )
+                })
+                .collect(),
+            _ => vec![(name.clone(), None)],
+        };
+
+        for (table_name, geometry) in tables {
+            let columns = if options.related_tables {
+                typedef_to_related_columns(schema, &table_name, ty, &mut table_infos)
+            } else {
+                typedef_to_columns(ty)
+            };
+            table_infos.insert(
+                table_name.clone(),
+                TableInfo {
+                    name: table_name,
+                    geometry,
+                    columns,
+                },
+            );
+        }
     });
 
     table_infos
 }
 
+const FEATURE_GEOMETRY_TYPES: [GeometryTypeName; 3] = [
+    GeometryTypeName::MultiPolygon,
+    GeometryTypeName::MultiLineString,
+    GeometryTypeName::MultiPoint,
+];
+
+/// Name of the table holding the features of the type with the kind of geometry (e.g. `bldg:Building_polygon`)
+pub fn feature_table_name(typename: &str, geometry_type: GeometryTypeName) -> String {
+    let suffix = match geometry_type {
+        GeometryTypeName::MultiPolygon => "polygon",
+        GeometryTypeName::MultiLineString => "line",
+        GeometryTypeName::MultiPoint => "point",
+    };
+    format!("{}_{}", typename, suffix)
+}
+
+/// Name of the table holding the multiple values of an attribute
+pub fn value_table_name(table_name: &str, attr_name: &str) -> String {
+    format!("{}_{}", table_name, attr_name)
+}
+
+/// Name of the mapping table relating the records of a table to the records of another table through an attribute
+pub fn mapping_table_name(table_name: &str, attr_name: &str, related_table_name: &str) -> String {
+    format!("{}_{}_{}", table_name, attr_name, related_table_name)
+}
+
 #[must_use]
 fn typedef_to_columns(ty: &TypeDef) -> Vec<ColumnInfo> {
     let mut columns: Vec<ColumnInfo> = vec![];
@@ -48,60 +120,82 @@ fn typedef_to_columns(ty: &TypeDef) -> Vec<ColumnInfo> {
     columns
 }
 
+/// Prepare the columns of a table whose nested data and multiple values are stored in related tables
+#[must_use]
+fn typedef_to_related_columns(
+    schema: &Schema,
+    name: &str,
+    ty: &TypeDef,
+    table_infos: &mut IndexMap<String, TableInfo>,
+) -> Vec<ColumnInfo> {
+    let (TypeDef::Feature(FeatureTypeDef { attributes, .. })
+    | TypeDef::Data(DataTypeDef { attributes, .. })) = ty
+    else {
+        return vec![];
+    };
+
+    let mut columns: Vec<ColumnInfo> = vec![];
+    attributes
+        .iter()
+        .for_each(|(attr_name, attr)| match &attr.type_ref {
+            TypeRef::Named(type_name) => {
+                // Data are stored in their own tables, and child features are flattened in the earlier step
+                if !is_stored_separately(schema, type_name) {
+                    columns.push(ColumnInfo {
+                        name: attr_name.to_string(),
+                        data_type: "TEXT".into(),
+                        mime_type: Some("application/json".into()),
+                        title: attr.original_name.clone(),
+                        description: Some(format!("JSON of {}", type_name)),
+                    });
+                }
+            }
+            _ if attr.max_occurs != Some(1) => {
+                if let Some(column) = attribute_to_column("value", attr) {
+                    let table_name = value_table_name(name, attr_name);
+                    table_infos.insert(
+                        table_name.clone(),
+                        TableInfo {
+                            name: table_name,
+                            geometry: None,
+                            columns: vec![column],
+                        },
+                    );
+                }
+            }
+            _ => {
+                if let Some(column) = attribute_to_column(attr_name, attr) {
+                    columns.push(column);
+                }
+            }
+        });
+
+    columns
+}
+
+/// Whether the values of the type are stored apart from the parent record
+fn is_stored_separately(schema: &Schema, type_name: &str) -> bool {
+    match schema.types.get(type_name) {
+        // Data with arbitrary attributes (e.g. generic attributes) have no table definition
+        Some(TypeDef::Data(data_td)) => !data_td.additional_attributes,
+        Some(TypeDef::Feature(_)) => true,
+        Some(TypeDef::Property(prop_td)) => prop_td.members.iter().all(|member| {
+            matches!(&member.type_ref, TypeRef::Named(name) if is_stored_separately(schema, name))
+        }),
+        None => false,
+    }
+}
+
 #[must_use]
 fn attribute_to_column(attr_name: &str, attr: &Attribute) -> Option<ColumnInfo> {
     // Note: `attr.max_occurs` is expected to be 1 (handled by the transformer in the earlier step)
-    match &attr.type_ref {
-        TypeRef::String => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "TEXT".into(),
-            mime_type: None,
-        }),
-        TypeRef::Code => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "TEXT".into(),
-            mime_type: None,
-        }),
-        TypeRef::Integer | TypeRef::NonNegativeInteger => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "INTEGER".into(),
-            mime_type: None,
-        }),
-        TypeRef::Double => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "REAL".into(),
-            mime_type: None,
-        }),
-        TypeRef::Boolean => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "BOOLEAN".into(),
-            mime_type: None,
-        }),
-        TypeRef::JsonString(_) => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "TEXT".into(),
-            mime_type: Some("application/json".into()),
-        }),
-        TypeRef::URI => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "TEXT".into(),
-            mime_type: None,
-        }),
-        TypeRef::Date => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "DATE".into(),
-            mime_type: None,
-        }),
-        TypeRef::DateTime => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "TEXT".into(),
-            mime_type: None,
-        }),
-        TypeRef::Measure => Some(ColumnInfo {
-            name: attr_name.to_string(),
-            data_type: "REAL".into(),
-            mime_type: None,
-        }),
+    let (data_type, mime_type) = match &attr.type_ref {
+        TypeRef::String | TypeRef::Code | TypeRef::URI | TypeRef::DateTime => ("TEXT", None),
+        TypeRef::Integer | TypeRef::NonNegativeInteger => ("INTEGER", None),
+        TypeRef::Double | TypeRef::Measure => ("REAL", None),
+        TypeRef::Boolean => ("BOOLEAN", None),
+        TypeRef::JsonString(_) => ("TEXT", Some("application/json")),
+        TypeRef::Date => ("DATE", None),
         TypeRef::Point => {
             // TODO: implement
             // Point struct currently does not contain any data
@@ -110,7 +204,7 @@ fn attribute_to_column(attr_name: &str, attr: &Attribute) -> Option<ColumnInfo>
                 attr.type_ref,
                 attr_name
             );
-            None
+            return None;
         }
         TypeRef::Named(_name) => {
             // Note: expected to be handled by the tranformer in the earlier step (flatten)
@@ -119,7 +213,7 @@ fn attribute_to_column(attr_name: &str, attr: &Attribute) -> Option<ColumnInfo>
                 attr.type_ref,
                 attr_name
             );
-            None
+            return None;
         }
         TypeRef::Unknown => {
             log::warn!(
@@ -127,14 +221,53 @@ fn attribute_to_column(attr_name: &str, attr: &Attribute) -> Option<ColumnInfo>
                 attr.type_ref,
                 attr_name
             );
-            None
+            return None;
         }
+    };
+
+    Some(ColumnInfo {
+        name: attr_name.to_string(),
+        data_type: data_type.into(),
+        mime_type: mime_type.map(Into::into),
+        title: attr.original_name.clone(),
+        description: attribute_description(attr),
+    })
+}
+
+/// Describe the values of the attribute with the types of the CityGML schema (e.g. `gml:MeasureType (uom: m)`)
+fn attribute_description(attr: &Attribute) -> Option<String> {
+    let mut description = match &attr.type_ref {
+        TypeRef::String => "xs:string".to_string(),
+        TypeRef::Code => "gml:CodeType (value from the codelist)".to_string(),
+        TypeRef::Integer => "xs:integer".to_string(),
+        TypeRef::NonNegativeInteger => "xs:nonNegativeInteger".to_string(),
+        TypeRef::Double => "xs:double".to_string(),
+        TypeRef::Boolean => "xs:boolean".to_string(),
+        TypeRef::JsonString(inner) => {
+            let inner_description = attribute_description(inner)?;
+            if inner.max_occurs == Some(1) {
+                format!("JSON of {}", inner_description)
+            } else {
+                format!("JSON array of {}", inner_description)
+            }
+        }
+        TypeRef::URI => "xs:anyURI".to_string(),
+        TypeRef::Date => "xs:date".to_string(),
+        TypeRef::DateTime => "xs:dateTime".to_string(),
+        TypeRef::Measure => "gml:MeasureType".to_string(),
+        TypeRef::Point => "gml:Point".to_string(),
+        TypeRef::Named(name) => name.to_string(),
+        TypeRef::Unknown => return None,
+    };
+    if let Some(uom) = &attr.uom {
+        description.push_str(&format!(" (uom: {})", uom));
     }
+    Some(description)
 }
 
 #[cfg(test)]
 mod tests {
-    use nusamai_citygml::schema::{DataTypeDef, FeatureTypeDef, PropertyTypeDef};
+    use nusamai_citygml::schema::PropertyTypeDef;
 
     use super::*;
 
@@ -174,29 +307,49 @@ mod tests {
             epsg: Some(srs_id),
         };
 
-        let table_infos = schema_to_table_infos(&schema);
+        let table_infos = schema_to_table_infos(&schema, &Default::default());
 
-        assert_eq!(table_infos.len(), 2);
+        // A table for each kind of geometry
         assert_eq!(
-            table_infos.get("feature").unwrap(),
+            table_infos.keys().collect::<Vec<_>>(),
+            vec!["feature_polygon", "feature_line", "feature_point", "data"]
+        );
+        assert_eq!(
+            table_infos.get("feature_line").unwrap().geometry,
+            Some(GeometryColumnInfo {
+                geometry_type: GeometryTypeName::MultiLineString,
+                has_z: true,
+            })
+        );
+        assert_eq!(
+            table_infos.get("feature_polygon").unwrap(),
             &TableInfo {
-                name: "feature".into(),
-                has_geometry: true,
+                name: "feature_polygon".into(),
+                geometry: Some(GeometryColumnInfo {
+                    geometry_type: GeometryTypeName::MultiPolygon,
+                    has_z: true,
+                }),
                 columns: vec![
                     ColumnInfo {
                         name: "text".into(),
                         data_type: "TEXT".into(),
                         mime_type: None,
+                        title: None,
+                        description: Some("xs:string".into()),
                     },
                     ColumnInfo {
                         name: "number".into(),
                         data_type: "INTEGER".into(),
                         mime_type: None,
+                        title: None,
+                        description: Some("xs:integer".into()),
                     },
                     ColumnInfo {
                         name: "date".into(),
                         data_type: "DATE".into(),
                         mime_type: None,
+                        title: None,
+                        description: Some("xs:date".into()),
                     },
                 ]
             }
@@ -205,28 +358,147 @@ mod tests {
             table_infos.get("data").unwrap(),
             &TableInfo {
                 name: "data".into(),
-                has_geometry: false,
+                geometry: None,
                 columns: vec![
                     ColumnInfo {
                         name: "json".into(),
                         data_type: "TEXT".into(),
                         mime_type: Some("application/json".into()),
+                        title: None,
+                        description: Some("JSON of xs:string".into()),
                     },
                     ColumnInfo {
                         name: "measure".into(),
                         data_type: "REAL".into(),
                         mime_type: None,
+                        title: None,
+                        description: Some("gml:MeasureType".into()),
                     },
                     ColumnInfo {
                         name: "bool".into(),
                         data_type: "BOOLEAN".into(),
                         mime_type: None,
+                        title: None,
+                        description: Some("xs:boolean".into()),
                     },
                 ]
             }
         );
     }
 
+    #[test]
+    fn test_schema_to_related_table_infos() {
+        let mut types = IndexMap::with_hasher(ahash::RandomState::default());
+
+        let mut attrs_1 = IndexMap::with_hasher(ahash::RandomState::default());
+        attrs_1.insert("text".into(), Attribute::new(TypeRef::String));
+        attrs_1.insert(
+            "names".into(),
+            Attribute {
+                max_occurs: None,
+                ..Attribute::new(TypeRef::String)
+            },
+        );
+        attrs_1.insert(
+            "detail".into(),
+            Attribute::new(TypeRef::Named("data".into())),
+        );
+        attrs_1.insert(
+            "risk".into(),
+            Attribute::new(TypeRef::Named("property".into())),
+        );
+        attrs_1.insert(
+            "generic".into(),
+            Attribute::new(TypeRef::Named("generic".into())),
+        );
+        types.insert(
+            "feature".into(),
+            TypeDef::Feature(FeatureTypeDef {
+                attributes: attrs_1,
+                additional_attributes: false,
+            }),
+        );
+
+        let mut attrs_2 = IndexMap::with_hasher(ahash::RandomState::default());
+        attrs_2.insert("measure".into(), Attribute::new(TypeRef::Measure));
+        types.insert(
+            "data".into(),
+            TypeDef::Data(DataTypeDef {
+                attributes: attrs_2,
+                additional_attributes: false,
+            }),
+        );
+        types.insert(
+            "property".into(),
+            TypeDef::Property(PropertyTypeDef {
+                members: vec![Attribute::new(TypeRef::Named("data".into()))],
+            }),
+        );
+        types.insert(
+            "generic".into(),
+            TypeDef::Data(DataTypeDef {
+                attributes: Default::default(),
+                additional_attributes: true,
+            }),
+        );
+        let schema = Schema {
+            types,
+            epsg: Some(4326),
+        };
+
+        let table_infos = schema_to_table_infos(
+            &schema,
+            &TableOptions {
+                related_tables: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            table_infos.get("feature_polygon").unwrap().columns,
+            vec![
+                ColumnInfo {
+                    name: "text".into(),
+                    data_type: "TEXT".into(),
+                    mime_type: None,
+                    title: None,
+                    description: Some("xs:string".into()),
+                },
+                ColumnInfo {
+                    name: "generic".into(),
+                    data_type: "TEXT".into(),
+                    mime_type: Some("application/json".into()),
+                    title: None,
+                    description: Some("JSON of generic".into()),
+                },
+            ]
+        );
+        assert_eq!(
+            table_infos.get("feature_point_names").unwrap(),
+            &TableInfo {
+                name: "feature_point_names".into(),
+                geometry: None,
+                columns: vec![ColumnInfo {
+                    name: "value".into(),
+                    data_type: "TEXT".into(),
+                    mime_type: None,
+                    title: None,
+                    description: Some("xs:string".into()),
+                }]
+            }
+        );
+        assert_eq!(
+            table_infos.get("data").unwrap().columns,
+            vec![ColumnInfo {
+                name: "measure".into(),
+                data_type: "REAL".into(),
+                mime_type: None,
+                title: None,
+                description: Some("gml:MeasureType".into()),
+            }]
+        );
+    }
+
     #[test]
     fn test_typedef_to_columns() {
         let mut attrs_1 = IndexMap::with_hasher(ahash::RandomState::default());
@@ -244,16 +516,22 @@ mod tests {
                     name: "text".into(),
                     data_type: "TEXT".into(),
                     mime_type: None,
+                    title: None,
+                    description: Some("xs:string".into()),
                 },
                 ColumnInfo {
                     name: "number".into(),
                     data_type: "INTEGER".into(),
                     mime_type: None,
+                    title: None,
+                    description: Some("xs:integer".into()),
                 },
                 ColumnInfo {
                     name: "date".into(),
                     data_type: "DATE".into(),
                     mime_type: None,
+                    title: None,
+                    description: Some("xs:date".into()),
                 },
             ]
         );
@@ -276,16 +554,22 @@ mod tests {
                     name: "json".into(),
                     data_type: "TEXT".into(),
                     mime_type: Some("application/json".into()),
+                    title: None,
+                    description: Some("JSON of xs:string".into()),
                 },
                 ColumnInfo {
                     name: "measure".into(),
                     data_type: "REAL".into(),
                     mime_type: None,
+                    title: None,
+                    description: Some("gml:MeasureType".into()),
                 },
                 ColumnInfo {
                     name: "bool".into(),
                     data_type: "BOOLEAN".into(),
                     mime_type: None,
+                    title: None,
+                    description: Some("xs:boolean".into()),
                 },
             ]
         );
@@ -303,6 +587,8 @@ mod tests {
                 name: "description".into(),
                 data_type: "TEXT".into(),
                 mime_type: None,
+                title: None,
+                description: Some("xs:string".into()),
             })
         );
 
@@ -316,10 +602,32 @@ mod tests {
                 name: "json".into(),
                 data_type: "TEXT".into(),
                 mime_type: Some("application/json".into()),
+                title: None,
+                description: Some("JSON of xs:string".into()),
             })
         );
 
         let result_3 = attribute_to_column("unknown", &Attribute::new(TypeRef::Unknown));
         assert_eq!(result_3, None);
+
+        // The original name and the unit of measure are kept for the documentation
+        let result_4 = attribute_to_column(
+            "measuredHeight",
+            &Attribute {
+                original_name: Some("bldg:measuredHeight".into()),
+                uom: Some("m".into()),
+                ..Attribute::new(TypeRef::Measure)
+            },
+        );
+        assert_eq!(
+            result_4,
+            Some(ColumnInfo {
+                name: "measuredHeight".into(),
+                data_type: "REAL".into(),
+                mime_type: None,
+                title: Some("bldg:measuredHeight".into()),
+                description: Some("gml:MeasureType (uom: m)".into()),
+            })
+        );
     }
 }
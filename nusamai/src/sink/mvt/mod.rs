@@ -80,6 +80,19 @@ impl DataSinkProvider for MvtSinkProvider {
                 }),
             },
         );
+        params.define(
+            "simplify".into(),
+            ParameterEntry {
+                description: "Simplification tolerance in pixels for each zoom level (0: disabled)"
+                    .into(),
+                required: true,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: Some(0),
+                    min: Some(0),
+                    max: Some(16),
+                }),
+            },
+        );
         params
     }
 
@@ -87,10 +100,15 @@ impl DataSinkProvider for MvtSinkProvider {
         let output_path = get_parameter_value!(params, "@output", FileSystemPath);
         let min_z = get_parameter_value!(params, "min_z", Integer).unwrap() as u8;
         let max_z = get_parameter_value!(params, "max_z", Integer).unwrap() as u8;
+        let simplify = get_parameter_value!(params, "simplify", Integer).unwrap() as u32;
 
         Box::<MvtSink>::new(MvtSink {
             output_path: output_path.as_ref().unwrap().into(),
-            mvt_options: MvtParams { min_z, max_z },
+            mvt_options: MvtParams {
+                min_z,
+                max_z,
+                simplify,
+            },
         })
     }
 }
@@ -103,6 +121,8 @@ struct MvtSink {
 struct MvtParams {
     min_z: u8,
     max_z: u8,
+    /// Simplification tolerance in pixels (0: disabled)
+    simplify: u32,
 }
 
 #[derive(Serialize, Deserialize, deepsize::DeepSizeOf)]
@@ -211,6 +231,7 @@ fn geometry_slicing_stage(
             mvt_options.max_z,
             max_detail,
             buffer_pixels,
+            mvt_options.simplify,
             |(z, x, y), mpoly| {
                 feedback.ensure_not_canceled()?;
 
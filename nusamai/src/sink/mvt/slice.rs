@@ -5,7 +5,10 @@ use nusamai_citygml::{
     geometry::GeometryType,
     object::{ObjectStereotype, Value},
 };
-use nusamai_geometry::{LineString2, MultiPolygon2, Polygon2};
+use nusamai_geometry::{
+    algorithm::simplify::{simplify_polygon, SimplifyMethod},
+    LineString2, MultiPolygon2, Polygon2,
+};
 use nusamai_mvt::{webmercator::lnglat_to_web_mercator, TileZXY};
 use nusamai_plateau::Entity;
 
@@ -15,6 +18,7 @@ pub fn slice_cityobj_geoms<E>(
     max_z: u8,
     max_detail: u32,
     buffer_pixels: u32,
+    simplify_pixels: u32,
     f: impl Fn(TileZXY, MultiPolygon2) -> Result<(), E>,
 ) -> Result<(), E> {
     assert!(
@@ -68,7 +72,14 @@ pub fn slice_cityobj_geoms<E>(
                         continue;
                     }
 
-                    slice_polygon(zoom, extent, buffer, &poly, &mut tiled_mpolys);
+                    if simplify_pixels > 0 {
+                        // Tolerance in the normalized web mercator coordinates at this zoom level
+                        let tolerance = simplify_pixels as f64 / 256.0 / (1 << zoom) as f64;
+                        let simplified = simplify_polygon2(&poly, tolerance);
+                        slice_polygon(zoom, extent, buffer, &simplified, &mut tiled_mpolys);
+                    } else {
+                        slice_polygon(zoom, extent, buffer, &poly, &mut tiled_mpolys);
+                    }
                 }
             }
         }
@@ -92,6 +103,22 @@ pub fn slice_cityobj_geoms<E>(
     // TODO: linestring, point
 }
 
+/// Simplifies a polygon in the normalized web mercator coordinates (topology-preserving).
+fn simplify_polygon2(poly: &Polygon2, tolerance: f64) -> Polygon2<'static> {
+    let rings: Vec<Vec<[f64; 3]>> = poly
+        .rings()
+        .map(|ring| ring.iter().map(|[x, y]| [x, y, 0.]).collect())
+        .collect();
+    let kept = simplify_polygon(&rings, tolerance, SimplifyMethod::DouglasPeucker);
+
+    let mut simplified = Polygon2::new();
+    for (ring, kept) in poly.rings().zip(&kept) {
+        let coords = ring.raw_coords();
+        simplified.add_ring(kept.iter().map(|&i| coords[i]));
+    }
+    simplified
+}
+
 fn slice_polygon(
     zoom: u8,
     extent: u32,
@@ -1,58 +1,79 @@
+use std::io::{self, Write};
+
 use chrono::Datelike;
-use hashbrown::HashMap;
+use hashbrown::HashSet;
+use indexmap::IndexMap;
 use nusamai_citygml::{
     object::{Map, Value},
     schema::{DataTypeDef, FeatureTypeDef, TypeDef, TypeRef},
 };
-use shapefile::dbase::{self, Date, FieldValue, Record};
+use shapefile::dbase::{self, Date, FieldName, FieldValue, Record};
 
-pub fn make_table_builder(
-    typedef: &TypeDef,
-) -> (dbase::TableWriterBuilder, HashMap<String, FieldValue>) {
-    let mut builder = dbase::TableWriterBuilder::new();
-    let mut defaults = HashMap::new();
+/// Maximum length of the field names (in bytes)
+const MAX_FIELD_NAME_LENGTH: usize = 10;
+
+/// Maximum length of the character values (in bytes)
+pub const MAX_CHARACTER_LENGTH: usize = 254;
+
+/// A field of the DBF table, made from an attribute
+#[derive(Debug, Clone)]
+pub struct Field {
+    /// Field name in the DBF table (valid and unique within the table)
+    pub name: String,
+    /// Name of the attribute in the original data (e.g. `bldg:measuredHeight`)
+    pub original_name: Option<String>,
+    /// Value for the records without the attribute
+    pub default: FieldValue,
+    /// Length of the values (in bytes)
+    pub length: u8,
+    pub decimal_count: u8,
+}
+
+/// The fields of the DBF table, keyed by the attribute names
+pub type Fields = IndexMap<String, Field>;
+
+/// Prepare the fields of the DBF table for the type
+///
+/// The attribute names are made valid as field names and unique within the 10-byte limit.
+pub fn make_fields(typedef: &TypeDef) -> Fields {
+    let mut fields = Fields::new();
+    let mut used_names = HashSet::new();
 
     let attributes = match typedef {
         TypeDef::Feature(FeatureTypeDef { attributes, .. }) => {
             let key = "id";
-            builder = builder.add_character_field(key.try_into().unwrap(), 255);
-            defaults.insert(key.into(), FieldValue::Character(None));
+            fields.insert(
+                key.into(),
+                Field {
+                    name: unique_field_name(key, &mut used_names),
+                    original_name: Some("gml:id".into()),
+                    default: FieldValue::Character(None),
+                    length: MAX_CHARACTER_LENGTH as u8,
+                    decimal_count: 0,
+                },
+            );
             attributes
         }
         TypeDef::Data(DataTypeDef { attributes, .. }) => attributes,
         TypeDef::Property(_) => unreachable!(),
     };
 
-    for (field_name, attr) in attributes {
-        let name = field_name.as_str().try_into().unwrap(); // FIXME: handle errors
-        let key = field_name.to_string();
-
-        match attr.type_ref {
+    for (attr_name, attr) in attributes {
+        let (default, length, decimal_count) = match attr.type_ref {
             TypeRef::String | TypeRef::Code | TypeRef::URI | TypeRef::JsonString(_) => {
-                builder = builder.add_character_field(name, 255);
-                defaults.insert(key, FieldValue::Character(None));
-            }
-            TypeRef::Integer | TypeRef::NonNegativeInteger => {
-                builder = builder.add_numeric_field(name, 11, 0);
-                defaults.insert(key, FieldValue::Numeric(None));
-            }
-            TypeRef::Double | TypeRef::Measure => {
-                builder = builder.add_numeric_field(name, 18, 6);
-                defaults.insert(key, FieldValue::Numeric(None));
-            }
-            TypeRef::Boolean => {
-                builder = builder.add_character_field(name, 6);
-                defaults.insert(key, FieldValue::Character(None));
-            }
-            TypeRef::Date => {
-                builder = builder.add_date_field(name);
-                defaults.insert(key, FieldValue::Date(None));
+                (FieldValue::Character(None), MAX_CHARACTER_LENGTH as u8, 0)
             }
+            TypeRef::Integer | TypeRef::NonNegativeInteger => (FieldValue::Numeric(None), 11, 0),
+            TypeRef::Double | TypeRef::Measure => (FieldValue::Numeric(None), 18, 6),
+            TypeRef::Boolean => (FieldValue::Character(None), 6, 0),
+            TypeRef::Date => (FieldValue::Date(None), 8, 0),
             TypeRef::DateTime => {
                 // todo
+                continue;
             }
             TypeRef::Point => {
                 // todo
+                continue;
             }
             TypeRef::Unknown => {
                 unreachable!();
@@ -60,103 +81,297 @@ pub fn make_table_builder(
             TypeRef::Named(_) => {
                 unreachable!();
             }
-        }
+        };
+        fields.insert(
+            attr_name.to_string(),
+            Field {
+                name: unique_field_name(attr_name, &mut used_names),
+                original_name: attr.original_name.clone(),
+                default,
+                length,
+                decimal_count,
+            },
+        );
     }
 
-    (builder, defaults)
+    fields
 }
 
+pub fn make_table_builder(fields: &Fields) -> dbase::TableWriterBuilder {
+    let mut builder = dbase::TableWriterBuilder::new();
+    for field in fields.values() {
+        let name = FieldName::try_from(field.name.as_str())
+            .expect("field names should be shorter than the limit");
+        builder = match field.default {
+            FieldValue::Numeric(_) => {
+                builder.add_numeric_field(name, field.length, field.decimal_count)
+            }
+            FieldValue::Date(_) => builder.add_date_field(name),
+            _ => builder.add_character_field(name, field.length),
+        };
+    }
+    builder
+}
+
+/// Length of a record of the DBF table (in bytes)
+pub fn record_length(fields: &Fields) -> u64 {
+    // a record starts with the deletion flag
+    1 + fields
+        .values()
+        .map(|field| field.length as u64)
+        .sum::<u64>()
+}
+
+/// Length of the header of the DBF table (in bytes)
+pub fn header_length(fields: &Fields) -> u64 {
+    // 32-byte file header, 32-byte field descriptors and the terminator (+ the end-of-file marker)
+    32 + 32 * fields.len() as u64 + 1 + 1
+}
+
+/// Write the correspondence between the field names and the attribute names as CSV
+pub fn write_field_mapping(mut writer: impl Write, fields: &Fields) -> io::Result<()> {
+    writeln!(writer, "field_name,attribute_name,original_name")?;
+    for (attr_name, field) in fields {
+        writeln!(
+            writer,
+            "{},{},{}",
+            csv_escape(&field.name),
+            csv_escape(attr_name),
+            csv_escape(field.original_name.as_deref().unwrap_or_default())
+        )?;
+    }
+    Ok(())
+}
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Make a valid field name from the attribute name, that is unique (case-insensitively) among `used_names`
+fn unique_field_name(attr_name: &str, used_names: &mut HashSet<String>) -> String {
+    // ASCII letters, digits and underscores only, starting with a letter
+    let sanitized: String = attr_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let sanitized = match sanitized.trim_matches('_') {
+        "" => "field".to_string(),
+        s if s.starts_with(|c: char| c.is_ascii_digit()) => format!("f{}", s),
+        s => s.to_string(),
+    };
+    let base = truncate_str(&sanitized, MAX_FIELD_NAME_LENGTH);
+
+    let mut name = base.to_string();
+    let mut n = 1;
+    while used_names.contains(&name.to_ascii_lowercase()) {
+        let suffix = format!("_{}", n);
+        name = format!(
+            "{}{}",
+            truncate_str(base, MAX_FIELD_NAME_LENGTH - suffix.len()),
+            suffix
+        );
+        n += 1;
+    }
+    used_names.insert(name.to_ascii_lowercase());
+    name
+}
+
+/// Make a record from the attributes
+///
+/// The character values longer than 254 bytes are truncated, and counted for each field in `truncated`.
 pub fn attributes_to_record(
     attributes: Map,
-    fields_default: &HashMap<String, FieldValue>,
+    fields: &Fields,
+    truncated: &mut IndexMap<String, usize>,
 ) -> Record {
     let mut record = dbase::Record::default();
 
     // Fill in with default values for attributes that are not present
-    for (name, default) in fields_default {
-        if !attributes.contains_key(name) {
-            record.insert(name.to_string(), default.clone());
+    for (attr_name, field) in fields {
+        if !attributes.contains_key(attr_name) {
+            record.insert(field.name.clone(), field.default.clone());
         }
     }
 
     for (attr_name, attr_value) in attributes {
-        match attr_value {
-            Value::String(s) => {
-                // Shapefile cannot store string longer than 254 bytes
-                let s = trim_string_bytes(s, 254);
-                record.insert(attr_name, FieldValue::Character(Some(s)));
+        let Some(field) = fields.get(&attr_name) else {
+            continue;
+        };
+        let mut character = |s: &str| {
+            let trimmed = truncate_str(s, MAX_CHARACTER_LENGTH);
+            if trimmed.len() < s.len() {
+                *truncated.entry(field.name.clone()).or_default() += 1;
             }
+            FieldValue::Character(Some(trimmed.to_string()))
+        };
+
+        let value = match attr_value {
+            Value::String(s) => character(&s),
             Value::Code(c) => {
                 // value of the code
-                record.insert(attr_name, FieldValue::Character(Some(c.value().to_owned())));
-            }
-            Value::Integer(i) => {
-                record.insert(attr_name, FieldValue::Numeric(Some(i as f64)));
-            }
-            Value::NonNegativeInteger(i) => {
-                record.insert(attr_name, FieldValue::Numeric(Some(i as f64)));
-            }
-            Value::Double(d) => {
-                record.insert(
-                    attr_name,
-                    FieldValue::Numeric(match d.is_nan() {
-                        true => None,
-                        false => Some(d),
-                    }),
-                );
-            }
-            Value::Measure(m) => {
-                record.insert(attr_name, FieldValue::Numeric(Some(m.value())));
-            }
-            Value::Boolean(b) => {
-                record.insert(
-                    attr_name,
-                    FieldValue::Character(Some(match b {
-                        true => "true".to_string(),
-                        false => "false".to_string(),
-                    })),
-                );
-            }
-            Value::Uri(u) => {
-                record.insert(
-                    attr_name,
-                    FieldValue::Character(Some(u.value().to_string())),
-                );
+                character(c.value())
             }
+            Value::Integer(i) => FieldValue::Numeric(Some(i as f64)),
+            Value::NonNegativeInteger(i) => FieldValue::Numeric(Some(i as f64)),
+            Value::Double(d) => FieldValue::Numeric(match d.is_nan() {
+                true => None,
+                false => Some(d),
+            }),
+            Value::Measure(m) => FieldValue::Numeric(Some(m.value())),
+            Value::Boolean(b) => FieldValue::Character(Some(match b {
+                true => "true".to_string(),
+                false => "false".to_string(),
+            })),
+            Value::Uri(u) => character(u.value().as_str()),
             Value::Date(d) => {
                 // Date represented as an ISO8601 string
-                record.insert(
-                    attr_name,
-                    FieldValue::Date(Some(Date::new(d.day(), d.month(), d.year() as u32))),
-                );
+                FieldValue::Date(Some(Date::new(d.day(), d.month(), d.year() as u32)))
             }
             Value::Point(_p) => {
                 // TODO: implement
+                field.default.clone()
             }
             Value::Array(_arr) => {
                 // TODO: handle multiple values
+                field.default.clone()
             }
             Value::Object(_obj) => {
                 // TODO: handle nested objects
+                field.default.clone()
             }
         };
+        record.insert(field.name.clone(), value);
     }
 
     record
 }
 
-fn trim_string_bytes(s: String, n: usize) -> String {
-    let bytes = s.as_bytes();
-    if bytes.len() <= n {
+/// Truncate the string to at most `n` bytes, at a character boundary
+fn truncate_str(s: &str, n: usize) -> &str {
+    if s.len() <= n {
         return s;
     }
-    log::warn!("string is too long, truncating to {} characters", n);
-    match std::str::from_utf8(&bytes[..n]) {
-        Ok(valid_str) => valid_str.to_string(),
-        Err(e) => {
-            let valid_up_to = e.valid_up_to();
-            let valid_str = std::str::from_utf8(&bytes[..valid_up_to]).unwrap();
-            valid_str.to_string()
+    let mut end = n;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::schema::Attribute;
+
+    use super::*;
+
+    #[test]
+    fn test_make_fields() {
+        let mut attributes = nusamai_citygml::schema::Map::default();
+        for (name, ty) in [
+            ("measuredHeight", TypeRef::Measure),
+            ("measuredHeightUom", TypeRef::String),
+            ("MEASUREDHEIGHT2", TypeRef::Integer),
+            ("gml:name", TypeRef::String),
+            ("建物の用途区分", TypeRef::Code),
+            ("3dModel", TypeRef::String),
+            ("ID", TypeRef::String),
+            ("creationDate", TypeRef::Date),
+        ] {
+            attributes.insert(
+                name.into(),
+                Attribute {
+                    original_name: Some(format!("bldg:{}", name)),
+                    ..Attribute::new(ty)
+                },
+            );
         }
+        let fields = make_fields(&TypeDef::Feature(FeatureTypeDef {
+            attributes,
+            additional_attributes: false,
+        }));
+
+        let names: Vec<_> = fields.values().map(|field| field.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "id",
+                "measuredHe",
+                "measured_1",
+                "MEASURED_2",
+                "gml_name",
+                "field",
+                "f3dModel",
+                "ID_1",
+                "creationDa"
+            ]
+        );
+        assert!(fields.values().all(|field| field.name.len() <= 10
+            && field.name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && field
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')));
+        assert_eq!(
+            fields["measuredHeight"].original_name.as_deref(),
+            Some("bldg:measuredHeight")
+        );
+        assert_eq!(
+            record_length(&fields),
+            1 + 254 + 18 + 254 + 11 + 254 + 254 + 254 + 254 + 8
+        );
+        assert_eq!(header_length(&fields), 32 + 32 * 9 + 2);
+
+        let mut csv = Vec::new();
+        write_field_mapping(&mut csv, &fields).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("field_name,attribute_name,original_name")
+        );
+        assert_eq!(lines.next(), Some("id,id,gml:id"));
+        assert_eq!(
+            lines.next(),
+            Some("measuredHe,measuredHeight,bldg:measuredHeight")
+        );
+    }
+
+    #[test]
+    fn test_attributes_to_record() {
+        let mut attributes = nusamai_citygml::schema::Map::default();
+        attributes.insert("description".into(), Attribute::new(TypeRef::String));
+        attributes.insert(
+            "storeysAboveGround".into(),
+            Attribute::new(TypeRef::Integer),
+        );
+        let fields = make_fields(&TypeDef::Data(DataTypeDef {
+            attributes,
+            additional_attributes: false,
+        }));
+
+        let mut values = Map::default();
+        values.insert("description".into(), Value::String("あ".repeat(100)));
+        values.insert("unknown".into(), Value::String("ignored".into()));
+
+        let mut truncated = IndexMap::new();
+        let record = attributes_to_record(values, &fields, &mut truncated);
+        assert_eq!(
+            record.get("descriptio"),
+            Some(&FieldValue::Character(Some("あ".repeat(84)))) // 252 bytes
+        );
+        assert_eq!(record.get("storeysAbo"), Some(&FieldValue::Numeric(None)));
+        assert_eq!(record.get("unknown"), None);
+        assert_eq!(truncated.get("descriptio"), Some(&1));
+    }
+
+    #[test]
+    fn test_csv_escape() {
+        assert_eq!(csv_escape("name"), "name");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
     }
 }
@@ -3,14 +3,18 @@
 mod attributes;
 mod crs;
 mod null_shape;
+mod parts;
 
 use std::{
     fs::{remove_file, File},
-    io::BufWriter,
-    path::PathBuf,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
-use attributes::{attributes_to_record, make_table_builder};
+use attributes::{
+    attributes_to_record, header_length, make_fields, make_table_builder, record_length,
+    write_field_mapping, Fields, MAX_CHARACTER_LENGTH,
+};
 use indexmap::IndexMap;
 use nusamai_citygml::{
     object::{Map, ObjectStereotype, Value},
@@ -19,6 +23,10 @@ use nusamai_citygml::{
 };
 use nusamai_plateau::Entity;
 use nusamai_shapefile::conversion::indexed_multipolygon_to_shape;
+use parts::{
+    part_file_stem, polygonz_record_size, split_into_parts, MAX_FILE_SIZE, NULL_SHAPE_RECORD_SIZE,
+    SHP_HEADER_SIZE,
+};
 use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use self::crs::ProjectionRepository;
@@ -113,8 +121,6 @@ impl DataSink for ShapefileSink {
             || {
                 // Write Shapefile to a file
 
-                // Attribute fields for the features
-                // FieldName byte representation cannot exceed 11 bytes
                 let mut grouped_features = IndexMap::<String, Vec<(shapefile::Shape, Map)>>::new();
 
                 receiver
@@ -139,99 +145,75 @@ impl DataSink for ShapefileSink {
                             ))
                         })?;
 
-                        let (table_builder, fields_default) = make_table_builder(typedef);
+                        let fields = make_fields(typedef);
 
-                        // Create all the files needed for the shapefile to be complete (.shp, .shx, .dbf)
                         std::fs::create_dir_all(&self.output_path)?;
-                        let shp_path = self
-                            .output_path
-                            .join(format!("{}.shp", typename.replace(':', "_")));
+                        let stem = typename.replace(':', "_");
+
+                        // The field names may differ from the attribute names (e.g. truncated to 10 bytes)
+                        let mapping_path = self.output_path.join(format!("{}_fields.csv", stem));
+                        write_field_mapping(BufWriter::new(File::create(mapping_path)?), &fields)?;
 
-                        let feature_count = features.len();
                         let has_no_geometry = features
                             .iter()
                             .all(|(shape, _)| matches!(shape, shapefile::Shape::NullShape));
 
-                        // NOTE: Need to be scoped to drop the writer before removing .shp/.shx
-                        {
-                            let mut writer = shapefile::Writer::from_path(&shp_path, table_builder)
-                                .map_err(|err| match err {
-                                    shapefile::Error::IoError(io_err) => {
-                                        PipelineError::IoError(io_err)
-                                    }
-                                    _ => PipelineError::Other(err.to_string()),
-                                })?;
-
-                            // Write each feature
-                            for (shape, attributes) in features {
-                                let record = attributes_to_record(attributes, &fields_default);
-
-                                match shape {
-                                    shapefile::Shape::PolygonZ(polygon) => {
-                                        writer.write_shape_and_record(&polygon, &record).map_err(
-                                            |err| match err {
-                                                shapefile::Error::IoError(io_err) => {
-                                                    PipelineError::IoError(io_err)
-                                                }
-                                                _ => PipelineError::Other(err.to_string()),
-                                            },
-                                        )?;
-                                    }
-                                    shapefile::Shape::NullShape if !has_no_geometry => {
-                                        // FIXME: feature may have no geometry. e.g.
-                                        // - Building (no geometry)
-                                        //     - BuildingPart (has geometry)
-                                        //     - BuildingPart (has geometry)
-                                        feedback.warn(
-                                            "Feature without geometry is not supported yet."
-                                                .to_string(),
-                                        );
-                                    }
-                                    shapefile::Shape::NullShape if has_no_geometry => {
-                                        // Write dummy data once because shapefile-rs cannot write NullShape file
-                                        let point = shapefile::Point::default();
-                                        writer.write_shape_and_record(&point, &record).map_err(
-                                            |err| match err {
-                                                shapefile::Error::IoError(io_err) => {
-                                                    PipelineError::IoError(io_err)
-                                                }
-                                                _ => PipelineError::Other(err.to_string()),
-                                            },
-                                        )?;
-                                    }
-                                    _ => {
-                                        log::warn!("Unsupported shape type");
-                                    }
-                                }
+                        // Split the features into parts before the files exceed the size limit
+                        let dbf_record_size = record_length(&fields);
+                        let record_sizes = features.iter().map(|(shape, _)| match shape {
+                            shapefile::Shape::PolygonZ(polygon) => (
+                                polygonz_record_size(
+                                    polygon.rings().len(),
+                                    polygon.rings().iter().map(|ring| ring.points().len()).sum(),
+                                ),
+                                dbf_record_size,
+                            ),
+                            shapefile::Shape::NullShape if has_no_geometry => {
+                                (NULL_SHAPE_RECORD_SIZE, dbf_record_size)
                             }
+                            // not written
+                            _ => (0, 0),
+                        });
+                        let part_lengths = split_into_parts(
+                            record_sizes,
+                            (SHP_HEADER_SIZE, header_length(&fields)),
+                            MAX_FILE_SIZE,
+                        );
+                        if part_lengths.len() > 1 {
+                            feedback.info(format!(
+                                "Splitting {} into {} files because of the file size limit",
+                                typename,
+                                part_lengths.len()
+                            ));
                         }
 
-                        // If this type has no geometry (i.e. Data or Object stereotype)
-                        if has_no_geometry {
-                            // Remove dummy .shp and .shx and write a NullShape file.
-                            remove_file(&shp_path)?;
-                            let shx_path = shp_path.with_extension("shx");
-                            remove_file(&shx_path)?;
-                            null_shape::write_shp(
-                                BufWriter::new(File::create(shp_path)?),
-                                feature_count,
-                            )?;
-                            null_shape::write_shx(
-                                BufWriter::new(File::create(shx_path)?),
-                                feature_count,
-                            )?;
-                        } else {
-                            // write .prj file if this type has geometry
-
-                            let repo = ProjectionRepository::new();
-                            let prj_path = &shp_path.with_extension("prj");
-                            crs::write_prj(
-                                BufWriter::new(File::create(prj_path)?),
-                                &repo,
-                                &schema.epsg.unwrap(),
+                        let mut truncated = IndexMap::<String, usize>::new();
+                        let mut features = features.into_iter();
+                        for (index, &part_length) in part_lengths.iter().enumerate() {
+                            feedback.ensure_not_canceled()?;
+
+                            let shp_path = self.output_path.join(format!(
+                                "{}.shp",
+                                part_file_stem(&stem, index, part_lengths.len())
+                            ));
+                            write_part(
+                                feedback,
+                                &shp_path,
+                                features.by_ref().take(part_length).collect(),
+                                &fields,
+                                has_no_geometry,
+                                schema,
+                                &mut truncated,
                             )?;
                         }
 
+                        for (field_name, count) in truncated {
+                            feedback.warn(format!(
+                                "{} values of the field '{}' of {} are truncated to {} bytes",
+                                count, field_name, typename, MAX_CHARACTER_LENGTH
+                            ));
+                        }
+
                         Ok::<(), PipelineError>(())
                     })
             },
@@ -250,6 +232,87 @@ impl DataSink for ShapefileSink {
     }
 }
 
+/// Write a set of the Shapefile files (.shp, .shx, .dbf, .prj and .cpg)
+fn write_part(
+    feedback: &Feedback,
+    shp_path: &Path,
+    features: Vec<(shapefile::Shape, Map)>,
+    fields: &Fields,
+    has_no_geometry: bool,
+    schema: &Schema,
+    truncated: &mut IndexMap<String, usize>,
+) -> Result<()> {
+    let feature_count = features.len();
+
+    // NOTE: Need to be scoped to drop the writer before removing .shp/.shx
+    {
+        let mut writer = shapefile::Writer::from_path(shp_path, make_table_builder(fields))
+            .map_err(shapefile_error)?;
+
+        // Write each feature
+        for (shape, attributes) in features {
+            let record = attributes_to_record(attributes, fields, truncated);
+
+            match shape {
+                shapefile::Shape::PolygonZ(polygon) => {
+                    writer
+                        .write_shape_and_record(&polygon, &record)
+                        .map_err(shapefile_error)?;
+                }
+                shapefile::Shape::NullShape if !has_no_geometry => {
+                    // FIXME: feature may have no geometry. e.g.
+                    // - Building (no geometry)
+                    //     - BuildingPart (has geometry)
+                    //     - BuildingPart (has geometry)
+                    feedback.warn("Feature without geometry is not supported yet.".to_string());
+                }
+                shapefile::Shape::NullShape if has_no_geometry => {
+                    // Write dummy data once because shapefile-rs cannot write NullShape file
+                    let point = shapefile::Point::default();
+                    writer
+                        .write_shape_and_record(&point, &record)
+                        .map_err(shapefile_error)?;
+                }
+                _ => {
+                    log::warn!("Unsupported shape type");
+                }
+            }
+        }
+    }
+
+    // If this type has no geometry (i.e. Data or Object stereotype)
+    if has_no_geometry {
+        // Remove dummy .shp and .shx and write a NullShape file.
+        remove_file(shp_path)?;
+        let shx_path = shp_path.with_extension("shx");
+        remove_file(&shx_path)?;
+        null_shape::write_shp(BufWriter::new(File::create(shp_path)?), feature_count)?;
+        null_shape::write_shx(BufWriter::new(File::create(shx_path)?), feature_count)?;
+    } else {
+        // write .prj file if this type has geometry
+
+        let repo = ProjectionRepository::new();
+        let prj_path = &shp_path.with_extension("prj");
+        crs::write_prj(
+            BufWriter::new(File::create(prj_path)?),
+            &repo,
+            &schema.epsg.unwrap(),
+        )?;
+    }
+
+    // The attributes are encoded in UTF-8
+    File::create(shp_path.with_extension("cpg"))?.write_all(b"UTF-8")?;
+
+    Ok(())
+}
+
+fn shapefile_error(err: shapefile::Error) -> PipelineError {
+    match err {
+        shapefile::Error::IoError(io_err) => PipelineError::IoError(io_err),
+        _ => PipelineError::Other(err.to_string()),
+    }
+}
+
 /// Create Shapefile features from a Entity
 /// Each feature for MultiPolygon, MultiLineString, and MultiPoint will be created (if it exists)
 /// TODO: Implement MultiLineString and MultiPoint handling
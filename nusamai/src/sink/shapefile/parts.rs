@@ -0,0 +1,99 @@
+//! Splitting of the outputs into multiple Shapefiles
+//!
+//! The .shp and .dbf files use 32-bit offsets, and many readers cannot open files larger than 2 GB.
+
+/// Maximum size of the .shp and .dbf files (in bytes)
+pub const MAX_FILE_SIZE: u64 = i32::MAX as u64;
+
+/// Size of the header of the .shp file (in bytes)
+pub const SHP_HEADER_SIZE: u64 = 100;
+
+/// Size of a NullShape record in the .shp file (in bytes)
+pub const NULL_SHAPE_RECORD_SIZE: u64 = 8 + 4;
+
+/// Size of a PolygonZ record in the .shp file (in bytes)
+pub fn polygonz_record_size(num_rings: usize, num_points: usize) -> u64 {
+    let (num_rings, num_points) = (num_rings as u64, num_points as u64);
+    // record header, shape type, bounding box, numbers of the rings and the points, ring offsets,
+    // XY coordinates, Z range and values, M range and values
+    8 + 4 + 32 + 4 + 4 + 4 * num_rings + 16 * num_points + 16 + 8 * num_points + 16 + 8 * num_points
+}
+
+/// Split the features into parts so that neither the .shp nor the .dbf file exceeds `max_size`, and return
+/// the number of the features in each part.
+///
+/// `record_sizes` are the sizes of the .shp and .dbf records of the features, and `header_sizes` are the sizes
+/// of the headers of the files. A feature that is too large by itself is put in its own part.
+pub fn split_into_parts(
+    record_sizes: impl IntoIterator<Item = (u64, u64)>,
+    header_sizes: (u64, u64),
+    max_size: u64,
+) -> Vec<usize> {
+    let mut parts = vec![0];
+    let (mut shp_size, mut dbf_size) = header_sizes;
+    for (shp_record_size, dbf_record_size) in record_sizes {
+        let count = parts.last_mut().unwrap();
+        if *count > 0
+            && (shp_size + shp_record_size > max_size || dbf_size + dbf_record_size > max_size)
+        {
+            parts.push(0);
+            (shp_size, dbf_size) = header_sizes;
+        }
+        *parts.last_mut().unwrap() += 1;
+        shp_size += shp_record_size;
+        dbf_size += dbf_record_size;
+    }
+    parts
+}
+
+/// File name (without the extension) of a part, numbered from 1 if the output is split
+pub fn part_file_stem(stem: &str, index: usize, num_parts: usize) -> String {
+    if num_parts > 1 {
+        format!("{}_{}", stem, index + 1)
+    } else {
+        stem.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_into_parts() {
+        // Everything fits in a file
+        assert_eq!(split_into_parts([(10, 5); 3], (100, 50), 1000), vec![3]);
+        assert_eq!(split_into_parts([], (100, 50), 1000), vec![0]);
+
+        // Limited by the .shp file
+        assert_eq!(
+            split_into_parts([(10, 5); 5], (100, 50), 125),
+            vec![2, 2, 1]
+        );
+
+        // Limited by the .dbf file
+        assert_eq!(split_into_parts([(1, 10); 5], (10, 100), 130), vec![3, 2]);
+
+        // A feature too large by itself
+        assert_eq!(
+            split_into_parts([(10, 5), (500, 5), (10, 5)], (100, 50), 200),
+            vec![1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn test_polygonz_record_size() {
+        // a triangle (closed ring with 4 points)
+        assert_eq!(
+            polygonz_record_size(1, 4),
+            8 + 44 + 4 + 64 + 16 + 32 + 16 + 32
+        );
+    }
+
+    #[test]
+    fn test_part_file_stem() {
+        assert_eq!(part_file_stem("bldg_Building", 0, 1), "bldg_Building");
+        assert_eq!(part_file_stem("bldg_Building", 0, 2), "bldg_Building_1");
+        assert_eq!(part_file_stem("bldg_Building", 1, 2), "bldg_Building_2");
+    }
+}
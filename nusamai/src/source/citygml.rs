@@ -8,12 +8,16 @@ use std::{
 };
 
 use nusamai_citygml::{CityGmlElement, CityGmlReader, Envelope, ParseError, SubTreeReader};
-use nusamai_plateau::{appearance::AppearanceStore, models, Entity};
+use nusamai_plateau::{
+    appearance::AppearanceStore, codelist::MissingCodelistPolicy, models, Entity,
+};
+use nusamai_projection::crs::EpsgCode;
 use rayon::prelude::*;
 use url::Url;
 
 use crate::{
-    parameters::Parameters,
+    get_parameter_value,
+    parameters::*,
     pipeline::{self, Feedback, Parcel, PipelineError, Sender},
     source::{DataSource, DataSourceProvider, SourceInfo},
 };
@@ -24,10 +28,21 @@ pub struct CityGmlSourceProvider {
 }
 
 impl DataSourceProvider for CityGmlSourceProvider {
-    fn create(&self, _params: &Parameters) -> Box<dyn DataSource> {
+    fn create(&self, params: &Parameters) -> Box<dyn DataSource> {
+        let strict_codelist = get_parameter_value!(params, "strict_codelist", Boolean);
+        let codelist_policy = match strict_codelist {
+            Some(true) => MissingCodelistPolicy::Error,
+            _ => MissingCodelistPolicy::Warn,
+        };
+
+        let input_epsg =
+            get_parameter_value!(params, "input_epsg", Integer).map(|epsg| epsg as EpsgCode);
+
         Box::new(CityGmlSource {
             filenames: self.filenames.clone(),
             appearance_parsing: false,
+            codelist_policy,
+            input_epsg,
         })
     }
 
@@ -38,13 +53,37 @@ impl DataSourceProvider for CityGmlSourceProvider {
     }
 
     fn parameters(&self) -> Parameters {
-        Parameters::default()
+        let mut params = Parameters::new();
+        params.define(
+            "strict_codelist".into(),
+            ParameterEntry {
+                description: "Fail on missing codelists instead of keeping the raw codes".into(),
+                required: false,
+                parameter: ParameterType::Boolean(BooleanParameter { value: None }),
+            },
+        );
+        params.define(
+            "input_epsg".into(),
+            ParameterEntry {
+                description: "EPSG code of the input CRS (for Tokyo datum or JGD2000 data)".into(),
+                required: false,
+                parameter: ParameterType::Integer(IntegerParameter {
+                    value: None,
+                    min: Some(0),
+                    max: Some(EpsgCode::MAX as i64),
+                }),
+            },
+        );
+        params
     }
 }
 
 pub struct CityGmlSource {
     filenames: Vec<PathBuf>,
     appearance_parsing: bool,
+    codelist_policy: MissingCodelistPolicy,
+    /// Overrides the CRS of the geometries (JGD2011 by default)
+    input_epsg: Option<EpsgCode>,
 }
 
 impl DataSource for CityGmlSource {
@@ -53,7 +92,7 @@ impl DataSource for CityGmlSource {
     }
 
     fn run(&mut self, downstream: Sender, feedback: &Feedback) -> pipeline::Result<()> {
-        let code_resolver = nusamai_plateau::codelist::Resolver::new();
+        let code_resolver = nusamai_plateau::codelist::Resolver::with_policy(self.codelist_policy);
 
         self.filenames.par_iter().try_for_each(|filename| {
             feedback.ensure_not_canceled()?;
@@ -68,7 +107,14 @@ impl DataSource for CityGmlSource {
             let mut citygml_reader = CityGmlReader::new(context);
 
             let mut st = citygml_reader.start_root(&mut xml_reader)?;
-            match toplevel_dispatcher(&mut st, &downstream, feedback, self.appearance_parsing) {
+            match toplevel_dispatcher(
+                &mut st,
+                &source_url,
+                &downstream,
+                feedback,
+                self.appearance_parsing,
+                self.input_epsg,
+            ) {
                 Ok(_) => Ok::<(), PipelineError>(()),
                 Err(ParseError::Canceled) => Err(PipelineError::Canceled),
                 Err(e) => Err(e.into()),
@@ -82,9 +128,11 @@ impl DataSource for CityGmlSource {
 // TODO: Move this to nusamai-plateau ?
 fn toplevel_dispatcher<R: BufRead>(
     st: &mut SubTreeReader<R>,
+    source_url: &Url,
     downstream: &Sender,
     feedback: &Feedback,
     parse_appearances: bool,
+    input_epsg: Option<EpsgCode>,
 ) -> Result<(), ParseError> {
     let mut entities = Vec::new();
     let mut global_appearances = AppearanceStore::default();
@@ -107,12 +155,15 @@ fn toplevel_dispatcher<R: BufRead>(
             b"core:cityObjectMember" => {
                 let mut cityobj: models::TopLevelCityObject = Default::default();
                 cityobj.parse(st)?;
-                let geometry_store = st.collect_geometries();
+                let mut geometry_store = st.collect_geometries();
+                if let Some(epsg) = input_epsg {
+                    geometry_store.epsg = epsg;
+                }
 
                 if let Some(root) = cityobj.into_object() {
                     let entity = Entity {
                         root,
-                        base_url: url::Url::parse("file:///dummy").unwrap(),
+                        base_url: source_url.clone(),
                         geometry_store: RwLock::new(geometry_store).into(),
                         appearance_store: Default::default(), // TODO: from local appearances
                     };
@@ -192,7 +243,7 @@ mod tests {
                     "../nusamai-plateau/tests/data/yokosuka-shi/udx/bldg/52397519_bldg_6697_op.gml",
                 )],
             };
-            let mut source = source_provider.create(&Parameters::default());
+            let mut source = source_provider.create(&source_provider.parameters());
             source.set_appearance_parsing(use_appearance);
             let (_, feedback, _) = feedback::watcher();
 
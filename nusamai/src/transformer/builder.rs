@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
 use nusamai_citygml::schema::Schema;
-use nusamai_projection::{crs, vshift::Jgd2011ToWgs84};
+use nusamai_projection::{crs, datumshift::JgdDatumShift, vshift::Jgd2011ToWgs84};
 
-use super::{transform::*, Transform};
-use crate::{sink::DataRequirements, transformer};
+use super::{transform::*, Aggregate, Transform};
+use crate::{pipeline::Feedback, sink::DataRequirements, transformer};
 
 pub struct Request {
     pub output_epsg: crs::EpsgCode,
@@ -16,6 +16,8 @@ pub struct Request {
     pub key_value: KeyValueSpec,
     pub lod_filter: LodFilterSpec,
     pub geom_stats: GeometryStatsSpec,
+    /// Keep the orthometric heights instead of converting them to ellipsoidal heights in WGS 84 outputs
+    pub keep_orthometric_height: bool,
 }
 
 impl Request {
@@ -36,6 +38,7 @@ impl From<DataRequirements> for Request {
             key_value: req.key_value,
             lod_filter: req.lod_filter,
             geom_stats: req.geom_stats,
+            keep_orthometric_height: false,
         }
     }
 }
@@ -93,14 +96,36 @@ pub enum GeometryStatsSpec {
 pub trait TransformBuilder: Send + Sync {
     fn build(&self) -> Box<dyn Transform>;
 
+    /// Builds the aggregation stage that runs before the transforms, if any
+    fn build_pre_aggregate(&self) -> Option<Box<dyn Aggregate>> {
+        None
+    }
+
+    /// Builds the aggregation stage that runs after the transforms, if any
+    fn build_aggregate(&self) -> Option<Box<dyn Aggregate>> {
+        None
+    }
+
+    /// Reports the totals of the transforms built, after all the entities are transformed
+    fn report(&self, _feedback: &Feedback) {}
+
     fn transform_schema(&self, schema: &mut Schema) {
+        if let Some(aggregate) = self.build_pre_aggregate() {
+            aggregate.transform_schema(schema);
+        }
         self.build().transform_schema(schema);
+        if let Some(aggregate) = self.build_aggregate() {
+            aggregate.transform_schema(schema);
+        }
     }
 }
 
 pub struct NusamaiTransformBuilder {
     request: transformer::Request,
     jgd2wgs: Arc<Jgd2011ToWgs84>,
+    datum_shift: Arc<JgdDatumShift>,
+    /// Shared by the cleaning transforms of all the threads
+    cleaning_stats: Arc<CleaningStats>,
 }
 
 impl TransformBuilder for NusamaiTransformBuilder {
@@ -108,10 +133,35 @@ impl TransformBuilder for NusamaiTransformBuilder {
         let mut transforms = SerialTransform::default();
         // TODO: build transformation based on config file
 
+        // Validate the geometries (before the projection, to measure in metres)
+        if let Some(validation) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.validation.as_ref())
+        {
+            transforms.push(Box::new(GeometryValidationTransform::new(
+                validation.planarity_tolerance,
+                validation.drop_invalid,
+            )));
+        }
+
+        // Compute the geometric attributes (before the projection, to measure in metres and degrees)
+        if let Some(geometry_stats) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.geometry_stats.as_ref())
+        {
+            transforms.push(Box::new(GeometryStatsTransform::new(geometry_stats)));
+        }
+
         // Transform the coordinate system
         transforms.push(Box::new(ProjectionTransform::new(
             self.jgd2wgs.clone(),
+            self.datum_shift.clone(),
             self.request.output_epsg,
+            self.request.keep_orthometric_height,
         )));
 
         match self.request.geom_stats {
@@ -126,6 +176,95 @@ impl TransformBuilder for NusamaiTransformBuilder {
             transforms.push(Box::new(ApplyAppearanceTransform::new()));
         }
 
+        // Clean the geometries (after the appearance, to keep the texture coordinates aligned)
+        if let Some(cleaning) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.cleaning.as_ref())
+        {
+            transforms.push(Box::new(GeometryCleaningTransform::new(
+                cleaning.tolerance,
+                cleaning.upward_surfaces,
+                self.cleaning_stats.clone(),
+            )));
+        }
+
+        // Extract the building footprints
+        if let Some(footprint) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.footprint.as_ref())
+        {
+            transforms.push(Box::new(FootprintTransform::new(footprint.replace)));
+        }
+
+        // Generate LOD1 block models from the footprints
+        if let Some(extrude) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.extrude.as_ref())
+        {
+            transforms.push(Box::new(ExtrudeTransform::new(
+                extrude.height_attribute.clone(),
+                extrude.default_height,
+            )));
+        }
+
+        // Simplify the surfaces and curves
+        if let Some(simplify) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.simplify.as_ref())
+        {
+            transforms.push(Box::new(SimplifyTransform::new(
+                simplify.tolerance,
+                simplify.method,
+            )));
+        }
+
+        // Split the thematic surfaces into separate entities (after the transforms that edit the geometries,
+        // as the surfaces share the geometry store with their parent; before selecting the attributes, to inherit
+        // them by the qualified names)
+        if let Some(thematic_surfaces) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.thematic_surfaces.as_ref())
+        {
+            transforms.push(Box::new(ThematicSurfaceTransform::new(thematic_surfaces)));
+        }
+
+        // Select the attributes to output (before renaming, to match the qualified names)
+        if let Some(mapping_rules) = &self.request.mapping_rules {
+            if !mapping_rules.keep.is_empty() || !mapping_rules.drop.is_empty() {
+                transforms.push(Box::new(AttributeSelectionTransform::new(
+                    &mapping_rules.keep,
+                    &mapping_rules.drop,
+                )));
+            }
+        }
+
+        // Normalize the units of measure (before renaming, to match the qualified names)
+        if let Some(units) = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.units.as_ref())
+        {
+            transforms.push(Box::new(MeasureUnitTransform::new(units)));
+        }
+
+        // Cast and format the attributes (after normalizing the units, to format the converted values)
+        if let Some(mapping_rules) = &self.request.mapping_rules {
+            if !mapping_rules.cast.is_empty() {
+                transforms.push(Box::new(AttributeCastTransform::new(&mapping_rules.cast)));
+            }
+        }
+
         transforms.push({
             let mut renamer = Box::<EditFieldNamesTransform>::default();
             if self.request.shorten_names_for_shapefile {
@@ -139,6 +278,13 @@ impl TransformBuilder for NusamaiTransformBuilder {
             renamer
         });
 
+        // Output the raw codes of codelist values (after renaming, to follow the new names)
+        if let Some(mapping_rules) = &self.request.mapping_rules {
+            if mapping_rules.codelist != transformer::CodelistOutput::Label {
+                transforms.push(Box::new(CodeOutputTransform::new(mapping_rules.codelist)));
+            }
+        }
+
         transforms.push(Box::new(FilterLodTransform::new(
             self.request.lod_filter.mask,
             self.request.lod_filter.mode,
@@ -184,13 +330,306 @@ impl TransformBuilder for NusamaiTransformBuilder {
 
         Box::new(transforms)
     }
+
+    fn build_pre_aggregate(&self) -> Option<Box<dyn Aggregate>> {
+        // Remove the features repeated in several input files (before the transforms, to find the keys as parsed)
+        let dedupe = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.dedupe.as_ref())?;
+        Some(Box::new(DedupeAggregate::new(dedupe.key, dedupe.mode)))
+    }
+
+    fn build_aggregate(&self) -> Option<Box<dyn Aggregate>> {
+        // Dissolve the polygons by the attribute
+        let dissolve = self
+            .request
+            .mapping_rules
+            .as_ref()
+            .and_then(|rules| rules.dissolve.as_ref())?;
+        Some(Box::new(DissolveAggregate::new(
+            dissolve.attribute.clone(),
+            dissolve.types.clone(),
+        )))
+    }
+
+    fn report(&self, feedback: &Feedback) {
+        self.cleaning_stats.report(feedback);
+    }
 }
 
 impl NusamaiTransformBuilder {
     pub fn new(req: transformer::Request) -> Self {
+        Self::with_geoid(req, Jgd2011ToWgs84::default())
+    }
+
+    /// Creates a builder that converts the heights with the given geoid model (e.g. loaded from a file)
+    pub fn with_geoid(req: transformer::Request, jgd2wgs: Jgd2011ToWgs84) -> Self {
         Self {
             request: req,
-            jgd2wgs: Jgd2011ToWgs84::default().into(),
+            jgd2wgs: jgd2wgs.into(),
+            datum_shift: Default::default(),
+            cleaning_stats: Default::default(),
+        }
+    }
+
+    /// Sets the grids to convert the inputs in the old datums (Tokyo datum, JGD2000) into JGD2011
+    pub fn with_datum_shift(mut self, datum_shift: JgdDatumShift) -> Self {
+        self.datum_shift = datum_shift.into();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{
+        object::{Object, ObjectStereotype, Value},
+        GeometryRef, GeometryStore, GeometryType,
+    };
+    use nusamai_geometry::{algorithm::plane::newell_normal, MultiPolygon};
+    use nusamai_plateau::Entity;
+    use nusamai_projection::{crs::EpsgCode, jprect::JPRZone};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_builder(
+        output_epsg: EpsgCode,
+        mapping_rules: &str,
+        lod_filter: LodFilterSpec,
+    ) -> NusamaiTransformBuilder {
+        NusamaiTransformBuilder::new(Request {
+            output_epsg,
+            shorten_names_for_shapefile: false,
+            mapping_rules: Some(serde_json::from_str(mapping_rules).unwrap()),
+            tree_flattening: TreeFlatteningSpec::None,
+            apply_appearance: false,
+            mergedown: MergedownSpec::NoMergedown,
+            key_value: KeyValueSpec::None,
+            lod_filter,
+            geom_stats: GeometryStatsSpec::None,
+            keep_orthometric_height: false,
+        })
+    }
+
+    /// Runs the transforms, checking that no warnings are reported
+    fn run(builder: &NusamaiTransformBuilder, entity: Entity) -> Vec<Entity> {
+        let (watcher, feedback, _canceller) = feedback::watcher();
+        let mut out = Vec::new();
+        builder.build().transform(&feedback, entity, &mut out);
+        drop(feedback);
+        let errors: Vec<_> = watcher
+            .into_iter()
+            .filter(|msg| msg.level <= log::Level::Warn)
+            .map(|msg| msg.message)
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+        out
+    }
+
+    #[test]
+    fn thematic_surfaces_projected_once() {
+        let builder = make_builder(
+            crs::EPSG_JGD2011_JPRECT_IX,
+            r#"{"thematic_surfaces": {"inherit": ["bldg:usage"]}}"#,
+            LodFilterSpec::default(),
+        );
+
+        // A building with a roof and a wall, in (lat, lng, height)
+        let vertices = vec![
+            [35.6812, 139.7671, 0.],
+            [35.6812, 139.7672, 0.],
+            [35.6813, 139.7672, 5.],
+            [35.6813, 139.7671, 5.],
+        ];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 2, 3]);
+        mpoly.add_exterior([0, 1, 2]);
+        let surface = |typename: &str, id: &str, pos: u32| {
+            Value::Object(Object {
+                typename: typename.to_string().into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: id.into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 2,
+                        pos,
+                        len: 1,
+                    }],
+                },
+            })
+        };
+        let mut attributes = nusamai_citygml::object::Map::default();
+        attributes.insert(
+            "bldg:boundedBy".into(),
+            Value::Array(vec![
+                surface("bldg:RoofSurface", "roof_1", 0),
+                surface("bldg:WallSurface", "wall_1", 1),
+            ]),
+        );
+        let entity = test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 2,
+                        pos: 0,
+                        len: 2,
+                    }],
+                },
+            }),
+            GeometryStore {
+                epsg: crs::EPSG_JGD2011_GEOGRAPHIC_3D,
+                vertices: vertices.clone(),
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        );
+
+        let out = run(&builder, entity);
+        assert_eq!(out.len(), 3);
+
+        let proj = JPRZone::from_epsg(crs::EPSG_JGD2011_JPRECT_IX)
+            .unwrap()
+            .projection();
+        for entity in &out {
+            let geom_store = entity.geometry_store.read().unwrap();
+            assert_eq!(geom_store.epsg, crs::EPSG_JGD2011_JPRECT_IX);
+            for (v, [lat, lng, height]) in geom_store.vertices.iter().zip(&vertices) {
+                let (x, y, _) = proj.project_forward(*lng, *lat, 0.).unwrap();
+                assert!((v[0] - x).abs() < 1e-6 && (v[1] - y).abs() < 1e-6);
+                assert_eq!(v[2], *height);
+            }
+        }
+    }
+
+    #[test]
+    fn geographic_output_after_projection() {
+        // The JGD2011 geographic output keeps the EPSG code, but the vertices are (lng, lat) after the projection
+        let builder = make_builder(
+            crs::EPSG_JGD2011_GEOGRAPHIC_3D,
+            r#"{"cleaning": {"upward_surfaces": true}, "footprint": {}, "extrude": {"default_height": 10}}"#,
+            LodFilterSpec {
+                mask: LodMask::all(),
+                mode: LodFilterMode::All,
+            },
+        );
+
+        // Equirectangular approximation around the first vertex, in (lng, lat)
+        let (lat0, lng0, d): (f64, f64, f64) = (35.6812, 139.7671, 0.0001);
+        let k = 6378137.0_f64.to_radians();
+        let to_metres = |[lng, lat, h]: [f64; 3]| {
+            [
+                (lng - lng0) * k * lat0.to_radians().cos(),
+                (lat - lat0) * k,
+                h,
+            ]
+        };
+        let expected_area = (d * k) * (d * k * lat0.to_radians().cos());
+
+        // A LOD2 box of about 10 x 10 x 10 metres, in (lat, lng, height)
+        let mut vertices: Vec<[f64; 3]> = (0..8)
+            .map(|i| {
+                let (x, y, z) = (i & 1, (i >> 1) & 1, (i >> 2) & 1);
+                [lat0 + y as f64 * d, lng0 + x as f64 * d, z as f64 * 10.]
+            })
+            .collect();
+        // and a vertex repeated 0.9 mm to the east, within the cleaning tolerance
+        let [lat, lng, h] = vertices[4];
+        vertices.push([lat, lng + 0.0009 / (k * lat0.to_radians().cos()), h]);
+        let mut mpoly = MultiPolygon::new();
+        // bottom, top, and the south, east, north and west walls (facing outward)
+        for ring in [
+            vec![0, 2, 3, 1],
+            vec![4, 8, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![1, 3, 7, 5],
+            vec![3, 2, 6, 7],
+            vec![2, 0, 4, 6],
+        ] {
+            mpoly.add_exterior(ring);
+        }
+        let entity = test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Solid,
+                        lod: 2,
+                        pos: 0,
+                        len: 6,
+                    }],
+                },
+            }),
+            GeometryStore {
+                epsg: crs::EPSG_JGD2011_GEOGRAPHIC_3D,
+                vertices,
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        );
+
+        let out = run(&builder, entity);
+        let mut lods = Vec::new();
+        for entity in &out {
+            let Value::Object(obj) = &entity.root else {
+                unreachable!()
+            };
+            let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype else {
+                unreachable!()
+            };
+            let geom_store = entity.geometry_store.read().unwrap();
+            assert_eq!(geom_store.epsg, crs::EPSG_JGD2011_GEOGRAPHIC_3D);
+            for geom in geometries {
+                lods.push(geom.lod);
+                let range = geom.pos as usize..(geom.pos + geom.len) as usize;
+                let normals: Vec<_> = geom_store
+                    .multipolygon
+                    .iter_range(range.clone())
+                    .map(|poly| {
+                        let ring: Vec<_> = poly
+                            .exterior()
+                            .iter()
+                            .map(|idx| to_metres(geom_store.vertices[idx as usize]))
+                            .collect();
+                        (newell_normal(&ring), ring[0])
+                    })
+                    .collect();
+                match geom.ty {
+                    GeometryType::Surface => {
+                        // The footprint faces upward, with the area of the box
+                        let area: f64 = normals.iter().map(|(n, _)| n[2] / 2.).sum();
+                        assert!((area / expected_area - 1.).abs() < 1e-3, "{}", area);
+                    }
+                    GeometryType::Solid => {
+                        // The repeated vertex is removed
+                        assert!(geom_store
+                            .multipolygon
+                            .iter_range(range.clone())
+                            .all(|poly| poly.exterior().len() == 4));
+                        // The faces are outward, with the volume of the box
+                        let volume: f64 = normals
+                            .iter()
+                            .map(|(n, p)| (n[0] * p[0] + n[1] * p[1] + n[2] * p[2]) / 6.)
+                            .sum();
+                        assert!(
+                            (volume / (expected_area * 10.) - 1.).abs() < 1e-3,
+                            "{}",
+                            volume
+                        );
+                    }
+                    _ => unreachable!(),
+                }
+            }
         }
+        lods.sort();
+        assert_eq!(lods, [0, 1, 2]);
     }
 }
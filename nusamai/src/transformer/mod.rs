@@ -33,3 +33,19 @@ pub trait Transform: Send {
     /// Transform the schema
     fn transform_schema(&self, schema: &mut Schema);
 }
+
+/// Stage that merges entities into new ones, which runs before or after the transforms
+///
+/// Unlike `Transform`, a single instance sees all the entities, so it is shared among the threads.
+pub trait Aggregate: Send + Sync {
+    /// Takes the entity to aggregate, or returns it back if it is not a target
+    fn add(&self, feedback: &Feedback, entity: Entity) -> Option<Entity>;
+    /// Outputs the aggregated entities one by one after all the entities are added
+    fn finish(
+        self: Box<Self>,
+        feedback: &Feedback,
+        out: &mut dyn FnMut(Entity) -> Result<()>,
+    ) -> Result<()>;
+    /// Transform the schema
+    fn transform_schema(&self, schema: &mut Schema);
+}
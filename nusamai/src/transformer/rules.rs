@@ -1,12 +1,270 @@
 use hashbrown::HashMap;
+use nusamai_geometry::algorithm::simplify::SimplifyMethod;
 use serde::{Deserialize, Serialize};
 
 /// Rules specified by the user in a JSON file
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MappingRules {
+    #[serde(default)]
     pub rename: RenameRules,
+    /// Attribute paths to output (e.g. `["bldg:measuredHeight", "uro:buildingDetailAttribute/uro:*"]`).
+    /// All the attributes are output if empty
+    /// Used by the `AttributeSelectionTransform` transformer
+    #[serde(default)]
+    pub keep: Vec<String>,
+    /// Attribute paths to remove (e.g. `["uro:*Attribute"]`), which takes precedence over `keep`
+    /// Used by the `AttributeSelectionTransform` transformer
+    #[serde(default)]
+    pub drop: Vec<String>,
+    #[serde(default)]
+    pub dedupe: Option<DedupeRules>,
+    #[serde(default)]
+    pub validation: Option<ValidationRules>,
+    #[serde(default)]
+    pub cleaning: Option<CleaningRules>,
+    #[serde(default)]
+    pub codelist: CodelistOutput,
+    #[serde(default)]
+    pub units: Option<UnitRules>,
+    #[serde(default)]
+    pub cast: CastRules,
+    #[serde(default)]
+    pub simplify: Option<SimplifyRules>,
+    #[serde(default)]
+    pub footprint: Option<FootprintRules>,
+    #[serde(default)]
+    pub extrude: Option<ExtrudeRules>,
+    #[serde(default)]
+    pub dissolve: Option<DissolveRules>,
+    #[serde(default)]
+    pub geometry_stats: Option<GeometryStatsRules>,
+    #[serde(default)]
+    pub thematic_surfaces: Option<ThematicSurfaceRules>,
 }
 
 /// Rules specified by the user to rename the attributes
 /// Used by the `EditFieldNamesTransform` transformer
 pub type RenameRules = HashMap<String, String>;
+
+/// Rules specified by the user to remove the features repeated in several input files
+/// Used by the `DedupeAggregate` aggregation
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DedupeRules {
+    /// What identifies the duplicates
+    #[serde(default)]
+    pub key: DedupeKey,
+    /// What to do with the duplicates
+    #[serde(default)]
+    pub mode: DedupeMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeKey {
+    /// The `gml:id` of the features
+    #[default]
+    Id,
+    /// The `uro:buildingID` of the features, or their `gml:id` if they have none
+    BuildingId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupeMode {
+    /// Keep the first feature and discard the others
+    #[default]
+    First,
+    /// Merge the geometries and the child features of the duplicates into the first one
+    Merge,
+}
+
+/// How to output the attributes whose values come from codelists
+/// Used by the `CodeOutputTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodelistOutput {
+    /// Output the resolved labels (e.g. `usage: "業務施設"`)
+    #[default]
+    Label,
+    /// Output the raw codes (e.g. `usage: "401"`)
+    Code,
+    /// Output both the labels and the raw codes (e.g. `usage` and `usage_code`)
+    Both,
+}
+
+/// Rules specified by the user to validate the geometries
+/// Used by the `GeometryValidationTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidationRules {
+    /// Tolerance (in metres) for the planarity check of polygons
+    #[serde(default = "default_planarity_tolerance")]
+    pub planarity_tolerance: f64,
+    /// Whether to discard entities with invalid geometries
+    #[serde(default)]
+    pub drop_invalid: bool,
+}
+
+fn default_planarity_tolerance() -> f64 {
+    0.01
+}
+
+/// Rules specified by the user to clean the geometries
+/// Used by the `GeometryCleaningTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CleaningRules {
+    /// Tolerance (in metres) for the repeated vertices, spikes and rings with no area
+    #[serde(default = "default_cleaning_tolerance")]
+    pub tolerance: f64,
+    /// Whether to make the horizontal surfaces face upward (e.g. for 2D outputs)
+    #[serde(default)]
+    pub upward_surfaces: bool,
+}
+
+impl Default for CleaningRules {
+    fn default() -> Self {
+        Self {
+            tolerance: default_cleaning_tolerance(),
+            upward_surfaces: false,
+        }
+    }
+}
+
+fn default_cleaning_tolerance() -> f64 {
+    0.001
+}
+
+/// Rules specified by the user to normalize the units of measure
+/// Used by the `MeasureUnitTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnitRules {
+    /// Whether to convert the measures of the known attributes (e.g. `*Height` and `*Area`) to the SI units
+    /// (e.g. `mm` -> `m`, `ha` -> `m2`)
+    #[serde(default = "default_convert_units")]
+    pub convert: bool,
+    /// Whether to output the unit as a sibling field (e.g. `measuredHeight_uom`)
+    #[serde(default)]
+    pub sibling_field: bool,
+    /// Target unit per attribute (e.g. `{"*:measuredHeight": "m"}`), which takes precedence over `convert`
+    #[serde(default)]
+    pub targets: HashMap<String, String>,
+}
+
+impl Default for UnitRules {
+    fn default() -> Self {
+        Self {
+            convert: default_convert_units(),
+            sibling_field: false,
+            targets: HashMap::new(),
+        }
+    }
+}
+
+fn default_convert_units() -> bool {
+    true
+}
+
+/// Rules specified by the user to cast or format the attributes, by attribute name
+/// (e.g. `{"bldg:usage": {"type": "string"}, "*:measuredHeight": {"type": "double", "decimals": 1}}`;
+/// the exact match takes precedence)
+/// Used by the `AttributeCastTransform` transformer
+pub type CastRules = HashMap<String, CastSpec>;
+
+/// Type to cast an attribute into, and how to format it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CastSpec {
+    #[serde(rename = "type")]
+    pub to: CastType,
+    /// Number of decimal places (for `double`, and numbers cast into `string`)
+    #[serde(default)]
+    pub decimals: Option<usize>,
+    /// strftime-like pattern (e.g. `%Y/%m/%d`) to format dates into `string`, or to parse strings into `date`
+    #[serde(default)]
+    pub date_format: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CastType {
+    String,
+    Integer,
+    Double,
+    Boolean,
+    Date,
+}
+
+/// Rules specified by the user to simplify the geometries
+/// Used by the `SimplifyTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimplifyRules {
+    /// Tolerance (in metres)
+    pub tolerance: f64,
+    /// Simplification algorithm (`douglas_peucker` or `visvalingam`)
+    #[serde(default)]
+    pub method: SimplifyMethod,
+}
+
+/// Rules specified by the user to extract the building footprints
+/// Used by the `FootprintTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FootprintRules {
+    /// Whether to remove the other geometries of the buildings
+    #[serde(default)]
+    pub replace: bool,
+}
+
+/// Rules specified by the user to generate LOD1 block models from the footprints
+/// Used by the `ExtrudeTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtrudeRules {
+    /// Attribute that holds the height of the buildings
+    #[serde(default = "default_height_attribute")]
+    pub height_attribute: String,
+    /// Height (in metres) used when the attribute is missing
+    #[serde(default)]
+    pub default_height: Option<f64>,
+}
+
+fn default_height_attribute() -> String {
+    "bldg:measuredHeight".to_string()
+}
+
+/// Rules specified by the user to dissolve the polygons by an attribute
+/// Used by the `DissolveAggregate` aggregation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DissolveRules {
+    /// Attribute to group the features by (e.g. `luse:class`)
+    pub attribute: String,
+    /// Feature types to dissolve (e.g. `["luse:LandUse", "urf:*"]`). All the types if empty
+    #[serde(default)]
+    pub types: Vec<String>,
+}
+
+/// Rules specified by the user to compute attributes from the geometries
+/// Used by the `GeometryStatsTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GeometryStatsRules {
+    /// Planar area of the footprint (`footprintArea`, in square metres)
+    #[serde(default)]
+    pub footprint_area: bool,
+    /// Total areas of the roof, wall and ground surfaces (`roofSurfaceArea`, `wallSurfaceArea` and `groundSurfaceArea`)
+    #[serde(default)]
+    pub surface_areas: bool,
+    /// Volume of the solids (`volume`, in cubic metres)
+    #[serde(default)]
+    pub volume: bool,
+    /// Centroid of the footprint (`centroidLng` and `centroidLat`)
+    #[serde(default)]
+    pub centroid: bool,
+    /// Bounding box (`minLng`, `minLat`, `maxLng` and `maxLat`)
+    #[serde(default)]
+    pub bbox: bool,
+}
+
+/// Rules specified by the user to output the thematic surfaces as separate features
+/// Used by the `ThematicSurfaceTransform` transformer
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ThematicSurfaceRules {
+    /// Attributes of the parent features copied to the surfaces (e.g. `["bldg:measuredHeight", "bldg:usage"]`)
+    #[serde(default)]
+    pub inherit: Vec<String>,
+}
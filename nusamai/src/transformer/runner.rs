@@ -1,7 +1,9 @@
+use std::sync::mpsc;
+
 use rayon::prelude::*;
 
-use super::{builder::TransformBuilder, Transformer};
-use crate::pipeline::{Feedback, Parcel, Receiver, Result, Sender};
+use super::{builder::TransformBuilder, Aggregate, Transformer};
+use crate::pipeline::{Feedback, Parcel, PipelineError, Receiver, Result, Sender};
 
 // transforms: Vec<Box<dyn Transform>>,
 
@@ -18,21 +20,83 @@ impl<T: TransformBuilder> MultiThreadTransformer<T> {
 
 impl<T: TransformBuilder> Transformer for MultiThreadTransformer<T> {
     fn run(&self, upstream: Receiver, downstream: Sender, feedback: &Feedback) -> Result<()> {
-        upstream.into_iter().par_bridge().try_for_each_init(
-            || (self.builder.build(), Vec::default()),
-            |(transform, buf), parcel| {
-                feedback.ensure_not_canceled()?;
+        let pre_aggregate = self.builder.build_pre_aggregate();
+        let aggregate = self.builder.build_aggregate();
+
+        std::thread::scope(|s| {
+            // Aggregate the entities before the transforms in a separate thread
+            let upstream = match pre_aggregate {
+                Some(pre_aggregate) => {
+                    let (sender, receiver) = mpsc::sync_channel(1000);
+                    s.spawn(move || {
+                        if let Err(error) =
+                            pre_aggregation_stage(feedback, pre_aggregate, upstream, sender)
+                        {
+                            feedback.fatal_error(error);
+                        }
+                    });
+                    receiver
+                }
+                None => upstream,
+            };
 
-                // Apply transform to entity
-                transform.transform(feedback, parcel.entity, buf);
+            upstream.into_iter().par_bridge().try_for_each_init(
+                || (self.builder.build(), Vec::default()),
+                |(transform, buf), parcel| -> Result<()> {
+                    feedback.ensure_not_canceled()?;
 
-                for entity in buf.drain(..) {
-                    if downstream.send(Parcel { entity }).is_err() {
-                        break;
+                    // Apply transform to entity
+                    transform.transform(feedback, parcel.entity, buf);
+
+                    for entity in buf.drain(..) {
+                        // Hold the entities to aggregate until the end
+                        let entity = match &aggregate {
+                            Some(aggregate) => match aggregate.add(feedback, entity) {
+                                Some(entity) => entity,
+                                None => continue,
+                            },
+                            None => entity,
+                        };
+                        if downstream.send(Parcel { entity }).is_err() {
+                            break;
+                        }
                     }
-                }
-                Ok(())
-            },
-        )
+                    Ok(())
+                },
+            )
+        })?;
+        self.builder.report(feedback);
+
+        if let Some(aggregate) = aggregate {
+            feedback.ensure_not_canceled()?;
+            aggregate.finish(feedback, &mut |entity| {
+                downstream
+                    .send(Parcel { entity })
+                    .map_err(|_| PipelineError::Canceled)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn pre_aggregation_stage(
+    feedback: &Feedback,
+    aggregate: Box<dyn Aggregate>,
+    upstream: Receiver,
+    downstream: Sender,
+) -> Result<()> {
+    // NOTE: A send error means that the downstream has stopped, so it is reported as a cancellation
+    let send = |entity| {
+        downstream
+            .send(Parcel { entity })
+            .map_err(|_| PipelineError::Canceled)
+    };
+    for parcel in upstream {
+        feedback.ensure_not_canceled()?;
+        if let Some(entity) = aggregate.add(feedback, parcel.entity) {
+            send(entity)?;
+        }
     }
+    feedback.ensure_not_canceled()?;
+    aggregate.finish(feedback, &mut |entity| send(entity))
 }
@@ -0,0 +1,68 @@
+//! Helpers for boolean operations on polygons (backed by the `geo` crate)
+
+use geo::BooleanOps;
+
+/// Creates a 2D polygon from rings of (x, y) coordinates. The first ring is the exterior.
+pub(crate) fn make_polygon(mut rings: impl Iterator<Item = Vec<[f64; 2]>>) -> geo::Polygon<f64> {
+    let exterior = rings.next().unwrap_or_default();
+    geo::Polygon::new(exterior.into(), rings.map(|ring| ring.into()).collect())
+}
+
+/// Computes the union of all the polygons.
+///
+/// The polygons are merged pairwise in a balanced manner, which is much faster
+/// than merging them one by one into a growing result.
+pub(crate) fn union_all(polygons: Vec<geo::Polygon<f64>>) -> geo::MultiPolygon<f64> {
+    let mut parts: Vec<geo::MultiPolygon<f64>> = polygons
+        .into_iter()
+        .map(|poly| geo::MultiPolygon::new(vec![poly]))
+        .collect();
+    while parts.len() > 1 {
+        parts = parts
+            .chunks(2)
+            .map(|pair| match pair {
+                [a, b] => a.union(b),
+                [a] => a.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    parts
+        .pop()
+        .unwrap_or_else(|| geo::MultiPolygon::new(vec![]))
+}
+
+/// Returns the coordinates of a ring without the closing point.
+pub(crate) fn open_ring(ring: &geo::LineString<f64>) -> Vec<[f64; 2]> {
+    let mut coords: Vec<[f64; 2]> = ring.coords().map(|c| [c.x, c.y]).collect();
+    if coords.len() >= 2 && coords.first() == coords.last() {
+        coords.pop();
+    }
+    coords
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_all() {
+        let squares = (0..3)
+            .map(|i| {
+                let x = i as f64;
+                make_polygon(std::iter::once(vec![
+                    [x, 0.],
+                    [x + 1., 0.],
+                    [x + 1., 1.],
+                    [x, 1.],
+                ]))
+            })
+            .collect();
+        let union = union_all(squares);
+        assert_eq!(union.0.len(), 1);
+        let exterior = open_ring(union.0[0].exterior());
+        assert_eq!(exterior.len(), 4);
+        assert!(exterior.contains(&[3., 1.]));
+        assert!(union_all(vec![]).0.is_empty());
+    }
+}
@@ -0,0 +1,445 @@
+use std::fmt::Write;
+
+use hashbrown::{HashMap, HashSet};
+use nusamai_citygml::{
+    object::{Map, Value},
+    schema::{self, Schema, TypeDef, TypeRef},
+    Date,
+};
+use nusamai_plateau::Entity;
+
+use crate::{
+    pipeline::Feedback,
+    transformer::{CastRules, CastSpec, CastType, Transform},
+};
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Transform to cast the attributes into the given types, and to format them
+///
+/// - `string`: codes become their labels, numbers are formatted with `decimals` and dates with `date_format`
+/// - `integer` / `double`: codes are parsed from their raw codes, and strings from their texts
+/// - `boolean`: numbers are `true` unless zero, and strings/codes are parsed from `true`/`false`/`1`/`0`
+/// - `date`: strings are parsed with `date_format` (`%Y-%m-%d` by default)
+///
+/// Values that cannot be cast are removed, since they would not fit the new type of the attribute.
+#[derive(Default, Clone)]
+pub struct AttributeCastTransform {
+    // Exact string match dictionary
+    exact_specs: HashMap<String, CastSpec>,
+    // general suffix match dictionary - the stored keys are the string after the prefix "*:"
+    general_specs: HashMap<String, CastSpec>,
+    // Attributes already reported as uncastable (to warn only once)
+    reported: HashSet<String>,
+}
+
+impl AttributeCastTransform {
+    pub fn new(rules: &CastRules) -> Self {
+        let mut transform = Self::default();
+        for (key, spec) in rules {
+            if let Some(key_stripped) = key.strip_prefix("*:") {
+                transform
+                    .general_specs
+                    .insert(key_stripped.into(), spec.clone());
+            } else {
+                transform.exact_specs.insert(key.clone(), spec.clone());
+            }
+        }
+        transform
+    }
+
+    fn spec(&self, name: &str) -> Option<&CastSpec> {
+        if let Some(spec) = self.exact_specs.get(name) {
+            return Some(spec);
+        }
+        let key = name.find(':').map(|pos| &name[pos + 1..]).unwrap_or(name);
+        self.general_specs.get(key)
+    }
+}
+
+impl Transform for AttributeCastTransform {
+    fn transform(&mut self, feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        self.edit_tree(feedback, &mut entity.root);
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        let update_attrs = |attrs: &mut schema::Map| {
+            for (key, attr) in attrs.iter_mut() {
+                let Some(spec) = self.spec(key) else {
+                    continue;
+                };
+                if !is_castable_type(&attr.type_ref) {
+                    continue;
+                }
+                // The units are kept only for the numbers
+                if !matches!(spec.to, CastType::Integer | CastType::Double) {
+                    attr.uom = None;
+                }
+                attr.type_ref = match spec.to {
+                    CastType::String => TypeRef::String,
+                    CastType::Integer => TypeRef::Integer,
+                    CastType::Double => TypeRef::Double,
+                    CastType::Boolean => TypeRef::Boolean,
+                    CastType::Date => TypeRef::Date,
+                };
+            }
+        };
+
+        for ty in schema.types.values_mut() {
+            match ty {
+                TypeDef::Data(data) => update_attrs(&mut data.attributes),
+                TypeDef::Feature(feat) => update_attrs(&mut feat.attributes),
+                TypeDef::Property(_) => continue,
+            };
+        }
+    }
+}
+
+/// Whether the attributes of the type hold scalar values that can be cast
+fn is_castable_type(type_ref: &TypeRef) -> bool {
+    !matches!(
+        type_ref,
+        TypeRef::Named(_) | TypeRef::JsonString(_) | TypeRef::Point
+    )
+}
+
+impl AttributeCastTransform {
+    fn edit_tree(&mut self, feedback: &Feedback, value: &mut Value) {
+        match value {
+            Value::Object(obj) => {
+                let mut new_attrs = Map::default();
+                for (key, mut value) in obj.attributes.drain(..) {
+                    if let Some(spec) = self.spec(&key).cloned() {
+                        if !self.cast_in_place(&spec, &mut value) {
+                            if self.reported.insert(key.clone()) {
+                                feedback.warn(format!(
+                                    "Some values of {key} cannot be cast into {:?}, they are removed",
+                                    spec.to
+                                ));
+                            }
+                            if !matches!(value, Value::Array(_)) {
+                                continue;
+                            }
+                        }
+                    }
+                    self.edit_tree(feedback, &mut value);
+                    new_attrs.insert(key, value);
+                }
+                obj.attributes = new_attrs;
+            }
+            Value::Array(arr) => {
+                for v in arr.iter_mut() {
+                    self.edit_tree(feedback, v);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Casts the value (or the elements of an array). Returns false if some values could not be cast.
+    fn cast_in_place(&self, spec: &CastSpec, value: &mut Value) -> bool {
+        match value {
+            Value::Array(arr) => {
+                let len = arr.len();
+                arr.retain_mut(|v| self.cast_in_place(spec, v));
+                arr.len() == len
+            }
+            // Nested objects are left as they are
+            Value::Object(_) => true,
+            _ => match cast_value(value, spec) {
+                Some(casted) => {
+                    *value = casted;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+/// Casts a scalar value into the type given by the spec.
+fn cast_value(value: &Value, spec: &CastSpec) -> Option<Value> {
+    match spec.to {
+        CastType::String => to_string(value, spec).map(Value::String),
+        CastType::Integer => to_integer(value).map(Value::Integer),
+        CastType::Double => to_double(value).map(|v| Value::Double(round(v, spec.decimals))),
+        CastType::Boolean => to_boolean(value).map(Value::Boolean),
+        CastType::Date => to_date(value, spec).map(Value::Date),
+    }
+}
+
+fn to_string(value: &Value, spec: &CastSpec) -> Option<String> {
+    let format_number = |v: f64| match spec.decimals {
+        Some(decimals) => format!("{v:.decimals$}"),
+        None => v.to_string(),
+    };
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Code(c) => Some(c.value().to_string()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::NonNegativeInteger(u) => Some(u.to_string()),
+        Value::Double(d) => Some(format_number(*d)),
+        Value::Measure(m) => Some(format_number(m.value())),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Uri(u) => Some(u.value().to_string()),
+        Value::Date(d) => format_date(d, spec.date_format.as_deref()),
+        Value::Point(_) | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+fn to_integer(value: &Value) -> Option<i64> {
+    let from_f64 = |v: f64| {
+        let v = v.round();
+        (v.is_finite() && v >= i64::MIN as f64 && v <= i64::MAX as f64).then_some(v as i64)
+    };
+    let from_str = |s: &str| {
+        let s = s.trim();
+        s.parse::<i64>()
+            .ok()
+            .or_else(|| s.parse::<f64>().ok().and_then(from_f64))
+    };
+    match value {
+        Value::Integer(i) => Some(*i),
+        Value::NonNegativeInteger(u) => i64::try_from(*u).ok(),
+        Value::Double(d) => from_f64(*d),
+        Value::Measure(m) => from_f64(m.value()),
+        Value::Boolean(b) => Some(*b as i64),
+        Value::String(s) => from_str(s),
+        Value::Code(c) => from_str(c.code()),
+        _ => None,
+    }
+}
+
+fn to_double(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(i) => Some(*i as f64),
+        Value::NonNegativeInteger(u) => Some(*u as f64),
+        Value::Double(d) => Some(*d),
+        Value::Measure(m) => Some(m.value()),
+        Value::Boolean(b) => Some(*b as i64 as f64),
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Code(c) => c.code().trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn to_boolean(value: &Value) -> Option<bool> {
+    let from_str = |s: &str| match s.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    };
+    match value {
+        Value::Boolean(b) => Some(*b),
+        Value::Integer(i) => Some(*i != 0),
+        Value::NonNegativeInteger(u) => Some(*u != 0),
+        Value::Double(d) => Some(*d != 0.),
+        Value::Measure(m) => Some(m.value() != 0.),
+        Value::String(s) => from_str(s),
+        Value::Code(c) => from_str(c.code()),
+        _ => None,
+    }
+}
+
+fn to_date(value: &Value, spec: &CastSpec) -> Option<Date> {
+    let format = spec.date_format.as_deref().unwrap_or(DEFAULT_DATE_FORMAT);
+    match value {
+        Value::Date(d) => Some(*d),
+        Value::String(s) => Date::parse_from_str(s.trim(), format).ok(),
+        _ => None,
+    }
+}
+
+fn format_date(date: &Date, format: Option<&str>) -> Option<String> {
+    let mut s = String::new();
+    // Writing (instead of `to_string()`) reports the invalid patterns as errors rather than panicking
+    write!(s, "{}", date.format(format.unwrap_or(DEFAULT_DATE_FORMAT))).ok()?;
+    Some(s)
+}
+
+fn round(v: f64, decimals: Option<usize>) -> f64 {
+    match decimals {
+        Some(decimals) => {
+            let factor = 10f64.powi(decimals.min(15) as i32);
+            (v * factor).round() / factor
+        }
+        None => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, schema::FeatureTypeDef, Code, GeometryStore, Measure};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity() -> Entity {
+        let mut attributes = Map::default();
+        attributes.insert(
+            "bldg:usage".into(),
+            Value::Code(Code::new("業務施設".into(), "401".into())),
+        );
+        attributes.insert(
+            "bldg:measuredHeight".into(),
+            Value::Measure(Measure::with_uom(12.345, Some("m".into()))),
+        );
+        attributes.insert("uro:surveyYear".into(), Value::String("2020".into()));
+        attributes.insert(
+            "bldg:yearOfConstruction".into(),
+            Value::Date(Date::from_ymd_opt(1998, 4, 1).unwrap()),
+        );
+        attributes.insert(
+            "uro:updateDate".into(),
+            Value::Array(vec![
+                Value::String("2021/03/31".into()),
+                Value::String("unknown".into()),
+            ]),
+        );
+        attributes.insert("uro:note".into(), Value::String("n/a".into()));
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: nusamai_citygml::object::ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: Default::default(),
+                },
+            }),
+            GeometryStore::default(),
+        )
+    }
+
+    fn spec(to: CastType, decimals: Option<usize>, date_format: Option<&str>) -> CastSpec {
+        CastSpec {
+            to,
+            decimals,
+            date_format: date_format.map(|s| s.to_string()),
+        }
+    }
+
+    fn make_rules() -> CastRules {
+        [
+            ("bldg:usage", spec(CastType::String, None, None)),
+            ("*:measuredHeight", spec(CastType::Double, Some(1), None)),
+            ("uro:surveyYear", spec(CastType::Integer, None, None)),
+            (
+                "bldg:yearOfConstruction",
+                spec(CastType::String, None, Some("%Y/%m/%d")),
+            ),
+            (
+                "uro:updateDate",
+                spec(CastType::Date, None, Some("%Y/%m/%d")),
+            ),
+            ("uro:note", spec(CastType::Boolean, None, None)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect()
+    }
+
+    #[test]
+    fn cast_values() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = AttributeCastTransform::new(&make_rules());
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        let Value::Object(obj) = out.pop().unwrap().root else {
+            unreachable!()
+        };
+        let attrs = obj.attributes;
+        assert_eq!(attrs["bldg:usage"], Value::String("業務施設".into()));
+        assert_eq!(attrs["bldg:measuredHeight"], Value::Double(12.3));
+        assert_eq!(attrs["uro:surveyYear"], Value::Integer(2020));
+        assert_eq!(
+            attrs["bldg:yearOfConstruction"],
+            Value::String("1998/04/01".into())
+        );
+        // uncastable values are removed
+        assert_eq!(
+            attrs["uro:updateDate"],
+            Value::Array(vec![Value::Date(Date::from_ymd_opt(2021, 3, 31).unwrap())])
+        );
+        assert!(!attrs.contains_key("uro:note"));
+    }
+
+    #[test]
+    fn cast_scalars() {
+        let code = Value::Code(Code::new("あり".into(), "1".into()));
+        assert_eq!(
+            cast_value(&code, &spec(CastType::Boolean, None, None)),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(
+            cast_value(&code, &spec(CastType::Integer, None, None)),
+            Some(Value::Integer(1))
+        );
+        assert_eq!(
+            cast_value(&Value::Double(2.5), &spec(CastType::String, Some(2), None)),
+            Some(Value::String("2.50".into()))
+        );
+        assert_eq!(
+            cast_value(
+                &Value::String(" 3.6 ".into()),
+                &spec(CastType::Integer, None, None)
+            ),
+            Some(Value::Integer(4))
+        );
+        assert_eq!(
+            cast_value(
+                &Value::Double(f64::NAN),
+                &spec(CastType::Integer, None, None)
+            ),
+            None
+        );
+        // invalid date pattern
+        let date = Value::Date(Date::from_ymd_opt(2020, 1, 1).unwrap());
+        assert_eq!(
+            cast_value(&date, &spec(CastType::String, None, Some("%Q"))),
+            None
+        );
+    }
+
+    #[test]
+    fn cast_schema() {
+        let mut schema = Schema::default();
+        let mut typedef = FeatureTypeDef::default();
+        typedef
+            .attributes
+            .insert("bldg:usage".into(), schema::Attribute::new(TypeRef::Code));
+        let mut height = schema::Attribute::new(TypeRef::Measure);
+        height.uom = Some("m".into());
+        typedef
+            .attributes
+            .insert("bldg:measuredHeight".into(), height);
+        typedef.attributes.insert(
+            "uro:note".into(),
+            schema::Attribute::new(TypeRef::Named("uro:Note".into())),
+        );
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(typedef));
+
+        let mut rules = make_rules();
+        rules.insert("uro:note".into(), spec(CastType::String, None, None));
+        AttributeCastTransform::new(&rules).transform_schema(&mut schema);
+        let TypeDef::Feature(typedef) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        assert_eq!(typedef.attributes["bldg:usage"].type_ref, TypeRef::String);
+        assert_eq!(
+            typedef.attributes["bldg:measuredHeight"].type_ref,
+            TypeRef::Double
+        );
+        assert_eq!(
+            typedef.attributes["bldg:measuredHeight"].uom.as_deref(),
+            Some("m")
+        );
+        // objects are not cast
+        assert_eq!(
+            typedef.attributes["uro:note"].type_ref,
+            TypeRef::Named("uro:Note".into())
+        );
+    }
+}
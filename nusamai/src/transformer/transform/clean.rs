@@ -0,0 +1,481 @@
+//! Geometry cleaning
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use nusamai_citygml::{
+    object::{ObjectStereotype, Value},
+    schema::Schema,
+    GeometryType,
+};
+use nusamai_geometry::{
+    algorithm::{
+        clean::{clean_ring, is_degenerate_ring},
+        plane::newell_normal,
+    },
+    MultiPolygon,
+};
+use nusamai_plateau::Entity;
+
+use super::metric::to_local_metric;
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// Transform to fix common defects of the polygons, so that the sinks get clean geometries
+///
+/// - Repeated vertices and spikes are removed from the rings.
+/// - Rings with no area are dropped, and polygons without their exterior are dropped entirely.
+/// - Interior rings are oriented opposite to their exterior ring.
+/// - Optionally, the exteriors of (nearly) horizontal surfaces are made to face upward.
+///
+/// The tolerance is given in metres. Texture coordinates and materials are kept aligned with the polygons.
+/// The numbers of the defects fixed are added to `CleaningStats`, shared by the transforms of all the threads.
+pub struct GeometryCleaningTransform {
+    tolerance: f64,
+    upward_surfaces: bool,
+    stats: Arc<CleaningStats>,
+}
+
+impl GeometryCleaningTransform {
+    pub fn new(tolerance: f64, upward_surfaces: bool, stats: Arc<CleaningStats>) -> Self {
+        Self {
+            tolerance,
+            upward_surfaces,
+            stats,
+        }
+    }
+}
+
+/// Total numbers of the defects fixed by `GeometryCleaningTransform`, and of the entities cleaned
+#[derive(Default)]
+pub struct CleaningStats {
+    cleaned_entities: AtomicUsize,
+    removed_vertices: AtomicUsize,
+    dropped_rings: AtomicUsize,
+    dropped_polygons: AtomicUsize,
+    reoriented_rings: AtomicUsize,
+}
+
+impl CleaningStats {
+    fn add(&self, report: &CleaningReport) {
+        self.cleaned_entities.fetch_add(1, Ordering::Relaxed);
+        self.removed_vertices
+            .fetch_add(report.removed_vertices, Ordering::Relaxed);
+        self.dropped_rings
+            .fetch_add(report.dropped_rings, Ordering::Relaxed);
+        self.dropped_polygons
+            .fetch_add(report.dropped_polygons, Ordering::Relaxed);
+        self.reoriented_rings
+            .fetch_add(report.reoriented_rings, Ordering::Relaxed);
+    }
+
+    /// Reports the totals, if any entity was cleaned, and resets them
+    pub fn report(&self, feedback: &Feedback) {
+        let cleaned_entities = self.cleaned_entities.swap(0, Ordering::Relaxed);
+        let removed_vertices = self.removed_vertices.swap(0, Ordering::Relaxed);
+        let dropped_rings = self.dropped_rings.swap(0, Ordering::Relaxed);
+        let dropped_polygons = self.dropped_polygons.swap(0, Ordering::Relaxed);
+        let reoriented_rings = self.reoriented_rings.swap(0, Ordering::Relaxed);
+        if cleaned_entities > 0 {
+            feedback.info(format!(
+                "Cleaned the geometries of {} entities: removed {} vertices, dropped {} rings and {} polygons, reoriented {} rings",
+                cleaned_entities, removed_vertices, dropped_rings, dropped_polygons, reoriented_rings
+            ));
+        }
+    }
+}
+
+/// Number of the defects fixed in an entity
+#[derive(Default, Debug, PartialEq)]
+struct CleaningReport {
+    removed_vertices: usize,
+    dropped_rings: usize,
+    dropped_polygons: usize,
+    reoriented_rings: usize,
+}
+
+impl Transform for GeometryCleaningTransform {
+    fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        let report = {
+            let mut geom_store = entity.geometry_store.write().unwrap();
+            let mut surfaces = vec![false; geom_store.multipolygon.len()];
+            collect_surfaces(&entity.root, &mut surfaces);
+
+            let (report, new_index) = self.clean(&mut geom_store, &surfaces);
+            if report.dropped_polygons > 0 {
+                update_geometry_refs(&mut entity.root, &new_index);
+                for span in geom_store.surface_spans.iter_mut() {
+                    span.start = new_index[span.start as usize];
+                    span.end = new_index[span.end as usize];
+                }
+            }
+            report
+        };
+
+        if report != CleaningReport::default() {
+            self.stats.add(&report);
+        }
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, _schema: &mut Schema) {
+        // do nothing
+    }
+}
+
+impl GeometryCleaningTransform {
+    /// Cleans all the polygons in the store.
+    ///
+    /// Returns the report and the new index of each polygon (the number of the polygons kept before it).
+    fn clean(
+        &self,
+        geom_store: &mut nusamai_citygml::GeometryStore,
+        surfaces: &[bool],
+    ) -> (CleaningReport, Vec<u32>) {
+        let vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, true);
+        let num_polygons = geom_store.multipolygon.len();
+        let num_rings: usize = geom_store
+            .multipolygon
+            .iter()
+            .map(|poly| poly.rings().count())
+            .sum();
+        let has_uvs = geom_store.polygon_uvs.len() == num_polygons;
+        let has_materials = geom_store.polygon_materials.len() == num_polygons;
+        let has_textures = geom_store.polygon_textures.len() == num_polygons;
+        let has_ring_ids = geom_store.ring_ids.len() == num_rings;
+
+        let mut report = CleaningReport::default();
+        let mut new_index = Vec::with_capacity(num_polygons + 1);
+        let mut new_mpoly = MultiPolygon::new();
+        let mut new_uvs = MultiPolygon::new();
+        let mut new_materials = Vec::new();
+        let mut new_textures = Vec::new();
+        let mut new_ring_ids = Vec::new();
+        let mut ring_pos = 0;
+
+        for (i, poly) in geom_store.multipolygon.iter().enumerate() {
+            new_index.push(new_mpoly.len() as u32);
+
+            // Clean the rings: (index of the ring, retained points)
+            let poly_rings: Vec<_> = poly.rings().collect();
+            let mut rings: Vec<(usize, Vec<usize>)> = Vec::new();
+            let mut exterior_normal = [0.; 3];
+            for (ri, ring) in poly_rings.iter().enumerate() {
+                let points: Vec<[f64; 3]> = ring.iter().map(|idx| vertices[idx as usize]).collect();
+                let mut kept = clean_ring(&points, self.tolerance);
+                let kept_points: Vec<_> = kept.iter().map(|&j| points[j]).collect();
+                if is_degenerate_ring(&kept_points, self.tolerance) {
+                    if ri == 0 {
+                        break;
+                    }
+                    report.dropped_rings += 1;
+                    continue;
+                }
+                report.removed_vertices += points.len() - kept.len();
+
+                let n = newell_normal(&kept_points);
+                let reverse = match ri {
+                    0 => {
+                        let is_horizontal = n[2].abs() >= n[0].abs().max(n[1].abs());
+                        self.upward_surfaces && surfaces[i] && is_horizontal && n[2] < 0.
+                    }
+                    _ => {
+                        let [ex, ey, ez] = exterior_normal;
+                        n[0] * ex + n[1] * ey + n[2] * ez > 0.
+                    }
+                };
+                if reverse {
+                    kept.reverse();
+                    report.reoriented_rings += 1;
+                }
+                if ri == 0 {
+                    exterior_normal = match reverse {
+                        true => [-n[0], -n[1], -n[2]],
+                        false => n,
+                    };
+                }
+                rings.push((ri, kept));
+            }
+
+            let first_ring_id = ring_pos;
+            ring_pos += poly_rings.len();
+
+            if !matches!(rings.first(), Some((0, _))) {
+                // The exterior has no area
+                report.dropped_polygons += 1;
+                report.dropped_rings += poly_rings.len();
+                continue;
+            }
+
+            for (ri, kept) in &rings {
+                let coords = poly_rings[*ri].raw_coords();
+                let iter = kept.iter().map(|&j| coords[j]);
+                match ri {
+                    0 => new_mpoly.add_exterior(iter),
+                    _ => new_mpoly.add_interior(iter),
+                }
+                if has_ring_ids {
+                    new_ring_ids.push(geom_store.ring_ids[first_ring_id + ri]);
+                }
+            }
+            if has_uvs {
+                let uv_poly = geom_store.polygon_uvs.get(i);
+                for (ri, kept) in &rings {
+                    let Some(uv_ring) = uv_poly.rings().nth(*ri) else {
+                        continue;
+                    };
+                    let coords = uv_ring.raw_coords();
+                    // Repeat the first point, so that only it is removed as the closing point
+                    let iter = kept
+                        .iter()
+                        .chain(kept.first())
+                        .map(|&j| coords.get(j).copied().unwrap_or_default());
+                    match ri {
+                        0 => new_uvs.add_exterior(iter),
+                        _ => new_uvs.add_interior(iter),
+                    }
+                }
+            }
+            if has_materials {
+                new_materials.push(geom_store.polygon_materials[i]);
+            }
+            if has_textures {
+                new_textures.push(geom_store.polygon_textures[i]);
+            }
+        }
+        new_index.push(new_mpoly.len() as u32);
+
+        geom_store.multipolygon = new_mpoly;
+        if has_uvs {
+            geom_store.polygon_uvs = new_uvs;
+        }
+        if has_materials {
+            geom_store.polygon_materials = new_materials;
+        }
+        if has_textures {
+            geom_store.polygon_textures = new_textures;
+        }
+        if has_ring_ids {
+            geom_store.ring_ids = new_ring_ids;
+        }
+
+        (report, new_index)
+    }
+}
+
+/// Marks the polygons of surfaces (as opposed to solids).
+fn collect_surfaces(value: &Value, surfaces: &mut [bool]) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype {
+                for geom in geometries {
+                    if geom.ty == GeometryType::Surface {
+                        surfaces[geom.pos as usize..(geom.pos + geom.len) as usize].fill(true);
+                    }
+                }
+            }
+            for value in obj.attributes.values() {
+                collect_surfaces(value, surfaces);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                collect_surfaces(value, surfaces);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Shifts the ranges of the polygons referenced by the features, and removes the emptied references.
+fn update_geometry_refs(value: &mut Value, new_index: &[u32]) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                geometries.retain_mut(|geom| match geom.ty {
+                    GeometryType::Solid | GeometryType::Surface | GeometryType::Triangle => {
+                        let start = new_index[geom.pos as usize];
+                        let end = new_index[(geom.pos + geom.len) as usize];
+                        geom.pos = start;
+                        geom.len = end - start;
+                        geom.len > 0
+                    }
+                    GeometryType::Curve | GeometryType::Point => true,
+                });
+            }
+            for value in obj.attributes.values_mut() {
+                update_geometry_refs(value, new_index);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr.iter_mut() {
+                update_geometry_refs(value, new_index);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, GeometryRef, GeometryStore};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity() -> Entity {
+        let vertices = vec![
+            // Square (clockwise seen from above) with a repeated vertex and a spike
+            [0., 0., 0.],
+            [0., 10., 0.],
+            [10., 10., 0.],
+            [10., 5., 0.],
+            [15., 5., 0.],
+            [10., 0., 0.],
+            // Hole with the same orientation as the exterior
+            [2., 2., 0.],
+            [2., 4., 0.],
+            [4., 4., 0.],
+            [4., 2., 0.],
+            // Collapsed polygon
+            [20., 0., 0.],
+            [30., 0., 0.],
+            // Vertical wall
+            [0., 0., 5.],
+            [0., 10., 5.],
+        ];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 1, 2, 3, 4, 3, 5]);
+        mpoly.add_interior([6, 7, 8, 9]);
+        mpoly.add_exterior([10, 11, 10]);
+        mpoly.add_exterior([0, 12, 13, 1]);
+
+        // Texture coordinates follow the vertex indices
+        let mut uvs = MultiPolygon::new();
+        for poly in &mpoly {
+            for (ri, ring) in poly.rings().enumerate() {
+                let iter = ring.iter_closed().map(|idx| [idx as f64, 0.]);
+                match ri {
+                    0 => uvs.add_exterior(iter),
+                    _ => uvs.add_interior(iter),
+                }
+            }
+        }
+
+        test_entity(
+            Value::Object(Object {
+                typename: "luse:LandUse".into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "luse_1".into(),
+                    geometries: vec![
+                        GeometryRef {
+                            ty: GeometryType::Surface,
+                            lod: 1,
+                            pos: 0,
+                            len: 2,
+                        },
+                        GeometryRef {
+                            ty: GeometryType::Surface,
+                            lod: 2,
+                            pos: 2,
+                            len: 1,
+                        },
+                    ],
+                },
+            }),
+            GeometryStore {
+                epsg: 6677,
+                vertices,
+                multipolygon: mpoly,
+                polygon_uvs: uvs,
+                polygon_materials: vec![Some(0), None, Some(2)],
+                polygon_textures: vec![Some(0), None, Some(2)],
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(upward_surfaces: bool) -> Entity {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform =
+            GeometryCleaningTransform::new(0.001, upward_surfaces, Default::default());
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        out.pop().unwrap()
+    }
+
+    #[test]
+    fn clean_polygons() {
+        let entity = run(false);
+        let geom_store = entity.geometry_store.read().unwrap();
+        assert_eq!(geom_store.multipolygon.len(), 2);
+
+        let poly = geom_store.multipolygon.get(0);
+        assert_eq!(poly.exterior().raw_coords(), [0, 1, 2, 3, 5]);
+        // The hole is reversed
+        let interiors: Vec<_> = poly.interiors().map(|r| r.raw_coords().to_vec()).collect();
+        assert_eq!(interiors, [vec![9, 8, 7, 6]]);
+        assert_eq!(
+            geom_store.multipolygon.get(1).exterior().raw_coords(),
+            [0, 12, 13, 1]
+        );
+
+        // The appearance stays aligned
+        let uv_poly = geom_store.polygon_uvs.get(0);
+        let uvs: Vec<_> = uv_poly.rings().map(|r| r.raw_coords().to_vec()).collect();
+        assert_eq!(
+            uvs,
+            [
+                vec![[0., 0.], [1., 0.], [2., 0.], [3., 0.], [5., 0.]],
+                vec![[9., 0.], [8., 0.], [7., 0.], [6., 0.]],
+            ]
+        );
+        assert_eq!(geom_store.polygon_materials, [Some(0), Some(2)]);
+        assert_eq!(geom_store.polygon_textures, [Some(0), Some(2)]);
+
+        // The references skip the dropped polygon
+        let Value::Object(obj) = &entity.root else {
+            unreachable!()
+        };
+        let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype else {
+            unreachable!()
+        };
+        let ranges: Vec<_> = geometries.iter().map(|g| (g.pos, g.len)).collect();
+        assert_eq!(ranges, [(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn report_in_total() {
+        let (watcher, feedback, _canceller) = feedback::watcher();
+        let stats = Arc::new(CleaningStats::default());
+        // The transforms of two threads
+        for _ in 0..2 {
+            let mut transform = GeometryCleaningTransform::new(0.001, false, stats.clone());
+            let mut out = Vec::new();
+            transform.transform(&feedback, make_entity(), &mut out);
+        }
+        stats.report(&feedback);
+        drop(feedback);
+
+        let messages: Vec<_> = watcher.into_iter().map(|msg| msg.message).collect();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("Cleaned the geometries of 2 entities"));
+    }
+
+    #[test]
+    fn upward_surfaces() {
+        let entity = run(true);
+        let geom_store = entity.geometry_store.read().unwrap();
+        let poly = geom_store.multipolygon.get(0);
+        assert_eq!(poly.exterior().raw_coords(), [5, 3, 2, 1, 0]);
+        let interiors: Vec<_> = poly.interiors().map(|r| r.raw_coords().to_vec()).collect();
+        assert_eq!(interiors, [vec![6, 7, 8, 9]]);
+        // Vertical surfaces are left as they are
+        assert_eq!(
+            geom_store.multipolygon.get(1).exterior().raw_coords(),
+            [0, 12, 13, 1]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use nusamai_citygml::{
+    object::{Map, Value},
+    schema::{self, Schema, TypeDef, TypeRef},
+    Code,
+};
+use nusamai_plateau::Entity;
+
+use crate::{
+    pipeline::Feedback,
+    transformer::{CodelistOutput, Transform},
+};
+
+/// Suffix of the fields that hold the raw codes
+pub const RAW_CODE_SUFFIX: &str = "_code";
+
+/// Transform to choose how code-typed attributes are output
+///
+/// - `Label`: keep the resolved labels (no-op)
+/// - `Code`: replace the labels with the raw codes
+/// - `Both`: keep the labels and add the raw codes as sibling fields (e.g. `usage` and `usage_code`)
+#[derive(Clone)]
+pub struct CodeOutputTransform {
+    output: CodelistOutput,
+}
+
+impl CodeOutputTransform {
+    pub fn new(output: CodelistOutput) -> Self {
+        Self { output }
+    }
+}
+
+impl Transform for CodeOutputTransform {
+    fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        self.edit_tree(&mut entity.root);
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        if self.output != CodelistOutput::Both {
+            return;
+        }
+
+        let drain_to_new_attrs = |attrs: &mut schema::Map| {
+            let mut new_attrs = schema::Map::default();
+            for (key, attr) in attrs.drain(..) {
+                let code_attr = (attr.type_ref == TypeRef::Code).then(|| schema::Attribute {
+                    type_ref: TypeRef::String,
+                    min_occurs: attr.min_occurs,
+                    max_occurs: attr.max_occurs,
+                    original_name: attr.original_name.clone(),
+                    uom: None,
+                });
+                new_attrs.insert(key.clone(), attr);
+                if let Some(code_attr) = code_attr {
+                    new_attrs.insert(format!("{key}{RAW_CODE_SUFFIX}"), code_attr);
+                }
+            }
+            new_attrs
+        };
+
+        for ty in schema.types.values_mut() {
+            match ty {
+                TypeDef::Data(data) => {
+                    data.attributes = drain_to_new_attrs(&mut data.attributes);
+                }
+                TypeDef::Feature(feat) => {
+                    feat.attributes = drain_to_new_attrs(&mut feat.attributes);
+                }
+                TypeDef::Property(_) => continue,
+            };
+        }
+    }
+}
+
+impl CodeOutputTransform {
+    fn edit_tree(&self, value: &mut Value) {
+        match value {
+            Value::Object(obj) => match self.output {
+                CodelistOutput::Label => {}
+                CodelistOutput::Code => {
+                    for value in obj.attributes.values_mut() {
+                        self.edit_tree(value);
+                    }
+                }
+                CodelistOutput::Both => {
+                    let mut new_attrs = Map::default();
+                    for (key, mut value) in obj.attributes.drain(..) {
+                        self.edit_tree(&mut value);
+                        let raw_code = raw_code(&value);
+                        new_attrs.insert(key.clone(), value);
+                        if let Some(raw_code) = raw_code {
+                            new_attrs.insert(format!("{key}{RAW_CODE_SUFFIX}"), raw_code);
+                        }
+                    }
+                    obj.attributes = new_attrs;
+                }
+            },
+            Value::Array(arr) => {
+                for v in arr.iter_mut() {
+                    self.edit_tree(v);
+                }
+            }
+            Value::Code(code) if self.output == CodelistOutput::Code => {
+                *code = Code::new(code.code().to_string(), code.code().to_string());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Returns the raw code(s) of a code (or an array of codes) as string(s).
+fn raw_code(value: &Value) -> Option<Value> {
+    match value {
+        Value::Code(code) => Some(Value::String(code.code().to_string())),
+        Value::Array(arr) if !arr.is_empty() && arr.iter().all(|v| matches!(v, Value::Code(_))) => {
+            Some(Value::Array(arr.iter().filter_map(raw_code).collect()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, schema::FeatureTypeDef, GeometryStore};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity() -> Entity {
+        let mut attributes = Map::default();
+        attributes.insert(
+            "usage".into(),
+            Value::Code(Code::new("業務施設".into(), "401".into())),
+        );
+        attributes.insert("name".into(), Value::String("foo".into()));
+        attributes.insert(
+            "roofType".into(),
+            Value::Array(vec![
+                Value::Code(Code::new("陸屋根".into(), "1".into())),
+                Value::Code(Code::new("切妻".into(), "2".into())),
+            ]),
+        );
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: nusamai_citygml::object::ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: Default::default(),
+                },
+            }),
+            GeometryStore::default(),
+        )
+    }
+
+    fn run(output: CodelistOutput) -> Map {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = CodeOutputTransform::new(output);
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        let Value::Object(obj) = out.pop().unwrap().root else {
+            unreachable!()
+        };
+        obj.attributes
+    }
+
+    #[test]
+    fn output_both() {
+        let attrs = run(CodelistOutput::Both);
+        let keys: Vec<_> = attrs.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["usage", "usage_code", "name", "roofType", "roofType_code"]
+        );
+        assert_eq!(attrs["usage_code"], Value::String("401".into()));
+        assert_eq!(
+            attrs["roofType_code"],
+            Value::Array(vec![Value::String("1".into()), Value::String("2".into())])
+        );
+
+        let mut schema = Schema::default();
+        let mut typedef = FeatureTypeDef::default();
+        typedef
+            .attributes
+            .insert("usage".into(), schema::Attribute::new(TypeRef::Code));
+        typedef
+            .attributes
+            .insert("name".into(), schema::Attribute::new(TypeRef::String));
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(typedef));
+        CodeOutputTransform::new(CodelistOutput::Both).transform_schema(&mut schema);
+        let TypeDef::Feature(typedef) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        let keys: Vec<_> = typedef.attributes.keys().map(|k| k.as_str()).collect();
+        assert_eq!(keys, ["usage", "usage_code", "name"]);
+        assert_eq!(typedef.attributes["usage_code"].type_ref, TypeRef::String);
+    }
+
+    #[test]
+    fn output_code() {
+        let attrs = run(CodelistOutput::Code);
+        assert_eq!(attrs.len(), 3);
+        let Value::Code(code) = &attrs["usage"] else {
+            unreachable!()
+        };
+        assert_eq!(code.value(), "401");
+    }
+}
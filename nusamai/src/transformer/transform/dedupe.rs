@@ -0,0 +1,612 @@
+//! Removing the features repeated in several input files
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use ahash::{HashSet, RandomState};
+use indexmap::IndexMap;
+use nusamai_citygml::{
+    object::{Object, ObjectStereotype, Value},
+    schema::Schema,
+    GeometryRef, GeometryStore, GeometryType, LocalId, SurfaceSpan,
+};
+use nusamai_plateau::{appearance::AppearanceStore, Entity};
+
+use crate::{
+    pipeline::{Feedback, PipelineError, Result},
+    transformer::{Aggregate, DedupeKey, DedupeMode},
+};
+
+/// Attribute that holds the building ID, used with `DedupeKey::BuildingId`
+const BUILDING_ID_ATTRIBUTE: &str = "uro:buildingID";
+
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Aggregation to remove the features that appear more than once, for example in the files of adjacent meshes
+///
+/// The features are identified by their type and the key (`gml:id` or `uro:buildingID`).
+/// With `DedupeMode::First`, the first feature is output immediately and the later ones are discarded.
+/// With `DedupeMode::Merge`, a feature may be repeated in any later file, so the features are written to
+/// a temporary file instead of being held in memory, and merged by the key at the end.
+///
+/// This runs before the transforms, since the merged geometries need to resolve their appearances
+/// and the key attributes may be renamed or removed by the transforms.
+pub struct DedupeAggregate {
+    key: DedupeKey,
+    mode: DedupeMode,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Keys of the features already output (`DedupeMode::First`)
+    seen: HashSet<FeatureKey>,
+    /// Number of the discarded features (`DedupeMode::First`)
+    discarded: usize,
+    /// Features written to the temporary file (`DedupeMode::Merge`)
+    spill: Option<Spill>,
+}
+
+/// Temporary file that holds the features to merge
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    len: u64,
+    /// File offsets of the features, grouped by the keys in the order of appearance
+    groups: IndexMap<FeatureKey, Vec<u64>, RandomState>,
+}
+
+/// Type name and key of a feature
+type FeatureKey = (String, String);
+
+impl Spill {
+    fn create() -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "nusamai-dedupe-{}-{}.bin",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            writer,
+            len: 0,
+            groups: Default::default(),
+        })
+    }
+
+    /// Appends an encoded feature
+    fn push(&mut self, key: FeatureKey, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.groups.entry(key).or_default().push(self.len);
+        self.len += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl DedupeAggregate {
+    pub fn new(key: DedupeKey, mode: DedupeMode) -> Self {
+        Self {
+            key,
+            mode,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the type and the key of the feature, or None if it is not a feature with a key.
+    fn entity_key(&self, entity: &Entity) -> Option<FeatureKey> {
+        let Value::Object(obj) = &entity.root else {
+            return None;
+        };
+        let ObjectStereotype::Feature { id, .. } = &obj.stereotype else {
+            return None;
+        };
+        let building_id = match self.key {
+            DedupeKey::Id => None,
+            DedupeKey::BuildingId => obj.attributes.values().find_map(find_building_id),
+        };
+        let key = building_id.unwrap_or(id);
+        if key.is_empty() {
+            return None;
+        }
+        Some((obj.typename.to_string(), key.clone()))
+    }
+}
+
+impl Aggregate for DedupeAggregate {
+    fn add(&self, feedback: &Feedback, entity: Entity) -> Option<Entity> {
+        let Some(key) = self.entity_key(&entity) else {
+            return Some(entity);
+        };
+        match self.mode {
+            DedupeMode::First => {
+                let mut state = self.state.lock().unwrap();
+                if state.seen.insert(key) {
+                    Some(entity)
+                } else {
+                    state.discarded += 1;
+                    None
+                }
+            }
+            DedupeMode::Merge => {
+                // Encode without holding the lock
+                let bytes =
+                    match bincode::serde::encode_to_vec(&entity, bincode::config::standard()) {
+                        Ok(bytes) => bytes,
+                        Err(err) => {
+                            feedback.warn(format!("Failed to hold a feature to merge: {}", err));
+                            return Some(entity);
+                        }
+                    };
+                let mut state = self.state.lock().unwrap();
+                if state.spill.is_none() {
+                    match Spill::create() {
+                        Ok(spill) => state.spill = Some(spill),
+                        Err(err) => {
+                            feedback.warn(format!("Failed to create a temporary file: {}", err));
+                            return Some(entity);
+                        }
+                    }
+                }
+                let spill = state.spill.as_mut().unwrap();
+                match spill.push(key, &bytes) {
+                    Ok(()) => None,
+                    Err(err) => {
+                        // Output the feature as it is, rather than losing it
+                        feedback.warn(format!("Failed to hold a feature to merge: {}", err));
+                        Some(entity)
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(
+        self: Box<Self>,
+        feedback: &Feedback,
+        out: &mut dyn FnMut(Entity) -> Result<()>,
+    ) -> Result<()> {
+        let state = self.state.into_inner().unwrap();
+        if state.discarded > 0 {
+            feedback.info(format!("Discarded {} duplicated features", state.discarded));
+        }
+        let Some(mut spill) = state.spill else {
+            return Ok(());
+        };
+
+        spill.writer.flush()?;
+        let mut reader = BufReader::new(File::open(&spill.path)?);
+        let mut read_entity = |offset: u64| -> Result<Entity> {
+            reader.seek(SeekFrom::Start(offset))?;
+            bincode::serde::decode_from_std_read(&mut reader, bincode::config::standard())
+                .map_err(|err| PipelineError::Other(err.to_string()))
+        };
+
+        let mut merged = 0;
+        for offsets in spill.groups.values() {
+            feedback.ensure_not_canceled()?;
+            let mut entity = read_entity(offsets[0])?;
+            for &offset in &offsets[1..] {
+                merge_entity(&mut entity, read_entity(offset)?);
+                merged += 1;
+            }
+            out(entity)?;
+        }
+        if merged > 0 {
+            feedback.info(format!("Merged {} duplicated features", merged));
+        }
+        Ok(())
+    }
+
+    fn transform_schema(&self, _schema: &mut Schema) {
+        // do nothing
+    }
+}
+
+/// Finds the building ID in the attribute, without looking into the child features.
+fn find_building_id(value: &Value) -> Option<&String> {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { .. } = obj.stereotype {
+                return None;
+            }
+            match obj.attributes.get(BUILDING_ID_ATTRIBUTE) {
+                Some(Value::String(id)) => Some(id),
+                _ => obj.attributes.values().find_map(find_building_id),
+            }
+        }
+        Value::Array(arr) => arr.iter().find_map(find_building_id),
+        _ => None,
+    }
+}
+
+/// Offsets of the geometries appended to a geometry store
+struct Offsets {
+    polygon: u32,
+    linestring: u32,
+    point: u32,
+}
+
+impl Offsets {
+    fn shift(&self, geom: &mut GeometryRef) {
+        geom.pos += match geom.ty {
+            GeometryType::Solid | GeometryType::Surface | GeometryType::Triangle => self.polygon,
+            GeometryType::Curve => self.linestring,
+            GeometryType::Point => self.point,
+        };
+    }
+}
+
+/// Merges the geometries and the child features of `other` into `entity`, keeping the other attributes of `entity`.
+fn merge_entity(entity: &mut Entity, other: Entity) {
+    let Value::Object(other_root) = other.root else {
+        return;
+    };
+    let Value::Object(root) = &mut entity.root else {
+        return;
+    };
+    let other_geoms = std::mem::take(&mut *other.geometry_store.write().unwrap());
+    let other_appearances = std::mem::take(&mut *other.appearance_store.write().unwrap());
+
+    let mut geom_store = entity.geometry_store.write().unwrap();
+    let mut appearance_store = entity.appearance_store.write().unwrap();
+
+    // The local IDs are numbered per input file, so the ones of the other feature are shifted to avoid conflicts
+    let id_offset = next_local_id(&geom_store, &appearance_store);
+    let offsets = append_geometry_store(&mut geom_store, other_geoms, id_offset);
+    append_appearance_store(&mut appearance_store, other_appearances, id_offset);
+    merge_objects(root, other_root, &offsets);
+}
+
+fn merge_objects(obj: &mut Object, other: Object, offsets: &Offsets) {
+    if let (
+        ObjectStereotype::Feature { geometries, .. },
+        ObjectStereotype::Feature {
+            geometries: other_geometries,
+            ..
+        },
+    ) = (&mut obj.stereotype, other.stereotype)
+    {
+        geometries.extend(other_geometries.into_iter().map(|mut geom| {
+            offsets.shift(&mut geom);
+            geom
+        }));
+    }
+
+    for (key, mut value) in other.attributes {
+        if !has_feature(&value) {
+            continue;
+        }
+        shift_geometries(&mut value, offsets);
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        match obj.attributes.get_mut(&key) {
+            Some(Value::Array(arr)) => arr.extend(values),
+            Some(existing) => {
+                let first = std::mem::replace(existing, Value::Array(Vec::new()));
+                *existing = Value::Array(std::iter::once(first).chain(values).collect());
+            }
+            None => {
+                obj.attributes.insert(key, Value::Array(values));
+            }
+        }
+    }
+}
+
+fn has_feature(value: &Value) -> bool {
+    match value {
+        Value::Object(obj) => {
+            matches!(obj.stereotype, ObjectStereotype::Feature { .. })
+                || obj.attributes.values().any(has_feature)
+        }
+        Value::Array(arr) => arr.iter().any(has_feature),
+        _ => false,
+    }
+}
+
+fn shift_geometries(value: &mut Value, offsets: &Offsets) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                geometries.iter_mut().for_each(|geom| offsets.shift(geom));
+            }
+            for value in obj.attributes.values_mut() {
+                shift_geometries(value, offsets);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                shift_geometries(value, offsets);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn next_local_id(geom_store: &GeometryStore, appearance_store: &AppearanceStore) -> u32 {
+    let ring_ids = geom_store.ring_ids.iter().flatten();
+    let surface_ids = geom_store.surface_spans.iter().map(|span| &span.id);
+    let theme_ids = appearance_store.themes.values().flat_map(|theme| {
+        theme
+            .ring_id_to_texture
+            .keys()
+            .chain(theme.surface_id_to_material.keys())
+    });
+    ring_ids
+        .chain(surface_ids)
+        .chain(theme_ids)
+        .map(|id| id.value() + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Appends the geometries of `other` to the geometry store, and returns the offsets of the appended geometries.
+fn append_geometry_store(
+    geom_store: &mut GeometryStore,
+    other: GeometryStore,
+    id_offset: u32,
+) -> Offsets {
+    let offsets = Offsets {
+        polygon: geom_store.multipolygon.len() as u32,
+        linestring: geom_store.multilinestring.len() as u32,
+        point: geom_store.multipoint.len() as u32,
+    };
+    let vertex_offset = geom_store.vertices.len() as u32;
+    geom_store.vertices.extend(other.vertices);
+
+    for poly in other.multipolygon.iter() {
+        for (ri, ring) in poly.rings().enumerate() {
+            let indices = ring.iter().map(|idx| idx + vertex_offset);
+            match ri {
+                0 => geom_store.multipolygon.add_exterior(indices),
+                _ => geom_store.multipolygon.add_interior(indices),
+            }
+        }
+    }
+    for linestring in other.multilinestring.iter() {
+        geom_store
+            .multilinestring
+            .add_linestring(linestring.iter().map(|idx| idx + vertex_offset));
+    }
+    for idx in other.multipoint.iter() {
+        geom_store.multipoint.push(idx + vertex_offset);
+    }
+
+    let shift_id = |id: LocalId| LocalId::new(id.value() + id_offset);
+    geom_store
+        .ring_ids
+        .extend(other.ring_ids.into_iter().map(|id| id.map(shift_id)));
+    geom_store
+        .surface_spans
+        .extend(other.surface_spans.into_iter().map(|span| SurfaceSpan {
+            id: shift_id(span.id),
+            start: span.start + offsets.polygon,
+            end: span.end + offsets.polygon,
+        }));
+    offsets
+}
+
+fn append_appearance_store(
+    appearance_store: &mut AppearanceStore,
+    other: AppearanceStore,
+    id_offset: u32,
+) {
+    let texture_offset = appearance_store.textures.len() as u32;
+    let material_offset = appearance_store.materials.len() as u32;
+    appearance_store.textures.extend(other.textures);
+    appearance_store.materials.extend(other.materials);
+
+    let shift_id = |id: LocalId| LocalId::new(id.value() + id_offset);
+    for (name, other_theme) in other.themes {
+        let theme = appearance_store.themes.entry(name).or_default();
+        theme.ring_id_to_texture.extend(
+            other_theme
+                .ring_id_to_texture
+                .into_iter()
+                .map(|(id, (tex, uvs))| (shift_id(id), (tex + texture_offset, uvs))),
+        );
+        theme.surface_id_to_material.extend(
+            other_theme
+                .surface_id_to_material
+                .into_iter()
+                .map(|(id, mat)| (shift_id(id), mat + material_offset)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::object::Map;
+    use nusamai_geometry::MultiPolygon;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity(id: &str, building_id: Option<&str>, x: f64) -> Entity {
+        let vertices = vec![[x, 0., 0.], [x + 1., 0., 0.], [x + 1., 1., 0.], [x, 1., 0.]];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 2, 3]);
+
+        let mut attributes = Map::default();
+        if let Some(building_id) = building_id {
+            let mut id_attributes = Map::default();
+            id_attributes.insert(
+                BUILDING_ID_ATTRIBUTE.into(),
+                Value::String(building_id.into()),
+            );
+            attributes.insert(
+                "uro:buildingIDAttribute".into(),
+                Value::Array(vec![Value::Object(Object {
+                    typename: "uro:BuildingIDAttribute".into(),
+                    stereotype: ObjectStereotype::Data,
+                    attributes: id_attributes,
+                })]),
+            );
+        }
+        attributes.insert(
+            "bldg:boundedBy".into(),
+            Value::Array(vec![Value::Object(Object {
+                typename: "bldg:RoofSurface".into(),
+                stereotype: ObjectStereotype::Feature {
+                    id: format!("{id}_roof"),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 2,
+                        pos: 0,
+                        len: 1,
+                    }],
+                },
+                attributes: Default::default(),
+            })]),
+        );
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                stereotype: ObjectStereotype::Feature {
+                    id: id.into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 2,
+                        pos: 0,
+                        len: 1,
+                    }],
+                },
+                attributes,
+            }),
+            GeometryStore {
+                epsg: 6697,
+                vertices,
+                multipolygon: mpoly,
+                ring_ids: vec![Some(LocalId::new(0))],
+                surface_spans: vec![SurfaceSpan {
+                    id: LocalId::new(1),
+                    start: 0,
+                    end: 1,
+                }],
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(aggregate: DedupeAggregate, entities: Vec<Entity>) -> Vec<Entity> {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let aggregate = Box::new(aggregate);
+        let mut out: Vec<_> = entities
+            .into_iter()
+            .filter_map(|entity| aggregate.add(&feedback, entity))
+            .collect();
+        aggregate
+            .finish(&feedback, &mut |entity| {
+                out.push(entity);
+                Ok(())
+            })
+            .unwrap();
+        out
+    }
+
+    fn root_id(entity: &Entity) -> &str {
+        let Value::Object(obj) = &entity.root else {
+            unreachable!()
+        };
+        obj.stereotype.id().unwrap()
+    }
+
+    #[test]
+    fn keep_first() {
+        let entities = vec![
+            make_entity("a", None, 0.),
+            make_entity("b", None, 1.),
+            make_entity("a", None, 2.),
+        ];
+        let out = run(
+            DedupeAggregate::new(DedupeKey::Id, DedupeMode::First),
+            entities,
+        );
+        assert_eq!(out.len(), 2);
+        assert_eq!(root_id(&out[0]), "a");
+        assert_eq!(root_id(&out[1]), "b");
+        assert_eq!(out[0].geometry_store.read().unwrap().vertices[0][0], 0.);
+    }
+
+    #[test]
+    fn keep_first_by_building_id() {
+        let entities = vec![
+            make_entity("a", Some("13101-bldg-1"), 0.),
+            make_entity("b", Some("13101-bldg-1"), 1.),
+            make_entity("c", None, 2.),
+        ];
+        let out = run(
+            DedupeAggregate::new(DedupeKey::BuildingId, DedupeMode::First),
+            entities,
+        );
+        assert_eq!(out.len(), 2);
+        assert_eq!(root_id(&out[0]), "a");
+        assert_eq!(root_id(&out[1]), "c");
+    }
+
+    #[test]
+    fn merge_geometries() {
+        let entities = vec![
+            make_entity("a", None, 0.),
+            make_entity("b", None, 5.),
+            make_entity("a", None, 1.),
+        ];
+        let out = run(
+            DedupeAggregate::new(DedupeKey::Id, DedupeMode::Merge),
+            entities,
+        );
+        assert_eq!(out.len(), 2);
+        assert_eq!(root_id(&out[0]), "a");
+        assert_eq!(root_id(&out[1]), "b");
+
+        let merged = &out[0];
+        let geom_store = merged.geometry_store.read().unwrap();
+        assert_eq!(geom_store.vertices.len(), 8);
+        assert_eq!(geom_store.multipolygon.len(), 2);
+        let second: Vec<_> = geom_store.multipolygon.get(1).exterior().iter().collect();
+        assert_eq!(second, [4, 5, 6, 7]);
+        assert_eq!(
+            geom_store.ring_ids,
+            [Some(LocalId::new(0)), Some(LocalId::new(2))]
+        );
+        assert_eq!(geom_store.surface_spans[1].id, LocalId::new(3));
+        assert_eq!(geom_store.surface_spans[1].start, 1);
+
+        let Value::Object(obj) = &merged.root else {
+            unreachable!()
+        };
+        let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype else {
+            unreachable!()
+        };
+        assert_eq!(
+            geometries.iter().map(|geom| geom.pos).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        let Value::Array(surfaces) = &obj.attributes["bldg:boundedBy"] else {
+            unreachable!()
+        };
+        assert_eq!(surfaces.len(), 2);
+        let Value::Object(surface) = &surfaces[1] else {
+            unreachable!()
+        };
+        let ObjectStereotype::Feature { geometries, .. } = &surface.stereotype else {
+            unreachable!()
+        };
+        assert_eq!(geometries[0].pos, 1);
+    }
+}
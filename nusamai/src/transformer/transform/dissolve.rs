@@ -0,0 +1,382 @@
+//! Dissolving polygons by an attribute
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Mutex, RwLock},
+};
+
+use geo::Area;
+use nusamai_citygml::{
+    object::{Map, Object, ObjectStereotype, Value},
+    schema::{Attribute, FeatureTypeDef, Schema, TypeDef, TypeRef},
+    GeometryRef, GeometryStore, GeometryType,
+};
+use nusamai_geometry::MultiPolygon;
+use nusamai_plateau::Entity;
+use nusamai_projection::crs::EpsgCode;
+use rayon::prelude::*;
+
+use super::{
+    boolean::{make_polygon, union_all},
+    footprint::{append_multipolygon, collect_geometries},
+    metric::to_local_metric,
+};
+use crate::{
+    pipeline::{Feedback, Result},
+    transformer::Aggregate,
+};
+
+/// Attribute of the dissolved features that holds the number of the merged features
+pub const DISSOLVE_COUNT_ATTRIBUTE: &str = "count";
+/// Attribute of the dissolved features that holds the total area (in square metres) of the merged features
+pub const DISSOLVE_AREA_ATTRIBUTE: &str = "totalArea";
+
+/// Aggregation to merge the surfaces of the features that have the same value of an attribute
+///
+/// The features of the target types are grouped by their type and the value of the attribute,
+/// which may be given without the namespace prefix (e.g. `class` for `luse:class`).
+/// The surfaces of the highest LOD in each group are unioned into a new feature that has
+/// the attribute, the number of the merged features and their total area.
+/// The dissolved surfaces are flat, at the height of the lowest vertex of the group.
+///
+/// Features that lack the attribute or surfaces are output as they are.
+pub struct DissolveAggregate {
+    attribute: String,
+    types: Vec<String>,
+    groups: Mutex<BTreeMap<(String, String), Group>>,
+}
+
+struct Group {
+    typename: Cow<'static, str>,
+    attribute: String,
+    value: Value,
+    base_url: url::Url,
+    epsg: EpsgCode,
+    lod: u8,
+    min_z: f64,
+    polygons: Vec<geo::Polygon<f64>>,
+    count: i64,
+    area: f64,
+    has_appearance: bool,
+}
+
+impl DissolveAggregate {
+    pub fn new(attribute: String, types: Vec<String>) -> Self {
+        Self {
+            attribute,
+            types,
+            groups: Default::default(),
+        }
+    }
+
+    fn is_target_type(&self, typename: &str) -> bool {
+        self.types.is_empty()
+            || self
+                .types
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => typename.starts_with(prefix),
+                    None => pattern == typename,
+                })
+    }
+
+    fn find_attribute<'a>(&self, obj: &'a Object) -> Option<(&'a String, &'a Value)> {
+        obj.attributes.get_key_value(&self.attribute).or_else(|| {
+            obj.attributes.iter().find(|(key, _)| {
+                let local_name = key.split_once(':').map_or(key.as_str(), |(_, name)| name);
+                local_name == self.attribute
+            })
+        })
+    }
+}
+
+impl Aggregate for DissolveAggregate {
+    fn add(&self, _feedback: &Feedback, entity: Entity) -> Option<Entity> {
+        let Value::Object(obj) = &entity.root else {
+            return Some(entity);
+        };
+        if !matches!(obj.stereotype, ObjectStereotype::Feature { .. })
+            || !self.is_target_type(&obj.typename)
+        {
+            return Some(entity);
+        }
+        let Some((attribute, value)) = self.find_attribute(obj) else {
+            return Some(entity);
+        };
+        let Some(key) = group_key(value) else {
+            return Some(entity);
+        };
+
+        let mut surfaces = Vec::new();
+        collect_geometries(&entity.root, GeometryType::Surface, &mut surfaces);
+        let Some(lod) = surfaces.iter().map(|(lod, _)| *lod).max() else {
+            return Some(entity);
+        };
+
+        let (polygons, area, min_z, epsg, has_appearance) = {
+            let geom_store = entity.geometry_store.read().unwrap();
+            let local_vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, true);
+            let mut polygons = Vec::new();
+            let mut area = 0.0;
+            let mut min_z = f64::MAX;
+            for (_, range) in surfaces.into_iter().filter(|(l, _)| *l == lod) {
+                for poly in geom_store.multipolygon.iter_range(range) {
+                    let make_2d = |vertices: &[[f64; 3]]| {
+                        make_polygon(poly.rings().map(|ring| {
+                            ring.iter()
+                                .map(|idx| {
+                                    let [x, y, _] = vertices[idx as usize];
+                                    [x, y]
+                                })
+                                .collect()
+                        }))
+                    };
+                    area += make_2d(&local_vertices).unsigned_area();
+                    polygons.push(make_2d(&geom_store.vertices));
+                    for ring in poly.rings() {
+                        for idx in ring.iter() {
+                            min_z = min_z.min(geom_store.vertices[idx as usize][2]);
+                        }
+                    }
+                }
+            }
+            let has_appearance = !geom_store.polygon_materials.is_empty();
+            (polygons, area, min_z, geom_store.epsg, has_appearance)
+        };
+
+        let mut groups = self.groups.lock().unwrap();
+        let group = groups
+            .entry((obj.typename.to_string(), key))
+            .or_insert_with(|| Group {
+                typename: obj.typename.clone(),
+                attribute: attribute.clone(),
+                value: value.clone(),
+                base_url: entity.base_url.clone(),
+                epsg,
+                lod,
+                min_z,
+                polygons: Vec::new(),
+                count: 0,
+                area: 0.0,
+                has_appearance,
+            });
+        group.lod = group.lod.max(lod);
+        group.min_z = group.min_z.min(min_z);
+        group.polygons.extend(polygons);
+        group.count += 1;
+        group.area += area;
+        None
+    }
+
+    fn finish(
+        self: Box<Self>,
+        feedback: &Feedback,
+        out: &mut dyn FnMut(Entity) -> Result<()>,
+    ) -> Result<()> {
+        let groups: Vec<_> = self.groups.into_inner().unwrap().into_iter().collect();
+        feedback.info(format!("Dissolving features into {} groups", groups.len()));
+        let entities: Vec<_> = groups
+            .into_par_iter()
+            .map(|((_, key), group)| dissolve_group(key, group))
+            .collect();
+        entities.into_iter().try_for_each(out)
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        for (typename, ty) in schema.types.iter_mut() {
+            if !self.is_target_type(typename) {
+                continue;
+            }
+            match ty {
+                TypeDef::Feature(FeatureTypeDef { attributes, .. }) => {
+                    attributes.insert(
+                        DISSOLVE_COUNT_ATTRIBUTE.to_string(),
+                        Attribute::new(TypeRef::Integer),
+                    );
+                    attributes.insert(
+                        DISSOLVE_AREA_ATTRIBUTE.to_string(),
+                        Attribute::new(TypeRef::Double),
+                    );
+                }
+                TypeDef::Data(_) | TypeDef::Property(_) => {}
+            }
+        }
+    }
+}
+
+/// Makes a new feature from the merged surfaces of the group.
+fn dissolve_group(key: String, group: Group) -> Entity {
+    let mut geom_store = GeometryStore {
+        epsg: group.epsg,
+        ..Default::default()
+    };
+    let union = union_all(group.polygons);
+    let len = append_multipolygon(&mut geom_store, &union, group.min_z);
+    if group.has_appearance {
+        // Keep the appearance buffers aligned for the sinks that resolve appearances
+        let mut uvs = MultiPolygon::new();
+        for poly in geom_store.multipolygon.iter() {
+            for (ri, ring) in poly.rings().enumerate() {
+                let zeros = ring.iter().map(|_| [0., 0.]);
+                match ri {
+                    0 => uvs.add_exterior(zeros),
+                    _ => uvs.add_interior(zeros),
+                }
+            }
+        }
+        geom_store.polygon_uvs = uvs;
+        geom_store.polygon_materials = vec![None; geom_store.multipolygon.len()];
+        geom_store.polygon_textures = vec![None; geom_store.multipolygon.len()];
+    }
+
+    let geometries = match len {
+        0 => Vec::new(),
+        len => vec![GeometryRef {
+            ty: GeometryType::Surface,
+            lod: group.lod,
+            pos: 0,
+            len,
+        }],
+    };
+
+    let mut attributes = Map::default();
+    attributes.insert(group.attribute, group.value);
+    attributes.insert(
+        DISSOLVE_COUNT_ATTRIBUTE.to_string(),
+        Value::Integer(group.count),
+    );
+    attributes.insert(
+        DISSOLVE_AREA_ATTRIBUTE.to_string(),
+        Value::Double(group.area),
+    );
+
+    Entity {
+        root: Value::Object(Object {
+            stereotype: ObjectStereotype::Feature {
+                id: format!("dissolved_{}_{}", group.typename.replace(':', "_"), key),
+                geometries,
+            },
+            typename: group.typename,
+            attributes,
+        }),
+        base_url: group.base_url,
+        geometry_store: RwLock::new(geom_store).into(),
+        appearance_store: Default::default(),
+    }
+}
+
+/// Returns the string to group the features by, if the value is a scalar.
+fn group_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Code(code) => Some(code.code().to_string()),
+        Value::Integer(v) => Some(v.to_string()),
+        Value::NonNegativeInteger(v) => Some(v.to_string()),
+        Value::Boolean(v) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::Code;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity(id: &str, x: f64, class: Option<&str>) -> Entity {
+        // A 1x1 square in a projected CRS
+        let vertices = vec![[x, 0., 1.], [x + 1., 0., 1.], [x + 1., 1., 1.], [x, 1., 1.]];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 2, 3]);
+
+        let mut attributes = Map::default();
+        if let Some(class) = class {
+            attributes.insert(
+                "luse:class".into(),
+                Value::Code(Code::new(format!("class {class}"), class.into())),
+            );
+        }
+        test_entity(
+            Value::Object(Object {
+                typename: "luse:LandUse".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: id.into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 1,
+                        pos: 0,
+                        len: 1,
+                    }],
+                },
+            }),
+            GeometryStore {
+                epsg: 6677,
+                vertices,
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn dissolve_by_class() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let aggregate = Box::new(DissolveAggregate::new(
+            "class".into(),
+            vec!["luse:*".into()],
+        ));
+
+        assert!(aggregate
+            .add(&feedback, make_entity("a", 0., Some("201")))
+            .is_none());
+        assert!(aggregate
+            .add(&feedback, make_entity("b", 1., Some("201")))
+            .is_none());
+        assert!(aggregate
+            .add(&feedback, make_entity("c", 5., Some("202")))
+            .is_none());
+        // Without the attribute
+        assert!(aggregate
+            .add(&feedback, make_entity("d", 7., None))
+            .is_some());
+
+        let mut entities = Vec::new();
+        aggregate
+            .finish(&feedback, &mut |entity| {
+                entities.push(entity);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(entities.len(), 2);
+
+        let Value::Object(obj) = &entities[0].root else {
+            unreachable!()
+        };
+        assert_eq!(obj.typename, "luse:LandUse");
+        assert_eq!(obj.attributes[DISSOLVE_COUNT_ATTRIBUTE], Value::Integer(2));
+        assert_eq!(obj.attributes[DISSOLVE_AREA_ATTRIBUTE], Value::Double(2.));
+        let Value::Code(code) = &obj.attributes["luse:class"] else {
+            unreachable!()
+        };
+        assert_eq!(code.code(), "201");
+
+        // The adjacent squares are merged into a rectangle
+        let geom_store = entities[0].geometry_store.read().unwrap();
+        assert_eq!(geom_store.multipolygon.len(), 1);
+        let mut ring: Vec<_> = geom_store
+            .multipolygon
+            .get(0)
+            .exterior()
+            .iter()
+            .map(|idx| geom_store.vertices[idx as usize])
+            .collect();
+        ring.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            ring,
+            [[0., 0., 1.], [0., 1., 1.], [2., 0., 1.], [2., 1., 1.]]
+        );
+    }
+}
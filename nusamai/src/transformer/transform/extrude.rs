@@ -0,0 +1,436 @@
+//! LOD1 block model generation
+
+use nusamai_citygml::{
+    object::{ObjectStereotype, Value},
+    schema::Schema,
+    GeometryRef, GeometryStore, GeometryType,
+};
+use nusamai_geometry::algorithm::plane::newell_normal;
+use nusamai_plateau::Entity;
+
+use super::{
+    footprint::{append_indexed_polygon, collect_geometries, FOOTPRINT_LOD},
+    metric::to_local_metric,
+    units::measure_in_meters,
+};
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// LOD of the generated block models
+pub const EXTRUSION_LOD: u8 = 1;
+
+/// Transform to generate LOD1 block models by extruding the LOD0 footprints of buildings
+///
+/// The outline of the lowest LOD0 geometry of a building (`lod0FootPrint` rather than `lod0RoofEdge`) is
+/// extruded upward by the value of the height attribute (e.g. `bldg:measuredHeight`, converted to meters).
+/// The prism stands on the lowest vertex of all the geometries of the building, so that the outline of a roof
+/// edge is put down on the ground of the footprint or the other LODs. A building with only a roof edge is
+/// extruded from the roof edge, as its ground is unknown.
+/// The attribute may be given without the namespace prefix (e.g. `measuredHeight`).
+/// The resulting closed prism is added to the building as a LOD1 solid.
+///
+/// Buildings that already have LOD1 solids, or have no valid height, are left as they are.
+#[derive(Clone)]
+pub struct ExtrudeTransform {
+    height_attribute: String,
+    default_height: Option<f64>,
+}
+
+impl ExtrudeTransform {
+    pub fn new(height_attribute: String, default_height: Option<f64>) -> Self {
+        Self {
+            height_attribute,
+            default_height,
+        }
+    }
+
+    fn height(&self, value: &Value) -> Option<f64> {
+        let Value::Object(obj) = value else {
+            return None;
+        };
+        let value = obj.attributes.get(&self.height_attribute).or_else(|| {
+            obj.attributes.iter().find_map(|(key, value)| {
+                let local_name = key.split_once(':').map_or(key.as_str(), |(_, name)| name);
+                (local_name == self.height_attribute).then_some(value)
+            })
+        });
+        let height = match value {
+            Some(Value::Measure(m)) => measure_in_meters(m),
+            Some(Value::Double(v)) => Some(*v),
+            Some(Value::Integer(v)) => Some(*v as f64),
+            Some(Value::NonNegativeInteger(v)) => Some(*v as f64),
+            Some(Value::String(s)) => s.parse().ok(),
+            _ => None,
+        };
+        height
+            .or(self.default_height)
+            .filter(|h| h.is_finite() && *h > 0.0)
+    }
+}
+
+impl Transform for ExtrudeTransform {
+    fn transform(&mut self, feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        let Value::Object(obj) = &entity.root else {
+            out.push(entity);
+            return;
+        };
+        let ObjectStereotype::Feature { id, geometries } = &obj.stereotype else {
+            out.push(entity);
+            return;
+        };
+        if obj.typename != "bldg:Building"
+            || geometries
+                .iter()
+                .any(|geom| geom.ty == GeometryType::Solid && geom.lod == EXTRUSION_LOD)
+        {
+            out.push(entity);
+            return;
+        }
+
+        // The lowest of the LOD0 geometries (i.e. the footprint rather than the roof edge)
+        // and the lowest vertex of all the geometries
+        let (outline, base) = {
+            let geom_store = entity.geometry_store.read().unwrap();
+            let lowest = |geom: &GeometryRef| {
+                lowest_z(
+                    &geom_store,
+                    geom.pos as usize..(geom.pos + geom.len) as usize,
+                )
+            };
+            let outline = geometries
+                .iter()
+                .filter(|geom| geom.lod == FOOTPRINT_LOD && geom.ty == GeometryType::Surface)
+                .filter_map(|geom| Some((geom.clone(), lowest(geom)?)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(geom, _)| geom);
+
+            let mut ranges = Vec::new();
+            collect_geometries(&entity.root, GeometryType::Surface, &mut ranges);
+            collect_geometries(&entity.root, GeometryType::Solid, &mut ranges);
+            let base = ranges
+                .into_iter()
+                .filter_map(|(_, range)| lowest_z(&geom_store, range))
+                .min_by(f64::total_cmp);
+            (outline, base)
+        };
+        let (Some(outline), Some(base)) = (outline, base) else {
+            out.push(entity);
+            return;
+        };
+        let Some(height) = self.height(&entity.root) else {
+            feedback.debug(format!("No height to extrude the building {}", id));
+            out.push(entity);
+            return;
+        };
+
+        let solid = {
+            let mut geom_store = entity.geometry_store.write().unwrap();
+            extrude(&mut geom_store, &outline, base, height)
+        };
+
+        if let Value::Object(obj) = &mut entity.root {
+            if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                geometries.push(solid);
+            }
+        }
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, _schema: &mut Schema) {
+        // do nothing
+    }
+}
+
+/// Returns the lowest elevation of the vertices of the polygons
+fn lowest_z(geom_store: &GeometryStore, range: std::ops::Range<usize>) -> Option<f64> {
+    let mut lowest: Option<f64> = None;
+    for poly in geom_store.multipolygon.iter_range(range) {
+        for ring in poly.rings() {
+            for idx in ring.iter() {
+                let z = geom_store.vertices[idx as usize][2];
+                lowest = Some(lowest.map_or(z, |lowest| lowest.min(z)));
+            }
+        }
+    }
+    lowest
+}
+
+/// Extrudes the polygons of the outline from `base` and appends the prism to the geometry store.
+///
+/// The faces share the vertices, so that the edges of the shell match.
+fn extrude(
+    geom_store: &mut GeometryStore,
+    outline: &GeometryRef,
+    base: f64,
+    height: f64,
+) -> GeometryRef {
+    let range = outline.pos as usize..(outline.pos + outline.len) as usize;
+    let top = base + height;
+
+    let mut faces: Vec<Vec<Vec<u32>>> = Vec::new();
+    let polygons: Vec<Vec<Vec<[f64; 3]>>> = geom_store
+        .multipolygon
+        .iter_range(range)
+        .map(|poly| {
+            poly.rings()
+                .map(|ring| {
+                    ring.iter()
+                        .map(|idx| geom_store.vertices[idx as usize])
+                        .collect()
+                })
+                .filter(|ring: &Vec<_>| ring.len() >= 3)
+                .collect()
+        })
+        .collect();
+    for mut rings in polygons {
+        if rings.is_empty() {
+            continue;
+        }
+
+        // Make the exterior counter-clockwise and the interiors clockwise (seen from above)
+        for (ri, ring) in rings.iter_mut().enumerate() {
+            for v in ring.iter_mut() {
+                v[2] = base;
+            }
+            let is_ccw = newell_normal(&to_local_metric(ring, geom_store.epsg, true))[2] > 0.0;
+            if is_ccw != (ri == 0) {
+                ring.reverse();
+            }
+        }
+
+        // Vertices of the bottom and the top of each ring
+        let rings: Vec<(Vec<u32>, Vec<u32>)> = rings
+            .iter()
+            .map(|ring| {
+                let start = geom_store.vertices.len() as u32;
+                let n = ring.len() as u32;
+                geom_store.vertices.extend(ring.iter().copied());
+                geom_store
+                    .vertices
+                    .extend(ring.iter().map(|&[x, y, _]| [x, y, top]));
+                (
+                    (start..start + n).collect(),
+                    (start + n..start + 2 * n).collect(),
+                )
+            })
+            .collect();
+
+        // Bottom (facing downward)
+        faces.push(
+            rings
+                .iter()
+                .map(|(bottom, _)| bottom.iter().rev().copied().collect())
+                .collect(),
+        );
+        // Top (facing upward)
+        faces.push(rings.iter().map(|(_, top)| top.clone()).collect());
+        // Walls (facing outward)
+        for (bottom, top) in &rings {
+            for i in 0..bottom.len() {
+                let j = (i + 1) % bottom.len();
+                faces.push(vec![vec![bottom[i], bottom[j], top[j], top[i]]]);
+            }
+        }
+    }
+
+    let pos = geom_store.multipolygon.len() as u32;
+    for face in &faces {
+        append_indexed_polygon(geom_store, face);
+    }
+
+    GeometryRef {
+        ty: GeometryType::Solid,
+        lod: EXTRUSION_LOD,
+        pos,
+        len: faces.len() as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{
+        object::{Map, Object},
+        Measure,
+    };
+    use nusamai_geometry::MultiPolygon;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity(height: Option<f64>) -> Entity {
+        // Clockwise footprint on the ground (should be reoriented)
+        let vertices = vec![[0., 0., 2.], [0., 1., 2.], [1., 1., 3.], [1., 0., 3.]];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 2, 3]);
+
+        let mut attributes = Map::default();
+        if let Some(height) = height {
+            attributes.insert(
+                "bldg:measuredHeight".into(),
+                Value::Measure(Measure::new(height)),
+            );
+        }
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 0,
+                        pos: 0,
+                        len: 1,
+                    }],
+                },
+            }),
+            GeometryStore {
+                vertices,
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(entity: Entity, transform: &mut ExtrudeTransform) -> Entity {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut out = Vec::new();
+        transform.transform(&feedback, entity, &mut out);
+        out.pop().unwrap()
+    }
+
+    fn geometries(entity: &Entity) -> Vec<GeometryRef> {
+        let Value::Object(obj) = &entity.root else {
+            unreachable!()
+        };
+        let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype else {
+            unreachable!()
+        };
+        geometries.clone()
+    }
+
+    #[test]
+    fn extrude_footprint() {
+        let mut transform = ExtrudeTransform::new("measuredHeight".into(), None);
+        let entity = run(make_entity(Some(10.)), &mut transform);
+        let geometries = geometries(&entity);
+        assert_eq!(geometries.len(), 2);
+        let solid = &geometries[1];
+        assert_eq!(solid.ty, GeometryType::Solid);
+        assert_eq!(solid.lod, EXTRUSION_LOD);
+        assert_eq!(solid.len, 6);
+
+        // All faces are facing outward
+        let geom_store = entity.geometry_store.read().unwrap();
+        let mut sum = [0.; 3];
+        let mut heights = vec![];
+        for poly in geom_store
+            .multipolygon
+            .iter_range(solid.pos as usize..(solid.pos + solid.len) as usize)
+        {
+            let ring: Vec<_> = poly
+                .exterior()
+                .iter()
+                .map(|idx| geom_store.vertices[idx as usize])
+                .collect();
+            let n = newell_normal(&ring);
+            let center = ring.iter().fold([0.; 3], |c, v| {
+                [c[0] + v[0] / 4., c[1] + v[1] / 4., c[2] + v[2] / 4.]
+            });
+            let outward = [center[0] - 0.5, center[1] - 0.5, center[2] - 7.];
+            assert!(n[0] * outward[0] + n[1] * outward[1] + n[2] * outward[2] > 0.);
+            sum = [sum[0] + n[0], sum[1] + n[1], sum[2] + n[2]];
+            heights.extend(ring.iter().map(|v| v[2]));
+        }
+        // The shell is closed
+        assert_eq!(sum, [0., 0., 0.]);
+        assert!(heights.iter().all(|&h| h == 2. || h == 12.));
+
+        // Each edge is shared by two faces in opposite directions
+        let mut edges = hashbrown::HashMap::new();
+        for poly in geom_store
+            .multipolygon
+            .iter_range(solid.pos as usize..(solid.pos + solid.len) as usize)
+        {
+            let ring = poly.exterior();
+            let indices: Vec<_> = ring.iter().collect();
+            for i in 0..indices.len() {
+                let (a, b) = (indices[i], indices[(i + 1) % indices.len()]);
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        assert_eq!(edges.len(), 24);
+        assert!(edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1)));
+    }
+
+    #[test]
+    fn extrude_roof_edge_from_ground() {
+        // A roof edge at the height of 12, given before the footprint
+        let mut entity = make_entity(Some(10.));
+        {
+            let mut geom_store = entity.geometry_store.write().unwrap();
+            let start = geom_store.vertices.len() as u32;
+            geom_store.vertices.extend([
+                [0., 0., 12.],
+                [1., 0., 12.],
+                [1., 1., 12.],
+                [0., 1., 12.],
+            ]);
+            geom_store.multipolygon.add_exterior(start..start + 4);
+        }
+        if let Value::Object(obj) = &mut entity.root {
+            if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                geometries.insert(
+                    0,
+                    GeometryRef {
+                        ty: GeometryType::Surface,
+                        lod: 0,
+                        pos: 1,
+                        len: 1,
+                    },
+                );
+            }
+        }
+
+        let mut transform = ExtrudeTransform::new("measuredHeight".into(), None);
+        let entity = run(entity, &mut transform);
+        let solid = geometries(&entity)[2].clone();
+        let geom_store = entity.geometry_store.read().unwrap();
+        let heights: Vec<_> = geom_store
+            .multipolygon
+            .iter_range(solid.pos as usize..(solid.pos + solid.len) as usize)
+            .flat_map(|poly| {
+                poly.exterior()
+                    .iter()
+                    .map(|idx| geom_store.vertices[idx as usize][2])
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert!(heights.iter().all(|&h| h == 2. || h == 12.));
+    }
+
+    #[test]
+    fn height_in_millimeters() {
+        let mut entity = make_entity(None);
+        if let Value::Object(obj) = &mut entity.root {
+            obj.attributes.insert(
+                "bldg:measuredHeight".into(),
+                Value::Measure(Measure::with_uom(10000., Some("mm".into()))),
+            );
+        }
+        let transform = ExtrudeTransform::new("measuredHeight".into(), None);
+        assert_eq!(transform.height(&entity.root), Some(10.));
+    }
+
+    #[test]
+    fn missing_height() {
+        let mut transform = ExtrudeTransform::new("bldg:measuredHeight".into(), None);
+        let entity = run(make_entity(None), &mut transform);
+        assert_eq!(geometries(&entity).len(), 1);
+
+        let mut transform = ExtrudeTransform::new("bldg:measuredHeight".into(), Some(3.));
+        let entity = run(make_entity(None), &mut transform);
+        assert_eq!(geometries(&entity).len(), 2);
+    }
+}
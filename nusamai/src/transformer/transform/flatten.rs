@@ -6,6 +6,7 @@ use nusamai_citygml::{
     GeometryStore,
 };
 use nusamai_plateau::{appearance::AppearanceStore, Entity};
+use url::Url;
 
 use crate::{pipeline::Feedback, transformer::Transform};
 
@@ -90,7 +91,14 @@ impl Transform for FlattenTreeTransform {
     fn transform(&mut self, _feedback: &Feedback, entity: Entity, out: &mut Vec<Entity>) {
         let geom_store = entity.geometry_store;
         let appearance_store = entity.appearance_store;
-        self.flatten_entity(entity.root, &geom_store, &appearance_store, out, &None);
+        self.flatten_entity(
+            entity.root,
+            &entity.base_url,
+            &geom_store,
+            &appearance_store,
+            out,
+            &None,
+        );
     }
 
     fn transform_schema(&self, schema: &mut Schema) {
@@ -105,6 +113,7 @@ impl Transform for FlattenTreeTransform {
                                 min_occurs: 0,
                                 max_occurs: Some(1),
                                 original_name: None,
+                                uom: None,
                             },
                         );
                         typedef.attributes.insert(
@@ -114,6 +123,7 @@ impl Transform for FlattenTreeTransform {
                                 min_occurs: 0,
                                 max_occurs: Some(1),
                                 original_name: None,
+                                uom: None,
                             },
                         );
                     }
@@ -127,6 +137,7 @@ impl Transform for FlattenTreeTransform {
                                 min_occurs: 0,
                                 max_occurs: Some(1),
                                 original_name: None,
+                                uom: None,
                             },
                         );
                         typedef.attributes.insert(
@@ -136,6 +147,7 @@ impl Transform for FlattenTreeTransform {
                                 min_occurs: 0,
                                 max_occurs: Some(1),
                                 original_name: None,
+                                uom: None,
                             },
                         );
                     }
@@ -158,6 +170,7 @@ impl FlattenTreeTransform {
     fn flatten_entity(
         &self,
         value: Value,
+        base_url: &Url,
         geom_store: &Arc<RwLock<GeometryStore>>,
         appearance_store: &Arc<RwLock<AppearanceStore>>,
         out: &mut Vec<Entity>,
@@ -182,9 +195,14 @@ impl FlattenTreeTransform {
                 // Attributes
                 let mut new_attribs = Map::default();
                 for (key, value) in obj.attributes.drain(..) {
-                    if let Some(v) =
-                        self.flatten_entity(value, geom_store, appearance_store, out, &new_parent)
-                    {
+                    if let Some(v) = self.flatten_entity(
+                        value,
+                        base_url,
+                        geom_store,
+                        appearance_store,
+                        out,
+                        &new_parent,
+                    ) {
                         new_attribs.insert(key, v);
                     }
                 }
@@ -220,7 +238,7 @@ impl FlattenTreeTransform {
                     }
                     out.push(Entity {
                         root: Value::Object(obj),
-                        base_url: url::Url::parse("file:///dummy").expect("should be valid"),
+                        base_url: base_url.clone(),
                         geometry_store: geom_store.clone(),
                         appearance_store: appearance_store.clone(),
                     });
@@ -232,9 +250,14 @@ impl FlattenTreeTransform {
             Value::Array(mut arr) => {
                 let mut new_arr = Vec::with_capacity(arr.len());
                 for value in arr.drain(..) {
-                    if let Some(v) =
-                        self.flatten_entity(value, geom_store, appearance_store, out, parent)
-                    {
+                    if let Some(v) = self.flatten_entity(
+                        value,
+                        base_url,
+                        geom_store,
+                        appearance_store,
+                        out,
+                        parent,
+                    ) {
                         new_arr.push(v)
                     }
                 }
@@ -325,11 +348,20 @@ mod tests {
         };
         let geom_store = Arc::new(RwLock::new(GeometryStore::default()));
         let appearance_store = Arc::new(RwLock::new(AppearanceStore::default()));
+        let base_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697.gml").unwrap();
         let mut out: Vec<Entity> = vec![];
-        transform.flatten_entity(root, &geom_store, &appearance_store, &mut out, &None);
+        transform.flatten_entity(
+            root,
+            &base_url,
+            &geom_store,
+            &appearance_store,
+            &mut out,
+            &None,
+        );
 
         // Check the result
         assert_eq!(out.len(), 2);
+        assert!(out.iter().all(|entity| entity.base_url == base_url));
         out.iter().enumerate().for_each(|(i, entity)| match i {
             0 => {
                 if let Value::Object(obj) = &entity.root {
@@ -404,8 +436,16 @@ mod tests {
         };
         let geom_store = Arc::new(RwLock::new(GeometryStore::default()));
         let appearance_store = Arc::new(RwLock::new(AppearanceStore::default()));
+        let base_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697.gml").unwrap();
         let mut out: Vec<Entity> = vec![];
-        transform.flatten_entity(root, &geom_store, &appearance_store, &mut out, &None);
+        transform.flatten_entity(
+            root,
+            &base_url,
+            &geom_store,
+            &appearance_store,
+            &mut out,
+            &None,
+        );
 
         // Check the result
         assert_eq!(out.len(), 3);
@@ -504,8 +544,16 @@ mod tests {
         };
         let geom_store = Arc::new(RwLock::new(GeometryStore::default()));
         let appearance_store = Arc::new(RwLock::new(AppearanceStore::default()));
+        let base_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697.gml").unwrap();
         let mut out: Vec<Entity> = vec![];
-        transform.flatten_entity(root, &geom_store, &appearance_store, &mut out, &None);
+        transform.flatten_entity(
+            root,
+            &base_url,
+            &geom_store,
+            &appearance_store,
+            &mut out,
+            &None,
+        );
 
         // Check the result
         assert_eq!(out.len(), 3);
@@ -602,8 +650,16 @@ mod tests {
         };
         let geom_store = Arc::new(RwLock::new(GeometryStore::default()));
         let appearance_store = Arc::new(RwLock::new(AppearanceStore::default()));
+        let base_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697.gml").unwrap();
         let mut out: Vec<Entity> = vec![];
-        transform.flatten_entity(root, &geom_store, &appearance_store, &mut out, &None);
+        transform.flatten_entity(
+            root,
+            &base_url,
+            &geom_store,
+            &appearance_store,
+            &mut out,
+            &None,
+        );
 
         // Check the result
         assert_eq!(out.len(), 2); // not 3, as the grand child is not considered
@@ -691,8 +747,16 @@ mod tests {
         };
         let geom_store = Arc::new(RwLock::new(GeometryStore::default()));
         let appearance_store = Arc::new(RwLock::new(AppearanceStore::default()));
+        let base_url = Url::parse("file:///data/udx/bldg/53394611_bldg_6697.gml").unwrap();
         let mut out: Vec<Entity> = vec![];
-        transform.flatten_entity(root, &geom_store, &appearance_store, &mut out, &None);
+        transform.flatten_entity(
+            root,
+            &base_url,
+            &geom_store,
+            &appearance_store,
+            &mut out,
+            &None,
+        );
 
         // Check the result
         assert_eq!(out.len(), 2); // a "generic attribute" is not flattened
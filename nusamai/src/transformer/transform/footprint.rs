@@ -0,0 +1,456 @@
+//! Building footprint extraction
+
+use std::ops::Range;
+
+use nusamai_citygml::{
+    object::{ObjectStereotype, Value},
+    schema::Schema,
+    GeometryRef, GeometryStore, GeometryType,
+};
+use nusamai_geometry::algorithm::plane::newell_normal;
+use nusamai_plateau::Entity;
+
+use super::{
+    boolean::{make_polygon, open_ring, union_all},
+    metric::to_local_metric,
+};
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// LOD of the generated footprints
+pub const FOOTPRINT_LOD: u8 = 0;
+
+/// Transform to derive a 2D footprint of each building
+///
+/// The footprint is the union of the `bldg:GroundSurface` faces of the building (and its parts),
+/// or, if there are none, the union of the upward-facing faces of the lowest-LOD solids projected onto the ground.
+/// It is added to the building as a LOD0 surface at the height of the lowest vertex used,
+/// so that sinks selecting the lowest LOD output the footprints.
+///
+/// Buildings that already have LOD0 geometries (e.g. `lod0FootPrint`) are left as they are.
+/// With `replace` enabled, all the other geometries of the building are removed.
+#[derive(Clone, Default)]
+pub struct FootprintTransform {
+    replace: bool,
+}
+
+impl FootprintTransform {
+    pub fn new(replace: bool) -> Self {
+        Self { replace }
+    }
+}
+
+impl Transform for FootprintTransform {
+    fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        let Value::Object(obj) = &entity.root else {
+            out.push(entity);
+            return;
+        };
+        if obj.typename != "bldg:Building" || has_lod(&entity.root, FOOTPRINT_LOD) {
+            out.push(entity);
+            return;
+        }
+
+        let footprint = {
+            let mut geom_store = entity.geometry_store.write().unwrap();
+            add_footprint(&entity.root, &mut geom_store)
+        };
+
+        if let Some(footprint) = footprint {
+            if self.replace {
+                clear_geometries(&mut entity.root);
+            }
+            if let Value::Object(obj) = &mut entity.root {
+                if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                    geometries.push(footprint);
+                }
+            }
+        }
+
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, _schema: &mut Schema) {
+        // do nothing
+    }
+}
+
+/// Computes the footprint and appends it to the geometry store.
+fn add_footprint(root: &Value, geom_store: &mut GeometryStore) -> Option<GeometryRef> {
+    let (faces, min_z) = footprint_faces(root, geom_store, &geom_store.vertices, true)?;
+
+    let union = union_all(faces);
+    if union.0.is_empty() {
+        return None;
+    }
+
+    let pos = geom_store.multipolygon.len() as u32;
+    let len = append_multipolygon(geom_store, &union, min_z);
+
+    (len > 0).then_some(GeometryRef {
+        ty: GeometryType::Surface,
+        lod: FOOTPRINT_LOD,
+        pos,
+        len,
+    })
+}
+
+/// Collects the faces that make up the footprint, in the 2D coordinates taken from `vertices`,
+/// and the height of the lowest vertex used. The faces overlap each other, so they need to be unioned.
+///
+/// These are the `GroundSurface` faces, or, if there are none, the upward-facing faces of the lowest-LOD solids.
+/// `projected` tells whether the geometry store has been projected (see `to_local_metric`).
+pub(super) fn footprint_faces(
+    root: &Value,
+    geom_store: &GeometryStore,
+    vertices: &[[f64; 3]],
+    projected: bool,
+) -> Option<(Vec<geo::Polygon<f64>>, f64)> {
+    let mut ground_surfaces = Vec::new();
+    collect_thematic_surfaces(root, ":GroundSurface", &mut ground_surfaces);
+
+    let local_vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, projected);
+    let (ranges, upward_only): (Vec<Range<usize>>, _) = if !ground_surfaces.is_empty() {
+        let ranges = ground_surfaces
+            .into_iter()
+            .map(|(_, range)| range)
+            .collect();
+        (ranges, false)
+    } else {
+        let mut solids = Vec::new();
+        collect_geometries(root, GeometryType::Solid, &mut solids);
+        let lowest_lod = solids.iter().map(|(lod, _)| *lod).min()?;
+        let ranges = solids
+            .into_iter()
+            .filter(|(lod, _)| *lod == lowest_lod)
+            .map(|(_, range)| range)
+            .collect();
+        (ranges, true)
+    };
+
+    let mut min_z = f64::MAX;
+    let mut faces = Vec::new();
+    for range in ranges {
+        for poly in geom_store.multipolygon.iter_range(range) {
+            for ring in poly.rings() {
+                for idx in ring.iter() {
+                    min_z = min_z.min(geom_store.vertices[idx as usize][2]);
+                }
+            }
+            if upward_only {
+                let exterior: Vec<_> = poly
+                    .exterior()
+                    .iter()
+                    .map(|idx| local_vertices[idx as usize])
+                    .collect();
+                let [nx, ny, nz] = newell_normal(&exterior);
+                // Skip walls and downward-facing faces
+                if nz <= 1e-3 * (nx * nx + ny * ny + nz * nz).sqrt() {
+                    continue;
+                }
+            }
+            faces.push(make_polygon(poly.rings().map(|ring| {
+                ring.iter()
+                    .map(|idx| {
+                        let [x, y, _] = vertices[idx as usize];
+                        [x, y]
+                    })
+                    .collect()
+            })));
+        }
+    }
+    (!faces.is_empty()).then_some((faces, min_z))
+}
+
+/// Appends the 2D polygons at the height `z` to the geometry store and returns the number of the polygons added.
+pub(super) fn append_multipolygon(
+    geom_store: &mut GeometryStore,
+    mpoly: &geo::MultiPolygon<f64>,
+    z: f64,
+) -> u32 {
+    let pos = geom_store.multipolygon.len();
+    for poly in &mpoly.0 {
+        let mut rings = Vec::new();
+        let all_rings = std::iter::once(poly.exterior()).chain(poly.interiors());
+        for (ri, ring) in all_rings.enumerate() {
+            let mut coords: Vec<_> = open_ring(ring)
+                .into_iter()
+                .map(|[x, y]| [x, y, z])
+                .collect();
+            if coords.len() < 3 {
+                match ri {
+                    0 => break,
+                    _ => continue,
+                }
+            }
+            // Exteriors are counter-clockwise and interiors are clockwise (seen from above)
+            let is_ccw = newell_normal(&to_local_metric(&coords, geom_store.epsg, true))[2] > 0.0;
+            if is_ccw != (ri == 0) {
+                coords.reverse();
+            }
+            rings.push(coords);
+        }
+        if !rings.is_empty() {
+            append_polygon(geom_store, &rings);
+        }
+    }
+    (geom_store.multipolygon.len() - pos) as u32
+}
+
+/// Appends a polygon with new vertices to the geometry store, keeping the appearance buffers aligned.
+pub(super) fn append_polygon(geom_store: &mut GeometryStore, rings: &[Vec<[f64; 3]>]) {
+    let rings: Vec<Vec<u32>> = rings
+        .iter()
+        .map(|ring| {
+            let start = geom_store.vertices.len() as u32;
+            geom_store.vertices.extend_from_slice(ring);
+            (start..start + ring.len() as u32).collect()
+        })
+        .collect();
+    append_indexed_polygon(geom_store, &rings);
+}
+
+/// Appends a polygon made of the existing vertices (given by their indices) to the geometry store,
+/// keeping the appearance buffers aligned.
+pub(super) fn append_indexed_polygon(geom_store: &mut GeometryStore, rings: &[Vec<u32>]) {
+    for (ri, ring) in rings.iter().enumerate() {
+        match ri {
+            0 => geom_store.multipolygon.add_exterior(ring.iter().copied()),
+            _ => geom_store.multipolygon.add_interior(ring.iter().copied()),
+        }
+        if !geom_store.polygon_uvs.is_empty() {
+            let uvs = ring.iter().map(|_| [0., 0.]);
+            match ri {
+                0 => geom_store.polygon_uvs.add_exterior(uvs),
+                _ => geom_store.polygon_uvs.add_interior(uvs),
+            }
+        }
+    }
+    if !geom_store.polygon_materials.is_empty() {
+        geom_store.polygon_materials.push(None);
+    }
+    if !geom_store.polygon_textures.is_empty() {
+        geom_store.polygon_textures.push(None);
+    }
+}
+
+pub(super) fn has_lod(value: &Value, lod: u8) -> bool {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype {
+                if geometries.iter().any(|geom| geom.lod == lod) {
+                    return true;
+                }
+            }
+            obj.attributes.values().any(|v| has_lod(v, lod))
+        }
+        Value::Array(arr) => arr.iter().any(|v| has_lod(v, lod)),
+        _ => false,
+    }
+}
+
+/// Collects the geometries of the thematic surfaces whose type name ends with the suffix (e.g. `:GroundSurface`).
+pub(super) fn collect_thematic_surfaces(
+    value: &Value,
+    typename_suffix: &str,
+    out: &mut Vec<(u8, Range<usize>)>,
+) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype {
+                if obj.typename.ends_with(typename_suffix) {
+                    out.extend(
+                        geometries.iter().map(|geom| {
+                            (geom.lod, geom.pos as usize..(geom.pos + geom.len) as usize)
+                        }),
+                    );
+                }
+            }
+            for value in obj.attributes.values() {
+                collect_thematic_surfaces(value, typename_suffix, out);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                collect_thematic_surfaces(value, typename_suffix, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Collects the geometries of the given type in the feature and its children.
+pub(super) fn collect_geometries(
+    value: &Value,
+    ty: GeometryType,
+    out: &mut Vec<(u8, Range<usize>)>,
+) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype {
+                out.extend(
+                    geometries
+                        .iter()
+                        .filter(|geom| geom.ty == ty)
+                        .map(|geom| (geom.lod, geom.pos as usize..(geom.pos + geom.len) as usize)),
+                );
+            }
+            for value in obj.attributes.values() {
+                collect_geometries(value, ty, out);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                collect_geometries(value, ty, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn clear_geometries(value: &mut Value) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &mut obj.stereotype {
+                geometries.clear();
+            }
+            for value in obj.attributes.values_mut() {
+                clear_geometries(value);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                clear_geometries(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::object::{Map, Object};
+    use nusamai_geometry::MultiPolygon;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    // Two adjacent boxes (an L-shaped roof seen from above), LOD1 solid
+    fn make_vertices() -> Vec<[f64; 3]> {
+        let mut vertices = Vec::new();
+        for [x0, y0, x1, y1] in [[0., 0., 2., 1.], [0., 1., 1., 2.]] {
+            for z in [5., 15.] {
+                vertices.extend([[x0, y0, z], [x1, y0, z], [x1, y1, z], [x0, y1, z]]);
+            }
+        }
+        vertices
+    }
+
+    fn add_box(mpoly: &mut MultiPolygon<'static, u32>, base: u32) {
+        for face in [
+            [0, 3, 2, 1],
+            [4, 5, 6, 7],
+            [0, 1, 5, 4],
+            [3, 7, 6, 2],
+            [0, 4, 7, 3],
+            [1, 2, 6, 5],
+        ] {
+            mpoly.add_exterior(face.iter().map(|i| i + base));
+        }
+    }
+
+    fn make_entity(with_ground_surface: bool) -> Entity {
+        let mut mpoly = MultiPolygon::new();
+        add_box(&mut mpoly, 0);
+        add_box(&mut mpoly, 8);
+
+        let mut attributes = Map::default();
+        if with_ground_surface {
+            // Only the first box is covered by the ground surface
+            mpoly.add_exterior([0, 3, 2, 1]);
+            attributes.insert(
+                "bldg:boundedBy".into(),
+                Value::Array(vec![Value::Object(Object {
+                    typename: "bldg:GroundSurface".into(),
+                    attributes: Default::default(),
+                    stereotype: ObjectStereotype::Feature {
+                        id: "ground_1".into(),
+                        geometries: vec![GeometryRef {
+                            ty: GeometryType::Surface,
+                            lod: 2,
+                            pos: 12,
+                            len: 1,
+                        }],
+                    },
+                })]),
+            );
+        }
+
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Solid,
+                        lod: 1,
+                        pos: 0,
+                        len: 12,
+                    }],
+                },
+            }),
+            GeometryStore {
+                vertices: make_vertices(),
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(entity: Entity, replace: bool) -> (Vec<GeometryRef>, Vec<[f64; 3]>) {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = FootprintTransform::new(replace);
+        let mut out = Vec::new();
+        transform.transform(&feedback, entity, &mut out);
+        let entity = out.pop().unwrap();
+        let Value::Object(obj) = &entity.root else {
+            unreachable!()
+        };
+        let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype else {
+            unreachable!()
+        };
+        let geom_store = entity.geometry_store.read().unwrap();
+        let footprint = geometries.last().unwrap();
+        assert_eq!(footprint.lod, FOOTPRINT_LOD);
+        assert_eq!(footprint.len, 1);
+        let exterior: Vec<_> = geom_store
+            .multipolygon
+            .get(footprint.pos as usize)
+            .exterior()
+            .iter()
+            .map(|idx| geom_store.vertices[idx as usize])
+            .collect();
+        (geometries.clone(), exterior)
+    }
+
+    #[test]
+    fn footprint_from_solid() {
+        let (geometries, exterior) = run(make_entity(false), false);
+        assert_eq!(geometries.len(), 2);
+        // L-shaped footprint at the bottom of the solid
+        assert_eq!(exterior.len(), 6);
+        assert!(exterior.iter().all(|v| v[2] == 5.));
+        assert!(exterior.contains(&[2., 0., 5.]) && exterior.contains(&[0., 2., 5.]));
+        // counter-clockwise
+        assert!(newell_normal(&exterior)[2] > 0.);
+    }
+
+    #[test]
+    fn footprint_from_ground_surface() {
+        let (geometries, exterior) = run(make_entity(true), true);
+        assert_eq!(geometries.len(), 1);
+        assert_eq!(exterior.len(), 4);
+        assert!(!exterior.contains(&[0., 2., 5.]));
+        assert!(newell_normal(&exterior)[2] > 0.);
+    }
+}
@@ -1,19 +1,182 @@
+use std::ops::Range;
+
+use geo::{Area, Centroid};
 use nusamai_citygml::{
     object::{ObjectStereotype, Value},
     schema::{FeatureTypeDef, Schema, TypeDef, TypeRef},
+    GeometryStore, GeometryType,
 };
+use nusamai_geometry::algorithm::plane::newell_normal;
 use nusamai_plateau::Entity;
 
-use crate::{pipeline::Feedback, transformer::Transform};
+use super::{
+    boolean::{make_polygon, union_all},
+    footprint::{collect_geometries, collect_thematic_surfaces, footprint_faces},
+    metric::{from_local_metric, to_lnglat, to_local_metric},
+};
+use crate::{
+    pipeline::Feedback,
+    transformer::{GeometryStatsRules, Transform},
+};
+
+/// Thematic surfaces and the attributes for their total areas
+const THEMATIC_SURFACES: [(&str, &str); 3] = [
+    (":RoofSurface", "roofSurfaceArea"),
+    (":WallSurface", "wallSurfaceArea"),
+    (":GroundSurface", "groundSurfaceArea"),
+];
+
+/// Add attributes computed from the geometries to each feature.
+///
+/// By default, only `minHeight` and `maxHeight` are added. The other attributes are selected
+/// with `GeometryStatsRules`, and they are measured in metres regardless of the CRS.
+/// The centroid and the bounding box are given in longitude and latitude, so they are
+/// added only while the geometries are geographic.
+#[derive(Clone)]
+pub struct GeometryStatsTransform {
+    min_max_heights: bool,
+    rules: GeometryStatsRules,
+}
+
+impl Default for GeometryStatsTransform {
+    fn default() -> Self {
+        Self {
+            min_max_heights: true,
+            rules: Default::default(),
+        }
+    }
+}
+
+impl GeometryStatsTransform {
+    /// Computes the attributes selected by the user (without the heights)
+    pub fn new(rules: &GeometryStatsRules) -> Self {
+        Self {
+            min_max_heights: false,
+            rules: rules.clone(),
+        }
+    }
+
+    fn attribute_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.min_max_heights {
+            names.extend(["maxHeight", "minHeight"]);
+        }
+        if self.rules.footprint_area {
+            names.push("footprintArea");
+        }
+        if self.rules.surface_areas {
+            names.extend(THEMATIC_SURFACES.iter().map(|(_, name)| *name));
+        }
+        if self.rules.volume {
+            names.push("volume");
+        }
+        if self.rules.centroid {
+            names.extend(["centroidLng", "centroidLat"]);
+        }
+        if self.rules.bbox {
+            names.extend(["minLng", "minLat", "maxLng", "maxLat"]);
+        }
+        names
+    }
 
-/// Jsonify all objects and arrays in the entity.
-#[derive(Clone, Default)]
-pub struct GeometryStatsTransform {}
+    fn compute(&self, root: &Value, geom_store: &GeometryStore) -> Vec<(&'static str, f64)> {
+        let mut stats = Vec::new();
+
+        if self.min_max_heights {
+            let mut max_h = f64::MIN;
+            let mut min_h = f64::MAX;
+            geom_store.vertices.iter().for_each(|&v| {
+                let [_lng, _lat, h] = v;
+                max_h = max_h.max(h);
+                min_h = min_h.min(h);
+            });
+            if max_h != f64::MIN {
+                stats.push(("maxHeight", max_h));
+            }
+            if min_h != f64::MAX {
+                stats.push(("minHeight", min_h));
+            }
+        }
+
+        let rules = &self.rules;
+        if rules.bbox {
+            stats.extend(bbox(geom_store));
+        }
+        if !(rules.footprint_area || rules.surface_areas || rules.volume || rules.centroid) {
+            return stats;
+        }
+
+        let local_vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, false);
+
+        if rules.footprint_area || rules.centroid {
+            let footprint = footprint(root, geom_store, &local_vertices);
+            if rules.footprint_area {
+                if let Some(footprint) = &footprint {
+                    stats.push(("footprintArea", footprint.unsigned_area()));
+                }
+            }
+            if rules.centroid {
+                let centroid = match footprint.as_ref().and_then(|fp| fp.centroid()) {
+                    Some(c) => from_local_metric(
+                        [c.x(), c.y()],
+                        &geom_store.vertices,
+                        geom_store.epsg,
+                        false,
+                    ),
+                    None => mean_lnglat(geom_store),
+                };
+                if let Some((lng, lat)) = centroid {
+                    stats.extend([("centroidLng", lng), ("centroidLat", lat)]);
+                }
+            }
+        }
+
+        if rules.surface_areas {
+            let surfaces: Vec<_> = THEMATIC_SURFACES
+                .iter()
+                .map(|(suffix, name)| {
+                    let mut geoms = Vec::new();
+                    collect_thematic_surfaces(root, suffix, &mut geoms);
+                    (*name, geoms)
+                })
+                .collect();
+            // Use the highest LOD of all the thematic surfaces, so that the areas are consistent
+            let lod = surfaces
+                .iter()
+                .flat_map(|(_, geoms)| geoms.iter().map(|(lod, _)| *lod))
+                .max();
+            if let Some(lod) = lod {
+                for (name, geoms) in surfaces {
+                    let area = geoms
+                        .into_iter()
+                        .filter(|(l, _)| *l == lod)
+                        .map(|(_, range)| surface_area(geom_store, &local_vertices, range))
+                        .sum();
+                    stats.push((name, area));
+                }
+            }
+        }
+
+        if rules.volume {
+            let mut solids = Vec::new();
+            collect_geometries(root, GeometryType::Solid, &mut solids);
+            if let Some(lod) = solids.iter().map(|(lod, _)| *lod).max() {
+                let volume = solids
+                    .into_iter()
+                    .filter(|(l, _)| *l == lod)
+                    .map(|(_, range)| solid_volume(geom_store, &local_vertices, range))
+                    .sum();
+                stats.push(("volume", volume));
+            }
+        }
+
+        stats
+    }
+}
 
 impl Transform for GeometryStatsTransform {
     fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
-        // ...
-        let Value::Object(obj) = &mut entity.root else {
+        let Value::Object(obj) = &entity.root else {
             out.push(entity);
             return;
         };
@@ -22,44 +185,291 @@ impl Transform for GeometryStatsTransform {
             return;
         };
 
-        let mut max_h = f64::MIN;
-        let mut min_h = f64::MAX;
-        {
+        let stats = {
             let geom_store = entity.geometry_store.read().unwrap();
-            geom_store.vertices.iter().for_each(|&v| {
-                let [_lng, _lat, h] = v;
-                max_h = max_h.max(h);
-                min_h = min_h.min(h);
-            });
-        }
+            self.compute(&entity.root, &geom_store)
+        };
 
-        if max_h != f64::MIN {
-            obj.attributes
-                .insert("maxHeight".to_string(), Value::Double(max_h));
-        }
-        if min_h != f64::MAX {
-            obj.attributes
-                .insert("minHeight".to_string(), Value::Double(min_h));
+        if let Value::Object(obj) = &mut entity.root {
+            for (name, value) in stats {
+                obj.attributes
+                    .insert(name.to_string(), Value::Double(value));
+            }
         }
 
         out.push(entity);
     }
 
     fn transform_schema(&self, schema: &mut Schema) {
+        let names = self.attribute_names();
         for ty in schema.types.values_mut() {
             match ty {
                 TypeDef::Feature(FeatureTypeDef { attributes, .. }) => {
-                    attributes.insert(
-                        "maxHeight".to_string(),
-                        nusamai_citygml::schema::Attribute::new(TypeRef::Double),
-                    );
-                    attributes.insert(
-                        "minHeight".to_string(),
-                        nusamai_citygml::schema::Attribute::new(TypeRef::Double),
-                    );
+                    for name in &names {
+                        attributes.insert(
+                            name.to_string(),
+                            nusamai_citygml::schema::Attribute::new(TypeRef::Double),
+                        );
+                    }
                 }
                 TypeDef::Data(_) | TypeDef::Property(_) => {}
             }
         }
     }
 }
+
+/// Computes the footprint in the local metric coordinates.
+///
+/// Features without ground surfaces or solids (e.g. land use) use their surfaces of the lowest LOD.
+fn footprint(
+    root: &Value,
+    geom_store: &GeometryStore,
+    local_vertices: &[[f64; 3]],
+) -> Option<geo::MultiPolygon<f64>> {
+    let faces = match footprint_faces(root, geom_store, local_vertices, false) {
+        Some((faces, _)) => faces,
+        None => {
+            let mut surfaces = Vec::new();
+            collect_geometries(root, GeometryType::Surface, &mut surfaces);
+            let lod = surfaces.iter().map(|(lod, _)| *lod).min()?;
+            surfaces
+                .into_iter()
+                .filter(|(l, _)| *l == lod)
+                .flat_map(|(_, range)| geom_store.multipolygon.iter_range(range))
+                .map(|poly| {
+                    make_polygon(poly.rings().map(|ring| {
+                        ring.iter()
+                            .map(|idx| {
+                                let [x, y, _] = local_vertices[idx as usize];
+                                [x, y]
+                            })
+                            .collect()
+                    }))
+                })
+                .collect()
+        }
+    };
+    let union = union_all(faces);
+    (!union.0.is_empty()).then_some(union)
+}
+
+/// Sums up the (3D) areas of the polygons, excluding their holes.
+pub(super) fn surface_area(
+    geom_store: &GeometryStore,
+    local_vertices: &[[f64; 3]],
+    range: Range<usize>,
+) -> f64 {
+    let mut area = 0.0;
+    for poly in geom_store.multipolygon.iter_range(range) {
+        for (ri, ring) in poly.rings().enumerate() {
+            let ring: Vec<_> = ring
+                .iter()
+                .map(|idx| local_vertices[idx as usize])
+                .collect();
+            let [nx, ny, nz] = newell_normal(&ring);
+            let ring_area = (nx * nx + ny * ny + nz * nz).sqrt() / 2.0;
+            match ri {
+                0 => area += ring_area,
+                _ => area -= ring_area,
+            }
+        }
+    }
+    area.max(0.0)
+}
+
+/// Computes the volume enclosed by the faces of a solid, using the divergence theorem.
+fn solid_volume(
+    geom_store: &GeometryStore,
+    local_vertices: &[[f64; 3]],
+    range: Range<usize>,
+) -> f64 {
+    let mut volume = 0.0;
+    for poly in geom_store.multipolygon.iter_range(range) {
+        // The interiors are oriented oppositely, so their terms are subtracted
+        for ring in poly.rings() {
+            let ring: Vec<_> = ring
+                .iter()
+                .map(|idx| local_vertices[idx as usize])
+                .collect();
+            if let Some(p) = ring.first() {
+                let n = newell_normal(&ring);
+                volume += (n[0] * p[0] + n[1] * p[1] + n[2] * p[2]) / 6.0;
+            }
+        }
+    }
+    volume.abs()
+}
+
+fn mean_lnglat(geom_store: &GeometryStore) -> Option<(f64, f64)> {
+    let (mut sum_lng, mut sum_lat, mut count) = (0.0, 0.0, 0);
+    for v in &geom_store.vertices {
+        let (lng, lat) = to_lnglat(v, geom_store.epsg, false)?;
+        sum_lng += lng;
+        sum_lat += lat;
+        count += 1;
+    }
+    (count > 0).then_some((sum_lng / count as f64, sum_lat / count as f64))
+}
+
+fn bbox(geom_store: &GeometryStore) -> Vec<(&'static str, f64)> {
+    let mut min = [f64::MAX; 2];
+    let mut max = [f64::MIN; 2];
+    for v in &geom_store.vertices {
+        let Some((lng, lat)) = to_lnglat(v, geom_store.epsg, false) else {
+            return Vec::new();
+        };
+        min = [min[0].min(lng), min[1].min(lat)];
+        max = [max[0].max(lng), max[1].max(lat)];
+    }
+    if min[0] == f64::MAX {
+        return Vec::new();
+    }
+    vec![
+        ("minLng", min[0]),
+        ("minLat", min[1]),
+        ("maxLng", max[0]),
+        ("maxLat", max[1]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, GeometryRef};
+    use nusamai_geometry::MultiPolygon;
+    use nusamai_projection::crs::EPSG_JGD2011_GEOGRAPHIC_3D;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    /// A 10 m cube (in a projected CRS) with its roof as a thematic surface
+    fn make_entity(epsg: u16, vertices: Vec<[f64; 3]>) -> Entity {
+        let mut mpoly = MultiPolygon::new();
+        for face in [
+            [0, 3, 2, 1], // bottom
+            [4, 5, 6, 7], // top
+            [0, 1, 5, 4],
+            [1, 2, 6, 5],
+            [2, 3, 7, 6],
+            [3, 0, 4, 7],
+        ] {
+            mpoly.add_exterior(face);
+        }
+        let roof = Value::Object(Object {
+            typename: "bldg:RoofSurface".into(),
+            attributes: Default::default(),
+            stereotype: ObjectStereotype::Feature {
+                id: "roof_1".into(),
+                geometries: vec![GeometryRef {
+                    ty: GeometryType::Surface,
+                    lod: 2,
+                    pos: 1,
+                    len: 1,
+                }],
+            },
+        });
+        let mut attributes = nusamai_citygml::object::Map::default();
+        attributes.insert("bldg:boundedBy".into(), Value::Array(vec![roof]));
+
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty: GeometryType::Solid,
+                        lod: 1,
+                        pos: 0,
+                        len: 6,
+                    }],
+                },
+            }),
+            GeometryStore {
+                epsg,
+                vertices,
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn cube(size: f64, map: impl Fn([f64; 3]) -> [f64; 3]) -> Vec<[f64; 3]> {
+        [
+            [0., 0., 0.],
+            [1., 0., 0.],
+            [1., 1., 0.],
+            [0., 1., 0.],
+            [0., 0., 1.],
+            [1., 0., 1.],
+            [1., 1., 1.],
+            [0., 1., 1.],
+        ]
+        .into_iter()
+        .map(|[x, y, z]| map([x * size, y * size, z * size]))
+        .collect()
+    }
+
+    fn run(entity: Entity, rules: &GeometryStatsRules) -> Object {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = GeometryStatsTransform::new(rules);
+        let mut out = Vec::new();
+        transform.transform(&feedback, entity, &mut out);
+        let Value::Object(obj) = out.pop().unwrap().root else {
+            unreachable!()
+        };
+        obj
+    }
+
+    fn get(obj: &Object, name: &str) -> f64 {
+        match obj.attributes.get(name) {
+            Some(Value::Double(v)) => *v,
+            _ => panic!("{name} is missing"),
+        }
+    }
+
+    #[test]
+    fn projected_cube() {
+        let rules = GeometryStatsRules {
+            footprint_area: true,
+            surface_areas: true,
+            volume: true,
+            centroid: true,
+            bbox: true,
+        };
+        let obj = run(make_entity(6677, cube(10., |v| v)), &rules);
+        assert!((get(&obj, "footprintArea") - 100.).abs() < 1e-9);
+        assert!((get(&obj, "volume") - 1000.).abs() < 1e-9);
+        assert!((get(&obj, "roofSurfaceArea") - 100.).abs() < 1e-9);
+        assert_eq!(get(&obj, "wallSurfaceArea"), 0.);
+        // Not geographic
+        assert!(!obj.attributes.contains_key("centroidLng"));
+        assert!(!obj.attributes.contains_key("minLng"));
+        assert!(!obj.attributes.contains_key("maxHeight"));
+    }
+
+    #[test]
+    fn geographic_cube() {
+        // About 10 m in (lat, lng, height)
+        let (lat0, lng0): (f64, f64) = (35.0, 139.0);
+        let deg_lat = 10. / 111_319.49;
+        let deg_lng = deg_lat / lat0.to_radians().cos();
+        let vertices = cube(1., |[x, y, z]| {
+            [lat0 + y * deg_lat, lng0 + x * deg_lng, z * 10.]
+        });
+        let rules = GeometryStatsRules {
+            footprint_area: true,
+            volume: true,
+            centroid: true,
+            bbox: true,
+            ..Default::default()
+        };
+        let obj = run(make_entity(EPSG_JGD2011_GEOGRAPHIC_3D, vertices), &rules);
+        assert!((get(&obj, "footprintArea") - 100.).abs() < 0.01);
+        assert!((get(&obj, "volume") - 1000.).abs() < 0.1);
+        assert!((get(&obj, "centroidLng") - (lng0 + deg_lng / 2.)).abs() < 1e-9);
+        assert!((get(&obj, "centroidLat") - (lat0 + deg_lat / 2.)).abs() < 1e-9);
+        assert_eq!(get(&obj, "minLng"), lng0);
+        assert_eq!(get(&obj, "maxLat"), lat0 + deg_lat);
+        assert!(!obj.attributes.contains_key("roofSurfaceArea"));
+    }
+}
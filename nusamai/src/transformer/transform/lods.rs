@@ -2,16 +2,25 @@ use std::ops::{BitAnd, BitAndAssign, BitOrAssign};
 
 use nusamai_citygml::{
     object::{ObjectStereotype, Value},
-    schema::Schema,
+    schema::{Attribute, Schema, TypeDef, TypeRef},
 };
 use nusamai_plateau::Entity;
 
 use crate::{pipeline::Feedback, transformer::Transform};
 
-#[derive(Clone, Copy)]
+/// Name of the attribute that holds the LOD of the entities split by `LodFilterMode::All`
+pub const LOD_ATTRIBUTE: &str = "lod";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LodFilterMode {
+    /// Keep the highest available LOD
     Highest,
+    /// Keep the lowest available LOD
     Lowest,
+    /// Split the entity into one entity per available LOD, tagged with the `lod` attribute
+    All,
+    /// Keep only the given LOD, and skip the entities without it
+    Exact(u8),
 }
 
 #[derive()]
@@ -34,6 +43,24 @@ impl Transform for FilterLodTransform {
         let target_lod = match self.mode {
             LodFilterMode::Highest => lods.highest_lod(),
             LodFilterMode::Lowest => lods.lowest_lod(),
+            LodFilterMode::Exact(lod) => lods.has_lod(lod).then_some(lod),
+            LodFilterMode::All => {
+                for lod in lods.iter() {
+                    let mut root = entity.root.clone();
+                    edit_tree(&mut root, lod);
+                    if let Value::Object(obj) = &mut root {
+                        obj.attributes
+                            .insert(LOD_ATTRIBUTE.into(), Value::NonNegativeInteger(lod as u64));
+                    }
+                    out.push(Entity {
+                        root,
+                        base_url: entity.base_url.clone(),
+                        geometry_store: entity.geometry_store.clone(),
+                        appearance_store: entity.appearance_store.clone(),
+                    });
+                }
+                return;
+            }
         };
 
         if let Some(target_lod) = target_lod {
@@ -42,8 +69,18 @@ impl Transform for FilterLodTransform {
         }
     }
 
-    fn transform_schema(&self, _schema: &mut Schema) {
-        // do nothing
+    fn transform_schema(&self, schema: &mut Schema) {
+        if self.mode != LodFilterMode::All {
+            return;
+        }
+        for ty in schema.types.values_mut() {
+            if let TypeDef::Feature(feat) = ty {
+                feat.attributes.insert(
+                    LOD_ATTRIBUTE.into(),
+                    Attribute::new(TypeRef::NonNegativeInteger),
+                );
+            }
+        }
     }
 }
 
@@ -107,7 +144,7 @@ impl LodMask {
     }
 
     pub fn remove_lod(&mut self, lod_no: u8) {
-        self.0 |= 1 << lod_no;
+        self.0 &= !(1 << lod_no);
     }
 
     pub fn has_lod(&self, lod_no: u8) -> bool {
@@ -133,6 +170,12 @@ impl LodMask {
             _ => Some(self.0.trailing_zeros() as u8),
         }
     }
+
+    /// Iterates over the LOD numbers in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u8> {
+        let mask = self.0;
+        (0..8).filter(move |lod| mask & (1 << lod) != 0)
+    }
 }
 
 impl BitOrAssign for LodMask {
@@ -157,7 +200,74 @@ impl BitAnd for LodMask {
 
 #[cfg(test)]
 mod tests {
+    use nusamai_citygml::{
+        geometry::{GeometryRef, GeometryType},
+        object::Object,
+        GeometryStore,
+    };
+
     use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity() -> Entity {
+        let geometries = [1, 2, 2]
+            .into_iter()
+            .map(|lod| GeometryRef {
+                ty: GeometryType::Solid,
+                lod,
+                pos: 0,
+                len: 1,
+            })
+            .collect();
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries,
+                },
+            }),
+            GeometryStore::default(),
+        )
+    }
+
+    fn run(mode: LodFilterMode) -> Vec<(Vec<u8>, Option<Value>)> {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = FilterLodTransform::new(LodMask::all(), mode);
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        out.into_iter()
+            .map(|entity| {
+                let Value::Object(obj) = entity.root else {
+                    unreachable!()
+                };
+                let ObjectStereotype::Feature { geometries, .. } = obj.stereotype else {
+                    unreachable!()
+                };
+                (
+                    geometries.iter().map(|g| g.lod).collect(),
+                    obj.attributes.get(LOD_ATTRIBUTE).cloned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_modes() {
+        assert_eq!(run(LodFilterMode::Highest), [(vec![2, 2], None)]);
+        assert_eq!(run(LodFilterMode::Lowest), [(vec![1], None)]);
+        assert_eq!(run(LodFilterMode::Exact(2)), [(vec![2, 2], None)]);
+        assert!(run(LodFilterMode::Exact(3)).is_empty());
+        assert_eq!(
+            run(LodFilterMode::All),
+            [
+                (vec![1], Some(Value::NonNegativeInteger(1))),
+                (vec![2, 2], Some(Value::NonNegativeInteger(2))),
+            ]
+        );
+    }
+
     #[test]
     fn test_lod_mask() {
         let mut mask = LodMask::default();
@@ -184,5 +294,9 @@ mod tests {
         mask2.add_lod(3);
         assert!((mask & mask2).has_lod(3));
         assert!(!(mask & mask2).has_lod(1));
+
+        assert_eq!(mask.iter().collect::<Vec<_>>(), [1, 2, 3]);
+        mask.remove_lod(2);
+        assert_eq!(mask.iter().collect::<Vec<_>>(), [1, 3]);
     }
 }
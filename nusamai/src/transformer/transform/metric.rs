@@ -0,0 +1,124 @@
+//! Helpers to measure geometries in metres
+
+use nusamai_projection::crs::*;
+
+const EARTH_RADIUS: f64 = 6378137.0;
+
+/// Converts the vertices to a local metric coordinate system, so that the tolerance can be given in metres.
+///
+/// Geographic coordinates are approximated with an equirectangular projection around the first vertex,
+/// which is accurate enough for a single city object. Other CRSs are assumed to be projected already.
+///
+/// `projected` tells whether `ProjectionTransform` has run: it keeps the EPSG code of a geographic output CRS
+/// but changes the axis order to (lng, lat).
+pub(crate) fn to_local_metric(
+    vertices: &[[f64; 3]],
+    epsg: EpsgCode,
+    projected: bool,
+) -> Vec<[f64; 3]> {
+    let Some((lng0, lat0)) = vertices.first().and_then(|v| to_lnglat(v, epsg, projected)) else {
+        // Projected CRS (or no vertices)
+        return vertices.to_vec();
+    };
+
+    let k = EARTH_RADIUS.to_radians();
+    let cos_lat0 = lat0.to_radians().cos();
+    vertices
+        .iter()
+        .map(|v| {
+            let (lng, lat) = to_lnglat(v, epsg, projected).unwrap();
+            [(lng - lng0) * k * cos_lat0, (lat - lat0) * k, v[2]]
+        })
+        .collect()
+}
+
+/// Converts a point given by `to_local_metric` back to (lng, lat).
+///
+/// Returns `None` if the vertices are not geographic.
+pub(crate) fn from_local_metric(
+    [x, y]: [f64; 2],
+    vertices: &[[f64; 3]],
+    epsg: EpsgCode,
+    projected: bool,
+) -> Option<(f64, f64)> {
+    let (lng0, lat0) = vertices
+        .first()
+        .and_then(|v| to_lnglat(v, epsg, projected))?;
+    let k = EARTH_RADIUS.to_radians();
+    let cos_lat0 = lat0.to_radians().cos();
+    Some((lng0 + x / (k * cos_lat0), lat0 + y / k))
+}
+
+/// Returns the (lng, lat) of a vertex, or `None` if the CRS is not geographic.
+///
+/// See `to_local_metric` for `projected`.
+pub(crate) fn to_lnglat(v: &[f64; 3], epsg: EpsgCode, projected: bool) -> Option<(f64, f64)> {
+    match epsg {
+        // (lat, lng) order as parsed, and (lng, lat) after the projection
+        EPSG_JGD2011_GEOGRAPHIC_2D
+        | EPSG_JGD2011_GEOGRAPHIC_3D
+        | EPSG_JGD2000_GEOGRAPHIC_2D
+        | EPSG_TOKYO_GEOGRAPHIC_2D => match projected {
+            false => Some((v[1], v[0])),
+            true => Some((v[0], v[1])),
+        },
+        // (lng, lat) order
+        EPSG_WGS84_GEOGRAPHIC_2D | EPSG_WGS84_GEOGRAPHIC_3D => Some((v[0], v[1])),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geographic_to_local_metric() {
+        let vertices = [[35.0, 139.0, 10.0], [35.001, 139.001, 20.0]];
+        let local = to_local_metric(&vertices, EPSG_JGD2011_GEOGRAPHIC_3D, false);
+        assert_eq!(local[0], [0., 0., 10.]);
+        assert!((local[1][0] - 91.2).abs() < 0.1);
+        assert!((local[1][1] - 111.3).abs() < 0.1);
+        assert_eq!(local[1][2], 20.);
+
+        let (lng, lat) = from_local_metric(
+            [local[1][0], local[1][1]],
+            &vertices,
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            false,
+        )
+        .unwrap();
+        assert!((lng - 139.001).abs() < 1e-9);
+        assert!((lat - 35.001).abs() < 1e-9);
+
+        // (lng, lat) after the projection
+        let projected = vertices.map(|[lat, lng, h]| [lng, lat, h]);
+        assert_eq!(
+            to_local_metric(&projected, EPSG_JGD2011_GEOGRAPHIC_3D, true),
+            local
+        );
+    }
+
+    #[test]
+    fn test_to_lnglat() {
+        let v = [35.0, 139.0, 10.0];
+        for epsg in [
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            EPSG_JGD2000_GEOGRAPHIC_2D,
+            EPSG_TOKYO_GEOGRAPHIC_2D,
+        ] {
+            assert_eq!(to_lnglat(&v, epsg, false), Some((139.0, 35.0)));
+        }
+        for projected in [false, true] {
+            assert_eq!(
+                to_lnglat(&[139.0, 35.0, 10.0], EPSG_WGS84_GEOGRAPHIC_3D, projected),
+                Some((139.0, 35.0))
+            );
+        }
+        assert_eq!(
+            to_lnglat(&[139.0, 35.0, 10.0], EPSG_JGD2011_GEOGRAPHIC_3D, true),
+            Some((139.0, 35.0))
+        );
+        assert_eq!(to_lnglat(&v, EPSG_JGD2011_JPRECT_IX, false), None);
+    }
+}
@@ -1,17 +1,38 @@
 mod appearance;
 mod attrname;
+mod boolean;
+mod cast;
+mod clean;
+mod codes;
+mod dedupe;
+mod dissolve;
 mod dots;
+mod extrude;
 pub mod flatten;
+mod footprint;
 mod geommerge;
 mod geomstats;
 mod jsonify;
 mod lods;
+mod metric;
 mod projection;
+mod select;
+mod simplify;
+mod surfaces;
+mod units;
+mod validation;
 
 pub use appearance::*;
 pub use attrname::*;
+pub use cast::*;
+pub use clean::*;
+pub use codes::*;
+pub use dedupe::*;
+pub use dissolve::*;
 pub use dots::*;
+pub use extrude::*;
 pub use flatten::*;
+pub use footprint::*;
 pub use geommerge::*;
 pub use geomstats::*;
 pub use jsonify::*;
@@ -19,6 +40,11 @@ pub use lods::*;
 use nusamai_citygml::schema::Schema;
 use nusamai_plateau::Entity;
 pub use projection::*;
+pub use select::*;
+pub use simplify::*;
+pub use surfaces::*;
+pub use units::*;
+pub use validation::*;
 
 use super::Transform;
 use crate::pipeline::Feedback;
@@ -75,10 +101,22 @@ impl Transform for IdentityTransform {
     }
 }
 
+/// Make an entity for the tests of the transforms
 #[cfg(test)]
-mod tests {
-    use std::sync::RwLock;
+pub(crate) fn test_entity(
+    root: nusamai_citygml::Value,
+    geometry_store: nusamai_citygml::GeometryStore,
+) -> Entity {
+    Entity {
+        root,
+        base_url: url::Url::parse("file:///dummy").unwrap(),
+        geometry_store: std::sync::RwLock::new(geometry_store).into(),
+        appearance_store: Default::default(),
+    }
+}
 
+#[cfg(test)]
+mod tests {
     use feedback::watcher;
     use nusamai_citygml::{object::Object, GeometryStore, Value};
 
@@ -96,8 +134,8 @@ mod tests {
         let mut entities = Vec::new();
         transform.transform(
             &feedback,
-            Entity {
-                root: Value::Object(Object {
+            test_entity(
+                Value::Object(Object {
                     typename: "test".into(),
                     attributes: Default::default(),
                     stereotype: nusamai_citygml::object::ObjectStereotype::Feature {
@@ -105,10 +143,8 @@ mod tests {
                         geometries: Default::default(),
                     },
                 }),
-                base_url: url::Url::parse("file:///dummy").unwrap(),
-                geometry_store: RwLock::new(GeometryStore::default()).into(),
-                appearance_store: Default::default(),
-            },
+                GeometryStore::default(),
+            ),
             &mut entities,
         );
         assert_eq!(entities.len(), 1);
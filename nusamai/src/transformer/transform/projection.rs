@@ -1,169 +1,143 @@
 use std::sync::Arc;
 
-use nusamai_citygml::schema::Schema;
+use nusamai_citygml::{object::Value, schema::Schema};
 use nusamai_plateau::Entity;
 use nusamai_projection::{
-    crs::*, etmerc::ExtendedTransverseMercatorProjection, jprect::JPRZone, vshift::Jgd2011ToWgs84,
+    cartesian::geodetic_to_geocentric,
+    crs::*,
+    datumshift::JgdDatumShift,
+    ellipsoid::{bessel, wgs84},
+    error::TransformError,
+    etmerc::ExtendedTransverseMercatorProjection,
+    jprect::JPRZone,
+    utm::{UtmDatum, UtmProjection, UtmZone},
+    vshift::Jgd2011ToWgs84,
 };
 
-use crate::{pipeline::Feedback, transformer::Transform};
+use crate::{
+    pipeline::{Feedback, PipelineError},
+    transformer::Transform,
+};
+
+/// Output coordinate reference system
+#[derive(Clone)]
+enum Target {
+    /// JGD2011 geographic with the elevation
+    Jgd2011Geographic,
+    /// WGS 84 geographic with the ellipsoidal height
+    Wgs84Geographic,
+    WebMercator,
+    /// Japan Plane Rectangular CS (the height is kept)
+    PlaneRectangular(ExtendedTransverseMercatorProjection),
+    /// UTM (the height is handled as in the geographic CRS of the same datum)
+    Utm(UtmProjection, UtmDatum),
+    /// WGS 84 geocentric (ECEF)
+    Geocentric,
+}
+
+impl Target {
+    fn from_epsg(epsg: EpsgCode) -> Result<Self, TransformError> {
+        match epsg {
+            EPSG_JGD2011_GEOGRAPHIC_3D => Ok(Target::Jgd2011Geographic),
+            EPSG_WGS84_GEOGRAPHIC_3D => Ok(Target::Wgs84Geographic),
+            EPSG_WEB_MERCATOR => Ok(Target::WebMercator),
+            EPSG_WGS84_GEOCENTRIC => Ok(Target::Geocentric),
+            EPSG_JGD2011_JPRECT_I_JGD2011_HEIGHT..=EPSG_JGD2011_JPRECT_XIII_JGD2011_HEIGHT
+            | EPSG_JGD2011_JPRECT_I..=EPSG_JGD2011_JPRECT_XIX => {
+                let zone = JPRZone::from_epsg(epsg).ok_or(TransformError::UnsupportedCrs(epsg))?;
+                Ok(Target::PlaneRectangular(zone.projection()))
+            }
+            _ => match UtmZone::from_epsg(epsg) {
+                Some(zone) => Ok(Target::Utm(zone.projection(), zone.datum())),
+                None => Err(TransformError::UnsupportedCrs(epsg)),
+            },
+        }
+    }
+}
+
+/// Input coordinate reference system
+///
+/// Vertices are in the axis order of the CRS: (lat, lng) or (northing, easting).
+enum Source {
+    Jgd2011Geographic,
+    Wgs84Geographic,
+    /// Tokyo datum, geographic or plane rectangular (on the Bessel ellipsoid)
+    Tokyo(Option<ExtendedTransverseMercatorProjection>),
+    /// JGD2000, geographic or plane rectangular
+    Jgd2000(Option<ExtendedTransverseMercatorProjection>),
+}
+
+impl Source {
+    fn from_epsg(epsg: EpsgCode) -> Result<Self, TransformError> {
+        match epsg {
+            EPSG_JGD2011_GEOGRAPHIC_3D => Ok(Source::Jgd2011Geographic),
+            EPSG_WGS84_GEOGRAPHIC_3D => Ok(Source::Wgs84Geographic),
+            EPSG_TOKYO_GEOGRAPHIC_2D => Ok(Source::Tokyo(None)),
+            EPSG_JGD2000_GEOGRAPHIC_2D => Ok(Source::Jgd2000(None)),
+            EPSG_TOKYO_JPRECT_I..=EPSG_TOKYO_JPRECT_XIX => Ok(Source::Tokyo(
+                JPRZone::from_epsg(epsg).map(|zone| zone.projection_with_ellipsoid(&bessel())),
+            )),
+            EPSG_JGD2000_JPRECT_I..=EPSG_JGD2000_JPRECT_XIX => Ok(Source::Jgd2000(
+                JPRZone::from_epsg(epsg).map(|zone| zone.projection()),
+            )),
+            _ => Err(TransformError::UnsupportedCrs(epsg)),
+        }
+    }
+}
+
+/// Returns the (lng, lat) of a vertex in a geographic or plane rectangular CRS.
+fn to_geographic(
+    proj: Option<&ExtendedTransverseMercatorProjection>,
+    [a, b, _]: [f64; 3],
+) -> Result<(f64, f64), TransformError> {
+    match proj {
+        Some(proj) => {
+            let (lng, lat, _) = proj.project_inverse(b, a, 0.)?;
+            Ok((lng, lat))
+        }
+        None => Ok((b, a)),
+    }
+}
 
 /// Coordinate transformation
 #[derive(Clone)]
 pub struct ProjectionTransform {
     jgd2wgs: Arc<Jgd2011ToWgs84>,
+    datum_shift: Arc<JgdDatumShift>,
     output_epsg: EpsgCode,
-    jpr_zone_proj: Option<ExtendedTransverseMercatorProjection>,
+    /// Whether to keep the orthometric heights in WGS 84 outputs (for viewers that expect them)
+    keep_orthometric_height: bool,
+    target: Result<Target, EpsgCode>,
 }
 
 impl Transform for ProjectionTransform {
-    fn transform(&mut self, _feedback: &Feedback, entity: Entity, out: &mut Vec<Entity>) {
-        let input_epsg = {
-            let geom_store = entity.geometry_store.read().unwrap();
-            geom_store.epsg
+    fn transform(&mut self, feedback: &Feedback, entity: Entity, out: &mut Vec<Entity>) {
+        let target = match &self.target {
+            Ok(target) => target,
+            Err(epsg) => {
+                feedback.fatal_error(PipelineError::Other(format!(
+                    "Cannot transform coordinates: {}",
+                    TransformError::UnsupportedCrs(*epsg)
+                )));
+                return;
+            }
         };
 
-        match input_epsg {
-            EPSG_JGD2011_GEOGRAPHIC_3D => match self.output_epsg {
-                EPSG_JGD2011_GEOGRAPHIC_3D => {
-                    let mut geom_store = entity.geometry_store.write().unwrap();
-                    geom_store.vertices.iter_mut().for_each(|v| {
-                        // Swap x and y (lat, lng -> lng, lat)
-                        (v[0], v[1], v[2]) = (v[1], v[0], v[2]);
-                    });
-                    geom_store.epsg = self.output_epsg;
-                }
-                EPSG_WGS84_GEOGRAPHIC_3D => {
-                    let mut geom_store = entity.geometry_store.write().unwrap();
-                    geom_store.vertices.iter_mut().for_each(|v| {
-                        // Swap x and y (lat, lng -> lng, lat)
-                        let (lng, lat, height) = (v[1], v[0], v[2]);
-                        // JGD2011 to WGS 84 (elevation to ellipsoidal height)
-                        (v[0], v[1], v[2]) = self.jgd2wgs.convert(lng, lat, height);
-                    });
-                    geom_store.epsg = self.output_epsg;
-                }
-                EPSG_WEB_MERCATOR => {
-                    let mut geom_store = entity.geometry_store.write().unwrap();
-                    geom_store.vertices.iter_mut().for_each(|v| {
-                        // Swap x and y (lat, lng -> lng, lat)
-                        let (lng, lat) = (v[1], v[0]);
-                        // LngLat to Web Mercator
-                        (v[0], v[1]) =
-                            nusamai_mvt::webmercator::lnglat_to_web_mercator_meters(lng, lat)
-                    });
-                    geom_store.epsg = self.output_epsg;
-                }
-                EPSG_JGD2011_JPRECT_I_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_II_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_III_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_IV_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_V_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VI_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VIII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_IX_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_X_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XI_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XIII_JGD2011_HEIGHT => {
-                    // To Japan Plane Rectangular CS + JGD2011 (vertical) height
-                    let proj = self.jpr_zone_proj.as_ref().unwrap();
-                    let mut geom_store = entity.geometry_store.write().unwrap();
-                    geom_store.vertices.iter_mut().for_each(|v| {
-                        let (lng, lat) = (v[1], v[0]);
-                        // Change x and y; keep the height
-                        // TODO: error handling
-                        (v[0], v[1], _) = proj.project_forward(lng, lat, 0.).unwrap();
-                    });
-                    geom_store.epsg = self.output_epsg;
-                }
-                EPSG_JGD2011_JPRECT_I
-                | EPSG_JGD2011_JPRECT_II
-                | EPSG_JGD2011_JPRECT_III
-                | EPSG_JGD2011_JPRECT_IV
-                | EPSG_JGD2011_JPRECT_V
-                | EPSG_JGD2011_JPRECT_VI
-                | EPSG_JGD2011_JPRECT_VII
-                | EPSG_JGD2011_JPRECT_VIII
-                | EPSG_JGD2011_JPRECT_IX
-                | EPSG_JGD2011_JPRECT_X
-                | EPSG_JGD2011_JPRECT_XI
-                | EPSG_JGD2011_JPRECT_XII
-                | EPSG_JGD2011_JPRECT_XIII
-                | EPSG_JGD2011_JPRECT_XIV
-                | EPSG_JGD2011_JPRECT_XV
-                | EPSG_JGD2011_JPRECT_XVI
-                | EPSG_JGD2011_JPRECT_XVII
-                | EPSG_JGD2011_JPRECT_XVIII
-                | EPSG_JGD2011_JPRECT_XIX => {
-                    // To Japan Plane Rectangular CS
-                    let proj = self.jpr_zone_proj.as_ref().unwrap();
-                    let mut geom_store = entity.geometry_store.write().unwrap();
-                    geom_store.vertices.iter_mut().for_each(|v| {
-                        let (lng, lat) = (v[1], v[0]);
-                        // Change x and y; keep the height
-                        // TODO: error handling
-                        (v[0], v[1], _) = proj.project_forward(lng, lat, 0.).unwrap();
-                    });
-                    geom_store.epsg = self.output_epsg;
-                }
-                _ => {
-                    panic!("Unsupported output CRS: {}", self.output_epsg);
-                }
-            },
-            EPSG_WGS84_GEOGRAPHIC_3D => match self.output_epsg {
-                EPSG_WGS84_GEOGRAPHIC_3D => {
-                    // Do nothing
-                }
-                EPSG_JGD2011_JPRECT_I_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_II_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_III_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_IV_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_V_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VI_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_VIII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_IX_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_X_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XI_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XII_JGD2011_HEIGHT
-                | EPSG_JGD2011_JPRECT_XIII_JGD2011_HEIGHT => {
-                    // TODO: implement
-                    unimplemented!("WGS84 to EPSG:{} not supported yet", self.output_epsg);
-                }
-                EPSG_JGD2011_JPRECT_I
-                | EPSG_JGD2011_JPRECT_II
-                | EPSG_JGD2011_JPRECT_III
-                | EPSG_JGD2011_JPRECT_IV
-                | EPSG_JGD2011_JPRECT_V
-                | EPSG_JGD2011_JPRECT_VI
-                | EPSG_JGD2011_JPRECT_VII
-                | EPSG_JGD2011_JPRECT_VIII
-                | EPSG_JGD2011_JPRECT_IX
-                | EPSG_JGD2011_JPRECT_X
-                | EPSG_JGD2011_JPRECT_XI
-                | EPSG_JGD2011_JPRECT_XII
-                | EPSG_JGD2011_JPRECT_XIII
-                | EPSG_JGD2011_JPRECT_XIV
-                | EPSG_JGD2011_JPRECT_XV
-                | EPSG_JGD2011_JPRECT_XVI
-                | EPSG_JGD2011_JPRECT_XVII
-                | EPSG_JGD2011_JPRECT_XVIII
-                | EPSG_JGD2011_JPRECT_XIX => {
-                    // TODO: implement
-                    unimplemented!("WGS84 to EPSG:{} not supported yet", self.output_epsg);
-                }
-                _ => {
-                    panic!("Unsupported output CRS: {}", self.output_epsg);
-                }
-            },
-            _ => {
-                panic!("Unsupported input CRS: {}", input_epsg);
+        match self.project_geometry(target, &entity) {
+            Ok(()) => out.push(entity),
+            Err(err @ (TransformError::OutsideProjectionDomain | TransformError::OutsideGrid)) => {
+                let id = match &entity.root {
+                    Value::Object(obj) => obj.stereotype.id().unwrap_or_default(),
+                    _ => "",
+                };
+                feedback.error(format!("Failed to transform coordinates of {id}: {err}"));
+            }
+            Err(err) => {
+                feedback.fatal_error(PipelineError::Other(format!(
+                    "Cannot transform coordinates: {err}"
+                )));
             }
         }
-
-        out.push(entity);
     }
 
     fn transform_schema(&self, schema: &mut Schema) {
@@ -172,14 +146,289 @@ impl Transform for ProjectionTransform {
 }
 
 impl ProjectionTransform {
-    pub fn new(jgd2wgs: Arc<Jgd2011ToWgs84>, output_epsg: EpsgCode) -> Self {
-        // For Japan Plane Rectangular CS
-        let jpr_zone_proj = JPRZone::from_epsg(output_epsg).map(|zone| zone.projection());
-
+    pub fn new(
+        jgd2wgs: Arc<Jgd2011ToWgs84>,
+        datum_shift: Arc<JgdDatumShift>,
+        output_epsg: EpsgCode,
+        keep_orthometric_height: bool,
+    ) -> Self {
         Self {
             jgd2wgs,
+            datum_shift,
             output_epsg,
-            jpr_zone_proj,
+            keep_orthometric_height,
+            target: Target::from_epsg(output_epsg).map_err(|_| output_epsg),
         }
     }
+
+    /// Checks whether the coordinates can be transformed into the given CRS.
+    pub fn check_output_epsg(output_epsg: EpsgCode) -> Result<(), TransformError> {
+        Target::from_epsg(output_epsg).map(|_| ())
+    }
+
+    fn project_geometry(&self, target: &Target, entity: &Entity) -> Result<(), TransformError> {
+        let mut geom_store = entity.geometry_store.write().unwrap();
+        let source = Source::from_epsg(geom_store.epsg)?;
+        for v in geom_store.vertices.iter_mut() {
+            *v = self.project(target, &source, *v)?;
+        }
+        geom_store.epsg = self.output_epsg;
+        Ok(())
+    }
+
+    fn project(
+        &self,
+        target: &Target,
+        source: &Source,
+        v: [f64; 3],
+    ) -> Result<[f64; 3], TransformError> {
+        match source {
+            // Swap x and y (lat, lng -> lng, lat)
+            Source::Jgd2011Geographic => self.project_jgd2011(target, v[1], v[0], v[2]),
+            Source::Wgs84Geographic => self.project_wgs84(target, v),
+            Source::Tokyo(proj) => {
+                let (lng, lat) = to_geographic(proj.as_ref(), v)?;
+                let (lng, lat, height) = self.datum_shift.tokyo_to_jgd2011(lng, lat, v[2])?;
+                self.project_jgd2011(target, lng, lat, height)
+            }
+            Source::Jgd2000(proj) => {
+                let (lng, lat) = to_geographic(proj.as_ref(), v)?;
+                let (lng, lat, height) = self.datum_shift.jgd2000_to_jgd2011(lng, lat, v[2]);
+                self.project_jgd2011(target, lng, lat, height)
+            }
+        }
+    }
+
+    fn project_jgd2011(
+        &self,
+        target: &Target,
+        lng: f64,
+        lat: f64,
+        height: f64,
+    ) -> Result<[f64; 3], TransformError> {
+        match target {
+            Target::Jgd2011Geographic => Ok([lng, lat, height]),
+            Target::Wgs84Geographic => Ok([lng, lat, self.to_wgs84_height(lng, lat, height)]),
+            Target::WebMercator => {
+                let (x, y) = nusamai_mvt::webmercator::lnglat_to_web_mercator_meters(lng, lat);
+                Ok([x, y, height])
+            }
+            Target::PlaneRectangular(proj) => {
+                // Change x and y; keep the height
+                let (x, y, _) = proj.project_forward(lng, lat, 0.)?;
+                Ok([x, y, height])
+            }
+            Target::Utm(proj, datum) => {
+                let (x, y, _) = proj.project_forward(lng, lat, 0.)?;
+                let height = match datum {
+                    UtmDatum::Jgd2011 => height,
+                    UtmDatum::Wgs84 => self.to_wgs84_height(lng, lat, height),
+                };
+                Ok([x, y, height])
+            }
+            Target::Geocentric => {
+                // ECEF always needs the ellipsoidal height
+                let (lng, lat, height) = self.jgd2wgs.convert(lng, lat, height);
+                let (x, y, z) = geodetic_to_geocentric(&wgs84(), lng, lat, height);
+                Ok([x, y, z])
+            }
+        }
+    }
+
+    fn project_wgs84(&self, target: &Target, v: [f64; 3]) -> Result<[f64; 3], TransformError> {
+        let (lng, lat, height) = (v[0], v[1], v[2]);
+        match target {
+            Target::Wgs84Geographic => Ok(v),
+            Target::WebMercator => {
+                let (x, y) = nusamai_mvt::webmercator::lnglat_to_web_mercator_meters(lng, lat);
+                Ok([x, y, height])
+            }
+            Target::Utm(proj, UtmDatum::Wgs84) => {
+                let (x, y, _) = proj.project_forward(lng, lat, 0.)?;
+                Ok([x, y, height])
+            }
+            Target::Geocentric => {
+                let (x, y, z) = geodetic_to_geocentric(&wgs84(), lng, lat, height);
+                Ok([x, y, z])
+            }
+            _ => {
+                // Ellipsoidal height to elevation
+                let (lng, lat, height) = self.jgd2wgs.convert_inverse(lng, lat, height);
+                self.project_jgd2011(target, lng, lat, height)
+            }
+        }
+    }
+
+    /// JGD2011 to WGS 84 (elevation to ellipsoidal height)
+    fn to_wgs84_height(&self, lng: f64, lat: f64, height: f64) -> f64 {
+        if self.keep_orthometric_height {
+            height
+        } else {
+            self.jgd2wgs.convert(lng, lat, height).2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{
+        object::{Object, ObjectStereotype},
+        GeometryStore,
+    };
+    use nusamai_projection::datumshift::ParGrid;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity(epsg: EpsgCode, vertices: Vec<[f64; 3]>) -> Entity {
+        let geometry_store = GeometryStore {
+            epsg,
+            vertices,
+            ..Default::default()
+        };
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: Vec::new(),
+                },
+                attributes: Default::default(),
+            }),
+            geometry_store,
+        )
+    }
+
+    fn run(output_epsg: EpsgCode, vertices: Vec<[f64; 3]>) -> Option<Vec<[f64; 3]>> {
+        run_with(
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            output_epsg,
+            JgdDatumShift::default(),
+            vertices,
+        )
+    }
+
+    fn run_with(
+        input_epsg: EpsgCode,
+        output_epsg: EpsgCode,
+        datum_shift: JgdDatumShift,
+        vertices: Vec<[f64; 3]>,
+    ) -> Option<Vec<[f64; 3]>> {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = ProjectionTransform::new(
+            Arc::new(Jgd2011ToWgs84::default()),
+            Arc::new(datum_shift),
+            output_epsg,
+            false,
+        );
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(input_epsg, vertices), &mut out);
+        out.pop().map(|entity| {
+            let geom_store = entity.geometry_store.read().unwrap();
+            assert_eq!(geom_store.epsg, output_epsg);
+            geom_store.vertices.clone()
+        })
+    }
+
+    #[test]
+    fn utm() {
+        let vertices = run(EPSG_JGD2011_UTM_54N, vec![[35.6812, 139.7671, 10.]]).unwrap();
+        assert!((vertices[0][0] - 388433.375).abs() < 1e-3);
+        assert!((vertices[0][1] - 3949290.013).abs() < 1e-3);
+        assert_eq!(vertices[0][2], 10.);
+
+        // WGS 84 outputs have the ellipsoidal height
+        let vertices = run(EPSG_WGS84_UTM_54N, vec![[35.6812, 139.7671, 10.]]).unwrap();
+        assert!((vertices[0][0] - 388433.375).abs() < 1e-3);
+        assert!(vertices[0][2] > 10.);
+    }
+
+    #[test]
+    fn geocentric() {
+        let vertices = run(EPSG_WGS84_GEOCENTRIC, vec![[37., 140., 10.]]).unwrap();
+        let (lng, lat, height) = nusamai_projection::cartesian::geocentric_to_geodetic(
+            &wgs84(),
+            vertices[0][0],
+            vertices[0][1],
+            vertices[0][2],
+        );
+        assert!((lng - 140.).abs() < 1e-9);
+        assert!((lat - 37.).abs() < 1e-9);
+        let (_, _, expected) = Jgd2011ToWgs84::default().convert(140., 37., 10.);
+        assert!((height - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn from_wgs84() {
+        // (lng, lat) with the ellipsoidal height
+        let (_, _, height) = Jgd2011ToWgs84::default().convert(139.7671, 35.6812, 10.);
+        let vertices = run_with(
+            EPSG_WGS84_GEOGRAPHIC_3D,
+            EPSG_JGD2011_UTM_54N,
+            JgdDatumShift::default(),
+            vec![[139.7671, 35.6812, height]],
+        )
+        .unwrap();
+        assert!((vertices[0][0] - 388433.375).abs() < 1e-3);
+        assert!((vertices[0][1] - 3949290.013).abs() < 1e-3);
+        assert!((vertices[0][2] - 10.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unsupported_crs() {
+        assert!(ProjectionTransform::check_output_epsg(EPSG_JGD2011_UTM_51N).is_ok());
+        assert!(ProjectionTransform::check_output_epsg(EPSG_WGS84_UTM_56N).is_ok());
+        assert!(ProjectionTransform::check_output_epsg(EPSG_JGD2011_JPRECT_XIX).is_ok());
+        assert!(ProjectionTransform::check_output_epsg(EPSG_JGD2000_JPRECT_I).is_err());
+        assert!(ProjectionTransform::check_output_epsg(EPSG_WGS84_GEOCENTRIC).is_ok());
+        assert!(ProjectionTransform::check_output_epsg(1234).is_err());
+
+        // Reported as an error instead of panicking
+        assert!(run(1234, vec![[35., 139., 0.]]).is_none());
+    }
+
+    #[test]
+    fn old_datums() {
+        // JGD2000 / Japan Plane Rectangular CS IX, in (northing, easting)
+        let (x, y, _) = JPRZone::from_epsg(EPSG_JGD2000_JPRECT_IX)
+            .unwrap()
+            .projection()
+            .project_forward(139.7671, 35.6812, 0.)
+            .unwrap();
+        let vertices = run_with(
+            EPSG_JGD2000_JPRECT_IX,
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            JgdDatumShift::default(),
+            vec![[y, x, 10.]],
+        )
+        .unwrap();
+        assert!((vertices[0][0] - 139.7671).abs() < 1e-9);
+        assert!((vertices[0][1] - 35.6812).abs() < 1e-9);
+        assert_eq!(vertices[0][2], 10.);
+
+        // Tokyo datum with a uniform TKY2JGD grid
+        let par = "53394611 10.0 -10.0\n\
+                   53394612 10.0 -10.0\n\
+                   53394621 10.0 -10.0\n\
+                   53394622 10.0 -10.0\n";
+        let grid = ParGrid::from_reader(par.as_bytes()).unwrap();
+        let vertices = run_with(
+            EPSG_TOKYO_GEOGRAPHIC_2D,
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            JgdDatumShift::new(Some(grid), None),
+            vec![[35.6812, 139.7671, 10.]],
+        )
+        .unwrap();
+        assert!((vertices[0][0] - (139.7671 - 10. / 3600.)).abs() < 1e-9);
+        assert!((vertices[0][1] - (35.6812 + 10. / 3600.)).abs() < 1e-9);
+
+        // The TKY2JGD grid is required
+        assert!(run_with(
+            EPSG_TOKYO_GEOGRAPHIC_2D,
+            EPSG_JGD2011_GEOGRAPHIC_3D,
+            JgdDatumShift::default(),
+            vec![[35.6812, 139.7671, 10.]],
+        )
+        .is_none());
+    }
 }
@@ -0,0 +1,435 @@
+use hashbrown::HashSet;
+use nusamai_citygml::{
+    object::{Object, ObjectStereotype, Value},
+    schema::{Schema, TypeDef, TypeRef},
+};
+use nusamai_plateau::Entity;
+
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// Transform to select the attributes to output by their paths
+///
+/// A path is a list of attribute names separated by `/` from the nearest feature
+/// (e.g. `uro:buildingDetailAttribute/uro:totalFloorArea`), and each name may contain `*` wildcards.
+/// A path also selects the descendants of the attribute.
+///
+/// - `keep`: only the matching attributes (and their ancestors) are output, unless it is empty
+/// - `drop`: the matching attributes are removed
+///
+/// The attributes holding child features (e.g. `bldg:boundedBy`) are never removed,
+/// the attributes of the child features are selected by the paths from themselves.
+#[derive(Default, Clone)]
+pub struct AttributeSelectionTransform {
+    keep: Vec<Vec<String>>,
+    drop: Vec<Vec<String>>,
+}
+
+impl AttributeSelectionTransform {
+    pub fn new(keep: &[String], drop: &[String]) -> Self {
+        let split = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| p.split('/').map(|s| s.to_string()).collect())
+                .collect()
+        };
+        Self {
+            keep: split(keep),
+            drop: split(drop),
+        }
+    }
+
+    fn is_selected(&self, path: &[String]) -> bool {
+        if self.drop.iter().any(|p| matches_path(p, path)) {
+            return false;
+        }
+        self.keep.is_empty()
+            || self
+                .keep
+                .iter()
+                .any(|p| matches_path(p, path) || matches_ancestor(p, path))
+    }
+}
+
+impl Transform for AttributeSelectionTransform {
+    fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        self.edit_tree(&mut entity.root, &mut Vec::new());
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        let mut selected = HashSet::new();
+        let mut visited = HashSet::new();
+        for (typename, ty) in &schema.types {
+            if let TypeDef::Feature(_) = ty {
+                self.visit_type(
+                    schema,
+                    typename,
+                    &mut Vec::new(),
+                    &mut selected,
+                    &mut visited,
+                );
+            }
+        }
+
+        let visited_types: HashSet<_> = visited.into_iter().map(|(name, _)| name).collect();
+        for (typename, ty) in schema.types.iter_mut() {
+            if !visited_types.contains(typename) {
+                continue;
+            }
+            let attrs = match ty {
+                TypeDef::Feature(feat) => &mut feat.attributes,
+                TypeDef::Data(data) => &mut data.attributes,
+                TypeDef::Property(_) => continue,
+            };
+            attrs.retain(|key, _| selected.contains(&(typename.clone(), key.clone())));
+        }
+    }
+}
+
+impl AttributeSelectionTransform {
+    fn edit_tree(&self, value: &mut Value, path: &mut Vec<String>) {
+        match value {
+            Value::Object(obj) => {
+                if let ObjectStereotype::Feature { .. } = obj.stereotype {
+                    self.edit_object(obj, &mut Vec::new());
+                } else {
+                    self.edit_object(obj, path);
+                }
+            }
+            Value::Array(arr) => {
+                for v in arr.iter_mut() {
+                    self.edit_tree(v, path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn edit_object(&self, obj: &mut Object, path: &mut Vec<String>) {
+        obj.attributes.retain(|key, value| {
+            if holds_features(value) {
+                self.edit_tree(value, path);
+                return true;
+            }
+            path.push(key.clone());
+            let selected = self.is_selected(path);
+            if selected {
+                self.edit_tree(value, path);
+            }
+            path.pop();
+            selected
+        });
+    }
+
+    /// Collects the selected attributes of the type as (typename, attribute name)
+    fn visit_type(
+        &self,
+        schema: &Schema,
+        typename: &str,
+        path: &mut Vec<String>,
+        selected: &mut HashSet<(String, String)>,
+        visited: &mut HashSet<(String, Vec<String>)>,
+    ) {
+        // Also prevents infinite recursion with the recursive types
+        if !visited.insert((typename.to_string(), path.clone())) {
+            return;
+        }
+        let attrs = match schema.types.get(typename) {
+            Some(TypeDef::Feature(feat)) => &feat.attributes,
+            Some(TypeDef::Data(data)) => &data.attributes,
+            _ => return,
+        };
+        for (key, attr) in attrs {
+            if refers_to_features(schema, &attr.type_ref) {
+                selected.insert((typename.to_string(), key.clone()));
+                continue;
+            }
+            path.push(key.clone());
+            if self.is_selected(path) {
+                selected.insert((typename.to_string(), key.clone()));
+                self.visit_type_ref(schema, &attr.type_ref, path, selected, visited);
+            }
+            path.pop();
+        }
+    }
+
+    fn visit_type_ref(
+        &self,
+        schema: &Schema,
+        type_ref: &TypeRef,
+        path: &mut Vec<String>,
+        selected: &mut HashSet<(String, String)>,
+        visited: &mut HashSet<(String, Vec<String>)>,
+    ) {
+        let TypeRef::Named(name) = type_ref else {
+            return;
+        };
+        match schema.types.get(name) {
+            Some(TypeDef::Data(_)) => self.visit_type(schema, name, path, selected, visited),
+            Some(TypeDef::Property(prop)) => {
+                for member in &prop.members {
+                    self.visit_type_ref(schema, &member.type_ref, path, selected, visited);
+                }
+            }
+            // Features are visited from themselves
+            Some(TypeDef::Feature(_)) | None => {}
+        }
+    }
+}
+
+/// Whether the value is a child feature (or an array of them)
+fn holds_features(value: &Value) -> bool {
+    match value {
+        Value::Object(obj) => matches!(obj.stereotype, ObjectStereotype::Feature { .. }),
+        Value::Array(arr) => arr.iter().any(holds_features),
+        _ => false,
+    }
+}
+
+/// Whether the attribute type refers to feature types
+fn refers_to_features(schema: &Schema, type_ref: &TypeRef) -> bool {
+    let TypeRef::Named(name) = type_ref else {
+        return false;
+    };
+    match schema.types.get(name) {
+        Some(TypeDef::Feature(_)) => true,
+        Some(TypeDef::Property(prop)) => prop
+            .members
+            .iter()
+            .any(|member| refers_to_features(schema, &member.type_ref)),
+        _ => false,
+    }
+}
+
+/// Whether the pattern matches the path or one of its ancestors
+fn matches_path(pattern: &[String], path: &[String]) -> bool {
+    pattern.len() <= path.len() && pattern.iter().zip(path).all(|(p, s)| glob_match(p, s))
+}
+
+/// Whether the path is an ancestor of the paths the pattern matches
+fn matches_ancestor(pattern: &[String], path: &[String]) -> bool {
+    path.len() < pattern.len() && pattern.iter().zip(path).all(|(p, s)| glob_match(p, s))
+}
+
+/// Matches the text with a pattern where `*` matches any sequence of characters
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match text.find(part) {
+            Some(pos) => text = &text[pos + part.len()..],
+            None => return false,
+        }
+    }
+    text.len() >= last.len() && text.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{
+        object::Map,
+        schema::{self, DataTypeDef, FeatureTypeDef, PropertyTypeDef},
+        GeometryStore,
+    };
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn object(typename: &str, stereotype: ObjectStereotype, attrs: Vec<(&str, Value)>) -> Value {
+        Value::Object(Object {
+            typename: typename.to_string().into(),
+            stereotype,
+            attributes: attrs
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect::<Map>(),
+        })
+    }
+
+    fn feature(typename: &str, attrs: Vec<(&str, Value)>) -> Value {
+        let stereotype = ObjectStereotype::Feature {
+            id: "id".into(),
+            geometries: Default::default(),
+        };
+        object(typename, stereotype, attrs)
+    }
+
+    fn make_entity() -> Entity {
+        let detail = object(
+            "uro:BuildingDetailAttribute",
+            ObjectStereotype::Data,
+            vec![
+                ("uro:totalFloorArea", Value::Double(100.)),
+                ("uro:buildingStructureType", Value::String("木造".into())),
+            ],
+        );
+        let disaster = object(
+            "uro:BuildingRiverFloodingRiskAttribute",
+            ObjectStereotype::Data,
+            vec![("uro:depth", Value::Double(1.))],
+        );
+        let part = feature(
+            "bldg:BuildingPart",
+            vec![
+                ("bldg:measuredHeight", Value::Double(5.)),
+                ("bldg:storeysAboveGround", Value::Integer(1)),
+            ],
+        );
+        let root = feature(
+            "bldg:Building",
+            vec![
+                ("bldg:measuredHeight", Value::Double(10.)),
+                ("bldg:storeysAboveGround", Value::Integer(3)),
+                ("uro:buildingDetailAttribute", Value::Array(vec![detail])),
+                ("uro:buildingDisasterRiskAttribute", disaster),
+                ("bldg:consistsOfBuildingPart", Value::Array(vec![part])),
+            ],
+        );
+        test_entity(root, GeometryStore::default())
+    }
+
+    fn keys(value: &Value) -> Vec<String> {
+        let Value::Object(obj) = value else {
+            unreachable!()
+        };
+        obj.attributes.keys().cloned().collect()
+    }
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("uro:*Attribute", "uro:buildingDetailAttribute"));
+        assert!(glob_match("*", "bldg:class"));
+        assert!(glob_match("uro:*", "uro:"));
+        assert!(glob_match("*:class", "bldg:class"));
+        assert!(glob_match("a*b*c", "abbc"));
+        assert!(!glob_match("a*b*c", "acb"));
+        assert!(!glob_match("uro:*Attribute", "bldg:class"));
+        assert!(!glob_match("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn keep_and_drop() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = AttributeSelectionTransform::new(
+            &strings(&[
+                "bldg:measuredHeight",
+                "uro:buildingDetailAttribute/uro:total*",
+                "uro:*RiskAttribute",
+            ]),
+            &strings(&["uro:buildingDisasterRiskAttribute"]),
+        );
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        let root = &out[0].root;
+        assert_eq!(
+            keys(root),
+            [
+                "bldg:measuredHeight",
+                "uro:buildingDetailAttribute",
+                "bldg:consistsOfBuildingPart"
+            ]
+        );
+        let Value::Object(obj) = root else {
+            unreachable!()
+        };
+        let Value::Array(details) = &obj.attributes["uro:buildingDetailAttribute"] else {
+            unreachable!()
+        };
+        assert_eq!(keys(&details[0]), ["uro:totalFloorArea"]);
+        // the paths of the child features start from themselves
+        let Value::Array(parts) = &obj.attributes["bldg:consistsOfBuildingPart"] else {
+            unreachable!()
+        };
+        assert_eq!(keys(&parts[0]), ["bldg:measuredHeight"]);
+    }
+
+    #[test]
+    fn select_schema() {
+        let mut schema = Schema::default();
+        let mut building = FeatureTypeDef::default();
+        for (key, type_ref) in [
+            ("bldg:measuredHeight", TypeRef::Double),
+            ("bldg:storeysAboveGround", TypeRef::Integer),
+            (
+                "uro:buildingDetailAttribute",
+                TypeRef::Named("uro:BuildingDetailAttribute".into()),
+            ),
+            (
+                "bldg:consistsOfBuildingPart",
+                TypeRef::Named("bldg:BuildingPartProperty".into()),
+            ),
+        ] {
+            building
+                .attributes
+                .insert(key.into(), schema::Attribute::new(type_ref));
+        }
+        let mut detail = DataTypeDef::default();
+        detail.attributes.insert(
+            "uro:totalFloorArea".into(),
+            schema::Attribute::new(TypeRef::Double),
+        );
+        detail.attributes.insert(
+            "uro:buildingStructureType".into(),
+            schema::Attribute::new(TypeRef::String),
+        );
+        let mut part = FeatureTypeDef::default();
+        part.attributes.insert(
+            "bldg:storeysAboveGround".into(),
+            schema::Attribute::new(TypeRef::Integer),
+        );
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(building));
+        schema
+            .types
+            .insert("uro:BuildingDetailAttribute".into(), TypeDef::Data(detail));
+        schema.types.insert(
+            "bldg:BuildingPartProperty".into(),
+            TypeDef::Property(PropertyTypeDef {
+                members: vec![schema::Attribute::new(TypeRef::Named(
+                    "bldg:BuildingPart".into(),
+                ))],
+            }),
+        );
+        schema
+            .types
+            .insert("bldg:BuildingPart".into(), TypeDef::Feature(part));
+
+        AttributeSelectionTransform::new(
+            &strings(&["bldg:measuredHeight", "uro:*/uro:total*"]),
+            &[],
+        )
+        .transform_schema(&mut schema);
+
+        let attr_keys = |typename: &str| -> Vec<String> {
+            match &schema.types[typename] {
+                TypeDef::Feature(feat) => feat.attributes.keys().cloned().collect(),
+                TypeDef::Data(data) => data.attributes.keys().cloned().collect(),
+                TypeDef::Property(_) => unreachable!(),
+            }
+        };
+        assert_eq!(
+            attr_keys("bldg:Building"),
+            [
+                "bldg:measuredHeight",
+                "uro:buildingDetailAttribute",
+                "bldg:consistsOfBuildingPart"
+            ]
+        );
+        assert_eq!(
+            attr_keys("uro:BuildingDetailAttribute"),
+            ["uro:totalFloorArea"]
+        );
+        assert!(attr_keys("bldg:BuildingPart").is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Geometry simplification
+
+use nusamai_citygml::{
+    object::{ObjectStereotype, Value},
+    schema::Schema,
+    GeometryType,
+};
+use nusamai_geometry::{
+    algorithm::simplify::{simplify_line, simplify_polygon, SimplifyMethod},
+    MultiLineString, MultiPolygon,
+};
+use nusamai_plateau::Entity;
+
+use super::metric::to_local_metric;
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// Transform to reduce the number of vertices of surfaces and curves
+///
+/// The tolerance is given in metres. Each polygon is simplified with its topology preserved,
+/// i.e. rings never collapse or cross each other. Solids are left as they are,
+/// since simplifying their faces independently would open the shells.
+#[derive(Clone)]
+pub struct SimplifyTransform {
+    tolerance: f64,
+    method: SimplifyMethod,
+}
+
+impl SimplifyTransform {
+    pub fn new(tolerance: f64, method: SimplifyMethod) -> Self {
+        Self { tolerance, method }
+    }
+}
+
+impl Transform for SimplifyTransform {
+    fn transform(&mut self, _feedback: &Feedback, entity: Entity, out: &mut Vec<Entity>) {
+        {
+            let mut geom_store = entity.geometry_store.write().unwrap();
+
+            let mut target_polygons = vec![false; geom_store.multipolygon.len()];
+            let mut target_lines = vec![false; geom_store.multilinestring.len()];
+            collect_targets(&entity.root, &mut target_polygons, &mut target_lines);
+
+            let vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, true);
+
+            if target_polygons.contains(&true) {
+                let has_uvs = geom_store.polygon_uvs.len() == geom_store.multipolygon.len();
+                let mut new_mpoly = MultiPolygon::new();
+                let mut new_uvs = MultiPolygon::new();
+
+                for (i, poly) in geom_store.multipolygon.iter().enumerate() {
+                    let uv_poly = has_uvs.then(|| geom_store.polygon_uvs.get(i));
+                    if !target_polygons[i] {
+                        new_mpoly.push(&poly);
+                        if let Some(uv_poly) = uv_poly {
+                            new_uvs.push(&uv_poly);
+                        }
+                        continue;
+                    }
+
+                    let rings: Vec<Vec<[f64; 3]>> = poly
+                        .rings()
+                        .map(|ring| ring.iter().map(|idx| vertices[idx as usize]).collect())
+                        .collect();
+                    let kept = simplify_polygon(&rings, self.tolerance, self.method);
+
+                    for (ri, (ring, kept)) in poly.rings().zip(&kept).enumerate() {
+                        let coords = ring.raw_coords();
+                        let iter = kept.iter().map(|&j| coords[j]);
+                        match ri {
+                            0 => new_mpoly.add_exterior(iter),
+                            _ => new_mpoly.add_interior(iter),
+                        }
+                    }
+                    if let Some(uv_poly) = uv_poly {
+                        for (ri, (ring, kept)) in uv_poly.rings().zip(&kept).enumerate() {
+                            let coords = ring.raw_coords();
+                            // Repeat the first point, so that only it is removed as the closing point
+                            let iter = kept.iter().chain(kept.first()).map(|&j| coords[j]);
+                            match ri {
+                                0 => new_uvs.add_exterior(iter),
+                                _ => new_uvs.add_interior(iter),
+                            }
+                        }
+                    }
+                }
+
+                geom_store.multipolygon = new_mpoly;
+                if has_uvs {
+                    geom_store.polygon_uvs = new_uvs;
+                }
+            }
+
+            if target_lines.contains(&true) {
+                let mut new_mls = MultiLineString::new();
+                for (i, line) in geom_store.multilinestring.iter().enumerate() {
+                    let coords = line.raw_coords();
+                    if !target_lines[i] {
+                        new_mls.add_linestring(coords.iter().copied());
+                        continue;
+                    }
+                    let points: Vec<_> = coords.iter().map(|&idx| vertices[idx as usize]).collect();
+                    let kept = simplify_line(&points, self.tolerance, self.method);
+                    new_mls.add_linestring(kept.iter().map(|&j| coords[j]));
+                }
+                geom_store.multilinestring = new_mls;
+            }
+        }
+
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, _schema: &mut Schema) {
+        // do nothing
+    }
+}
+
+/// Marks the polygons of surfaces and the line-strings of curves to simplify.
+fn collect_targets(value: &Value, polygons: &mut [bool], lines: &mut [bool]) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { geometries, .. } = &obj.stereotype {
+                for geom in geometries {
+                    let range = geom.pos as usize..(geom.pos + geom.len) as usize;
+                    match geom.ty {
+                        GeometryType::Surface => polygons[range].fill(true),
+                        GeometryType::Curve => lines[range].fill(true),
+                        GeometryType::Solid | GeometryType::Triangle | GeometryType::Point => {}
+                    }
+                }
+            }
+            for value in obj.attributes.values() {
+                collect_targets(value, polygons, lines);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr {
+                collect_targets(value, polygons, lines);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, GeometryRef, GeometryStore};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity(ty: GeometryType) -> Entity {
+        // A square with nearly collinear points on its edges
+        let vertices = vec![
+            [0., 0., 0.],
+            [5., 0.01, 0.],
+            [10., 0., 0.],
+            [10., 10., 0.],
+            [5., 10.02, 0.],
+            [0., 10., 0.],
+        ];
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([0, 1, 2, 3, 4, 5]);
+        let mut mls = MultiLineString::new();
+        mls.add_linestring([0, 1, 2, 3]);
+
+        let geometries = vec![
+            GeometryRef {
+                ty,
+                lod: 1,
+                pos: 0,
+                len: 1,
+            },
+            GeometryRef {
+                ty: GeometryType::Curve,
+                lod: 1,
+                pos: 0,
+                len: 1,
+            },
+        ];
+        test_entity(
+            Value::Object(Object {
+                typename: "luse:LandUse".into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "luse_1".into(),
+                    geometries,
+                },
+            }),
+            GeometryStore {
+                vertices,
+                multipolygon: mpoly,
+                multilinestring: mls,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(ty: GeometryType) -> (Vec<u32>, Vec<u32>) {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = SimplifyTransform::new(0.5, SimplifyMethod::DouglasPeucker);
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(ty), &mut out);
+        let geom_store = out[0].geometry_store.read().unwrap();
+        let ring = geom_store
+            .multipolygon
+            .get(0)
+            .exterior()
+            .raw_coords()
+            .to_vec();
+        let line = geom_store
+            .multilinestring
+            .iter()
+            .next()
+            .unwrap()
+            .raw_coords()
+            .to_vec();
+        (ring, line)
+    }
+
+    #[test]
+    fn simplify_surface() {
+        let (ring, line) = run(GeometryType::Surface);
+        assert_eq!(ring, [0, 2, 3, 5]);
+        assert_eq!(line, [0, 2, 3]);
+    }
+
+    #[test]
+    fn keep_solid() {
+        let (ring, _) = run(GeometryType::Solid);
+        assert_eq!(ring, [0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,471 @@
+use nusamai_citygml::{
+    object::{Object, ObjectStereotype, Value},
+    schema::{Attribute, Schema, TypeDef, TypeRef},
+};
+use nusamai_plateau::Entity;
+use nusamai_projection::crs::EPSG_WEB_MERCATOR;
+
+use super::{geomstats::surface_area, metric::to_local_metric};
+use crate::{
+    pipeline::Feedback,
+    transformer::{ThematicSurfaceRules, Transform},
+};
+
+/// Local names of the thematic surface types (of buildings, bridges and tunnels)
+const THEMATIC_SURFACE_TYPES: &[&str] = &[
+    "RoofSurface",
+    "WallSurface",
+    "GroundSurface",
+    "ClosureSurface",
+    "OuterCeilingSurface",
+    "OuterFloorSurface",
+    "CeilingSurface",
+    "InteriorWallSurface",
+    "FloorSurface",
+];
+
+/// Name of the attribute that holds the area of the surface (in square metres)
+pub const SURFACE_AREA_ATTRIBUTE: &str = "surfaceArea";
+
+fn is_thematic_surface(typename: &str) -> bool {
+    let local_name = typename.split_once(':').map_or(typename, |(_, name)| name);
+    THEMATIC_SURFACE_TYPES.contains(&local_name)
+}
+
+/// Transform to output the thematic surfaces (e.g. `bldg:RoofSurface`) as separate entities
+///
+/// Each surface gets the id and the type of its parent feature (`parentId` and `parentType`),
+/// the attributes listed in `inherit` (from the nearest ancestor that has them),
+/// and its area (`surfaceArea`, in square metres, of its highest LOD).
+/// The surfaces share the geometry store with their parent, so this runs after the geometries are edited.
+/// The openings (e.g. `bldg:Window`) stay in their surfaces.
+#[derive(Clone, Default)]
+pub struct ThematicSurfaceTransform {
+    inherit: Vec<String>,
+}
+
+impl ThematicSurfaceTransform {
+    pub fn new(rules: &ThematicSurfaceRules) -> Self {
+        Self {
+            inherit: rules.inherit.clone(),
+        }
+    }
+}
+
+/// Feature that contains thematic surfaces
+struct Parent {
+    id: String,
+    typename: String,
+    inherited: Vec<(String, Value)>,
+}
+
+impl Transform for ThematicSurfaceTransform {
+    fn transform(&mut self, _feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        let mut surfaces = Vec::new();
+        if let Value::Object(obj) = &mut entity.root {
+            if let ObjectStereotype::Feature { .. } = obj.stereotype {
+                self.split_object(obj, &mut Vec::new(), &mut surfaces);
+            }
+        }
+        if surfaces.is_empty() {
+            out.push(entity);
+            return;
+        }
+
+        {
+            let geom_store = entity.geometry_store.read().unwrap();
+            let local_vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, true);
+            // Web Mercator enlarges the areas by 1 / cos^2(lat)
+            let scale = match (geom_store.epsg, geom_store.vertices.first()) {
+                (EPSG_WEB_MERCATOR, Some(v)) => {
+                    let (_, lat) =
+                        nusamai_mvt::webmercator::web_mercator_meters_to_lnglat(v[0], v[1]);
+                    lat.to_radians().cos().powi(2)
+                }
+                _ => 1.,
+            };
+            for surface in surfaces.iter_mut() {
+                let ObjectStereotype::Feature { geometries, .. } = &surface.stereotype else {
+                    continue;
+                };
+                let Some(lod) = geometries.iter().map(|geom| geom.lod).max() else {
+                    continue;
+                };
+                let area = geometries
+                    .iter()
+                    .filter(|geom| geom.lod == lod)
+                    .map(|geom| {
+                        let range = geom.pos as usize..(geom.pos + geom.len) as usize;
+                        surface_area(&geom_store, &local_vertices, range)
+                    })
+                    .sum::<f64>()
+                    * scale;
+                surface
+                    .attributes
+                    .insert(SURFACE_AREA_ATTRIBUTE.into(), Value::Double(area));
+            }
+        }
+
+        for surface in surfaces {
+            out.push(Entity {
+                root: Value::Object(surface),
+                base_url: entity.base_url.clone(),
+                geometry_store: entity.geometry_store.clone(),
+                appearance_store: entity.appearance_store.clone(),
+            });
+        }
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        // Definitions of the inherited attributes, taken from the other features
+        let inherited: Vec<(String, Attribute)> = self
+            .inherit
+            .iter()
+            .filter_map(|key| {
+                schema.types.iter().find_map(|(typename, ty)| match ty {
+                    TypeDef::Feature(feat) if !is_thematic_surface(typename) => {
+                        feat.attributes.get(key).map(|attr| {
+                            let mut attr = attr.clone();
+                            attr.min_occurs = 0;
+                            (key.clone(), attr)
+                        })
+                    }
+                    _ => None,
+                })
+            })
+            .collect();
+
+        for (typename, ty) in schema.types.iter_mut() {
+            let TypeDef::Feature(feat) = ty else {
+                continue;
+            };
+            if !is_thematic_surface(typename) {
+                continue;
+            }
+            for name in ["parentId", "parentType"] {
+                feat.attributes
+                    .insert(name.into(), Attribute::new(TypeRef::String));
+            }
+            for (key, attr) in &inherited {
+                if !feat.attributes.contains_key(key) {
+                    feat.attributes.insert(key.clone(), attr.clone());
+                }
+            }
+            feat.attributes.insert(
+                SURFACE_AREA_ATTRIBUTE.into(),
+                Attribute::new(TypeRef::Double),
+            );
+        }
+    }
+}
+
+impl ThematicSurfaceTransform {
+    fn split_object(&self, obj: &mut Object, parents: &mut Vec<Parent>, out: &mut Vec<Object>) {
+        let is_feature = if let ObjectStereotype::Feature { id, .. } = &obj.stereotype {
+            parents.push(Parent {
+                id: id.clone(),
+                typename: obj.typename.to_string(),
+                inherited: self
+                    .inherit
+                    .iter()
+                    .filter_map(|key| Some((key.clone(), obj.attributes.get(key)?.clone())))
+                    .collect(),
+            });
+            true
+        } else {
+            false
+        };
+
+        obj.attributes
+            .retain(|_, value| self.split_value(value, parents, out));
+
+        if is_feature {
+            parents.pop();
+        }
+    }
+
+    /// Moves the thematic surfaces in the value to `out`. Returns false if nothing is left.
+    fn split_value(
+        &self,
+        value: &mut Value,
+        parents: &mut Vec<Parent>,
+        out: &mut Vec<Object>,
+    ) -> bool {
+        match value {
+            Value::Object(obj)
+                if matches!(obj.stereotype, ObjectStereotype::Feature { .. })
+                    && is_thematic_surface(&obj.typename) =>
+            {
+                let Value::Object(surface) = std::mem::replace(value, Value::Array(Vec::new()))
+                else {
+                    unreachable!()
+                };
+                out.push(self.make_surface(surface, parents));
+                false
+            }
+            Value::Object(obj) => {
+                self.split_object(obj, parents, out);
+                true
+            }
+            Value::Array(arr) => {
+                arr.retain_mut(|v| self.split_value(v, parents, out));
+                !arr.is_empty()
+            }
+            _ => true,
+        }
+    }
+
+    fn make_surface(&self, mut surface: Object, parents: &[Parent]) -> Object {
+        if let Some(parent) = parents.last() {
+            surface
+                .attributes
+                .insert("parentId".into(), Value::String(parent.id.clone()));
+            surface
+                .attributes
+                .insert("parentType".into(), Value::String(parent.typename.clone()));
+        }
+        for key in &self.inherit {
+            if surface.attributes.contains_key(key) {
+                continue;
+            }
+            let value = parents.iter().rev().find_map(|parent| {
+                parent
+                    .inherited
+                    .iter()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.clone())
+            });
+            if let Some(value) = value {
+                surface.attributes.insert(key.clone(), value);
+            }
+        }
+        surface
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{
+        object::Map, schema::FeatureTypeDef, GeometryRef, GeometryStore, GeometryType,
+    };
+    use nusamai_geometry::MultiPolygon;
+    use nusamai_projection::crs::{EpsgCode, EPSG_JGD2011_GEOGRAPHIC_3D, EPSG_JGD2011_JPRECT_IX};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn surface(typename: &str, id: &str, pos: u32) -> Value {
+        Value::Object(Object {
+            typename: typename.to_string().into(),
+            attributes: Default::default(),
+            stereotype: ObjectStereotype::Feature {
+                id: id.into(),
+                geometries: vec![GeometryRef {
+                    ty: GeometryType::Surface,
+                    lod: 2,
+                    pos,
+                    len: 1,
+                }],
+            },
+        })
+    }
+
+    /// A 10 m x 10 m x 5 m box with a roof and a wall, and a building part with a roof
+    ///
+    /// `to_crs` converts the vertices from metres.
+    fn make_entity(epsg: EpsgCode, to_crs: impl Fn([f64; 3]) -> [f64; 3]) -> Entity {
+        let vertices = [
+            [0., 0., 0.],
+            [10., 0., 0.],
+            [10., 10., 0.],
+            [0., 10., 0.],
+            [0., 0., 5.],
+            [10., 0., 5.],
+            [10., 10., 5.],
+            [0., 10., 5.],
+        ]
+        .into_iter()
+        .map(to_crs)
+        .collect();
+        let mut mpoly = MultiPolygon::new();
+        mpoly.add_exterior([4, 5, 6, 7]); // roof
+        mpoly.add_exterior([0, 1, 5, 4]); // wall
+
+        let mut part_attrs = Map::default();
+        part_attrs.insert("bldg:measuredHeight".into(), Value::Double(3.));
+        part_attrs.insert(
+            "bldg:boundedBy".into(),
+            Value::Array(vec![surface("bldg:RoofSurface", "roof_2", 0)]),
+        );
+        let part = Value::Object(Object {
+            typename: "bldg:BuildingPart".into(),
+            attributes: part_attrs,
+            stereotype: ObjectStereotype::Feature {
+                id: "part_1".into(),
+                geometries: Vec::new(),
+            },
+        });
+
+        let mut attributes = Map::default();
+        attributes.insert("bldg:measuredHeight".into(), Value::Double(5.));
+        attributes.insert("bldg:usage".into(), Value::String("住宅".into()));
+        attributes.insert(
+            "bldg:boundedBy".into(),
+            Value::Array(vec![
+                surface("bldg:RoofSurface", "roof_1", 0),
+                surface("bldg:WallSurface", "wall_1", 1),
+            ]),
+        );
+        attributes.insert("bldg:consistsOfBuildingPart".into(), part);
+
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: Vec::new(),
+                },
+            }),
+            GeometryStore {
+                epsg,
+                vertices,
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn rules() -> ThematicSurfaceRules {
+        ThematicSurfaceRules {
+            inherit: vec!["bldg:measuredHeight".into(), "bldg:usage".into()],
+        }
+    }
+
+    #[test]
+    fn split_surfaces() {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = ThematicSurfaceTransform::new(&rules());
+        let mut out = Vec::new();
+        transform.transform(
+            &feedback,
+            make_entity(EPSG_JGD2011_JPRECT_IX, |v| v),
+            &mut out,
+        );
+        let objects: Vec<Object> = out
+            .into_iter()
+            .map(|entity| match entity.root {
+                Value::Object(obj) => obj,
+                _ => unreachable!(),
+            })
+            .collect();
+        let typenames: Vec<_> = objects.iter().map(|obj| obj.typename.as_ref()).collect();
+        assert_eq!(
+            typenames,
+            [
+                "bldg:RoofSurface",
+                "bldg:WallSurface",
+                "bldg:RoofSurface",
+                "bldg:Building"
+            ]
+        );
+
+        let roof = &objects[0].attributes;
+        assert_eq!(roof["parentId"], Value::String("bldg_1".into()));
+        assert_eq!(roof["parentType"], Value::String("bldg:Building".into()));
+        assert_eq!(roof["bldg:measuredHeight"], Value::Double(5.));
+        assert_eq!(roof["bldg:usage"], Value::String("住宅".into()));
+        assert_eq!(roof[SURFACE_AREA_ATTRIBUTE], Value::Double(100.));
+        assert_eq!(
+            objects[1].attributes[SURFACE_AREA_ATTRIBUTE],
+            Value::Double(50.)
+        );
+
+        // the surface of the building part inherits from the nearest parent
+        let part_roof = &objects[2].attributes;
+        assert_eq!(part_roof["parentId"], Value::String("part_1".into()));
+        assert_eq!(part_roof["bldg:measuredHeight"], Value::Double(3.));
+        assert_eq!(part_roof["bldg:usage"], Value::String("住宅".into()));
+
+        // the surfaces are removed from the building
+        let building = &objects[3];
+        assert!(!building.attributes.contains_key("bldg:boundedBy"));
+        let Value::Object(part) = &building.attributes["bldg:consistsOfBuildingPart"] else {
+            unreachable!()
+        };
+        assert!(!part.attributes.contains_key("bldg:boundedBy"));
+    }
+
+    #[test]
+    fn surface_area_in_geographic_output() {
+        // JGD2011 geographic, in (lng, lat) after the projection
+        let (lng0, lat0) = (139.7671_f64, 35.6812_f64);
+        let k = 6378137.0_f64.to_radians();
+        let to_lnglat =
+            |[x, y, z]: [f64; 3]| [lng0 + x / (k * lat0.to_radians().cos()), lat0 + y / k, z];
+
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = ThematicSurfaceTransform::new(&rules());
+        let mut out = Vec::new();
+        transform.transform(
+            &feedback,
+            make_entity(EPSG_JGD2011_GEOGRAPHIC_3D, to_lnglat),
+            &mut out,
+        );
+        let areas: Vec<f64> = out
+            .iter()
+            .filter_map(|entity| match &entity.root {
+                Value::Object(obj) => match obj.attributes.get(SURFACE_AREA_ATTRIBUTE) {
+                    Some(Value::Double(area)) => Some(*area),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(areas.len(), 3);
+        for (area, expected) in areas.iter().zip([100., 50., 100.]) {
+            assert!((area - expected).abs() < 1e-6, "{}", area);
+        }
+    }
+
+    #[test]
+    fn surface_schema() {
+        let mut schema = Schema::default();
+        let mut building = FeatureTypeDef::default();
+        building.attributes.insert(
+            "bldg:measuredHeight".into(),
+            Attribute::new(TypeRef::Measure),
+        );
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(building));
+        schema.types.insert(
+            "bldg:RoofSurface".into(),
+            TypeDef::Feature(FeatureTypeDef::default()),
+        );
+
+        ThematicSurfaceTransform::new(&rules()).transform_schema(&mut schema);
+        let TypeDef::Feature(roof) = &schema.types["bldg:RoofSurface"] else {
+            unreachable!()
+        };
+        let keys: Vec<_> = roof.attributes.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "parentId",
+                "parentType",
+                "bldg:measuredHeight",
+                SURFACE_AREA_ATTRIBUTE
+            ]
+        );
+        assert_eq!(
+            roof.attributes["bldg:measuredHeight"].type_ref,
+            TypeRef::Measure
+        );
+        let TypeDef::Feature(building) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        assert!(!building.attributes.contains_key("parentId"));
+    }
+}
@@ -0,0 +1,562 @@
+use hashbrown::{HashMap, HashSet};
+use nusamai_citygml::{
+    object::{Map, Value},
+    schema::{self, Schema, TypeDef, TypeRef},
+    Measure,
+};
+use nusamai_plateau::Entity;
+
+use crate::{
+    pipeline::Feedback,
+    transformer::{Transform, UnitRules},
+};
+
+/// Suffix of the fields that hold the units of measure
+pub const UOM_SUFFIX: &str = "_uom";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Length,
+    Area,
+    Volume,
+    Mass,
+    Time,
+}
+
+impl Dimension {
+    /// The SI unit of the dimension
+    fn canonical_unit(&self) -> &'static str {
+        match self {
+            Dimension::Length => "m",
+            Dimension::Area => "m2",
+            Dimension::Volume => "m3",
+            Dimension::Mass => "kg",
+            Dimension::Time => "s",
+        }
+    }
+}
+
+/// A known unit: (symbol, dimension, factor to the SI unit)
+type Unit = (&'static str, Dimension, f64);
+
+/// Known units
+const UNITS: &[Unit] = &[
+    ("m", Dimension::Length, 1.0),
+    ("mm", Dimension::Length, 1e-3),
+    ("cm", Dimension::Length, 1e-2),
+    ("km", Dimension::Length, 1e3),
+    ("m2", Dimension::Area, 1.0),
+    ("mm2", Dimension::Area, 1e-6),
+    ("cm2", Dimension::Area, 1e-4),
+    ("km2", Dimension::Area, 1e6),
+    ("a", Dimension::Area, 1e2),
+    ("ha", Dimension::Area, 1e4),
+    ("m3", Dimension::Volume, 1.0),
+    ("cm3", Dimension::Volume, 1e-6),
+    ("l", Dimension::Volume, 1e-3),
+    ("kg", Dimension::Mass, 1.0),
+    ("g", Dimension::Mass, 1e-3),
+    ("t", Dimension::Mass, 1e3),
+    ("s", Dimension::Time, 1.0),
+    ("min", Dimension::Time, 60.0),
+    ("h", Dimension::Time, 3600.0),
+];
+
+/// Normalizes the notation of a unit (e.g. `urn:ogc:def:uom:UCUM::m2`, `m^2` and `m²` -> `m2`)
+fn normalize_unit(uom: &str) -> String {
+    let symbol = uom.rsplit([':', '/', '#']).next().unwrap_or(uom).trim();
+    let symbol = symbol.replace('^', "").replace('²', "2").replace('³', "3");
+    match symbol.as_str() {
+        "L" => "l".to_string(),
+        _ => symbol,
+    }
+}
+
+/// Dimensions of the known measure attributes, by the endings of their names (e.g. `uro:totalFloorArea`)
+const ATTRIBUTE_DIMENSIONS: &[(&str, Dimension)] = &[
+    ("Area", Dimension::Area),
+    ("area", Dimension::Area),
+    ("Volume", Dimension::Volume),
+    ("volume", Dimension::Volume),
+    ("Height", Dimension::Length),
+    ("Length", Dimension::Length),
+    ("Width", Dimension::Length),
+    ("Depth", Dimension::Length),
+    ("depth", Dimension::Length),
+    ("Distance", Dimension::Length),
+    ("Elevation", Dimension::Length),
+    ("Perimeter", Dimension::Length),
+    ("Weight", Dimension::Mass),
+    ("duration", Dimension::Time),
+];
+
+/// Returns the dimension of a measure attribute, if it is known from its name
+fn attribute_dimension(name: &str) -> Option<Dimension> {
+    let local_name = name.rsplit(':').next().unwrap_or(name);
+    ATTRIBUTE_DIMENSIONS
+        .iter()
+        .find(|(ending, _)| local_name.ends_with(ending))
+        .map(|(_, dim)| *dim)
+}
+
+fn lookup_unit(uom: &str) -> Option<Unit> {
+    let symbol = normalize_unit(uom);
+    UNITS.iter().find(|(s, _, _)| *s == symbol).copied()
+}
+
+/// Returns the value of a length in meters, or `None` if the unit is not a known unit of length
+///
+/// A measure without the unit is regarded as in meters.
+pub(super) fn measure_in_meters(m: &Measure) -> Option<f64> {
+    match m.uom() {
+        None => Some(m.value()),
+        Some(uom) => match lookup_unit(uom) {
+            Some((_, Dimension::Length, factor)) => Some(m.value() * factor),
+            _ => None,
+        },
+    }
+}
+
+/// Transform to normalize the units of measure of `Measure` values
+///
+/// - Convert the values to the target unit given per attribute by the user
+///   (e.g. `{"bldg:measuredHeight": "m"}` or `{"*:nominalArea": "ha"}`; the exact match takes precedence)
+/// - Convert the values of the other known attributes (e.g. `*Height` and `*Area`) to the SI unit of their dimension
+///   (when `convert` is enabled)
+/// - Add the unit as a sibling field (e.g. `measuredHeight` and `measuredHeight_uom`, when `sibling_field` is enabled)
+///
+/// The unit converted to is recorded in the schema. Values without the unit are regarded as in the SI unit, and
+/// values with unknown units, or units incompatible with the target, are removed. Targets that are not known units
+/// are ignored.
+#[derive(Default, Clone)]
+pub struct MeasureUnitTransform {
+    convert: bool,
+    sibling_field: bool,
+    // Exact string match dictionary
+    exact_targets: HashMap<String, String>,
+    // general suffix match dictionary - the stored keys are the string after the prefix "*:"
+    general_targets: HashMap<String, String>,
+    // Units already reported as unconvertible (to warn only once)
+    reported: HashSet<String>,
+}
+
+impl MeasureUnitTransform {
+    pub fn new(rules: &UnitRules) -> Self {
+        let mut transform = Self {
+            convert: rules.convert,
+            sibling_field: rules.sibling_field,
+            ..Default::default()
+        };
+        for (key, unit) in &rules.targets {
+            if let Some(key_stripped) = key.strip_prefix("*:") {
+                transform
+                    .general_targets
+                    .insert(key_stripped.into(), unit.clone());
+            } else {
+                transform.exact_targets.insert(key.clone(), unit.clone());
+            }
+        }
+        transform
+    }
+
+    fn target_unit(&self, name: &str) -> Option<&str> {
+        if let Some(unit) = self.exact_targets.get(name) {
+            return Some(unit);
+        }
+        let key = name.find(':').map(|pos| &name[pos + 1..]).unwrap_or(name);
+        if let Some(unit) = self.general_targets.get(key) {
+            return Some(unit);
+        }
+        match self.convert {
+            true => attribute_dimension(name).map(|dim| dim.canonical_unit()),
+            false => None,
+        }
+    }
+
+    /// Returns the unit to convert the attribute to, warning once if the target is not a known unit
+    fn resolve_target(&mut self, feedback: &Feedback, name: &str) -> Option<Unit> {
+        let target = self.target_unit(name)?.to_string();
+        let unit = lookup_unit(&target);
+        if unit.is_none() && self.reported.insert(target.clone()) {
+            feedback.warn(format!("Unknown unit of measure: {target}"));
+        }
+        unit
+    }
+}
+
+impl Transform for MeasureUnitTransform {
+    fn transform(&mut self, feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        self.edit_tree(feedback, &mut entity.root);
+        out.push(entity);
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        let drain_to_new_attrs = |attrs: &mut schema::Map| {
+            let mut new_attrs = schema::Map::default();
+            for (key, mut attr) in attrs.drain(..) {
+                if attr.type_ref != TypeRef::Measure {
+                    new_attrs.insert(key, attr);
+                    continue;
+                }
+                if let Some((unit, _, _)) = self.target_unit(&key).and_then(lookup_unit) {
+                    attr.uom = Some(unit.to_string());
+                }
+                let uom_attr = self.sibling_field.then_some(schema::Attribute {
+                    type_ref: TypeRef::String,
+                    min_occurs: 0,
+                    max_occurs: attr.max_occurs,
+                    original_name: None,
+                    uom: None,
+                });
+                new_attrs.insert(key.clone(), attr);
+                if let Some(uom_attr) = uom_attr {
+                    new_attrs.insert(format!("{key}{UOM_SUFFIX}"), uom_attr);
+                }
+            }
+            new_attrs
+        };
+
+        for ty in schema.types.values_mut() {
+            match ty {
+                TypeDef::Data(data) => {
+                    data.attributes = drain_to_new_attrs(&mut data.attributes);
+                }
+                TypeDef::Feature(feat) => {
+                    feat.attributes = drain_to_new_attrs(&mut feat.attributes);
+                }
+                TypeDef::Property(_) => continue,
+            };
+        }
+    }
+}
+
+impl MeasureUnitTransform {
+    fn edit_tree(&mut self, feedback: &Feedback, value: &mut Value) {
+        match value {
+            Value::Object(obj) => {
+                let mut new_attrs = Map::default();
+                for (key, mut value) in obj.attributes.drain(..) {
+                    if let Some(target) = self.resolve_target(feedback, &key) {
+                        if !self.convert_value(feedback, &mut value, target) {
+                            continue;
+                        }
+                    }
+                    self.edit_tree(feedback, &mut value);
+                    let uom = match self.sibling_field {
+                        true => uom_value(&value),
+                        false => None,
+                    };
+                    new_attrs.insert(key.clone(), value);
+                    if let Some(uom) = uom {
+                        new_attrs.insert(format!("{key}{UOM_SUFFIX}"), uom);
+                    }
+                }
+                obj.attributes = new_attrs;
+            }
+            Value::Array(arr) => {
+                for v in arr.iter_mut() {
+                    self.edit_tree(feedback, v);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Converts the measures to the target unit, and returns false if the value cannot be converted.
+    ///
+    /// The measures that cannot be converted are removed from arrays.
+    fn convert_value(&mut self, feedback: &Feedback, value: &mut Value, target: Unit) -> bool {
+        match value {
+            Value::Measure(m) => match self.convert_measure(feedback, m, target) {
+                Some(converted) => {
+                    *m = converted;
+                    true
+                }
+                None => false,
+            },
+            Value::Array(arr) => {
+                arr.retain_mut(|v| self.convert_value(feedback, v, target));
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn convert_measure(
+        &mut self,
+        feedback: &Feedback,
+        m: &Measure,
+        (to_unit, to_dim, to_factor): Unit,
+    ) -> Option<Measure> {
+        let factor = match m.uom() {
+            // Regarded as in the SI unit
+            None => 1.0,
+            Some(uom) => match lookup_unit(uom) {
+                Some((_, dim, factor)) if dim == to_dim => factor,
+                Some(_) => {
+                    if self.reported.insert(format!("{uom}->{to_unit}")) {
+                        feedback.warn(format!(
+                            "Cannot convert unit {uom} to {to_unit}; removing the values"
+                        ));
+                    }
+                    return None;
+                }
+                None => {
+                    if self.reported.insert(uom.to_string()) {
+                        feedback.warn(format!(
+                            "Unknown unit of measure: {uom}; removing the values to convert"
+                        ));
+                    }
+                    return None;
+                }
+            },
+        };
+        Some(Measure::with_uom(
+            m.value() * factor / to_factor,
+            Some(to_unit.to_string()),
+        ))
+    }
+}
+
+/// Returns the unit(s) of a measure (or an array of measures) as string(s).
+fn uom_value(value: &Value) -> Option<Value> {
+    match value {
+        Value::Measure(m) => m.uom().map(|uom| Value::String(uom.to_string())),
+        Value::Array(arr)
+            if !arr.is_empty() && arr.iter().all(|v| matches!(v, Value::Measure(_))) =>
+        {
+            Some(Value::Array(
+                arr.iter()
+                    .map(|v| uom_value(v).unwrap_or(Value::String(String::new())))
+                    .collect(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, schema::FeatureTypeDef, GeometryStore};
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    fn make_entity() -> Entity {
+        let mut attributes = Map::default();
+        attributes.insert(
+            "bldg:measuredHeight".into(),
+            Value::Measure(Measure::with_uom(12500.0, Some("mm".into()))),
+        );
+        attributes.insert(
+            "uro:buildingRoofEdgeArea".into(),
+            Value::Measure(Measure::with_uom(0.5, Some("ha".into()))),
+        );
+        attributes.insert(
+            "uro:unknown".into(),
+            Value::Measure(Measure::with_uom(1.0, Some("furlong".into()))),
+        );
+        attributes.insert(
+            "uro:totalFloorArea".into(),
+            Value::Measure(Measure::new(120.0)),
+        );
+        attributes.insert(
+            "uro:eaveHeight".into(),
+            Value::Array(vec![
+                Value::Measure(Measure::with_uom(3.0, Some("m".into()))),
+                Value::Measure(Measure::with_uom(1.0, Some("furlong".into()))),
+            ]),
+        );
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes,
+                stereotype: nusamai_citygml::object::ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: Default::default(),
+                },
+            }),
+            GeometryStore::default(),
+        )
+    }
+
+    fn run(rules: &UnitRules) -> Map {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = MeasureUnitTransform::new(rules);
+        let mut out = Vec::new();
+        transform.transform(&feedback, make_entity(), &mut out);
+        let Value::Object(obj) = out.pop().unwrap().root else {
+            unreachable!()
+        };
+        obj.attributes
+    }
+
+    fn measure(value: &Value) -> (f64, Option<&str>) {
+        let Value::Measure(m) = value else {
+            unreachable!()
+        };
+        (m.value(), m.uom())
+    }
+
+    #[test]
+    fn test_normalize_unit() {
+        assert_eq!(normalize_unit("urn:ogc:def:uom:UCUM::m2"), "m2");
+        assert_eq!(normalize_unit("http://example.com/uom#km"), "km");
+        assert_eq!(normalize_unit("m^2"), "m2");
+        assert_eq!(normalize_unit("m³"), "m3");
+        assert_eq!(normalize_unit("L"), "l");
+    }
+
+    #[test]
+    fn test_measure_in_meters() {
+        assert_eq!(measure_in_meters(&Measure::new(3.)), Some(3.));
+        assert_eq!(
+            measure_in_meters(&Measure::with_uom(2500., Some("mm".into()))),
+            Some(2.5)
+        );
+        assert_eq!(
+            measure_in_meters(&Measure::with_uom(1., Some("ha".into()))),
+            None
+        );
+    }
+
+    #[test]
+    fn convert_to_si() {
+        let attrs = run(&UnitRules::default());
+        assert_eq!(measure(&attrs["bldg:measuredHeight"]), (12.5, Some("m")));
+        assert_eq!(
+            measure(&attrs["uro:buildingRoofEdgeArea"]),
+            (5000.0, Some("m2"))
+        );
+        assert_eq!(measure(&attrs["uro:unknown"]), (1.0, Some("furlong")));
+        // without the unit: regarded as in the SI unit
+        assert_eq!(measure(&attrs["uro:totalFloorArea"]), (120.0, Some("m2")));
+        // unknown unit: removed
+        let Value::Array(eave_heights) = &attrs["uro:eaveHeight"] else {
+            unreachable!()
+        };
+        assert_eq!(eave_heights.len(), 1);
+        assert_eq!(measure(&eave_heights[0]), (3.0, Some("m")));
+    }
+
+    #[test]
+    fn unknown_target_unit() {
+        let rules = UnitRules {
+            convert: false,
+            targets: [("*:measuredHeight".to_string(), "furlong".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let attrs = run(&rules);
+        assert_eq!(
+            measure(&attrs["bldg:measuredHeight"]),
+            (12500.0, Some("mm"))
+        );
+
+        let mut schema = Schema::default();
+        let mut typedef = FeatureTypeDef::default();
+        typedef.attributes.insert(
+            "bldg:measuredHeight".into(),
+            schema::Attribute::new(TypeRef::Measure),
+        );
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(typedef));
+        MeasureUnitTransform::new(&rules).transform_schema(&mut schema);
+        let TypeDef::Feature(typedef) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        assert_eq!(typedef.attributes["bldg:measuredHeight"].uom, None);
+    }
+
+    #[test]
+    fn record_canonical_unit_in_schema() {
+        let mut schema = Schema::default();
+        let mut typedef = FeatureTypeDef::default();
+        for name in ["bldg:measuredHeight", "uro:totalFloorArea", "uro:slope"] {
+            typedef
+                .attributes
+                .insert(name.into(), schema::Attribute::new(TypeRef::Measure));
+        }
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(typedef));
+        MeasureUnitTransform::new(&UnitRules::default()).transform_schema(&mut schema);
+        let TypeDef::Feature(typedef) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        assert_eq!(
+            typedef.attributes["bldg:measuredHeight"].uom.as_deref(),
+            Some("m")
+        );
+        assert_eq!(
+            typedef.attributes["uro:totalFloorArea"].uom.as_deref(),
+            Some("m2")
+        );
+        // unknown dimension: not converted
+        assert_eq!(typedef.attributes["uro:slope"].uom, None);
+    }
+
+    #[test]
+    fn convert_to_target_with_sibling_field() {
+        let rules = UnitRules {
+            sibling_field: true,
+            targets: [
+                ("*:measuredHeight".to_string(), "cm".to_string()),
+                // incompatible dimension: removed
+                ("uro:buildingRoofEdgeArea".to_string(), "kg".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let attrs = run(&rules);
+        let keys: Vec<_> = attrs.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "bldg:measuredHeight",
+                "bldg:measuredHeight_uom",
+                "uro:unknown",
+                "uro:unknown_uom",
+                "uro:totalFloorArea",
+                "uro:totalFloorArea_uom",
+                "uro:eaveHeight",
+                "uro:eaveHeight_uom",
+            ]
+        );
+        assert_eq!(measure(&attrs["bldg:measuredHeight"]), (1250.0, Some("cm")));
+        assert_eq!(attrs["bldg:measuredHeight_uom"], Value::String("cm".into()));
+
+        let mut schema = Schema::default();
+        let mut typedef = FeatureTypeDef::default();
+        typedef.attributes.insert(
+            "bldg:measuredHeight".into(),
+            schema::Attribute::new(TypeRef::Measure),
+        );
+        typedef
+            .attributes
+            .insert("bldg:class".into(), schema::Attribute::new(TypeRef::Code));
+        schema
+            .types
+            .insert("bldg:Building".into(), TypeDef::Feature(typedef));
+        MeasureUnitTransform::new(&rules).transform_schema(&mut schema);
+        let TypeDef::Feature(typedef) = &schema.types["bldg:Building"] else {
+            unreachable!()
+        };
+        let keys: Vec<_> = typedef.attributes.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "bldg:measuredHeight",
+                "bldg:measuredHeight_uom",
+                "bldg:class"
+            ]
+        );
+        assert_eq!(
+            typedef.attributes["bldg:measuredHeight"].uom.as_deref(),
+            Some("cm")
+        );
+    }
+}
@@ -0,0 +1,483 @@
+//! Geometry validation
+
+use std::collections::BTreeSet;
+
+use hashbrown::HashMap;
+use nusamai_citygml::{
+    object::{ObjectStereotype, Value},
+    schema::{Attribute, FeatureTypeDef, Schema, TypeDef, TypeRef},
+    GeometryType,
+};
+use nusamai_geometry::{
+    algorithm::{
+        intersection::ring_self_intersects,
+        plane::{max_distance_to_plane, project_to_2d, unit_normal},
+    },
+    Polygon,
+};
+use nusamai_plateau::Entity;
+
+use super::metric::to_local_metric;
+use crate::{pipeline::Feedback, transformer::Transform};
+
+/// Name of the attribute to store the validation error codes
+pub const GEOMETRY_ERRORS_ATTRIBUTE: &str = "geometryErrors";
+
+/// Geometry errors, numbered according to ISO 19107 and [val3dity](https://val3dity.readthedocs.io/en/latest/errors/).
+///
+/// NOTE: Rings in `GeometryStore` are implicitly closed, so `103 RING_NOT_CLOSED` is never reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GeometryError {
+    /// A ring has less than 3 distinct points
+    TooFewPoints = 101,
+    /// A ring has two consecutive points at the same location
+    ConsecutivePointsSame = 102,
+    /// A ring intersects itself or collapses to a line
+    RingSelfIntersection = 104,
+    /// A point of a polygon is farther from its fitted plane than the tolerance
+    NonPlanarPolygonDistancePlane = 203,
+    /// An interior ring has the same orientation as the exterior ring
+    OrientationRingsSame = 208,
+    /// A shell has less than 4 polygons
+    TooFewPolygons = 301,
+    /// A shell has holes (an edge is used only once)
+    ShellNotClosed = 302,
+    /// An edge of a shell is shared by more than 2 polygons
+    NonManifoldEdge = 304,
+    /// Some polygons of a shell are not oriented consistently with their neighbours
+    PolygonWrongOrientation = 307,
+    /// All polygons of a shell are oriented inwards
+    AllPolygonsWrongOrientation = 308,
+}
+
+impl GeometryError {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeometryError::TooFewPoints => "TOO_FEW_POINTS",
+            GeometryError::ConsecutivePointsSame => "CONSECUTIVE_POINTS_SAME",
+            GeometryError::RingSelfIntersection => "RING_SELF_INTERSECTION",
+            GeometryError::NonPlanarPolygonDistancePlane => "NON_PLANAR_POLYGON_DISTANCE_PLANE",
+            GeometryError::OrientationRingsSame => "ORIENTATION_RINGS_SAME",
+            GeometryError::TooFewPolygons => "TOO_FEW_POLYGONS",
+            GeometryError::ShellNotClosed => "SHELL_NOT_CLOSED",
+            GeometryError::NonManifoldEdge => "NON_MANIFOLD_EDGE",
+            GeometryError::PolygonWrongOrientation => "POLYGON_WRONG_ORIENTATION",
+            GeometryError::AllPolygonsWrongOrientation => "ALL_POLYGONS_WRONG_ORIENTATION",
+        }
+    }
+}
+
+/// Validates the polygons and solids of features, and tags the features with the error codes found.
+///
+/// The error codes are stored in the `geometryErrors` attribute of each invalid feature.
+#[derive(Clone)]
+pub struct GeometryValidationTransform {
+    /// Tolerance (in metres) for the planarity check
+    planarity_tolerance: f64,
+    /// Whether to discard entities that contain invalid geometries
+    drop_invalid: bool,
+}
+
+impl Default for GeometryValidationTransform {
+    fn default() -> Self {
+        Self {
+            planarity_tolerance: 0.01,
+            drop_invalid: false,
+        }
+    }
+}
+
+impl GeometryValidationTransform {
+    pub fn new(planarity_tolerance: f64, drop_invalid: bool) -> Self {
+        Self {
+            planarity_tolerance,
+            drop_invalid,
+        }
+    }
+}
+
+impl Transform for GeometryValidationTransform {
+    fn transform(&mut self, feedback: &Feedback, mut entity: Entity, out: &mut Vec<Entity>) {
+        let mut invalid_features = Vec::new();
+        {
+            let geom_store = entity.geometry_store.read().unwrap();
+            let vertices = to_local_metric(&geom_store.vertices, geom_store.epsg, false);
+            let validator = Validator {
+                vertices: &vertices,
+                multipolygon: &geom_store.multipolygon,
+                planarity_tolerance: self.planarity_tolerance,
+            };
+            validate_tree(&mut entity.root, &validator, &mut invalid_features);
+        }
+
+        if invalid_features.is_empty() {
+            out.push(entity);
+            return;
+        }
+
+        for (id, errors) in &invalid_features {
+            let errors = errors
+                .iter()
+                .map(|e| format!("{} {}", e.code(), e.name()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            feedback.warn(format!("Invalid geometry in feature {}: {}", id, errors));
+        }
+
+        if self.drop_invalid {
+            feedback.warn(format!(
+                "Dropped an entity with {} invalid feature(s)",
+                invalid_features.len()
+            ));
+        } else {
+            out.push(entity);
+        }
+    }
+
+    fn transform_schema(&self, schema: &mut Schema) {
+        for ty in schema.types.values_mut() {
+            match ty {
+                TypeDef::Feature(FeatureTypeDef { attributes, .. }) => {
+                    attributes.insert(
+                        GEOMETRY_ERRORS_ATTRIBUTE.to_string(),
+                        Attribute {
+                            type_ref: TypeRef::Integer,
+                            max_occurs: None,
+                            ..Default::default()
+                        },
+                    );
+                }
+                TypeDef::Data(_) | TypeDef::Property(_) => {}
+            }
+        }
+    }
+}
+
+struct Validator<'a> {
+    vertices: &'a [[f64; 3]],
+    multipolygon: &'a nusamai_geometry::MultiPolygon<'static, u32>,
+    planarity_tolerance: f64,
+}
+
+/// Validates all the features in the tree, and collects the ids and errors of the invalid ones.
+fn validate_tree(
+    value: &mut Value,
+    validator: &Validator,
+    invalid_features: &mut Vec<(String, BTreeSet<GeometryError>)>,
+) {
+    match value {
+        Value::Object(obj) => {
+            if let ObjectStereotype::Feature { id, geometries } = &obj.stereotype {
+                let mut errors = BTreeSet::new();
+                for geom in geometries {
+                    let range = geom.pos as usize..(geom.pos + geom.len) as usize;
+                    match geom.ty {
+                        GeometryType::Solid => {
+                            for poly in validator.multipolygon.iter_range(range.clone()) {
+                                validator.validate_polygon(&poly, &mut errors);
+                            }
+                            validator.validate_shell(range, &mut errors);
+                        }
+                        GeometryType::Surface | GeometryType::Triangle => {
+                            for poly in validator.multipolygon.iter_range(range) {
+                                validator.validate_polygon(&poly, &mut errors);
+                            }
+                        }
+                        GeometryType::Curve | GeometryType::Point => {}
+                    }
+                }
+                if !errors.is_empty() {
+                    obj.attributes.insert(
+                        GEOMETRY_ERRORS_ATTRIBUTE.to_string(),
+                        Value::Array(
+                            errors
+                                .iter()
+                                .map(|e| Value::Integer(e.code() as i64))
+                                .collect(),
+                        ),
+                    );
+                    invalid_features.push((id.clone(), errors));
+                }
+            }
+            for value in obj.attributes.values_mut() {
+                validate_tree(value, validator, invalid_features);
+            }
+        }
+        Value::Array(arr) => {
+            for value in arr.iter_mut() {
+                validate_tree(value, validator, invalid_features);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Validator<'_> {
+    fn validate_polygon(&self, poly: &Polygon<u32>, errors: &mut BTreeSet<GeometryError>) {
+        let mut exterior_normal = None;
+
+        for (ring_idx, ring) in poly.rings().enumerate() {
+            let mut points: Vec<[f64; 3]> =
+                ring.iter().map(|idx| self.vertices[idx as usize]).collect();
+
+            // Duplicate consecutive points, including the implicit closing edge
+            let len_before = points.len();
+            points.dedup();
+            while points.len() > 1 && points.first() == points.last() {
+                points.pop();
+            }
+            if points.len() != len_before {
+                errors.insert(GeometryError::ConsecutivePointsSame);
+            }
+
+            if points.len() < 3 {
+                errors.insert(GeometryError::TooFewPoints);
+                continue;
+            }
+
+            let Some(normal) = unit_normal(&points) else {
+                // collapsed to a line
+                errors.insert(GeometryError::RingSelfIntersection);
+                continue;
+            };
+
+            if let Some(dist) = max_distance_to_plane(&points) {
+                if dist > self.planarity_tolerance {
+                    errors.insert(GeometryError::NonPlanarPolygonDistancePlane);
+                }
+            }
+
+            if ring_self_intersects(&project_to_2d(&points, normal)) {
+                errors.insert(GeometryError::RingSelfIntersection);
+            }
+
+            match exterior_normal {
+                None if ring_idx == 0 => exterior_normal = Some(normal),
+                Some(ext) => {
+                    let dot = ext[0] * normal[0] + ext[1] * normal[1] + ext[2] * normal[2];
+                    if dot > 0. {
+                        errors.insert(GeometryError::OrientationRingsSame);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn validate_shell(&self, range: std::ops::Range<usize>, errors: &mut BTreeSet<GeometryError>) {
+        if range.len() < 4 {
+            errors.insert(GeometryError::TooFewPolygons);
+        }
+
+        // (forward, backward) usage count of each undirected edge
+        let mut edges: HashMap<(u32, u32), (u32, u32)> = HashMap::new();
+        let mut volume = 0.0;
+
+        for poly in self.multipolygon.iter_range(range) {
+            for ring in poly.rings() {
+                let indices: Vec<u32> = ring.iter().collect();
+                for (i, &a) in indices.iter().enumerate() {
+                    let b = indices[(i + 1) % indices.len()];
+                    if a == b {
+                        continue;
+                    }
+                    let entry = edges.entry((a.min(b), a.max(b))).or_default();
+                    if a < b {
+                        entry.0 += 1;
+                    } else {
+                        entry.1 += 1;
+                    }
+                }
+
+                // signed volume of the shell (divergence theorem, fan triangulation)
+                if let Some((&first, rest)) = indices.split_first() {
+                    let v0 = self.vertices[first as usize];
+                    for w in rest.windows(2) {
+                        let v1 = self.vertices[w[0] as usize];
+                        let v2 = self.vertices[w[1] as usize];
+                        volume += v0[0] * (v1[1] * v2[2] - v1[2] * v2[1])
+                            - v0[1] * (v1[0] * v2[2] - v1[2] * v2[0])
+                            + v0[2] * (v1[0] * v2[1] - v1[1] * v2[0]);
+                    }
+                }
+            }
+        }
+
+        let mut consistent = true;
+        for &(forward, backward) in edges.values() {
+            match forward + backward {
+                1 => {
+                    errors.insert(GeometryError::ShellNotClosed);
+                }
+                2 if forward != backward => {
+                    errors.insert(GeometryError::PolygonWrongOrientation);
+                    consistent = false;
+                }
+                2 => {}
+                _ => {
+                    errors.insert(GeometryError::NonManifoldEdge);
+                }
+            }
+        }
+
+        if consistent && !edges.is_empty() && volume < 0. {
+            errors.insert(GeometryError::AllPolygonsWrongOrientation);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nusamai_citygml::{object::Object, GeometryRef, GeometryStore};
+    use nusamai_geometry::MultiPolygon;
+
+    use super::*;
+    use crate::{pipeline::feedback, transformer::transform::test_entity};
+
+    const CUBE_VERTICES: [[f64; 3]; 8] = [
+        [0., 0., 0.],
+        [1., 0., 0.],
+        [1., 1., 0.],
+        [0., 1., 0.],
+        [0., 0., 1.],
+        [1., 0., 1.],
+        [1., 1., 1.],
+        [0., 1., 1.],
+    ];
+
+    // Outward-facing faces of a unit cube
+    const CUBE_FACES: [[u32; 4]; 6] = [
+        [0, 3, 2, 1],
+        [4, 5, 6, 7],
+        [0, 1, 5, 4],
+        [3, 7, 6, 2],
+        [0, 4, 7, 3],
+        [1, 2, 6, 5],
+    ];
+
+    fn make_entity(vertices: &[[f64; 3]], faces: &[Vec<u32>], ty: GeometryType) -> Entity {
+        let mut mpoly = MultiPolygon::<u32>::new();
+        for face in faces {
+            mpoly.add_exterior(face.iter().copied());
+        }
+        let len = mpoly.len() as u32;
+        test_entity(
+            Value::Object(Object {
+                typename: "bldg:Building".into(),
+                attributes: Default::default(),
+                stereotype: ObjectStereotype::Feature {
+                    id: "bldg_1".into(),
+                    geometries: vec![GeometryRef {
+                        ty,
+                        lod: 1,
+                        pos: 0,
+                        len,
+                    }],
+                },
+            }),
+            GeometryStore {
+                vertices: vertices.to_vec(),
+                multipolygon: mpoly,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn run(entity: Entity, drop_invalid: bool) -> (Vec<Entity>, Vec<i64>) {
+        let (_watcher, feedback, _canceller) = feedback::watcher();
+        let mut transform = GeometryValidationTransform::new(0.01, drop_invalid);
+        let mut out = Vec::new();
+        transform.transform(&feedback, entity, &mut out);
+
+        let codes = match out.first().map(|e| &e.root) {
+            Some(Value::Object(obj)) => match obj.attributes.get(GEOMETRY_ERRORS_ATTRIBUTE) {
+                Some(Value::Array(arr)) => arr
+                    .iter()
+                    .map(|v| match v {
+                        Value::Integer(i) => *i,
+                        _ => unreachable!(),
+                    })
+                    .collect(),
+                _ => vec![],
+            },
+            _ => vec![],
+        };
+        (out, codes)
+    }
+
+    #[test]
+    fn valid_cube() {
+        let faces: Vec<_> = CUBE_FACES.iter().map(|f| f.to_vec()).collect();
+        let (out, codes) = run(
+            make_entity(&CUBE_VERTICES, &faces, GeometryType::Solid),
+            true,
+        );
+        assert_eq!(out.len(), 1);
+        assert!(codes.is_empty());
+    }
+
+    #[test]
+    fn flipped_face() {
+        let mut faces: Vec<_> = CUBE_FACES.iter().map(|f| f.to_vec()).collect();
+        faces[1].reverse();
+        let (out, codes) = run(
+            make_entity(&CUBE_VERTICES, &faces, GeometryType::Solid),
+            false,
+        );
+        assert_eq!(out.len(), 1);
+        assert_eq!(codes, vec![307]);
+    }
+
+    #[test]
+    fn inverted_cube() {
+        let faces: Vec<_> = CUBE_FACES
+            .iter()
+            .map(|f| f.iter().rev().copied().collect())
+            .collect();
+        let (_, codes) = run(
+            make_entity(&CUBE_VERTICES, &faces, GeometryType::Solid),
+            false,
+        );
+        assert_eq!(codes, vec![308]);
+    }
+
+    #[test]
+    fn open_shell() {
+        let faces: Vec<_> = CUBE_FACES[..5].iter().map(|f| f.to_vec()).collect();
+        let (_, codes) = run(
+            make_entity(&CUBE_VERTICES, &faces, GeometryType::Solid),
+            false,
+        );
+        assert_eq!(codes, vec![302]);
+    }
+
+    #[test]
+    fn invalid_surfaces() {
+        let vertices = [
+            [0., 0., 0.],
+            [1., 0., 0.],
+            [1., 1., 0.],
+            [0., 1., 0.],
+            [1., 1., 0.5],
+        ];
+        let faces = vec![
+            // bowtie
+            vec![0, 2, 1, 3],
+            // non-planar
+            vec![0, 1, 4, 3],
+            // too few points
+            vec![0, 1, 1],
+        ];
+        let (out, codes) = run(make_entity(&vertices, &faces, GeometryType::Surface), false);
+        assert_eq!(out.len(), 1);
+        assert_eq!(codes, vec![101, 102, 104, 203]);
+
+        // dropped
+        let (out, _) = run(make_entity(&vertices, &faces, GeometryType::Surface), true);
+        assert!(out.is_empty());
+    }
+}